@@ -0,0 +1,160 @@
+use super::{lan::lan_connect, signaling::signaling_visit, AppState};
+use mirrorx_core::{
+    api::config::entity::address_book::{ConnectionMode, Contact, ContactFilter},
+    core_error,
+    error::CoreResult,
+};
+use serde::Serialize;
+use tauri::State;
+use tauri_egui::EguiPluginHandle;
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state, contact))]
+pub async fn address_book_create(
+    app_state: State<'_, AppState>,
+    contact: Contact,
+) -> CoreResult<Contact> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.address_book().add_contact(contact)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn address_book_get(app_state: State<'_, AppState>, id: i64) -> CoreResult<Contact> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.address_book().get_contact_by_id(id)
+}
+
+#[derive(Serialize)]
+pub struct AddressBookListResponse {
+    pub total: u32,
+    pub contacts: Vec<Contact>,
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn address_book_list(
+    app_state: State<'_, AppState>,
+    page: u32,
+    limit: u32,
+    filter: Option<ContactFilter>,
+) -> CoreResult<AddressBookListResponse> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    let (total, contacts) =
+        storage
+            .address_book()
+            .get_contacts(page, limit, &filter.unwrap_or_default())?;
+
+    Ok(AddressBookListResponse { total, contacts })
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn address_book_groups(app_state: State<'_, AppState>) -> CoreResult<Vec<String>> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.address_book().get_groups()
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn address_book_tags(app_state: State<'_, AppState>) -> CoreResult<Vec<String>> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.address_book().get_tags()
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state, contact))]
+pub async fn address_book_update(
+    app_state: State<'_, AppState>,
+    contact: Contact,
+) -> CoreResult<Contact> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.address_book().update_contact(contact)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn address_book_delete(app_state: State<'_, AppState>, id: i64) -> CoreResult<()> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.address_book().delete_contact(id)
+}
+
+// the saved password is opened here only, it never leaves the backend
+#[tauri::command]
+#[tracing::instrument(skip(app_handle, app_state, egui_plugin))]
+pub async fn address_book_connect(
+    app_handle: tauri::AppHandle,
+    app_state: State<'_, AppState>,
+    egui_plugin: State<'_, EguiPluginHandle>,
+    id: i64,
+) -> CoreResult<()> {
+    let (contact, password, primary_domain) = {
+        let Some(ref storage) = *app_state.storage.lock().await else {
+            return Err(core_error!("storage not initialize"));
+        };
+
+        (
+            storage.address_book().get_contact_by_id(id)?,
+            storage.address_book().get_contact_password(id)?,
+            storage.domain().get_primary_domain()?,
+        )
+    };
+
+    let visit_desktop = contact.connection_mode == ConnectionMode::Desktop;
+
+    if contact.device_id == 0 {
+        let Some(lan_addr) = contact.lan_addr else {
+            return Err(core_error!("contact has neither device id nor lan addr"));
+        };
+
+        return lan_connect(app_handle, app_state, egui_plugin, lan_addr, visit_desktop).await;
+    }
+
+    // visits are launched from the primary domain
+    if contact.domain != primary_domain.name {
+        return Err(core_error!("contact belongs to a non-primary domain"));
+    }
+
+    let Some(password) = password else {
+        return Err(core_error!("contact has no saved password"));
+    };
+
+    let device_id = format!("{:010}", contact.device_id);
+    let remote_device_id = format!(
+        "{}-{}-{}",
+        &device_id[..2],
+        &device_id[2..6],
+        &device_id[6..]
+    );
+
+    signaling_visit(
+        app_handle,
+        app_state,
+        egui_plugin,
+        remote_device_id,
+        password,
+        visit_desktop,
+    )
+    .await
+}
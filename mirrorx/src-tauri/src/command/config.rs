@@ -1,11 +1,20 @@
-use crate::command::AppState;
+use crate::{
+    command::{signaling::signaling_connect, AppState},
+    utility::set_log_level,
+};
 use mirrorx_core::{
     api::{
         config::{
-            entity::{domain::Domain, history::Record, kv::Theme},
+            backup::MergeStrategy,
+            entity::{
+                domain::Domain,
+                history::{HistoryFilter, Record, DEFAULT_RETENTION_DAYS},
+                kv::{Settings, Theme},
+                profile::ConnectionProfile,
+            },
             LocalStorage,
         },
-        signaling::http_message::Response,
+        signaling::{ensure_client_version, http_message::Response, transport::parse_proxy},
     },
     core_error,
     error::CoreResult,
@@ -30,21 +39,34 @@ pub async fn config_init(
     std::fs::create_dir_all(config_dir.clone())?;
     let storage_path = config_dir.join("mirrorx.db");
 
+    // the secret key sealing saved passwords lives out of the config dir, which is the
+    // one roamed or synced across machines
+    let secret_key_path = app_handle
+        .path_resolver()
+        .app_local_data_dir()
+        .ok_or(core_error!("read app local data dir failed"))?
+        .join("mirrorx.key");
+
     tracing::info!(path = ?storage_path, "read config");
 
-    let storage = LocalStorage::new(storage_path)?;
+    let storage = LocalStorage::new(storage_path, secret_key_path)?;
     let domain_count = storage.domain().get_domain_count()?;
 
+    set_log_level(storage.kv().get_settings()?.log_level);
+
     let mut storage_guard = app_state.storage.lock().await;
     *storage_guard = Some(storage);
     drop(storage_guard);
 
     if domain_count == 0 {
         config_domain_create(
+            app_handle,
             app_state,
             String::from("http://mirrorx.cloud:28000"),
             true,
             String::default(),
+            None,
+            false,
         )
         .await?;
     }
@@ -88,13 +110,18 @@ pub async fn config_domain_get_id_and_names(
 }
 
 #[tauri::command]
-#[tracing::instrument(skip(app_state))]
+#[tracing::instrument(skip(app_handle, app_state))]
 pub async fn config_domain_create(
+    app_handle: AppHandle,
     app_state: State<'_, AppState>,
     addr: String,
     is_primary: bool,
     remarks: String,
+    proxy: Option<String>,
+    subscribe_tls: bool,
 ) -> CoreResult<()> {
+    let proxy = proxy.filter(|proxy| !proxy.trim().is_empty());
+
     let uri = addr
         .parse::<SocketAddr>()
         .map(|addr| {
@@ -107,12 +134,18 @@ pub async fn config_domain_create(
         })
         .unwrap_or_else(|_| Uri::try_from(addr).map_err(|_| core_error!("invalid uri format")))?;
 
-    let client = mirrorx_core::api::signaling::SignalingClient::new(uri.to_string())?;
+    let client =
+        mirrorx_core::api::signaling::SignalingClient::new(uri.to_string(), proxy.as_deref())?;
     let response = match client.identity().await? {
         Response::Message(resp) => resp,
         Response::Error(err) => return Err(core_error!("http error: {:?}", err)),
     };
 
+    ensure_client_version(
+        &app_handle.package_info().version.to_string(),
+        &response.min_client_version,
+    )?;
+
     let Some(ref storage) = *app_state.storage.lock().await else {
         return Err(core_error!("storage not initialize"));
     };
@@ -141,6 +174,9 @@ pub async fn config_domain_create(
         password: mirrorx_core::utility::rand::generate_random_password(),
         finger_print,
         remarks,
+        expire: response.expire,
+        proxy,
+        subscribe_tls,
     })?;
 
     Ok(())
@@ -149,6 +185,8 @@ pub async fn config_domain_create(
 #[tauri::command]
 #[tracing::instrument(skip(app_state))]
 pub async fn config_domain_delete(id: i64, app_state: State<'_, AppState>) -> CoreResult<()> {
+    let mut signaling_clients = app_state.signaling_clients.lock().await;
+
     let Some(ref storage) = *app_state.storage.lock().await else {
         return Err(core_error!("storage not initialize"));
     };
@@ -156,6 +194,55 @@ pub async fn config_domain_delete(id: i64, app_state: State<'_, AppState>) -> Co
     let domain = storage.domain().get_domain_by_id(id)?;
     storage.domain().delete_domain(id)?;
     storage.history().delete_domain_related(&domain.name)?;
+    storage.profile().delete_domain_related(&domain.name)?;
+
+    // dropping the client closes the subscription of the deleted domain
+    signaling_clients.remove(&id);
+
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state, passphrase))]
+pub async fn config_export(
+    app_state: State<'_, AppState>,
+    path: String,
+    passphrase: Option<String>,
+) -> CoreResult<()> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.export(path, passphrase.as_deref())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_handle, app_state, passphrase))]
+pub async fn config_import(
+    app_handle: AppHandle,
+    app_state: State<'_, AppState>,
+    path: String,
+    merge_strategy: MergeStrategy,
+    passphrase: Option<String>,
+) -> CoreResult<()> {
+    let settings = match *app_state.storage.lock().await {
+        Some(ref storage) => {
+            storage.import(path, merge_strategy, passphrase.as_deref())?;
+            storage.kv().get_settings()?
+        }
+        None => return Err(core_error!("storage not initialize")),
+    };
+
+    set_log_level(settings.log_level);
+
+    if let Some(ref lan_provider) = *app_state.lan_provider.lock().await {
+        lan_provider.set_discoverable(settings.lan_discoverable);
+    }
+
+    // domains may have been replaced or added without a registration, subscribe them again
+    if let Err(err) = signaling_connect(app_handle, app_state, true).await {
+        tracing::warn!(?err, "resubscribe signaling after import failed");
+    }
 
     Ok(())
 }
@@ -194,6 +281,8 @@ pub enum ConfigDomainUpdateType {
     SetPrimary,
     Password(String),
     Remarks(String),
+    Proxy(Option<String>),
+    SubscribeTls(bool),
 }
 
 #[tauri::command]
@@ -208,9 +297,8 @@ pub async fn config_domain_update(
 
     match req.update_type {
         ConfigDomainUpdateType::SetPrimary => {
-            let current_signaling = app_state.signaling_client.lock().await;
-            if let Some((domain_id, _)) = *current_signaling {
-                if domain_id == req.id {
+            if let Ok(primary_domain) = storage.domain().get_primary_domain() {
+                if primary_domain.id == req.id {
                     return Ok(());
                 }
             }
@@ -223,6 +311,19 @@ pub async fn config_domain_update(
         ConfigDomainUpdateType::Remarks(new_remarks) => {
             storage.domain().set_domain_remarks(req.id, &new_remarks)?
         }
+        ConfigDomainUpdateType::Proxy(new_proxy) => {
+            let new_proxy = new_proxy.filter(|proxy| !proxy.trim().is_empty());
+            if let Some(ref new_proxy) = new_proxy {
+                parse_proxy(new_proxy)?;
+            }
+
+            storage
+                .domain()
+                .set_domain_proxy(req.id, new_proxy.as_deref())?
+        }
+        ConfigDomainUpdateType::SubscribeTls(subscribe_tls) => storage
+            .domain()
+            .set_domain_subscribe_tls(req.id, subscribe_tls)?,
     }
 
     Ok(())
@@ -351,18 +452,133 @@ pub async fn config_theme_set(app_state: State<'_, AppState>, theme: Theme) -> C
     Ok(())
 }
 
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn config_settings_get(app_state: State<'_, AppState>) -> CoreResult<Settings> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.kv().get_settings()
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn config_settings_set(
+    app_state: State<'_, AppState>,
+    settings: Settings,
+) -> CoreResult<()> {
+    match *app_state.storage.lock().await {
+        Some(ref storage) => storage.kv().set_settings(&settings)?,
+        None => return Err(core_error!("storage not initialize")),
+    };
+
+    set_log_level(settings.log_level);
+
+    if let Some(ref lan_provider) = *app_state.lan_provider.lock().await {
+        lan_provider.set_discoverable(settings.lan_discoverable);
+    }
+
+    Ok(())
+}
+
 #[tauri::command]
 #[tracing::instrument(skip(app_state))]
 pub async fn config_history_get(
     app_state: State<'_, AppState>,
-    time_range: Option<(i64, i64)>,
+    filter: Option<HistoryFilter>,
 ) -> CoreResult<Vec<Record>> {
     let Some(ref storage) = *app_state.storage.lock().await else {
         return Err(core_error!("storage not initialize"));
     };
 
-    tracing::info!(?time_range, "query");
-    let records = storage.history().query(time_range)?;
+    tracing::info!(?filter, "query");
+    let records = storage.history().query(&filter.unwrap_or_default())?;
 
     Ok(records)
 }
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn config_history_retention_get(app_state: State<'_, AppState>) -> CoreResult<u32> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    Ok(storage
+        .kv()
+        .get_history_retention_days()?
+        .unwrap_or(DEFAULT_RETENTION_DAYS))
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn config_history_retention_set(
+    app_state: State<'_, AppState>,
+    retention_days: u32,
+) -> CoreResult<()> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.kv().set_history_retention_days(retention_days)?;
+    storage.history().delete_expired(retention_days)?;
+
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn config_profile_get(
+    app_state: State<'_, AppState>,
+    domain: String,
+    device_id: i64,
+) -> CoreResult<ConnectionProfile> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    match storage.profile().get_profile(&domain, device_id)? {
+        Some(profile) => Ok(profile),
+        None => Ok(ConnectionProfile::from_settings(
+            domain,
+            device_id,
+            &storage.kv().get_settings()?,
+        )),
+    }
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn config_profile_list(
+    app_state: State<'_, AppState>,
+) -> CoreResult<Vec<ConnectionProfile>> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.profile().get_profiles()
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn config_profile_save(
+    app_state: State<'_, AppState>,
+    profile: ConnectionProfile,
+) -> CoreResult<ConnectionProfile> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.profile().save_profile(profile)
+}
+
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn config_profile_delete(app_state: State<'_, AppState>, id: i64) -> CoreResult<()> {
+    let Some(ref storage) = *app_state.storage.lock().await else {
+        return Err(core_error!("storage not initialize"));
+    };
+
+    storage.profile().delete_profile(id)
+}
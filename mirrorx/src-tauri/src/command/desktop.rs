@@ -0,0 +1,65 @@
+use super::AppState;
+use crate::utility::capture_file_path;
+use mirrorx_core::{component::screenshot::save_screenshot, core_error, error::CoreResult};
+use std::{path::PathBuf, sync::Weak};
+use tauri::api::dialog::FileDialogBuilder;
+
+// the file is always chosen in a save dialog, so the frontend can't write anywhere else.
+// returns none if the dialog is cancelled
+#[tauri::command]
+#[tracing::instrument(skip(app_state))]
+pub async fn desktop_screenshot(
+    app_state: tauri::State<'_, AppState>,
+    remote: String,
+) -> CoreResult<Option<PathBuf>> {
+    let frame_slot = app_state
+        .desktop_frames
+        .lock()
+        .await
+        .get(&remote)
+        .and_then(Weak::upgrade);
+
+    let Some(frame_slot) = frame_slot else {
+        return Err(core_error!("desktop session not exists"));
+    };
+
+    // copy the frame out so the render isn't blocked while the dialog is open
+    let frame = frame_slot
+        .lock()
+        .map_err(|_| core_error!("lock desktop frame failed"))?
+        .clone();
+
+    let mut dialog = FileDialogBuilder::new()
+        .add_filter("PNG", &["png"])
+        .add_filter("JPEG", &["jpg", "jpeg"]);
+
+    if let Some(default_path) = capture_file_path(tauri::api::path::picture_dir(), &remote, "png") {
+        if let Some(dir) = default_path.parent() {
+            dialog = dialog.set_directory(dir);
+        }
+
+        if let Some(file_name) = default_path.file_name().and_then(|name| name.to_str()) {
+            dialog = dialog.set_file_name(file_name);
+        }
+    }
+
+    let (path_tx, path_rx) = tokio::sync::oneshot::channel();
+    dialog.save_file(move |path| {
+        let _ = path_tx.send(path);
+    });
+
+    let Ok(Some(path)) = path_rx.await else {
+        return Ok(None);
+    };
+
+    tokio::task::spawn_blocking({
+        let path = path.clone();
+        move || save_screenshot(&frame, &path)
+    })
+    .await
+    .map_err(|err| core_error!("join screenshot task failed ({})", err))??;
+
+    tracing::info!(?path, "screenshot saved");
+
+    Ok(Some(path))
+}
@@ -1,8 +1,15 @@
 use crate::{command::AppState, window::create_desktop_window};
 use mirrorx_core::{
-    api::endpoint::{
-        create_desktop_active_endpoint_client, create_file_manager_active_endpoint_client,
-        id::EndPointID, EndPointStream,
+    api::{
+        config::entity::{
+            history::{Direction, Record, SessionType},
+            kv::Settings,
+            profile::ConnectionProfile,
+        },
+        endpoint::{
+            create_desktop_active_endpoint_client, create_file_manager_active_endpoint_client,
+            id::EndPointID, record_session, EndPointStream,
+        },
     },
     component::lan::{LANProvider, Node},
     core_error,
@@ -14,10 +21,11 @@ use tauri_egui::EguiPluginHandle;
 #[tauri::command]
 #[tracing::instrument(skip(app_state))]
 pub async fn lan_init(app_state: tauri::State<'_, AppState>, force: bool) -> CoreResult<()> {
+    let storage = app_state.storage.lock().await.clone();
     let mut lan_provider = app_state.lan_provider.lock().await;
 
     if force || lan_provider.is_none() {
-        *lan_provider = Some(LANProvider::new().await?);
+        *lan_provider = Some(LANProvider::new(storage).await?);
     }
 
     Ok(())
@@ -55,15 +63,45 @@ pub async fn lan_connect(
         remote_ip,
     };
 
+    let mut session_record =
+        Record::new(Direction::Outgoing, SessionType::LAN, String::default(), 0);
+    session_record.peer_addr = Some(remote_ip.to_string());
+
+    if let Some(ref lan_provider) = *app_state.lan_provider.lock().await {
+        session_record.peer_os = lan_provider
+            .nodes()
+            .await
+            .into_iter()
+            .find(|node| node.addrs.contains_key(&remote_ip))
+            .map(|node| format!("{} {}", node.os, node.os_version));
+    }
+
+    let storage = app_state.storage.lock().await.clone();
+    let settings = match storage {
+        Some(ref storage) => storage.kv().get_settings()?,
+        None => Settings::default(),
+    };
+
     if visit_desktop {
         let (client, render_frame_rx) = create_desktop_active_endpoint_client(
             endpoint_id,
             None,
             EndPointStream::ActiveTCP(remote_addr),
             None,
+            settings.clone(),
+            ConnectionProfile::from_settings(String::default(), 0, &settings),
         )
         .await?;
 
+        if let Some(storage) = storage {
+            record_session(storage, client.clone(), session_record);
+        }
+
+        let window_client = client.clone();
+        let frame_slot = app_state
+            .register_desktop_frame(remote_ip.to_string())
+            .await;
+
         if let Err(err) = egui_plugin.create_window(
             window_label.clone(),
             Box::new(move |cc| {
@@ -72,8 +110,9 @@ pub async fn lan_connect(
                         cc,
                         gl_context.clone(),
                         endpoint_id,
-                        client,
+                        window_client,
                         render_frame_rx,
+                        frame_slot,
                     ))
                 } else {
                     panic!("get gl context failed");
@@ -85,6 +124,7 @@ pub async fn lan_connect(
                 ..Default::default()
             },
         ) {
+            client.close();
             tracing::error!(?err, "create desktop window failed");
             return Err(core_error!("create remote desktop window failed"));
         }
@@ -97,11 +137,15 @@ pub async fn lan_connect(
         )
         .await?;
 
+        if let Some(storage) = storage {
+            record_session(storage, client.clone(), session_record);
+        }
+
         app_state
             .files_endpoints
             .lock()
             .await
-            .insert(remote_ip.to_string(), client)
+            .insert(remote_ip.to_string(), client.clone())
             .await;
 
         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -127,6 +171,8 @@ pub async fn lan_connect(
         let create_result = rx.await.map_err(|_| core_error!("create window failed"))?;
 
         if let Some(err) = create_result {
+            client.close();
+
             app_state
                 .files_endpoints
                 .lock()
@@ -186,12 +232,18 @@ pub async fn lan_discoverable_set(
     app_state: tauri::State<'_, AppState>,
     discoverable: bool,
 ) -> CoreResult<()> {
-    if let Some(ref discover) = *app_state.lan_provider.lock().await {
-        discover.set_discoverable(discoverable);
-        Ok(())
-    } else {
-        Err(core_error!("lan discover is empty"))
+    match *app_state.lan_provider.lock().await {
+        Some(ref discover) => discover.set_discoverable(discoverable),
+        None => return Err(core_error!("lan discover is empty")),
+    };
+
+    if let Some(ref storage) = *app_state.storage.lock().await {
+        let mut settings = storage.kv().get_settings()?;
+        settings.lan_discoverable = discoverable;
+        storage.kv().set_settings(&settings)?;
     }
+
+    Ok(())
 }
 
 #[tauri::command]
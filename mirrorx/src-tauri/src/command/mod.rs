@@ -1,4 +1,6 @@
+pub mod address_book;
 pub mod config;
+pub mod desktop;
 pub mod file_manager;
 pub mod lan;
 pub mod signaling;
@@ -7,25 +9,55 @@ pub mod utility;
 use mirrorx_core::{
     api::{config::LocalStorage, endpoint::client::EndPointClient, signaling::SignalingClient},
     component::lan::LANProvider,
+    DesktopDecodeFrame,
 };
 use moka::future::{Cache, CacheBuilder};
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as StdMutex, Weak},
+};
 use tauri::async_runtime::Mutex;
 
 pub struct AppState {
     storage: Mutex<Option<LocalStorage>>,
-    signaling_client: Mutex<Option<(i64, SignalingClient)>>,
+    signaling_clients: Mutex<HashMap<i64, SignalingClient>>,
+    // never locked while holding the storage guard or the reverse
     lan_provider: Mutex<Option<LANProvider>>,
     files_endpoints: Mutex<Cache<String, Arc<EndPointClient>>>,
+    // last rendered frames of desktop windows, entries expire with their window
+    desktop_frames: Mutex<HashMap<String, Weak<StdMutex<DesktopDecodeFrame>>>>,
 }
 
 impl AppState {
     pub fn new() -> Self {
         Self {
             storage: Mutex::new(None),
-            signaling_client: Mutex::new(None),
+            signaling_clients: Mutex::new(HashMap::new()),
             lan_provider: Mutex::new(None),
             files_endpoints: Mutex::new(CacheBuilder::new(64).build()),
+            desktop_frames: Mutex::new(HashMap::new()),
         }
     }
+
+    pub async fn close_files_endpoint(&self, remote: &str) {
+        let files_endpoints = self.files_endpoints.lock().await;
+        if let Some(client) = files_endpoints.get(remote) {
+            client.close();
+        }
+
+        files_endpoints.invalidate(remote).await;
+    }
+
+    pub async fn register_desktop_frame(
+        &self,
+        remote: String,
+    ) -> Arc<StdMutex<DesktopDecodeFrame>> {
+        let frame_slot = Arc::new(StdMutex::new(DesktopDecodeFrame::default()));
+
+        let mut desktop_frames = self.desktop_frames.lock().await;
+        desktop_frames.retain(|_, frame_slot| frame_slot.strong_count() > 0);
+        desktop_frames.insert(remote, Arc::downgrade(&frame_slot));
+
+        frame_slot
+    }
 }
@@ -1,94 +1,159 @@
 use super::AppState;
 use crate::window::create_desktop_window;
+use futures::future::join_all;
 use mirrorx_core::{
     api::{
+        config::{
+            entity::{
+                domain::Domain,
+                history::{Direction, Record, SessionType},
+                profile::ConnectionProfile,
+            },
+            LocalStorage,
+        },
         endpoint::{
             create_desktop_active_endpoint_client, create_file_manager_active_endpoint_client,
-            id::EndPointID, EndPointStream,
+            id::EndPointID, record_session,
         },
-        signaling::{http_message::Response, SignalingClient},
+        signaling::{ensure_client_version, http_message::Response, SignalingClient},
     },
+    component::lan::{visit::LANVisits, LANProvider},
     core_error,
     error::CoreResult,
 };
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs};
-use tauri::http::Uri;
+use std::net::SocketAddr;
 use tauri_egui::EguiPluginHandle;
 
 #[tauri::command]
-#[tracing::instrument(skip(app_state))]
+#[tracing::instrument(skip(app_handle, app_state))]
 pub async fn signaling_connect(
+    app_handle: tauri::AppHandle,
     app_state: tauri::State<'_, AppState>,
     force: bool,
 ) -> CoreResult<()> {
-    let mut current_signaling = app_state.signaling_client.lock().await;
+    let client_version = app_handle.package_info().version.to_string();
 
-    let Some(ref storage) = *app_state.storage.lock().await else {
-        return Err(core_error!("storage not initialize"));
+    let lan_visits = app_state
+        .lan_provider
+        .lock()
+        .await
+        .as_ref()
+        .map(LANProvider::visits);
+
+    let (domains, storage) = {
+        let Some(ref storage) = *app_state.storage.lock().await else {
+            return Err(core_error!("storage not initialize"));
+        };
+
+        (storage.domain().get_all_domains()?, storage.clone())
     };
 
-    let primary_domain = storage.domain().get_primary_domain()?;
+    let pending_domains: Vec<Domain> = {
+        let mut signaling_clients = app_state.signaling_clients.lock().await;
 
-    if let Some((current_domain_id, _)) = *current_signaling {
-        if current_domain_id == primary_domain.id && !force {
-            return Ok(());
+        // drop subscriptions of the domains which have been deleted
+        signaling_clients
+            .retain(|domain_id, _| domains.iter().any(|domain| domain.id == *domain_id));
+
+        domains
+            .into_iter()
+            .filter(|domain| force || !signaling_clients.contains_key(&domain.id))
+            .collect()
+    };
+
+    // domains are connected concurrently without holding any lock, so a slow or
+    // unreachable domain neither delays the others nor blocks other commands
+    let results = join_all(pending_domains.into_iter().map(|domain| {
+        let storage = storage.clone();
+        let lan_visits = lan_visits.clone();
+        let client_version = &client_version;
+        async move {
+            let domain_id = domain.id;
+            let domain_name = domain.name.clone();
+            let is_primary = domain.is_primary;
+            let result = subscribe_domain(domain, storage, lan_visits, client_version).await;
+            (domain_id, domain_name, is_primary, result)
         }
-    }
+    }))
+    .await;
+
+    let mut signaling_clients = app_state.signaling_clients.lock().await;
+    let mut primary_domain_err = None;
+
+    // domains are deleted while holding the clients lock, so the domains read here can't
+    // change until the results are inserted
+    let domain_ids: Vec<i64> = storage
+        .domain()
+        .get_all_domains()?
+        .into_iter()
+        .map(|domain| domain.id)
+        .collect();
 
-    let addrs: Vec<SocketAddr> = if let Ok(ipv4_addr) = primary_domain.addr.parse::<Ipv4Addr>() {
-        vec![(ipv4_addr, primary_domain.subscribe_port).into()]
-    } else if let Ok(ipv6_addr) = primary_domain.addr.parse::<Ipv6Addr>() {
-        vec![(ipv6_addr, primary_domain.subscribe_port).into()]
-    } else if let Ok(url_addr) = primary_domain.addr.parse::<Uri>() {
-        if let Some(host) = url_addr.host() {
-            let host = host.to_string();
-            let (tx, rx) = tokio::sync::oneshot::channel();
-            tokio::task::spawn_blocking(move || {
-                match (host, primary_domain.subscribe_port).to_socket_addrs() {
-                    Ok(addrs) => {
-                        let addrs: Vec<SocketAddr> = addrs.collect();
-                        let _ = tx.send(Some(addrs));
-                    }
-                    Err(_) => {
-                        let _ = tx.send(None);
-                    }
-                };
-            });
-
-            match rx.await {
-                Ok(addrs) => match addrs {
-                    Some(addrs) => addrs,
-                    None => {
-                        return Err(core_error!("resolve empty socket addr"));
-                    }
-                },
-                Err(_) => {
-                    return Err(core_error!(
-                        "receive addr resolve result failed, this shouldn't happen"
-                    ));
+    for (domain_id, domain_name, is_primary, result) in results {
+        match result {
+            Ok(client) => {
+                // dropping the client closes its subscription
+                if !domain_ids.contains(&domain_id) {
+                    tracing::info!(domain = domain_name, "domain deleted while subscribing");
+                    drop(client);
+                } else if !force && signaling_clients.contains_key(&domain_id) {
+                    tracing::info!(domain = domain_name, "domain subscribed concurrently");
+                    drop(client);
+                } else {
+                    signaling_clients.insert(domain_id, client);
+                }
+            }
+            Err(err) => {
+                tracing::error!(?err, domain = domain_name, "subscribe domain failed");
+
+                // a failed forced subscription must not keep serving with a stale client,
+                // otherwise the client is one a concurrent call subscribed meanwhile
+                if force {
+                    signaling_clients.remove(&domain_id);
+                }
+
+                if is_primary {
+                    primary_domain_err = Some(err);
                 }
             }
-        } else {
-            return Err(core_error!("invalid domain addr"));
         }
-    } else {
-        return Err(core_error!("invalid domain addr"));
-    };
+    }
+
+    // visits are launched from the primary domain, so only its failure is reported,
+    // other domains are subscribed in best effort
+    match primary_domain_err {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+async fn subscribe_domain(
+    domain: Domain,
+    storage: LocalStorage,
+    lan_visits: Option<LANVisits>,
+    client_version: &str,
+) -> CoreResult<SignalingClient> {
+    let mut client = SignalingClient::new(domain.addr, domain.proxy.as_deref())?;
 
-    let mut client = SignalingClient::new(primary_domain.addr)?;
+    match client.identity().await? {
+        Response::Message(resp) => ensure_client_version(client_version, &resp.min_client_version)?,
+        Response::Error(err) => return Err(core_error!("http error: {:?}", err)),
+    };
 
     client
         .subscribe(
-            addrs,
-            primary_domain.device_id,
-            &primary_domain.finger_print,
-            storage.clone(),
+            domain.subscribe_port,
+            domain.subscribe_tls,
+            domain.id,
+            domain.device_id,
+            &domain.finger_print,
+            domain.expire,
+            storage,
+            lan_visits,
         )
         .await?;
 
-    *current_signaling = Some((primary_domain.id, client));
-
-    Ok(())
+    Ok(client)
 }
 
 #[tauri::command]
@@ -113,16 +178,33 @@ pub async fn signaling_visit(
         format!("MirrorX File Transfer {remote_device_id}")
     };
 
-    let Some(ref storage) = *app_state.storage.lock().await else {
-        return Err(core_error!("storage not initialize"));
-    };
+    let lan_visits = app_state
+        .lan_provider
+        .lock()
+        .await
+        .as_ref()
+        .map(LANProvider::visits);
 
-    let Some((_,ref signaling_client)) = *app_state.signaling_client.lock().await else {
-        return Err(core_error!("storage not initialize"));
+    let storage = match *app_state.storage.lock().await {
+        Some(ref storage) => storage.clone(),
+        None => return Err(core_error!("storage not initialize")),
     };
 
     let remote_device_id_num = remote_device_id.replace('-', "").parse()?;
     let primary_domain = storage.domain().get_primary_domain()?;
+
+    // the visit waits for the remote user and the path selection, no lock may be held
+    // meanwhile or every other command and subscription blocks on it
+    let signaling_client = match app_state
+        .signaling_clients
+        .lock()
+        .await
+        .get(&primary_domain.id)
+    {
+        Some(signaling_client) => signaling_client.detached(),
+        None => return Err(core_error!("primary domain signaling not connected")),
+    };
+
     let local_device_id = primary_domain.device_id;
     let resp = signaling_client
         .visit(
@@ -130,16 +212,18 @@ pub async fn signaling_visit(
             remote_device_id_num,
             password,
             visit_desktop,
+            lan_visits.as_ref(),
         )
         .await?;
 
-    let (endpoint_addr, visit_credentials, opening_key, sealing_key) = match resp {
-        Response::Message(result) => match result {
-            Ok(v) => v,
-            Err(reason) => return Err(core_error!("Visit Failed ({:?})", reason)),
-        },
-        Response::Error(err) => return Err(core_error!("Visit Failed ({:?})", err)),
-    };
+    let (endpoint_addr, visit_credentials, opening_key, sealing_key, direct_paths, peer_os) =
+        match resp {
+            Response::Message(result) => match result {
+                Ok(v) => v,
+                Err(reason) => return Err(core_error!("Visit Failed ({:?})", reason)),
+            },
+            Response::Error(err) => return Err(core_error!("Visit Failed ({:?})", err)),
+        };
 
     let endpoint_addr: SocketAddr = endpoint_addr
         .parse()
@@ -152,15 +236,52 @@ pub async fn signaling_visit(
         remote_device_id: remote_device_id_num,
     };
 
+    let stream = direct_paths
+        .select(endpoint_id, endpoint_addr, visit_credentials)
+        .await?;
+
+    let mut session_record = Record::new(
+        Direction::Outgoing,
+        if visit_desktop {
+            SessionType::Desktop
+        } else {
+            SessionType::FileManager
+        },
+        primary_domain.name.clone(),
+        remote_device_id_num,
+    );
+    session_record.peer_os = peer_os;
+
     if visit_desktop {
+        let settings = storage.kv().get_settings()?;
+        let profile = match storage
+            .profile()
+            .get_profile(&primary_domain.name, remote_device_id_num)?
+        {
+            Some(profile) => profile,
+            None => ConnectionProfile::from_settings(
+                primary_domain.name.clone(),
+                remote_device_id_num,
+                &settings,
+            ),
+        };
+
         let (client, render_frame_rx) = create_desktop_active_endpoint_client(
             endpoint_id,
             Some((opening_key, sealing_key)),
-            EndPointStream::ActiveTCP(endpoint_addr),
-            Some(visit_credentials),
+            stream,
+            None,
+            settings,
+            profile,
         )
         .await?;
 
+        record_session(storage.clone(), client.clone(), session_record);
+        let window_client = client.clone();
+        let frame_slot = app_state
+            .register_desktop_frame(remote_device_id.clone())
+            .await;
+
         if let Err(err) = egui_plugin.create_window(
             window_label,
             Box::new(move |cc| {
@@ -169,8 +290,9 @@ pub async fn signaling_visit(
                         cc,
                         gl_context.clone(),
                         endpoint_id,
-                        client,
+                        window_client,
                         render_frame_rx,
+                        frame_slot,
                     ))
                 } else {
                     panic!("get gl context failed");
@@ -182,6 +304,7 @@ pub async fn signaling_visit(
                 ..Default::default()
             },
         ) {
+            client.close();
             tracing::error!(?err, "create desktop window failed");
             return Err(core_error!("create remote desktop window failed"));
         }
@@ -189,16 +312,18 @@ pub async fn signaling_visit(
         let client = create_file_manager_active_endpoint_client(
             endpoint_id,
             Some((opening_key, sealing_key)),
-            EndPointStream::ActiveTCP(endpoint_addr),
-            Some(visit_credentials),
+            stream,
+            None,
         )
         .await?;
 
+        record_session(storage.clone(), client.clone(), session_record);
+
         app_state
             .files_endpoints
             .lock()
             .await
-            .insert(remote_device_id.clone(), client)
+            .insert(remote_device_id.clone(), client.clone())
             .await;
 
         let (tx, rx) = tokio::sync::oneshot::channel();
@@ -225,6 +350,8 @@ pub async fn signaling_visit(
         let create_result = rx.await.map_err(|_| core_error!("create window failed"))?;
 
         if let Some(err) = create_result {
+            client.close();
+
             app_state
                 .files_endpoints
                 .lock()
@@ -236,9 +363,5 @@ pub async fn signaling_visit(
         }
     }
 
-    let _ = storage
-        .history()
-        .create(remote_device_id_num, &primary_domain.name);
-
     Ok(())
 }
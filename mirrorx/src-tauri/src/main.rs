@@ -10,8 +10,9 @@ mod window;
 #[cfg(target_os = "macos")]
 use tauri::Icon;
 
+use mirrorx_core::api::config::entity::kv::LogLevel;
 use tauri::{App, Manager, SystemTray, SystemTrayEvent, WindowEvent};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt};
 
 #[cfg(target_os = "macos")]
 static TRAY_ICON_MACOS: &[u8] = include_bytes!("../assets/icons/tray-macOS.png");
@@ -39,14 +40,20 @@ async fn main() {
         .pretty()
         .with_writer(std::io::stderr);
 
+    // the log level is changed at runtime after the settings are loaded
+    let (filter_layer, filter_handle) = reload::Layer::new(utility::log_filter(LogLevel::Info));
+    utility::init_log_filter_handle(filter_handle);
+
     tracing_subscriber::Registry::default()
-        .with(EnvFilter::from("info,tao=info"))
+        .with(filter_layer)
         .with(console_layer)
         .with(file_layer)
         .init();
 
     tracing::info!(path = ?log_dir, "log dir");
 
+    mirrorx_core::component::punch::spawn_reflector_from_env();
+
     app.run(|app_handle, event| match event {
         tauri::RunEvent::WindowEvent { label, event, .. } => {
             if label == "main" {
@@ -56,6 +63,18 @@ async fn main() {
                         api.prevent_close();
                     }
                 }
+            } else if let Some(remote) = label.strip_prefix("FileManager:") {
+                if let WindowEvent::Destroyed = event {
+                    // lan window labels replace dots of ip with underscores
+                    let remote = remote.replace('_', ".");
+                    let app_handle = app_handle.clone();
+                    tauri::async_runtime::spawn(async move {
+                        app_handle
+                            .state::<command::AppState>()
+                            .close_files_endpoint(&remote)
+                            .await;
+                    });
+                }
             }
         }
         tauri::RunEvent::ExitRequested { api, .. } => {
@@ -160,19 +179,38 @@ fn build_app() -> App {
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
+            command::address_book::address_book_create,
+            command::address_book::address_book_get,
+            command::address_book::address_book_list,
+            command::address_book::address_book_groups,
+            command::address_book::address_book_tags,
+            command::address_book::address_book_update,
+            command::address_book::address_book_delete,
+            command::address_book::address_book_connect,
             command::config::config_init,
             command::config::config_domain_get,
             command::config::config_domain_get_by_name,
             command::config::config_domain_get_id_and_names,
             command::config::config_domain_create,
             command::config::config_domain_delete,
+            command::config::config_export,
+            command::config::config_import,
             command::config::config_domain_list,
             command::config::config_domain_update,
             command::config::config_language_get,
             command::config::config_language_set,
             command::config::config_theme_get,
             command::config::config_theme_set,
+            command::config::config_settings_get,
+            command::config::config_settings_set,
+            command::config::config_profile_get,
+            command::config::config_profile_list,
+            command::config::config_profile_save,
+            command::config::config_profile_delete,
             command::config::config_history_get,
+            command::config::config_history_retention_get,
+            command::config::config_history_retention_set,
+            command::desktop::desktop_screenshot,
             command::lan::lan_init,
             command::lan::lan_connect,
             command::lan::lan_nodes_list,
@@ -1,6 +1,51 @@
+use mirrorx_core::api::config::entity::kv::LogLevel;
+use once_cell::sync::OnceCell;
+use std::{
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tracing_subscriber::{reload::Handle, EnvFilter, Registry};
+
+static LOG_FILTER_HANDLE: OnceCell<Handle<EnvFilter, Registry>> = OnceCell::new();
+
 pub fn format_device_id(device_id: i64) -> String {
     let mut device_id = format!("{device_id:0>10}");
     device_id.insert(2, '-');
     device_id.insert(7, '-');
     device_id
 }
+
+// recordings and screenshots are named after the remote and current time, lan
+// remotes may be ipv6 addresses which contain characters invalid in file names
+pub fn capture_file_path(dir: Option<PathBuf>, remote: &str, extension: &str) -> Option<PathBuf> {
+    let dir = dir.or_else(tauri::api::path::home_dir)?;
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+
+    Some(dir.join(format!(
+        "MirrorX_{}_{}.{}",
+        remote.replace(':', "-"),
+        timestamp,
+        extension
+    )))
+}
+
+pub fn log_filter(level: LogLevel) -> EnvFilter {
+    let level: &str = level.into();
+    EnvFilter::from(format!("{level},tao=info"))
+}
+
+pub fn init_log_filter_handle(handle: Handle<EnvFilter, Registry>) {
+    let _ = LOG_FILTER_HANDLE.set(handle);
+}
+
+pub fn set_log_level(level: LogLevel) {
+    if let Some(handle) = LOG_FILTER_HANDLE.get() {
+        if let Err(err) = handle.reload(log_filter(level)) {
+            tracing::error!(?err, "reload log filter failed");
+        }
+    }
+}
@@ -23,11 +23,13 @@ use tauri_egui::{
         glow::{self, Context},
     },
     egui::{
-        epaint::Shadow, style::Margin, Align, CentralPanel, Color32, FontId, Frame, Layout, Pos2,
-        Rect, RichText, Rounding, Sense, Stroke, Ui, Vec2,
+        epaint::Shadow, style::Margin, Align, CentralPanel, Color32, ComboBox, FontId, Frame,
+        Layout, Pos2, Rect, RichText, Rounding, Sense, Stroke, Ui, Vec2,
     },
 };
 
+const FRAME_RATE_OPTIONS: [u8; 4] = [15, 30, 60, 120];
+
 static ICON_MAXIMIZE_BYTES:&[u8]=br#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 448 512"><!--! Font Awesome Pro 6.2.0 by @fontawesome - https://fontawesome.com License - https://fontawesome.com/license (Commercial License) Copyright 2022 Fonticons, Inc. --><path style="fill:rgb(255,255,255)" d="M168 32H24C10.7 32 0 42.7 0 56V200c0 9.7 5.8 18.5 14.8 22.2s19.3 1.7 26.2-5.2l40-40 79 79L81 335 41 295c-6.9-6.9-17.2-8.9-26.2-5.2S0 302.3 0 312V456c0 13.3 10.7 24 24 24H168c9.7 0 18.5-5.8 22.2-14.8s1.7-19.3-5.2-26.2l-40-40 79-79 79 79-40 40c-6.9 6.9-8.9 17.2-5.2 26.2s12.5 14.8 22.2 14.8H424c13.3 0 24-10.7 24-24V312c0-9.7-5.8-18.5-14.8-22.2s-19.3-1.7-26.2 5.2l-40 40-79-79 79-79 40 40c6.9 6.9 17.2 8.9 26.2 5.2s14.8-12.5 14.8-22.2V56c0-13.3-10.7-24-24-24H280c-9.7 0-18.5 5.8-22.2 14.8s-1.7 19.3 5.2 26.2l40 40-79 79-79-79 40-40c6.9-6.9 8.9-17.2 5.2-26.2S177.7 32 168 32z"/></svg>"#;
 static ICON_SCALE_BYTES:&[u8]=br#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 640 512"><!--! Font Awesome Pro 6.2.0 by @fontawesome - https://fontawesome.com License - https://fontawesome.com/license (Commercial License) Copyright 2022 Fonticons, Inc. --><path style="fill:rgb(255,255,255)" d="M32 64c17.7 0 32 14.3 32 32l0 320c0 17.7-14.3 32-32 32s-32-14.3-32-32V96C0 78.3 14.3 64 32 64zm214.6 73.4c12.5 12.5 12.5 32.8 0 45.3L205.3 224l229.5 0-41.4-41.4c-12.5-12.5-12.5-32.8 0-45.3s32.8-12.5 45.3 0l96 96c12.5 12.5 12.5 32.8 0 45.3l-96 96c-12.5 12.5-32.8 12.5-45.3 0s-12.5-32.8 0-45.3L434.7 288l-229.5 0 41.4 41.4c12.5 12.5 12.5 32.8 0 45.3s-32.8 12.5-45.3 0l-96-96c-12.5-12.5-12.5-32.8 0-45.3l96-96c12.5-12.5 32.8-12.5 45.3 0zM640 96V416c0 17.7-14.3 32-32 32s-32-14.3-32-32V96c0-17.7 14.3-32 32-32s32 14.3 32 32z"/></svg>"#;
 
@@ -48,9 +50,8 @@ impl DesktopWindow {
         gl_context: Arc<Context>,
         client: Arc<EndPointClient>,
         render_frame_rx: tokio::sync::mpsc::Receiver<DesktopDecodeFrame>,
+        frame_slot: Arc<Mutex<DesktopDecodeFrame>>,
     ) -> Self {
-        let frame_slot = Arc::new(Mutex::new(DesktopDecodeFrame::default()));
-
         let state = State::new(endpoint_id, client, render_frame_rx, frame_slot.clone());
 
         let desktop_render = Arc::new(RwLock::new(
@@ -138,9 +139,22 @@ impl DesktopWindow {
         let (frame_width, frame_height) = self.state.update_desktop_frame();
 
         if frame_width > 0 && frame_height > 0 {
+            // frames fitted into the window are encoded at the window size, the original
+            // size is requested back when showing the desktop without scaling
+            if self.state.desktop_frame_scaled() {
+                self.state.request_resolution(0, 0);
+            } else {
+                let pixels_per_point = ui.ctx().pixels_per_point();
+                self.state.request_resolution(
+                    (ui.available_width() * pixels_per_point) as u16,
+                    (ui.available_height() * pixels_per_point) as u16,
+                );
+            }
+
             // when client area bigger than original desktop frame, disable scale button
             self.state.set_desktop_frame_scalable(
-                ui.available_width() < frame_width as _
+                self.state.resolution_limited()
+                    || ui.available_width() < frame_width as _
                     || ui.available_height() < frame_height as _,
             );
 
@@ -247,7 +261,7 @@ impl DesktopWindow {
 
     fn build_toolbar(&mut self, ui: &mut Ui) {
         // put the toolbar at central top
-        let (mut rect, _) = ui.allocate_at_least(Vec2::new(220.0, 35.0), Sense::click());
+        let (mut rect, _) = ui.allocate_at_least(Vec2::new(300.0, 35.0), Sense::click());
         rect.set_center(Pos2::new(ui.max_rect().width() / 2.0, 50.0));
 
         ui.allocate_ui_at_rect(rect, |ui| {
@@ -273,6 +287,18 @@ impl DesktopWindow {
 
                         ui.separator();
 
+                        self.build_toolbar_frame_rate(ui);
+
+                        ui.separator();
+
+                        self.build_toolbar_record(ui);
+
+                        ui.separator();
+
+                        self.build_toolbar_statistics(ui);
+
+                        ui.separator();
+
                         // FPS
 
                         ui.label(
@@ -307,6 +333,86 @@ impl DesktopWindow {
             }
         });
     }
+
+    fn build_toolbar_frame_rate(&mut self, ui: &mut Ui) {
+        let current_frame_rate = self.state.frame_rate();
+        let mut frame_rate = current_frame_rate;
+
+        ComboBox::from_id_source("frame_rate")
+            .width(56.0)
+            .selected_text(format!("{} fps", frame_rate))
+            .show_ui(ui, |ui| {
+                for option in FRAME_RATE_OPTIONS {
+                    ui.selectable_value(&mut frame_rate, option, format!("{} fps", option));
+                }
+            });
+
+        if frame_rate != current_frame_rate {
+            self.state.set_frame_rate(frame_rate);
+        }
+    }
+
+    fn build_toolbar_record(&mut self, ui: &mut Ui) {
+        let recording = self.state.recording();
+        let text = RichText::new("REC").font(FontId::monospace(16.0));
+        let text = if recording {
+            text.color(Color32::RED)
+        } else {
+            text
+        };
+
+        if ui.selectable_label(recording, text).clicked() {
+            self.state.set_recording(!recording);
+        }
+
+        if let Some(error) = self.state.recording_error() {
+            ui.label(RichText::new("!").color(Color32::RED))
+                .on_hover_text(format!("recording failed: {error}"));
+        }
+    }
+
+    fn build_toolbar_statistics(&mut self, ui: &mut Ui) {
+        let statistics = *self.state.statistics();
+
+        let latency = match statistics.latency_p50_ms {
+            Some(latency) => format!("{latency:.0} ms"),
+            None => String::from("-- ms"),
+        };
+
+        let format_optional = |value: Option<f32>| match value {
+            Some(value) => format!("{value:.1} ms"),
+            None => String::from("--"),
+        };
+
+        let details = format!(
+            "latency p50 / p95 / p99: {} / {} / {}\n\
+             encode: {:.1} ms\n\
+             network: {}\n\
+             decode: {:.1} ms\n\
+             render: {:.1} ms\n\
+             round trip: {}\n\
+             received: {:.0} fps, {} kbps\n\
+             rendered: {:.0} fps\n\
+             lost frames: {}\n\
+             dropped frames: {}",
+            format_optional(statistics.latency_p50_ms),
+            format_optional(statistics.latency_p95_ms),
+            format_optional(statistics.latency_p99_ms),
+            statistics.encode_ms,
+            format_optional(statistics.network_ms),
+            statistics.decode_ms,
+            statistics.render_ms,
+            format_optional(statistics.round_trip_ms),
+            statistics.received_fps,
+            statistics.bitrate_kbps,
+            statistics.rendered_fps,
+            statistics.lost_frames,
+            statistics.dropped_frames,
+        );
+
+        ui.label(RichText::new(latency).font(FontId::monospace(16.0)))
+            .on_hover_text(details);
+    }
 }
 
 impl DesktopWindow {
@@ -416,6 +522,8 @@ impl tauri_egui::eframe::App for DesktopWindow {
     }
 
     fn on_exit(&mut self, gl: Option<&glow::Context>) {
+        self.state.endpoint_client().close();
+
         if let Some(gl) = gl {
             self.render.write().unwrap().destroy(gl);
         }
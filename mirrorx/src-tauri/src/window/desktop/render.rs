@@ -32,6 +32,8 @@ const VERTICES_INDICES_SLICE: &[u8] = unsafe {
 pub struct Render {
     program: Program,
     textures: Vec<NativeTexture>,
+    // textures are recreated when the format or size of frames changes
+    textures_params: Option<(DesktopDecodeFrameFormat, i32, i32)>,
     vao: NativeVertexArray,
     vbo: NativeBuffer,
     ebo: NativeBuffer,
@@ -71,9 +73,10 @@ impl Render {
             uniform sampler2D nv12_textureY;
             uniform sampler2D nv12_textureUV;
 
-            uniform sampler2D yuv420p_textureY;
-            uniform sampler2D yuv420p_textureU;
-            uniform sampler2D yuv420p_textureV;
+            // yuv420p and yuv444p frames only differ in the size of u and v textures
+            uniform sampler2D planar_textureY;
+            uniform sampler2D planar_textureU;
+            uniform sampler2D planar_textureV;
 
             in vec2 texCoord;
             layout (location = 0) out vec4 fragColor;
@@ -99,9 +102,9 @@ impl Render {
                     yuv.y = texture(nv12_textureUV, texCoord).r - 0.5;
                     yuv.z = texture(nv12_textureUV, texCoord).g - 0.5;
                 } else {
-                    yuv.x = texture(yuv420p_textureY, texCoord).r - 0.0625;
-                    yuv.y = texture(yuv420p_textureU, texCoord).r - 0.5;
-                    yuv.z = texture(yuv420p_textureV, texCoord).r - 0.5;
+                    yuv.x = texture(planar_textureY, texCoord).r - 0.0625;
+                    yuv.y = texture(planar_textureU, texCoord).r - 0.5;
+                    yuv.z = texture(planar_textureV, texCoord).r - 0.5;
                 }
                 
                 rgb = yuv * YCbCrToRGBmatrix;
@@ -230,6 +233,7 @@ impl Render {
             Ok(Self {
                 program,
                 textures: Vec::new(),
+                textures_params: None,
                 vao,
                 vbo,
                 ebo,
@@ -279,7 +283,13 @@ impl Render {
         }
 
         unsafe {
-            if self.textures.is_empty() {
+            let textures_params = (frame.format, frame.width, frame.height);
+            if self.textures_params != Some(textures_params) {
+                for texture in self.textures.drain(..) {
+                    gl.delete_texture(texture);
+                    check_for_gl_error!(gl);
+                }
+
                 match frame.format {
                     DesktopDecodeFrameFormat::NV12 => {
                         self.textures
@@ -292,25 +302,29 @@ impl Render {
                             frame.height / 2,
                         )?);
                     }
-                    DesktopDecodeFrameFormat::YUV420P => {
+                    DesktopDecodeFrameFormat::YUV420P | DesktopDecodeFrameFormat::YUV444P => {
+                        let (chrominance_width, chrominance_height) = chrominance_size(frame);
+
                         self.textures
                             .push(create_texture(gl, RED, frame.width, frame.height)?);
 
                         self.textures.push(create_texture(
                             gl,
                             RED,
-                            frame.width / 2,
-                            frame.height / 2,
+                            chrominance_width,
+                            chrominance_height,
                         )?);
 
                         self.textures.push(create_texture(
                             gl,
                             RED,
-                            frame.width / 2,
-                            frame.height / 2,
+                            chrominance_width,
+                            chrominance_height,
                         )?);
                     }
                 }
+
+                self.textures_params = Some(textures_params);
             };
 
             if self.frame_count_instant.is_none() {
@@ -330,8 +344,8 @@ impl Render {
                     self.upload_nv12(gl, frame);
                     1
                 }
-                DesktopDecodeFrameFormat::YUV420P => {
-                    self.upload_yuv420p(gl, frame);
+                DesktopDecodeFrameFormat::YUV420P | DesktopDecodeFrameFormat::YUV444P => {
+                    self.upload_planar(gl, frame);
                     0
                 }
             };
@@ -426,7 +440,9 @@ impl Render {
         gl.pixel_store_i32(UNPACK_ROW_LENGTH, 0);
     }
 
-    unsafe fn upload_yuv420p(&mut self, gl: &Context, frame: &DesktopDecodeFrame) {
+    unsafe fn upload_planar(&mut self, gl: &Context, frame: &DesktopDecodeFrame) {
+        let (chrominance_width, chrominance_height) = chrominance_size(frame);
+
         // upload Y plane
         gl.active_texture(TEXTURE0);
         check_for_gl_error!(gl);
@@ -450,7 +466,7 @@ impl Render {
         );
         check_for_gl_error!(gl);
 
-        let y_uniform_location = gl.get_uniform_location(self.program, "yuv420p_textureY");
+        let y_uniform_location = gl.get_uniform_location(self.program, "planar_textureY");
         check_for_gl_error!(gl);
 
         gl.uniform_1_i32(y_uniform_location.as_ref(), 0);
@@ -471,15 +487,15 @@ impl Render {
             0,
             0,
             0,
-            frame.width / 2,
-            frame.height / 2,
+            chrominance_width,
+            chrominance_height,
             RED,
             UNSIGNED_BYTE,
             PixelUnpackData::Slice(&frame.plane_data[1]),
         );
         check_for_gl_error!(gl);
 
-        let u_uniform_location = gl.get_uniform_location(self.program, "yuv420p_textureU");
+        let u_uniform_location = gl.get_uniform_location(self.program, "planar_textureU");
         check_for_gl_error!(gl);
 
         gl.uniform_1_i32(u_uniform_location.as_ref(), 1);
@@ -500,15 +516,15 @@ impl Render {
             0,
             0,
             0,
-            frame.width / 2,
-            frame.height / 2,
+            chrominance_width,
+            chrominance_height,
             RED,
             UNSIGNED_BYTE,
             PixelUnpackData::Slice(&frame.plane_data[2]),
         );
         check_for_gl_error!(gl);
 
-        let v_uniform_location = gl.get_uniform_location(self.program, "yuv420p_textureV");
+        let v_uniform_location = gl.get_uniform_location(self.program, "planar_textureV");
         check_for_gl_error!(gl);
 
         gl.uniform_1_i32(v_uniform_location.as_ref(), 2);
@@ -519,6 +535,13 @@ impl Render {
     }
 }
 
+fn chrominance_size(frame: &DesktopDecodeFrame) -> (i32, i32) {
+    match frame.format {
+        DesktopDecodeFrameFormat::YUV444P => (frame.width, frame.height),
+        _ => (frame.width / 2, frame.height / 2),
+    }
+}
+
 unsafe fn create_texture(
     gl: &Context,
     texture_format: u32,
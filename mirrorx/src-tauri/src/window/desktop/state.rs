@@ -1,11 +1,20 @@
-use crate::utility::format_device_id;
+use crate::utility::{capture_file_path, format_device_id};
 use mirrorx_core::{
     api::endpoint::{client::EndPointClient, id::EndPointID},
+    component::{recording::RecordingEvent, video_decoder::latency::VideoStatisticsSnapshot},
     DesktopDecodeFrame,
 };
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc::Receiver;
 
+// resizing the window changes the size every frame, requests are sent at most once
+// in this interval
+const RESOLUTION_REQUEST_INTERVAL: Duration = Duration::from_millis(500);
+const STATISTICS_REFRESH_INTERVAL: Duration = Duration::from_millis(500);
+
 pub struct State {
     format_remote_device_id: String,
     endpoint_client: Arc<EndPointClient>,
@@ -14,6 +23,11 @@ pub struct State {
     render_rx: Receiver<DesktopDecodeFrame>,
     frame_slot: Arc<Mutex<DesktopDecodeFrame>>,
     frame_size: (i32, i32),
+    requested_resolution: (u16, u16),
+    last_resolution_request: Option<Instant>,
+    statistics: VideoStatisticsSnapshot,
+    last_statistics_refresh: Option<Instant>,
+    recording_error: Option<String>,
 }
 
 impl State {
@@ -41,6 +55,11 @@ impl State {
             render_rx: render_frame_rx,
             frame_slot,
             frame_size: (0, 0),
+            requested_resolution: (0, 0),
+            last_resolution_request: None,
+            statistics: VideoStatisticsSnapshot::default(),
+            last_statistics_refresh: None,
+            recording_error: None,
         }
     }
 
@@ -57,12 +76,21 @@ impl State {
     }
 
     pub fn update_desktop_frame(&mut self) -> (i32, i32) {
-        let mut new_frame = None;
+        let mut new_frame: Option<DesktopDecodeFrame> = None;
         while let Ok(frame) = self.render_rx.try_recv() {
-            new_frame = Some(frame);
+            // only the newest frame is painted
+            if let Some(dropped_frame) = new_frame.replace(frame) {
+                self.endpoint_client.update_video_statistics(|statistics| {
+                    statistics.on_video_dropped(dropped_frame.sequence)
+                });
+            }
         }
 
         if let Some(new_frame) = new_frame {
+            self.endpoint_client.update_video_statistics(|statistics| {
+                statistics.on_video_rendered(new_frame.sequence)
+            });
+
             self.frame_size = (new_frame.width, new_frame.height);
             (*self.frame_slot.lock().unwrap()) = new_frame;
         }
@@ -73,6 +101,42 @@ impl State {
     pub fn desktop_frame_scalable(&self) -> bool {
         self.desktop_frame_scalable
     }
+
+    pub fn frame_rate(&self) -> u8 {
+        self.endpoint_client.frame_rate()
+    }
+
+    pub fn resolution_limited(&self) -> bool {
+        self.requested_resolution != (0, 0)
+    }
+
+    pub fn statistics(&mut self) -> &VideoStatisticsSnapshot {
+        if self.last_statistics_refresh.map_or(true, |instant| {
+            instant.elapsed() >= STATISTICS_REFRESH_INTERVAL
+        }) {
+            self.statistics = self.endpoint_client.video_statistics();
+            self.last_statistics_refresh = Some(Instant::now());
+        }
+
+        &self.statistics
+    }
+
+    pub fn recording(&self) -> bool {
+        self.endpoint_client.is_recording()
+    }
+
+    pub fn recording_error(&mut self) -> Option<&str> {
+        while let Some(event) = self.endpoint_client.take_recording_event() {
+            match event {
+                RecordingEvent::Failed { path, error } => {
+                    tracing::error!(?path, ?error, "recording failed");
+                    self.recording_error = Some(error);
+                }
+            }
+        }
+
+        self.recording_error.as_deref()
+    }
 }
 
 impl State {
@@ -83,4 +147,62 @@ impl State {
     pub fn set_desktop_frame_scalable(&mut self, scalable: bool) {
         self.desktop_frame_scalable = scalable
     }
+
+    pub fn set_frame_rate(&mut self, frame_rate: u8) {
+        if let Err(err) = self.endpoint_client.update_frame_rate(frame_rate) {
+            tracing::error!(?err, "update frame rate failed");
+        }
+    }
+
+    pub fn set_recording(&mut self, recording: bool) {
+        if !recording {
+            // the writer finalizes the files in background, the ui isn't blocked meanwhile
+            let endpoint_client = self.endpoint_client.clone();
+            tauri::async_runtime::spawn(async move {
+                match endpoint_client.stop_recording().await {
+                    Ok(paths) => tracing::info!(?paths, "recording saved"),
+                    Err(err) => tracing::error!(?err, "stop recording failed"),
+                }
+            });
+            return;
+        }
+
+        self.recording_error = None;
+
+        let Some(path) = capture_file_path(
+            tauri::api::path::video_dir(),
+            &self.format_remote_device_id,
+            "mkv",
+        ) else {
+            tracing::error!("can't find a directory to save recording");
+            return;
+        };
+
+        if let Err(err) = self.endpoint_client.start_recording(path) {
+            tracing::error!(?err, "start recording failed");
+        }
+    }
+
+    pub fn request_resolution(&mut self, max_width: u16, max_height: u16) {
+        if self.requested_resolution == (max_width, max_height) {
+            return;
+        }
+
+        if let Some(instant) = self.last_resolution_request {
+            if instant.elapsed() < RESOLUTION_REQUEST_INTERVAL {
+                return;
+            }
+        }
+
+        if let Err(err) = self
+            .endpoint_client
+            .update_resolution(max_width, max_height)
+        {
+            tracing::error!(?err, "update resolution failed");
+            return;
+        }
+
+        self.requested_resolution = (max_width, max_height);
+        self.last_resolution_request = Some(Instant::now());
+    }
 }
@@ -6,7 +6,10 @@ use mirrorx_core::{
     DesktopDecodeFrame,
 };
 use once_cell::sync::Lazy;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 use tauri_egui::{
     eframe::CreationContext,
     egui::{FontData, FontDefinitions, FontFamily},
@@ -55,12 +58,19 @@ pub fn create_desktop_window(
     endpoint_id: EndPointID,
     client: Arc<EndPointClient>,
     render_frame_rx: tokio::sync::mpsc::Receiver<DesktopDecodeFrame>,
+    frame_slot: Arc<Mutex<DesktopDecodeFrame>>,
 ) -> DesktopWindow {
     set_fonts(&cc.egui_ctx);
 
     // cc.egui_ctx.set_debug_on_hover(true);
 
-    crate::window::desktop::DesktopWindow::new(endpoint_id, gl_context, client, render_frame_rx)
+    crate::window::desktop::DesktopWindow::new(
+        endpoint_id,
+        gl_context,
+        client,
+        render_frame_rx,
+        frame_slot,
+    )
 }
 
 fn set_fonts(ctx: &tauri_egui::egui::Context) {
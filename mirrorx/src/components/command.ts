@@ -1,5 +1,15 @@
 import { invoke } from '@tauri-apps/api';
-import type { Directory, Domain, HistoryRecord, LanDiscoverNode } from '$lib/components/types';
+import type {
+	ConnectionProfile,
+	Contact,
+	ContactFilter,
+	Directory,
+	Domain,
+	HistoryFilter,
+	HistoryRecord,
+	LanDiscoverNode,
+	Settings
+} from '$lib/components/types';
 
 export function invoke_config_init(): Promise<void> {
 	return invoke('config_init');
@@ -17,14 +27,31 @@ export function invoke_config_domain_get_id_and_names(): Promise<Array<[number,
 	return invoke('config_domain_get_id_and_names');
 }
 
-export function invoke_config_domain_create(addr: string, remarks: string): Promise<void> {
-	return invoke('config_domain_create', { addr, isPrimary: false, remarks });
+export function invoke_config_domain_create(
+	addr: string,
+	remarks: string,
+	proxy: string | null = null,
+	subscribeTls = false
+): Promise<void> {
+	return invoke('config_domain_create', { addr, isPrimary: false, remarks, proxy, subscribeTls });
 }
 
 export function invoke_config_domain_delete(id: number): Promise<void> {
 	return invoke('config_domain_delete', { id });
 }
 
+export function invoke_config_export(path: string, passphrase: string | null): Promise<void> {
+	return invoke('config_export', { path, passphrase });
+}
+
+export function invoke_config_import(
+	path: string,
+	mergeStrategy: 'replace' | 'keep_local' | 'prefer_imported',
+	passphrase: string | null
+): Promise<void> {
+	return invoke('config_import', { path, mergeStrategy, passphrase });
+}
+
 export function invoke_config_domain_list(
 	page: number,
 	limit: number
@@ -34,7 +61,12 @@ export function invoke_config_domain_list(
 
 export function invoke_config_domain_update(
 	id: number,
-	update_type: 'set_primary' | { password: string } | { remarks: string }
+	update_type:
+		| 'set_primary'
+		| { password: string }
+		| { remarks: string }
+		| { proxy: string | null }
+		| { subscribe_tls: boolean }
 ): Promise<void> {
 	return invoke('config_domain_update', { req: { id, update_type } });
 }
@@ -55,10 +87,83 @@ export function invoke_config_theme_set(theme: 'light' | 'dark' | 'auto'): Promi
 	return invoke('config_theme_set', { theme });
 }
 
+export function invoke_config_settings_get(): Promise<Settings> {
+	return invoke('config_settings_get');
+}
+
+export function invoke_config_settings_set(settings: Settings): Promise<void> {
+	return invoke('config_settings_set', { settings });
+}
+
+export function invoke_config_profile_get(
+	domain: string,
+	deviceId: number
+): Promise<ConnectionProfile> {
+	return invoke('config_profile_get', { domain, deviceId });
+}
+
+export function invoke_config_profile_list(): Promise<Array<ConnectionProfile>> {
+	return invoke('config_profile_list');
+}
+
+export function invoke_config_profile_save(
+	profile: ConnectionProfile
+): Promise<ConnectionProfile> {
+	return invoke('config_profile_save', { profile });
+}
+
+export function invoke_config_profile_delete(id: number): Promise<void> {
+	return invoke('config_profile_delete', { id });
+}
+
 export function invoke_config_history_get(
-	time_range: [number, number] | null
+	filter: HistoryFilter | null
 ): Promise<Array<HistoryRecord>> {
-	return invoke('config_history_get', { timeRange: time_range });
+	return invoke('config_history_get', { filter });
+}
+
+export function invoke_config_history_retention_get(): Promise<number> {
+	return invoke('config_history_retention_get');
+}
+
+export function invoke_config_history_retention_set(retentionDays: number): Promise<void> {
+	return invoke('config_history_retention_set', { retentionDays });
+}
+
+export function invoke_address_book_create(contact: Contact): Promise<Contact> {
+	return invoke('address_book_create', { contact });
+}
+
+export function invoke_address_book_get(id: number): Promise<Contact> {
+	return invoke('address_book_get', { id });
+}
+
+export function invoke_address_book_list(
+	page: number,
+	limit: number,
+	filter: ContactFilter | null = null
+): Promise<{ total: number; contacts: Array<Contact> }> {
+	return invoke('address_book_list', { page, limit, filter });
+}
+
+export function invoke_address_book_groups(): Promise<Array<string>> {
+	return invoke('address_book_groups');
+}
+
+export function invoke_address_book_tags(): Promise<Array<string>> {
+	return invoke('address_book_tags');
+}
+
+export function invoke_address_book_update(contact: Contact): Promise<Contact> {
+	return invoke('address_book_update', { contact });
+}
+
+export function invoke_address_book_delete(id: number): Promise<void> {
+	return invoke('address_book_delete', { id });
+}
+
+export function invoke_address_book_connect(id: number): Promise<void> {
+	return invoke('address_book_connect', { id });
 }
 
 export function invoke_lan_init(force: boolean): Promise<void> {
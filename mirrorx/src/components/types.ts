@@ -11,6 +11,9 @@ export interface Domain {
 	password: string;
 	finger_print: string;
 	remarks: string;
+	expire: number;
+	proxy: string | null;
+	subscribe_tls: boolean;
 }
 
 export interface LanDiscoverNode {
@@ -25,6 +28,69 @@ export interface HistoryRecord {
 	device_id: number;
 	domain: string;
 	timestamp: number;
+	end_timestamp: number | null;
+	direction: 'incoming' | 'outgoing';
+	session_type: 'desktop' | 'file_manager' | 'lan';
+	transferred_bytes: number;
+	end_reason: 'local_closed' | 'remote_closed' | 'connection_error' | null;
+	peer_os: string | null;
+	peer_addr: string | null;
+}
+
+export interface Settings {
+	video_quality: 'low' | 'balanced' | 'high';
+	frame_rate: number;
+	max_bitrate_kbps: number;
+	audio_enabled: boolean;
+	clipboard_sync: boolean;
+	lan_discoverable: boolean;
+	log_level: 'error' | 'warn' | 'info' | 'debug' | 'trace';
+	video_codecs: Array<'H264' | 'Hevc' | 'VP8' | 'VP9' | 'AV1'>;
+	intra_refresh: boolean;
+	chroma_format: 'YUV420' | 'YUV444';
+	jitter_buffer: 'low_latency' | 'smooth';
+}
+
+export interface ConnectionProfile {
+	id: number;
+	domain: string;
+	device_id: number;
+	video_codecs: Array<'H264' | 'Hevc' | 'VP8' | 'VP9' | 'AV1'>;
+	frame_rate: number;
+	max_bitrate_kbps: number;
+	audio_enabled: boolean;
+	monitor_id: string | null;
+	updated_at: number;
+}
+
+export interface HistoryFilter {
+	time_range: [number, number] | null;
+	direction?: 'incoming' | 'outgoing' | null;
+	session_type?: 'desktop' | 'file_manager' | 'lan' | null;
+	domain?: string | null;
+	device_id?: number | null;
+}
+
+export interface Contact {
+	id: number;
+	domain: string;
+	device_id: number;
+	alias: string;
+	tags: Array<string>;
+	group_name: string;
+	notes: string;
+	password: string | null;
+	has_password: boolean;
+	connection_mode: 'desktop' | 'files';
+	lan_addr: string | null;
+	created_at: number;
+	updated_at: number;
+}
+
+export interface ContactFilter {
+	keyword: string | null;
+	group_name: string | null;
+	tag: string | null;
 }
 
 export interface Directory {
@@ -0,0 +1,703 @@
+use super::{
+    entity::{
+        address_book::Contact,
+        domain::Domain,
+        history::{HistoryFilter, Record},
+        kv::{Settings, Theme},
+        profile::ConnectionProfile,
+    },
+    secret::SecretKey,
+    LocalStorage,
+};
+use crate::{
+    core_error,
+    error::CoreResult,
+    utility::rand::{generate_device_finger_print, generate_random_password},
+};
+use base64::{engine::general_purpose::STANDARD as base64_standard, Engine};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::{ops::RangeInclusive, path::Path};
+
+const DOCUMENT_VERSION: u32 = 1;
+const PASSPHRASE_ITERATIONS: u32 = 100_000;
+// a crafted document must not make the key derivation trivial or endless
+const PASSPHRASE_ITERATIONS_RANGE: RangeInclusive<u32> = 10_000..=10_000_000;
+const PASSPHRASE_CHECK: &str = "mirrorx";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    // remove all local data before import
+    Replace,
+    // local data wins when it conflicts with the imported
+    KeepLocal,
+    // imported data wins when it conflicts with the local
+    PreferImported,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SecretsEncryption {
+    salt: String,
+    iterations: u32,
+    // sealed constant to tell a wrong passphrase before any secret is opened
+    check: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ConfigDocument {
+    version: u32,
+    exported_at: i64,
+    encryption: Option<SecretsEncryption>,
+    domains: Vec<Domain>,
+    language: Option<String>,
+    theme: Option<Theme>,
+    settings: Option<Settings>,
+    history_retention_days: Option<u32>,
+    history: Vec<Record>,
+    address_book: Vec<Contact>,
+    profiles: Vec<ConnectionProfile>,
+}
+
+impl LocalStorage {
+    pub fn export<P>(&self, path: P, passphrase: Option<&str>) -> CoreResult<()>
+    where
+        P: AsRef<Path>,
+    {
+        let (encryption, secret_key) = match passphrase.filter(|passphrase| !passphrase.is_empty())
+        {
+            Some(passphrase) => {
+                let mut salt = [0u8; 16];
+                SystemRandom::new().fill(&mut salt)?;
+
+                let secret_key = SecretKey::derive(passphrase, &salt, PASSPHRASE_ITERATIONS)?;
+                let encryption = SecretsEncryption {
+                    salt: base64_standard.encode(salt),
+                    iterations: PASSPHRASE_ITERATIONS,
+                    check: secret_key.seal(PASSPHRASE_CHECK)?,
+                };
+
+                (Some(encryption), Some(secret_key))
+            }
+            None => (None, None),
+        };
+
+        let mut domains = self.domain().get_all_domains()?;
+        let mut address_book = self.address_book().get_all_contacts()?;
+
+        // the device identity is bound to this machine, importing it elsewhere would make
+        // two machines subscribe as the same device
+        for domain in domains.iter_mut() {
+            domain.device_id = 0;
+            domain.finger_print = String::default();
+            domain.expire = 0;
+        }
+
+        // passwords are only exported sealed with a passphrase, otherwise they're stripped
+        match secret_key {
+            Some(ref secret_key) => {
+                for domain in domains.iter_mut() {
+                    domain.password = secret_key.seal(&domain.password)?;
+                }
+
+                for contact in address_book
+                    .iter_mut()
+                    .filter(|contact| contact.has_password)
+                {
+                    if let Some(password) = self.address_book().get_contact_password(contact.id)? {
+                        contact.password = Some(secret_key.seal(&password)?);
+                    }
+                }
+            }
+            None => {
+                for domain in domains.iter_mut() {
+                    domain.password = String::default();
+                }
+
+                for contact in address_book.iter_mut() {
+                    contact.has_password = false;
+                }
+            }
+        }
+
+        let document = ConfigDocument {
+            version: DOCUMENT_VERSION,
+            exported_at: chrono::Utc::now().timestamp(),
+            encryption,
+            domains,
+            language: self.kv().get_language()?,
+            theme: self.kv().get_theme()?,
+            settings: self.kv().get_saved_settings()?,
+            history_retention_days: self.kv().get_history_retention_days()?,
+            history: self.history().query(&HistoryFilter::default())?,
+            address_book,
+            profiles: self.profile().get_profiles()?,
+        };
+
+        std::fs::write(path, serde_json::to_vec_pretty(&document)?)?;
+
+        Ok(())
+    }
+
+    pub fn import<P>(
+        &self,
+        path: P,
+        merge_strategy: MergeStrategy,
+        passphrase: Option<&str>,
+    ) -> CoreResult<()>
+    where
+        P: AsRef<Path>,
+    {
+        let mut document: ConfigDocument = serde_json::from_slice(&std::fs::read(path)?)?;
+
+        if document.version > DOCUMENT_VERSION {
+            return Err(core_error!(
+                "configuration document version {} is newer than supported",
+                document.version
+            ));
+        }
+
+        match document.encryption {
+            Some(ref encryption) => {
+                let Some(passphrase) = passphrase else {
+                    return Err(core_error!("configuration document requires passphrase"));
+                };
+
+                if !PASSPHRASE_ITERATIONS_RANGE.contains(&encryption.iterations) {
+                    return Err(core_error!(
+                        "configuration document passphrase iterations {} out of range",
+                        encryption.iterations
+                    ));
+                }
+
+                let salt = base64_standard.decode(&encryption.salt)?;
+                let secret_key = SecretKey::derive(passphrase, &salt, encryption.iterations)?;
+
+                if !matches!(secret_key.open(&encryption.check), Ok(check) if check == PASSPHRASE_CHECK)
+                {
+                    return Err(core_error!("incorrect passphrase"));
+                }
+
+                for domain in document.domains.iter_mut() {
+                    domain.password = secret_key.open(&domain.password)?;
+                }
+
+                for contact in document.address_book.iter_mut() {
+                    if let Some(ref password) = contact.password {
+                        contact.password = Some(secret_key.open(password)?);
+                    }
+
+                    contact.has_password = contact.password.is_some();
+                }
+            }
+            None => {
+                // a document without passphrase carries no passwords, the local ones are
+                // kept and new domains get a generated one
+                for domain in document.domains.iter_mut() {
+                    domain.password = String::default();
+                }
+
+                for contact in document.address_book.iter_mut() {
+                    contact.password = None;
+                    contact.has_password = true;
+                }
+            }
+        }
+
+        // everything is validated and applied in one transaction, so a failed import
+        // leaves the local configuration untouched
+        self.transaction(|storage| {
+            let local_domains = storage.domain().get_all_domains()?;
+
+            if merge_strategy == MergeStrategy::Replace {
+                storage.clear()?;
+            }
+
+            storage.import_kv(&document, merge_strategy)?;
+            storage.import_domains(document.domains, &local_domains, merge_strategy)?;
+            storage.import_history(document.history)?;
+            storage.import_address_book(document.address_book, merge_strategy)?;
+            storage.import_profiles(document.profiles, merge_strategy)?;
+
+            Ok(())
+        })
+    }
+
+    fn clear(&self) -> CoreResult<()> {
+        for domain in self.domain().get_all_domains()? {
+            self.domain().delete_domain(domain.id)?;
+            self.history().delete_domain_related(&domain.name)?;
+            self.profile().delete_domain_related(&domain.name)?;
+        }
+
+        for contact in self.address_book().get_all_contacts()? {
+            self.address_book().delete_contact(contact.id)?;
+        }
+
+        // lan sessions aren't related to any domain
+        self.history().delete_domain_related("")?;
+        self.profile().delete_domain_related("")?;
+
+        Ok(())
+    }
+
+    fn import_domains(
+        &self,
+        domains: Vec<Domain>,
+        local_domains: &[Domain],
+        merge_strategy: MergeStrategy,
+    ) -> CoreResult<()> {
+        for mut domain in domains {
+            let primary = domain.is_primary;
+
+            // the device identity is never taken from the document, a domain new to this
+            // machine is registered again on its next subscription
+            match local_domains
+                .iter()
+                .find(|local_domain| local_domain.name == domain.name)
+            {
+                Some(local_domain) => {
+                    domain.device_id = local_domain.device_id;
+                    domain.finger_print = local_domain.finger_print.clone();
+                    domain.expire = local_domain.expire;
+
+                    if domain.password.is_empty() {
+                        domain.password = local_domain.password.clone();
+                    }
+                }
+                None => {
+                    domain.device_id = 0;
+                    domain.finger_print = generate_device_finger_print();
+                    domain.expire = 0;
+
+                    if domain.password.is_empty() {
+                        domain.password = generate_random_password();
+                    }
+                }
+            }
+
+            if self.domain().domain_exist(&domain.name)? {
+                if merge_strategy == MergeStrategy::KeepLocal {
+                    continue;
+                }
+
+                let local_domain = self.domain().get_domain_by_name(domain.name.clone())?;
+                self.domain().delete_domain(local_domain.id)?;
+            }
+
+            // the primary flag is set after insert so that only one domain keeps it
+            domain.is_primary = false;
+            let domain = self.domain().add_domain(domain)?;
+
+            let has_primary = self.domain().get_primary_domain().is_ok();
+            if primary && (!has_primary || merge_strategy != MergeStrategy::KeepLocal) {
+                self.domain().set_domain_is_primary(domain.id)?;
+            }
+        }
+
+        // a local primary domain may be replaced by an imported non-primary one
+        if self.domain().get_primary_domain().is_err() {
+            if let Some(domain) = self.domain().get_all_domains()?.first() {
+                self.domain().set_domain_is_primary(domain.id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn import_kv(
+        &self,
+        document: &ConfigDocument,
+        merge_strategy: MergeStrategy,
+    ) -> CoreResult<()> {
+        let keep_local = merge_strategy == MergeStrategy::KeepLocal;
+
+        if let Some(ref language) = document.language {
+            if !keep_local || self.kv().get_language()?.is_none() {
+                self.kv().set_language(language)?;
+            }
+        }
+
+        if let Some(theme) = document.theme {
+            if !keep_local || self.kv().get_theme()?.is_none() {
+                self.kv().set_theme(theme)?;
+            }
+        }
+
+        if let Some(ref settings) = document.settings {
+            if !keep_local || self.kv().get_saved_settings()?.is_none() {
+                self.kv().set_settings(settings)?;
+            }
+        }
+
+        if let Some(retention_days) = document.history_retention_days {
+            if !keep_local || self.kv().get_history_retention_days()?.is_none() {
+                self.kv().set_history_retention_days(retention_days)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn import_history(&self, records: Vec<Record>) -> CoreResult<()> {
+        let local_records = self.history().query(&HistoryFilter::default())?;
+
+        // history is append only, a record exported twice is imported once
+        for record in records {
+            let exist = local_records.iter().any(|local_record| {
+                local_record.domain == record.domain
+                    && local_record.device_id == record.device_id
+                    && local_record.timestamp == record.timestamp
+                    && local_record.direction == record.direction
+            });
+
+            if !exist {
+                self.history().create(record)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn import_address_book(
+        &self,
+        contacts: Vec<Contact>,
+        merge_strategy: MergeStrategy,
+    ) -> CoreResult<()> {
+        let local_contacts = self.address_book().get_all_contacts()?;
+
+        for contact in contacts {
+            let local_contact = local_contacts.iter().find(|local_contact| {
+                if contact.device_id != 0 {
+                    local_contact.domain == contact.domain
+                        && local_contact.device_id == contact.device_id
+                } else {
+                    local_contact.device_id == 0
+                        && local_contact.alias == contact.alias
+                        && local_contact.lan_addr == contact.lan_addr
+                }
+            });
+
+            match local_contact {
+                Some(_) if merge_strategy == MergeStrategy::KeepLocal => {}
+                Some(local_contact) => {
+                    self.address_book().update_contact(Contact {
+                        id: local_contact.id,
+                        ..contact
+                    })?;
+                }
+                None => {
+                    self.address_book().add_contact(contact)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn import_profiles(
+        &self,
+        profiles: Vec<ConnectionProfile>,
+        merge_strategy: MergeStrategy,
+    ) -> CoreResult<()> {
+        for profile in profiles {
+            if merge_strategy == MergeStrategy::KeepLocal
+                && self
+                    .profile()
+                    .get_profile(&profile.domain, profile.device_id)?
+                    .is_some()
+            {
+                continue;
+            }
+
+            self.profile().save_profile(profile)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::config::{entity::address_book::ConnectionMode, TestStorage};
+    use std::path::PathBuf;
+
+    fn add_domain(storage: &LocalStorage, name: &str, is_primary: bool, remarks: &str) -> Domain {
+        let domain = storage
+            .domain()
+            .add_domain(Domain {
+                id: 0,
+                name: name.to_string(),
+                addr: format!("{name}.example.com"),
+                signaling_port: 28000,
+                subscribe_port: 28001,
+                is_primary: false,
+                device_id: 1_000_000_001,
+                password: format!("{name}-password"),
+                finger_print: format!("{name}-finger-print"),
+                remarks: remarks.to_string(),
+                expire: 1_700_000_000,
+                proxy: None,
+                subscribe_tls: false,
+            })
+            .unwrap();
+
+        if is_primary {
+            storage.domain().set_domain_is_primary(domain.id).unwrap();
+        }
+
+        domain
+    }
+
+    fn add_contact(storage: &LocalStorage, domain: &str, alias: &str, password: &str) -> Contact {
+        storage
+            .address_book()
+            .add_contact(Contact {
+                id: 0,
+                domain: domain.to_string(),
+                device_id: 1_000_000_002,
+                alias: alias.to_string(),
+                tags: vec![],
+                group_name: String::default(),
+                notes: String::default(),
+                password: Some(password.to_string()),
+                has_password: false,
+                connection_mode: ConnectionMode::Desktop,
+                lan_addr: None,
+                created_at: 0,
+                updated_at: 0,
+            })
+            .unwrap()
+    }
+
+    // exports a storage holding "shared" as primary domain and "exported" with a contact
+    fn export_document(passphrase: Option<&str>) -> (TestStorage, PathBuf) {
+        let source = TestStorage::new();
+        add_domain(&source.storage, "shared", true, "exported remarks");
+        add_domain(&source.storage, "exported", false, "");
+        add_contact(
+            &source.storage,
+            "exported",
+            "exported contact",
+            "contact-password",
+        );
+        source.storage.kv().set_language("en").unwrap();
+
+        let path = source.dir.join("export.json");
+        source.storage.export(&path, passphrase).unwrap();
+
+        (source, path)
+    }
+
+    fn domain_names(storage: &LocalStorage) -> Vec<String> {
+        let mut names: Vec<String> = storage
+            .domain()
+            .get_all_domains()
+            .unwrap()
+            .into_iter()
+            .map(|domain| domain.name)
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_export_strips_device_identity() {
+        let (_source, path) = export_document(Some("passphrase"));
+        let document: ConfigDocument =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+
+        assert_eq!(document.domains.len(), 2);
+        for domain in document.domains.iter() {
+            assert_eq!(domain.device_id, 0);
+            assert!(domain.finger_print.is_empty());
+            assert_eq!(domain.expire, 0);
+            assert_ne!(domain.password, format!("{}-password", domain.name));
+        }
+
+        assert_eq!(document.address_book.len(), 1);
+        assert_ne!(
+            document.address_book[0].password.as_deref(),
+            Some("contact-password")
+        );
+
+        // without passphrase no password leaves the machine
+        let (_source, path) = export_document(None);
+        let document: ConfigDocument =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+
+        assert!(document.encryption.is_none());
+        assert!(document
+            .domains
+            .iter()
+            .all(|domain| domain.password.is_empty()));
+        assert!(document
+            .address_book
+            .iter()
+            .all(|contact| contact.password.is_none() && !contact.has_password));
+    }
+
+    #[test]
+    fn test_import_replace() {
+        let (_source, path) = export_document(Some("passphrase"));
+
+        let target = TestStorage::new();
+        add_domain(&target.storage, "local", true, "");
+        add_contact(&target.storage, "local", "local contact", "local-password");
+
+        target
+            .storage
+            .import(&path, MergeStrategy::Replace, Some("passphrase"))
+            .unwrap();
+
+        assert_eq!(domain_names(&target.storage), ["exported", "shared"]);
+        assert_eq!(
+            target.storage.domain().get_primary_domain().unwrap().name,
+            "shared"
+        );
+
+        // a domain new to this machine registers again with its own identity
+        let domain = target
+            .storage
+            .domain()
+            .get_domain_by_name(String::from("exported"))
+            .unwrap();
+        assert_eq!(domain.device_id, 0);
+        assert_eq!(domain.expire, 0);
+        assert!(!domain.finger_print.is_empty());
+        assert_ne!(domain.finger_print, "exported-finger-print");
+        assert_eq!(domain.password, "exported-password");
+
+        let contacts = target.storage.address_book().get_all_contacts().unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].alias, "exported contact");
+        assert!(contacts[0].password.is_none());
+        assert_eq!(
+            target
+                .storage
+                .address_book()
+                .get_contact_password(contacts[0].id)
+                .unwrap()
+                .as_deref(),
+            Some("contact-password")
+        );
+    }
+
+    #[test]
+    fn test_import_keep_local() {
+        let (_source, path) = export_document(Some("passphrase"));
+
+        let target = TestStorage::new();
+        let local = add_domain(&target.storage, "local", true, "");
+        add_domain(&target.storage, "shared", false, "local remarks");
+        target.storage.kv().set_language("zh").unwrap();
+
+        target
+            .storage
+            .import(&path, MergeStrategy::KeepLocal, Some("passphrase"))
+            .unwrap();
+
+        assert_eq!(
+            domain_names(&target.storage),
+            ["exported", "local", "shared"]
+        );
+        assert_eq!(
+            target.storage.domain().get_primary_domain().unwrap().id,
+            local.id
+        );
+
+        let shared = target
+            .storage
+            .domain()
+            .get_domain_by_name(String::from("shared"))
+            .unwrap();
+        assert_eq!(shared.remarks, "local remarks");
+        assert_eq!(shared.password, "shared-password");
+        assert_eq!(
+            target.storage.kv().get_language().unwrap().as_deref(),
+            Some("zh")
+        );
+    }
+
+    #[test]
+    fn test_import_prefer_imported() {
+        let (_source, path) = export_document(Some("passphrase"));
+
+        let target = TestStorage::new();
+        add_domain(&target.storage, "local", true, "");
+        let local_shared = add_domain(&target.storage, "shared", false, "local remarks");
+        target.storage.kv().set_language("zh").unwrap();
+
+        target
+            .storage
+            .import(&path, MergeStrategy::PreferImported, Some("passphrase"))
+            .unwrap();
+
+        assert_eq!(
+            domain_names(&target.storage),
+            ["exported", "local", "shared"]
+        );
+
+        // the imported fields win, the device identity stays the local one
+        let shared = target.storage.domain().get_primary_domain().unwrap();
+        assert_eq!(shared.name, "shared");
+        assert_eq!(shared.remarks, "exported remarks");
+        assert_eq!(shared.device_id, local_shared.device_id);
+        assert_eq!(shared.finger_print, local_shared.finger_print);
+        assert_eq!(shared.expire, local_shared.expire);
+        assert_eq!(
+            target.storage.kv().get_language().unwrap().as_deref(),
+            Some("en")
+        );
+    }
+
+    #[test]
+    fn test_import_wrong_passphrase() {
+        let (_source, path) = export_document(Some("passphrase"));
+
+        let target = TestStorage::new();
+        add_domain(&target.storage, "local", true, "");
+
+        let err = target
+            .storage
+            .import(&path, MergeStrategy::Replace, Some("wrong passphrase"))
+            .unwrap_err();
+        assert!(err.to_string().contains("incorrect passphrase"), "{err}");
+
+        assert!(target
+            .storage
+            .import(&path, MergeStrategy::Replace, None)
+            .is_err());
+
+        // nothing was applied
+        assert_eq!(domain_names(&target.storage), ["local"]);
+    }
+
+    #[test]
+    fn test_import_iterations_out_of_range() {
+        let (_source, path) = export_document(Some("passphrase"));
+        let document: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+
+        let target = TestStorage::new();
+
+        for iterations in [
+            *PASSPHRASE_ITERATIONS_RANGE.start() - 1,
+            *PASSPHRASE_ITERATIONS_RANGE.end() + 1,
+        ] {
+            let mut document = document.clone();
+            document["encryption"]["iterations"] = iterations.into();
+            std::fs::write(&path, serde_json::to_vec(&document).unwrap()).unwrap();
+
+            let err = target
+                .storage
+                .import(&path, MergeStrategy::Replace, Some("passphrase"))
+                .unwrap_err();
+            assert!(err.to_string().contains("out of range"), "{err}");
+        }
+
+        assert!(domain_names(&target.storage).is_empty());
+    }
+}
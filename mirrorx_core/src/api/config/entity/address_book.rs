@@ -0,0 +1,716 @@
+use crate::{
+    api::config::{secret::SecretKey, ConnectionSource},
+    core_error,
+    error::CoreResult,
+};
+use rusqlite::{named_params, params, Row};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, str::FromStr, sync::Arc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionMode {
+    Desktop,
+    Files,
+}
+
+impl<'a> From<ConnectionMode> for &'a str {
+    fn from(val: ConnectionMode) -> Self {
+        match val {
+            ConnectionMode::Desktop => "desktop",
+            ConnectionMode::Files => "files",
+        }
+    }
+}
+
+impl FromStr for ConnectionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "desktop" => Ok(ConnectionMode::Desktop),
+            "files" => Ok(ConnectionMode::Files),
+            _ => Err(String::from("Unknown connection mode")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Contact {
+    pub id: i64,
+    pub domain: String,
+    pub device_id: i64,
+    pub alias: String,
+    pub tags: Vec<String>,
+    pub group_name: String,
+    pub notes: String,
+    // the saved password is only taken on create and update, contacts read back never
+    // carry it, it's opened by the connect command alone
+    pub password: Option<String>,
+    #[serde(default)]
+    pub has_password: bool,
+    pub connection_mode: ConnectionMode,
+    pub lan_addr: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ContactFilter {
+    pub keyword: Option<String>,
+    pub group_name: Option<String>,
+    pub tag: Option<String>,
+}
+
+pub struct AddressBookRepository {
+    pool: ConnectionSource,
+    secret_key: Arc<SecretKey>,
+}
+
+impl AddressBookRepository {
+    pub fn new(pool: ConnectionSource, secret_key: Arc<SecretKey>) -> Self {
+        Self { pool, secret_key }
+    }
+
+    pub fn ensure_table(&self) -> CoreResult<()> {
+        let conn = self.pool.get()?;
+
+        const CREATE_TABLE_COMMAND: &str = r"
+        CREATE TABLE IF NOT EXISTS address_book(
+            id INTEGER PRIMARY KEY,
+            domain TEXT NOT NULL,
+            device_id INTEGER NOT NULL,
+            alias TEXT NOT NULL,
+            tags TEXT NOT NULL,
+            group_name TEXT NOT NULL,
+            notes TEXT NOT NULL,
+            password TEXT,
+            connection_mode TEXT NOT NULL,
+            lan_addr TEXT,
+            created_at INTEGER NOT NULL,
+            updated_at INTEGER NOT NULL
+        )";
+
+        conn.execute(CREATE_TABLE_COMMAND, [])?;
+
+        // contacts only reachable in lan have no device id
+        const CREATE_UNIQUE_INDEX_COMMAND: &str = r"
+        CREATE UNIQUE INDEX IF NOT EXISTS uq_address_book_domain_device_id
+        ON address_book(domain, device_id) WHERE device_id != 0";
+
+        conn.execute(CREATE_UNIQUE_INDEX_COMMAND, [])?;
+
+        Ok(())
+    }
+
+    pub fn add_contact(&self, mut contact: Contact) -> CoreResult<Contact> {
+        const COMMAND: &str = r#"
+        INSERT INTO address_book(
+            domain,
+            device_id,
+            alias,
+            tags,
+            group_name,
+            notes,
+            password,
+            connection_mode,
+            lan_addr,
+            created_at,
+            updated_at
+        )
+        VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#;
+
+        let timestamp = chrono::Utc::now().timestamp();
+        contact.created_at = timestamp;
+        contact.updated_at = timestamp;
+
+        let sealed_password = self.seal_password(contact.password.take().as_deref())?;
+        let connection_mode: &str = contact.connection_mode.into();
+        contact.has_password = sealed_password.is_some();
+
+        let conn = self.pool.get()?;
+        conn.execute(
+            COMMAND,
+            params![
+                contact.domain,
+                contact.device_id,
+                contact.alias,
+                serde_json::to_string(&contact.tags)?,
+                contact.group_name,
+                contact.notes,
+                sealed_password,
+                connection_mode,
+                contact.lan_addr,
+                contact.created_at,
+                contact.updated_at,
+            ],
+        )?;
+
+        contact.id = conn.last_insert_rowid();
+
+        Ok(contact)
+    }
+
+    pub fn get_contact_by_id(&self, id: i64) -> CoreResult<Contact> {
+        const COMMAND: &str = r"SELECT * FROM address_book WHERE id = ?";
+
+        self.pool
+            .get()?
+            .query_row_and_then(COMMAND, [id], Self::parse_contact)
+    }
+
+    pub fn get_contact_by_device(
+        &self,
+        domain: &str,
+        device_id: i64,
+    ) -> CoreResult<Option<Contact>> {
+        const COMMAND: &str =
+            r"SELECT * FROM address_book WHERE domain = ? AND device_id = ? LIMIT 1";
+
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare(COMMAND)?;
+        let contact = stmt
+            .query_and_then(params![domain, device_id], Self::parse_contact)?
+            .next()
+            .transpose()?;
+
+        Ok(contact)
+    }
+
+    pub fn get_contacts(
+        &self,
+        page: u32,
+        limit: u32,
+        filter: &ContactFilter,
+    ) -> CoreResult<(u32, Vec<Contact>)> {
+        const FILTER_CONDITION: &str = r"
+        (:keyword IS NULL
+            OR alias LIKE :keyword ESCAPE '\'
+            OR notes LIKE :keyword ESCAPE '\'
+            OR tags LIKE :keyword ESCAPE '\'
+            OR lan_addr LIKE :keyword ESCAPE '\'
+            OR CAST(device_id AS TEXT) LIKE :keyword ESCAPE '\')
+        AND (:group_name IS NULL OR group_name = :group_name)
+        AND (:tag IS NULL OR tags LIKE :tag ESCAPE '\')";
+
+        let count_command = format!("SELECT COUNT(*) FROM address_book WHERE {FILTER_CONDITION}");
+        let pagination_command = format!(
+            "SELECT * FROM address_book WHERE {FILTER_CONDITION} ORDER BY alias LIMIT :limit OFFSET :offset"
+        );
+
+        let keyword = filter
+            .keyword
+            .as_deref()
+            .filter(|keyword| !keyword.trim().is_empty())
+            .map(|keyword| format!("%{}%", escape_like(keyword.trim())));
+
+        // tags are saved as json array, so an exact tag is matched with its quotes
+        let tag = match filter.tag {
+            Some(ref tag) => Some(format!("%{}%", escape_like(&serde_json::to_string(tag)?))),
+            None => None,
+        };
+
+        let conn = self.pool.get()?;
+
+        let count = conn.query_row_and_then(
+            &count_command,
+            named_params! {
+                ":keyword": keyword,
+                ":group_name": filter.group_name,
+                ":tag": tag,
+            },
+            |row| -> CoreResult<u32> { Ok(row.get(0)?) },
+        )?;
+
+        let mut stmt = conn.prepare(&pagination_command)?;
+        let rows = stmt.query_and_then(
+            named_params! {
+                ":keyword": keyword,
+                ":group_name": filter.group_name,
+                ":tag": tag,
+                ":limit": limit,
+                ":offset": (page.max(1) - 1) * limit,
+            },
+            Self::parse_contact,
+        )?;
+
+        let mut contacts = Vec::new();
+        for row in rows {
+            contacts.push(row?);
+        }
+
+        Ok((count, contacts))
+    }
+
+    pub fn get_all_contacts(&self) -> CoreResult<Vec<Contact>> {
+        const COMMAND: &str = r"SELECT * FROM address_book ORDER BY id";
+
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare(COMMAND)?;
+        let rows = stmt.query_and_then([], Self::parse_contact)?;
+
+        let mut contacts = Vec::new();
+        for row in rows {
+            contacts.push(row?);
+        }
+
+        Ok(contacts)
+    }
+
+    pub fn get_groups(&self) -> CoreResult<Vec<String>> {
+        const COMMAND: &str = r"SELECT DISTINCT group_name FROM address_book WHERE group_name != '' ORDER BY group_name";
+
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare(COMMAND)?;
+        let rows = stmt.query_and_then([], |row| -> CoreResult<String> { Ok(row.get(0)?) })?;
+
+        let mut groups = Vec::new();
+        for row in rows {
+            groups.push(row?);
+        }
+
+        Ok(groups)
+    }
+
+    pub fn get_tags(&self) -> CoreResult<Vec<String>> {
+        const COMMAND: &str = r"SELECT DISTINCT tags FROM address_book";
+
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare(COMMAND)?;
+        let rows = stmt.query_and_then([], |row| -> CoreResult<Vec<String>> {
+            Ok(serde_json::from_str(&row.get::<_, String>(0)?)?)
+        })?;
+
+        let mut tags = BTreeSet::new();
+        for row in rows {
+            tags.extend(row?);
+        }
+
+        Ok(tags.into_iter().collect())
+    }
+
+    pub fn update_contact(&self, mut contact: Contact) -> CoreResult<Contact> {
+        const COMMAND: &str = r#"
+        UPDATE address_book SET
+            domain = ?,
+            device_id = ?,
+            alias = ?,
+            tags = ?,
+            group_name = ?,
+            notes = ?,
+            password = CASE WHEN ? THEN password ELSE ? END,
+            connection_mode = ?,
+            lan_addr = ?,
+            updated_at = ?
+        WHERE id = ?"#;
+
+        contact.updated_at = chrono::Utc::now().timestamp();
+
+        // without a new password the saved one is kept, unless the flag has been cleared
+        let sealed_password = self.seal_password(contact.password.take().as_deref())?;
+        let keep_password = sealed_password.is_none() && contact.has_password;
+        let connection_mode: &str = contact.connection_mode.into();
+
+        let updated = self.pool.get()?.execute(
+            COMMAND,
+            params![
+                contact.domain,
+                contact.device_id,
+                contact.alias,
+                serde_json::to_string(&contact.tags)?,
+                contact.group_name,
+                contact.notes,
+                keep_password,
+                sealed_password,
+                connection_mode,
+                contact.lan_addr,
+                contact.updated_at,
+                contact.id,
+            ],
+        )?;
+
+        if updated == 0 {
+            return Err(core_error!("contact not exists"));
+        }
+
+        contact.has_password = if keep_password {
+            self.pool.get()?.query_row_and_then(
+                r"SELECT password IS NOT NULL FROM address_book WHERE id = ?",
+                [contact.id],
+                |row| -> CoreResult<bool> { Ok(row.get(0)?) },
+            )?
+        } else {
+            sealed_password.is_some()
+        };
+
+        Ok(contact)
+    }
+
+    pub fn delete_contact(&self, id: i64) -> CoreResult<()> {
+        const COMMAND: &str = r"DELETE FROM address_book WHERE id = ?";
+
+        self.pool.get()?.execute(COMMAND, [id])?;
+
+        Ok(())
+    }
+
+    pub fn get_contact_password(&self, id: i64) -> CoreResult<Option<String>> {
+        const COMMAND: &str = r"SELECT password FROM address_book WHERE id = ?";
+
+        let sealed_password = self.pool.get()?.query_row_and_then(
+            COMMAND,
+            [id],
+            |row| -> CoreResult<Option<String>> { Ok(row.get(0)?) },
+        )?;
+
+        match sealed_password {
+            Some(sealed_password) => Ok(Some(self.secret_key.open(&sealed_password)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn seal_password(&self, password: Option<&str>) -> CoreResult<Option<String>> {
+        match password.filter(|password| !password.is_empty()) {
+            Some(password) => Ok(Some(self.secret_key.seal(password)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn parse_contact(row: &Row) -> CoreResult<Contact> {
+        let connection_mode = ConnectionMode::from_str(&row.get::<_, String>(8)?)
+            .map_err(|err| core_error!("{}", err))?;
+
+        Ok(Contact {
+            id: row.get(0)?,
+            domain: row.get(1)?,
+            device_id: row.get(2)?,
+            alias: row.get(3)?,
+            tags: serde_json::from_str(&row.get::<_, String>(4)?)?,
+            group_name: row.get(5)?,
+            notes: row.get(6)?,
+            password: None,
+            has_password: row.get::<_, Option<String>>(7)?.is_some(),
+            connection_mode,
+            lan_addr: row.get(9)?,
+            created_at: row.get(10)?,
+            updated_at: row.get(11)?,
+        })
+    }
+}
+
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('%', r"\%")
+        .replace('_', r"\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::config::TestStorage;
+
+    fn contact(device_id: i64, alias: &str, tags: &[&str], group_name: &str) -> Contact {
+        Contact {
+            id: 0,
+            domain: String::from("mirrorx.cloud"),
+            device_id,
+            alias: alias.to_string(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            group_name: group_name.to_string(),
+            notes: String::default(),
+            password: None,
+            has_password: false,
+            connection_mode: ConnectionMode::Desktop,
+            lan_addr: None,
+            created_at: 0,
+            updated_at: 0,
+        }
+    }
+
+    fn aliases(contacts: &[Contact]) -> Vec<&str> {
+        contacts
+            .iter()
+            .map(|contact| contact.alias.as_str())
+            .collect()
+    }
+
+    fn search(repository: &AddressBookRepository, keyword: &str) -> Vec<String> {
+        let filter = ContactFilter {
+            keyword: Some(keyword.to_string()),
+            ..Default::default()
+        };
+
+        let (_, contacts) = repository.get_contacts(1, 100, &filter).unwrap();
+        contacts.into_iter().map(|contact| contact.alias).collect()
+    }
+
+    #[test]
+    fn test_contact_crud() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.address_book();
+
+        let added = repository
+            .add_contact(Contact {
+                notes: String::from("office"),
+                connection_mode: ConnectionMode::Files,
+                lan_addr: Some(String::from("192.168.1.2:48001")),
+                ..contact(1_000_000_001, "workstation", &["work", "linux"], "team")
+            })
+            .unwrap();
+
+        assert!(added.id > 0);
+        assert!(added.created_at > 0);
+
+        let read = repository.get_contact_by_id(added.id).unwrap();
+        assert_eq!(read.alias, "workstation");
+        assert_eq!(read.tags, ["work", "linux"]);
+        assert_eq!(read.group_name, "team");
+        assert_eq!(read.notes, "office");
+        assert_eq!(read.connection_mode, ConnectionMode::Files);
+        assert_eq!(read.lan_addr.as_deref(), Some("192.168.1.2:48001"));
+        assert!(!read.has_password);
+
+        let by_device = repository
+            .get_contact_by_device("mirrorx.cloud", 1_000_000_001)
+            .unwrap()
+            .unwrap();
+        assert_eq!(by_device.id, added.id);
+        assert!(repository
+            .get_contact_by_device("mirrorx.cloud", 1_000_000_009)
+            .unwrap()
+            .is_none());
+
+        // a device is saved once per domain
+        assert!(repository
+            .add_contact(contact(1_000_000_001, "duplicate", &[], ""))
+            .is_err());
+
+        let updated = repository
+            .update_contact(Contact {
+                alias: String::from("laptop"),
+                tags: vec![],
+                ..read
+            })
+            .unwrap();
+        assert_eq!(updated.alias, "laptop");
+
+        let read = repository.get_contact_by_id(added.id).unwrap();
+        assert_eq!(read.alias, "laptop");
+        assert!(read.tags.is_empty());
+
+        repository.delete_contact(added.id).unwrap();
+        assert!(repository.get_contact_by_id(added.id).is_err());
+        assert!(repository.update_contact(read).is_err());
+    }
+
+    #[test]
+    fn test_tags_round_trip() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.address_book();
+
+        let tags = ["home", "with \"quotes\"", "逗号,comma", "back\\slash"];
+        let added = repository
+            .add_contact(contact(1_000_000_001, "a", &tags, ""))
+            .unwrap();
+        repository
+            .add_contact(contact(1_000_000_002, "b", &["home", "office"], ""))
+            .unwrap();
+
+        assert_eq!(repository.get_contact_by_id(added.id).unwrap().tags, tags);
+
+        let mut all_tags: Vec<&str> = tags.to_vec();
+        all_tags.push("office");
+        all_tags.sort_unstable();
+        assert_eq!(repository.get_tags().unwrap(), all_tags);
+
+        // a tag only matches whole, not as a part of another tag
+        for (tag, expected) in [
+            ("home", vec!["a", "b"]),
+            ("hom", vec![]),
+            ("with \"quotes\"", vec!["a"]),
+            ("back\\slash", vec!["a"]),
+        ] {
+            let filter = ContactFilter {
+                tag: Some(tag.to_string()),
+                ..Default::default()
+            };
+
+            let (count, contacts) = repository.get_contacts(1, 10, &filter).unwrap();
+            assert_eq!(aliases(&contacts), expected, "{tag}");
+            assert_eq!(count as usize, expected.len());
+        }
+    }
+
+    #[test]
+    fn test_search_escapes_like_wildcards() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.address_book();
+
+        for (device_id, alias) in [
+            (1_000_000_001, "100% cpu"),
+            (1_000_000_002, "1000 cpu"),
+            (1_000_000_003, "build_server"),
+            (1_000_000_004, "build-server"),
+            (1_000_000_005, "c:\\share"),
+        ] {
+            repository
+                .add_contact(contact(device_id, alias, &[], ""))
+                .unwrap();
+        }
+
+        assert_eq!(search(repository, "0%"), ["100% cpu"]);
+        assert_eq!(search(repository, "d_s"), ["build_server"]);
+        assert_eq!(search(repository, "\\s"), ["c:\\share"]);
+        assert_eq!(
+            search(repository, "build"),
+            ["build-server", "build_server"]
+        );
+        assert_eq!(search(repository, "%").len(), 1);
+        assert_eq!(search(repository, "_").len(), 1);
+
+        // device ids are searched as text
+        assert_eq!(search(repository, "00003"), ["build_server"]);
+
+        // blank keywords don't filter
+        assert_eq!(search(repository, "  ").len(), 5);
+    }
+
+    #[test]
+    fn test_pagination() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.address_book();
+
+        for index in 0..7 {
+            let group_name = if index % 2 == 0 { "even" } else { "odd" };
+            repository
+                .add_contact(contact(
+                    1_000_000_000 + index,
+                    &format!("device {index}"),
+                    &[],
+                    group_name,
+                ))
+                .unwrap();
+        }
+
+        let filter = ContactFilter::default();
+
+        let (count, contacts) = repository.get_contacts(1, 3, &filter).unwrap();
+        assert_eq!(count, 7);
+        assert_eq!(aliases(&contacts), ["device 0", "device 1", "device 2"]);
+
+        let (_, contacts) = repository.get_contacts(3, 3, &filter).unwrap();
+        assert_eq!(aliases(&contacts), ["device 6"]);
+
+        let (count, contacts) = repository.get_contacts(4, 3, &filter).unwrap();
+        assert_eq!(count, 7);
+        assert!(contacts.is_empty());
+
+        // the first page is served for page zero
+        let (_, contacts) = repository.get_contacts(0, 3, &filter).unwrap();
+        assert_eq!(aliases(&contacts), ["device 0", "device 1", "device 2"]);
+
+        let filter = ContactFilter {
+            group_name: Some(String::from("odd")),
+            ..Default::default()
+        };
+
+        let (count, contacts) = repository.get_contacts(2, 2, &filter).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(aliases(&contacts), ["device 5"]);
+        assert_eq!(repository.get_groups().unwrap(), ["even", "odd"]);
+    }
+
+    #[test]
+    fn test_password_sealed() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.address_book();
+
+        let added = repository
+            .add_contact(Contact {
+                password: Some(String::from("plain password")),
+                ..contact(1_000_000_001, "a", &[], "")
+            })
+            .unwrap();
+
+        assert!(added.password.is_none());
+        assert!(added.has_password);
+
+        // the database holds the sealed password alone
+        let saved: String = repository
+            .pool
+            .get()
+            .unwrap()
+            .query_row(
+                "SELECT password FROM address_book WHERE id = ?",
+                [added.id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!saved.contains("plain password"));
+
+        let read = repository.get_contact_by_id(added.id).unwrap();
+        assert!(read.password.is_none());
+        assert!(read.has_password);
+
+        let (_, contacts) = repository
+            .get_contacts(1, 10, &ContactFilter::default())
+            .unwrap();
+        assert!(contacts.iter().all(|contact| contact.password.is_none()));
+        assert!(repository
+            .get_all_contacts()
+            .unwrap()
+            .iter()
+            .all(|contact| contact.password.is_none()));
+
+        assert_eq!(
+            repository
+                .get_contact_password(added.id)
+                .unwrap()
+                .as_deref(),
+            Some("plain password")
+        );
+
+        // an update without password keeps the saved one
+        let updated = repository
+            .update_contact(Contact {
+                alias: String::from("b"),
+                ..read.clone()
+            })
+            .unwrap();
+        assert!(updated.password.is_none());
+        assert!(updated.has_password);
+        assert_eq!(
+            repository
+                .get_contact_password(added.id)
+                .unwrap()
+                .as_deref(),
+            Some("plain password")
+        );
+
+        let updated = repository
+            .update_contact(Contact {
+                password: Some(String::from("new password")),
+                ..read.clone()
+            })
+            .unwrap();
+        assert!(updated.password.is_none());
+        assert_eq!(
+            repository
+                .get_contact_password(added.id)
+                .unwrap()
+                .as_deref(),
+            Some("new password")
+        );
+
+        // clearing the flag removes the saved password
+        let updated = repository
+            .update_contact(Contact {
+                has_password: false,
+                ..read
+            })
+            .unwrap();
+        assert!(!updated.has_password);
+        assert_eq!(repository.get_contact_password(added.id).unwrap(), None);
+    }
+}
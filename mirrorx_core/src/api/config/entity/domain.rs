@@ -1,10 +1,8 @@
-use crate::error::{CoreError, CoreResult};
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{params, OptionalExtension, Row};
-use serde::Serialize;
+use crate::{api::config::ConnectionSource, error::CoreResult};
+use rusqlite::{params, OptionalExtension, Row, Transaction, TransactionBehavior};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Domain {
     pub id: i64,
     pub name: String,
@@ -16,14 +14,17 @@ pub struct Domain {
     pub password: String,
     pub finger_print: String,
     pub remarks: String,
+    pub expire: i64,
+    pub proxy: Option<String>,
+    pub subscribe_tls: bool,
 }
 
 pub struct DomainRepository {
-    pool: Pool<SqliteConnectionManager>,
+    pool: ConnectionSource,
 }
 
 impl DomainRepository {
-    pub fn new(pool: Pool<SqliteConnectionManager>) -> Self {
+    pub fn new(pool: ConnectionSource) -> Self {
         Self { pool }
     }
 
@@ -41,11 +42,36 @@ impl DomainRepository {
             device_id INTEGER NOT NULL,
             password TEXT NOT NULL,
             finger_print TEXT NOT NULL,
-            remarks TEXT NOT NULL
+            remarks TEXT NOT NULL,
+            expire INTEGER NOT NULL DEFAULT 0,
+            proxy TEXT,
+            subscribe_tls BOOLEAN NOT NULL DEFAULT 0
         )";
 
         conn.execute(COMMAND, [])?;
 
+        // tables created by earlier versions lack the columns added later
+        const COLUMN_EXIST_COMMAND: &str =
+            r"SELECT 1 FROM pragma_table_info('domains') WHERE name = ?";
+        const ADDED_COLUMNS: [(&str, &str); 3] = [
+            ("expire", "INTEGER NOT NULL DEFAULT 0"),
+            ("proxy", "TEXT"),
+            ("subscribe_tls", "BOOLEAN NOT NULL DEFAULT 0"),
+        ];
+
+        for (column, definition) in ADDED_COLUMNS {
+            let column_exist = conn
+                .query_row(COLUMN_EXIST_COMMAND, [column], |row| row.get::<_, u32>(0))
+                .optional()?;
+
+            if column_exist.is_none() {
+                conn.execute(
+                    &format!("ALTER TABLE domains ADD COLUMN {column} {definition}"),
+                    [],
+                )?;
+            }
+        }
+
         Ok(())
     }
 
@@ -60,9 +86,12 @@ impl DomainRepository {
             device_id,
             password,
             finger_print,
-            remarks
+            remarks,
+            expire,
+            proxy,
+            subscribe_tls
         )
-        VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?)"#;
+        VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#;
 
         let conn = self.pool.get()?;
         conn.execute(
@@ -77,6 +106,9 @@ impl DomainRepository {
                 domain.password,
                 domain.finger_print,
                 domain.remarks,
+                domain.expire,
+                domain.proxy,
+                domain.subscribe_tls,
             ],
         )?;
 
@@ -144,6 +176,21 @@ impl DomainRepository {
         Ok(domain)
     }
 
+    pub fn get_all_domains(&self) -> CoreResult<Vec<Domain>> {
+        const COMMAND: &str = r"SELECT * FROM domains";
+
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare(COMMAND)?;
+        let rows = stmt.query_and_then([], parse_domain)?;
+
+        let mut domains = Vec::new();
+        for row in rows {
+            domains.push(row?);
+        }
+
+        Ok(domains)
+    }
+
     pub fn get_domains(&self, page: u32, limit: u32) -> CoreResult<(u32, Vec<Domain>)> {
         const COUNT_COMMAND: &str = r"SELECT COUNT(*) FROM domains";
         const PAGINATION_COMMAND: &str = r"SELECT * FROM domains LIMIT ? OFFSET ?";
@@ -177,20 +224,25 @@ impl DomainRepository {
             r"UPDATE domains SET is_primary = 0 WHERE is_primary = 1";
         const SET_PRIMARY_COMMAND: &str = r"UPDATE domains SET is_primary = 1 WHERE id = ?";
 
-        let mut conn = self.pool.get()?;
-        let tx = conn.transaction()?;
-        if let Err(err) = tx.execute(UNSET_PRIMARY_COMMAND, []) {
-            tx.rollback()?;
-            return Err(CoreError::SQLiteError(err));
-        }
-
-        if let Err(err) = tx.execute(SET_PRIMARY_COMMAND, [domain_id]) {
-            tx.rollback()?;
-            return Err(CoreError::SQLiteError(err));
+        // inside the transaction of a configuration import the outer transaction already
+        // keeps both updates together
+        let conn = self.pool.get()?;
+        let tx = if conn.is_autocommit() {
+            Some(Transaction::new_unchecked(
+                &conn,
+                TransactionBehavior::Deferred,
+            )?)
+        } else {
+            None
+        };
+
+        conn.execute(UNSET_PRIMARY_COMMAND, [])?;
+        conn.execute(SET_PRIMARY_COMMAND, [domain_id])?;
+
+        if let Some(tx) = tx {
+            tx.commit()?;
         }
 
-        tx.commit()?;
-
         Ok(())
     }
 
@@ -224,6 +276,36 @@ impl DomainRepository {
         Ok(())
     }
 
+    pub fn set_domain_expire(&self, domain_id: i64, expire: i64) -> CoreResult<()> {
+        const COMMAND: &str = r"UPDATE domains SET expire = ? WHERE id =?";
+
+        self.pool
+            .get()?
+            .execute(COMMAND, params![expire, domain_id])?;
+
+        Ok(())
+    }
+
+    pub fn set_domain_proxy(&self, domain_id: i64, proxy: Option<&str>) -> CoreResult<()> {
+        const COMMAND: &str = r"UPDATE domains SET proxy = ? WHERE id =?";
+
+        self.pool
+            .get()?
+            .execute(COMMAND, params![proxy, domain_id])?;
+
+        Ok(())
+    }
+
+    pub fn set_domain_subscribe_tls(&self, domain_id: i64, subscribe_tls: bool) -> CoreResult<()> {
+        const COMMAND: &str = r"UPDATE domains SET subscribe_tls = ? WHERE id =?";
+
+        self.pool
+            .get()?
+            .execute(COMMAND, params![subscribe_tls, domain_id])?;
+
+        Ok(())
+    }
+
     pub fn delete_domain(&self, domain_id: i64) -> CoreResult<()> {
         const COMMAND: &str = r"DELETE FROM domains WHERE id = ?";
 
@@ -245,5 +327,8 @@ fn parse_domain(row: &Row) -> CoreResult<Domain> {
         password: row.get(7)?,
         finger_print: row.get(8)?,
         remarks: row.get(9)?,
+        expire: row.get(10)?,
+        proxy: row.get(11)?,
+        subscribe_tls: row.get(12)?,
     })
 }
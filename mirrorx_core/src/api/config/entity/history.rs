@@ -1,23 +1,154 @@
-use crate::error::CoreResult;
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
-use rusqlite::{params, Row};
-use serde::Serialize;
+use crate::{api::config::ConnectionSource, core_error, error::CoreResult};
+use rusqlite::{named_params, params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize)]
+pub const DEFAULT_RETENTION_DAYS: u32 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+impl<'a> From<Direction> for &'a str {
+    fn from(val: Direction) -> Self {
+        match val {
+            Direction::Incoming => "incoming",
+            Direction::Outgoing => "outgoing",
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "incoming" => Ok(Direction::Incoming),
+            "outgoing" => Ok(Direction::Outgoing),
+            _ => Err(String::from("Unknown session direction")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionType {
+    Desktop,
+    FileManager,
+    LAN,
+}
+
+impl<'a> From<SessionType> for &'a str {
+    fn from(val: SessionType) -> Self {
+        match val {
+            SessionType::Desktop => "desktop",
+            SessionType::FileManager => "file_manager",
+            SessionType::LAN => "lan",
+        }
+    }
+}
+
+impl FromStr for SessionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "desktop" => Ok(SessionType::Desktop),
+            "file_manager" => Ok(SessionType::FileManager),
+            "lan" => Ok(SessionType::LAN),
+            _ => Err(String::from("Unknown session type")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    LocalClosed,
+    RemoteClosed,
+    ConnectionError,
+}
+
+impl<'a> From<EndReason> for &'a str {
+    fn from(val: EndReason) -> Self {
+        match val {
+            EndReason::LocalClosed => "local_closed",
+            EndReason::RemoteClosed => "remote_closed",
+            EndReason::ConnectionError => "connection_error",
+        }
+    }
+}
+
+impl FromStr for EndReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local_closed" => Ok(EndReason::LocalClosed),
+            "remote_closed" => Ok(EndReason::RemoteClosed),
+            "connection_error" => Ok(EndReason::ConnectionError),
+            _ => Err(String::from("Unknown session end reason")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Record {
     pub id: i64,
     pub device_id: i64,
     pub domain: String,
+    // the start time of session
     pub timestamp: i64,
+    pub end_timestamp: Option<i64>,
+    pub direction: Direction,
+    pub session_type: SessionType,
+    pub transferred_bytes: i64,
+    pub end_reason: Option<EndReason>,
+    pub peer_os: Option<String>,
+    pub peer_addr: Option<String>,
+}
+
+impl Record {
+    pub fn new(
+        direction: Direction,
+        session_type: SessionType,
+        domain: String,
+        device_id: i64,
+    ) -> Self {
+        Self {
+            id: 0,
+            device_id,
+            domain,
+            timestamp: chrono::Utc::now().timestamp(),
+            end_timestamp: None,
+            direction,
+            session_type,
+            transferred_bytes: 0,
+            end_reason: None,
+            peer_os: None,
+            peer_addr: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct HistoryFilter {
+    pub time_range: Option<(i64, i64)>,
+    pub direction: Option<Direction>,
+    pub session_type: Option<SessionType>,
+    pub domain: Option<String>,
+    pub device_id: Option<i64>,
 }
 
 pub struct HistoryRepository {
-    pool: Pool<SqliteConnectionManager>,
+    pool: ConnectionSource,
 }
 
 impl HistoryRepository {
-    pub fn new(pool: Pool<SqliteConnectionManager>) -> Self {
+    pub fn new(pool: ConnectionSource) -> Self {
         Self { pool }
     }
 
@@ -29,42 +160,146 @@ impl HistoryRepository {
             id INTEGER PRIMARY KEY,
             device_id INTEGER NOT NULL,
             domain TEXT NOT NULL,
-            timestamp INTEGER NOT NULL
+            timestamp INTEGER NOT NULL,
+            end_timestamp INTEGER,
+            direction TEXT NOT NULL DEFAULT 'outgoing',
+            session_type TEXT NOT NULL DEFAULT 'desktop',
+            transferred_bytes INTEGER NOT NULL DEFAULT 0,
+            end_reason TEXT,
+            peer_os TEXT,
+            peer_addr TEXT
         )";
 
         conn.execute(CREATE_TABLE_COMMAND, [])?;
 
-        const CREATE_UNIQUE_INDEX_COMMAND: &str = r"
-        CREATE UNIQUE INDEX IF NOT EXISTS uq_device_id_domain ON history(device_id, domain)";
+        // earlier versions only kept the last visit of every device
+        const DROP_UNIQUE_INDEX_COMMAND: &str = r"DROP INDEX IF EXISTS uq_device_id_domain";
+
+        conn.execute(DROP_UNIQUE_INDEX_COMMAND, [])?;
+
+        const COLUMN_EXIST_COMMAND: &str =
+            r"SELECT 1 FROM pragma_table_info('history') WHERE name = ?";
+        const ADDED_COLUMNS: [(&str, &str); 7] = [
+            ("end_timestamp", "INTEGER"),
+            ("direction", "TEXT NOT NULL DEFAULT 'outgoing'"),
+            ("session_type", "TEXT NOT NULL DEFAULT 'desktop'"),
+            ("transferred_bytes", "INTEGER NOT NULL DEFAULT 0"),
+            ("end_reason", "TEXT"),
+            ("peer_os", "TEXT"),
+            ("peer_addr", "TEXT"),
+        ];
+
+        for (column, definition) in ADDED_COLUMNS {
+            let column_exist = conn
+                .query_row(COLUMN_EXIST_COMMAND, [column], |row| row.get::<_, u32>(0))
+                .optional()?;
 
-        conn.execute(CREATE_UNIQUE_INDEX_COMMAND, [])?;
+            if column_exist.is_none() {
+                conn.execute(
+                    &format!("ALTER TABLE history ADD COLUMN {column} {definition}"),
+                    [],
+                )?;
+            }
+        }
+
+        const CREATE_INDEX_COMMAND: &str =
+            r"CREATE INDEX IF NOT EXISTS idx_history_timestamp ON history(timestamp)";
+
+        conn.execute(CREATE_INDEX_COMMAND, [])?;
 
         Ok(())
     }
 
-    pub fn create(&self, device_id: i64, domain: &str) -> CoreResult<()> {
-        const COMMAND: &str = r"INSERT INTO history(device_id, domain, timestamp) VALUES(?, ?, ?) ON CONFLICT DO UPDATE SET timestamp = ?";
+    pub fn create(&self, mut record: Record) -> CoreResult<Record> {
+        const COMMAND: &str = r#"
+        INSERT INTO history(
+            device_id,
+            domain,
+            timestamp,
+            end_timestamp,
+            direction,
+            session_type,
+            transferred_bytes,
+            end_reason,
+            peer_os,
+            peer_addr
+        )
+        VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#;
+
+        let direction: &str = record.direction.into();
+        let session_type: &str = record.session_type.into();
+        let end_reason: Option<&str> = record.end_reason.map(Into::into);
+
+        let conn = self.pool.get()?;
+        conn.execute(
+            COMMAND,
+            params![
+                record.device_id,
+                record.domain,
+                record.timestamp,
+                record.end_timestamp,
+                direction,
+                session_type,
+                record.transferred_bytes,
+                end_reason,
+                record.peer_os,
+                record.peer_addr,
+            ],
+        )?;
+
+        record.id = conn.last_insert_rowid();
+
+        Ok(record)
+    }
+
+    pub fn finish(&self, id: i64, transferred_bytes: u64, end_reason: EndReason) -> CoreResult<()> {
+        const COMMAND: &str = r"UPDATE history SET end_timestamp = ?, transferred_bytes = ?, end_reason = ? WHERE id = ?";
 
-        let timestamp = chrono::Utc::now().timestamp();
+        let end_reason: &str = end_reason.into();
 
-        let _ = self
-            .pool
-            .get()?
-            .execute(COMMAND, params![device_id, domain, timestamp, timestamp])?;
+        self.pool.get()?.execute(
+            COMMAND,
+            params![
+                chrono::Utc::now().timestamp(),
+                transferred_bytes as i64,
+                end_reason,
+                id
+            ],
+        )?;
 
         Ok(())
     }
 
-    pub fn query(&self, time_range: Option<(i64, i64)>) -> CoreResult<Vec<Record>> {
-        const COMMAND: &str =
-            r"SELECT * FROM history WHERE timestamp BETWEEN ? AND ? ORDER BY timestamp DESC";
+    pub fn query(&self, filter: &HistoryFilter) -> CoreResult<Vec<Record>> {
+        const COMMAND: &str = r"
+        SELECT * FROM history
+        WHERE timestamp BETWEEN :start AND :end
+        AND (:direction IS NULL OR direction = :direction)
+        AND (:session_type IS NULL OR session_type = :session_type)
+        AND (:domain IS NULL OR domain = :domain)
+        AND (:device_id IS NULL OR device_id = :device_id)
+        ORDER BY timestamp DESC";
 
-        let (start, end) = time_range.unwrap_or_else(|| (0, chrono::Utc::now().timestamp()));
+        let (start, end) = filter
+            .time_range
+            .unwrap_or_else(|| (0, chrono::Utc::now().timestamp()));
+        let direction: Option<&str> = filter.direction.map(Into::into);
+        let session_type: Option<&str> = filter.session_type.map(Into::into);
 
         let conn = self.pool.get()?;
 
         let mut stmt = conn.prepare(COMMAND)?;
-        let rows = stmt.query_and_then([start, end], parse_record)?;
+        let rows = stmt.query_and_then(
+            named_params! {
+                ":start": start,
+                ":end": end,
+                ":direction": direction,
+                ":session_type": session_type,
+                ":domain": filter.domain,
+                ":device_id": filter.device_id,
+            },
+            parse_record,
+        )?;
 
         let mut records = Vec::new();
         for row in rows {
@@ -74,6 +309,22 @@ impl HistoryRepository {
         Ok(records)
     }
 
+    pub fn delete_expired(&self, retention_days: u32) -> CoreResult<()> {
+        const COMMAND: &str = r"DELETE FROM history WHERE timestamp < ?";
+
+        // zero retention days keeps records forever
+        if retention_days == 0 {
+            return Ok(());
+        }
+
+        let expire_timestamp =
+            chrono::Utc::now().timestamp() - i64::from(retention_days) * 24 * 60 * 60;
+
+        let _ = self.pool.get()?.execute(COMMAND, [expire_timestamp])?;
+
+        Ok(())
+    }
+
     pub fn delete_domain_related(&self, domain: &str) -> CoreResult<()> {
         const COMMAND: &str = r"DELETE FROM history WHERE domain = ?";
 
@@ -84,10 +335,261 @@ impl HistoryRepository {
 }
 
 fn parse_record(row: &Row) -> CoreResult<Record> {
+    let direction =
+        Direction::from_str(&row.get::<_, String>(5)?).map_err(|err| core_error!("{}", err))?;
+
+    let session_type =
+        SessionType::from_str(&row.get::<_, String>(6)?).map_err(|err| core_error!("{}", err))?;
+
+    let end_reason = match row.get::<_, Option<String>>(8)? {
+        Some(end_reason) => {
+            Some(EndReason::from_str(&end_reason).map_err(|err| core_error!("{}", err))?)
+        }
+        None => None,
+    };
+
     Ok(Record {
         id: row.get(0)?,
         device_id: row.get(1)?,
         domain: row.get(2)?,
         timestamp: row.get(3)?,
+        end_timestamp: row.get(4)?,
+        direction,
+        session_type,
+        transferred_bytes: row.get(7)?,
+        end_reason,
+        peer_os: row.get(9)?,
+        peer_addr: row.get(10)?,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::config::TestStorage;
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    fn create(
+        repository: &HistoryRepository,
+        timestamp: i64,
+        direction: Direction,
+        session_type: SessionType,
+        domain: &str,
+        device_id: i64,
+    ) -> Record {
+        repository
+            .create(Record {
+                timestamp,
+                ..Record::new(direction, session_type, domain.to_string(), device_id)
+            })
+            .unwrap()
+    }
+
+    fn ids(repository: &HistoryRepository, filter: &HistoryFilter) -> Vec<i64> {
+        repository
+            .query(filter)
+            .unwrap()
+            .iter()
+            .map(|record| record.id)
+            .collect()
+    }
+
+    #[test]
+    fn test_query_filters() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.history();
+        let now = chrono::Utc::now().timestamp();
+
+        let a = create(
+            repository,
+            now - 3 * DAY,
+            Direction::Outgoing,
+            SessionType::Desktop,
+            "mirrorx.cloud",
+            1,
+        );
+        let b = create(
+            repository,
+            now - 2 * DAY,
+            Direction::Incoming,
+            SessionType::Desktop,
+            "mirrorx.cloud",
+            2,
+        );
+        let c = create(
+            repository,
+            now - DAY,
+            Direction::Outgoing,
+            SessionType::FileManager,
+            "example.com",
+            1,
+        );
+        let d = create(
+            repository,
+            now - 10,
+            Direction::Outgoing,
+            SessionType::LAN,
+            "lan",
+            0,
+        );
+
+        // newest first
+        assert_eq!(
+            ids(repository, &HistoryFilter::default()),
+            [d.id, c.id, b.id, a.id]
+        );
+
+        // both ends of the time range are included
+        let filter = HistoryFilter {
+            time_range: Some((now - 3 * DAY, now - DAY)),
+            ..Default::default()
+        };
+        assert_eq!(ids(repository, &filter), [c.id, b.id, a.id]);
+
+        let filter = HistoryFilter {
+            time_range: Some((now - 3 * DAY + 1, now - DAY - 1)),
+            ..Default::default()
+        };
+        assert_eq!(ids(repository, &filter), [b.id]);
+
+        let filter = HistoryFilter {
+            direction: Some(Direction::Incoming),
+            ..Default::default()
+        };
+        assert_eq!(ids(repository, &filter), [b.id]);
+
+        let filter = HistoryFilter {
+            session_type: Some(SessionType::Desktop),
+            ..Default::default()
+        };
+        assert_eq!(ids(repository, &filter), [b.id, a.id]);
+
+        let filter = HistoryFilter {
+            domain: Some(String::from("mirrorx.cloud")),
+            ..Default::default()
+        };
+        assert_eq!(ids(repository, &filter), [b.id, a.id]);
+
+        let filter = HistoryFilter {
+            device_id: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(ids(repository, &filter), [c.id, a.id]);
+
+        // filters are combined
+        let filter = HistoryFilter {
+            direction: Some(Direction::Outgoing),
+            domain: Some(String::from("mirrorx.cloud")),
+            device_id: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(ids(repository, &filter), [a.id]);
+
+        let filter = HistoryFilter {
+            session_type: Some(SessionType::LAN),
+            direction: Some(Direction::Incoming),
+            ..Default::default()
+        };
+        assert!(ids(repository, &filter).is_empty());
+    }
+
+    #[test]
+    fn test_finish_record() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.history();
+
+        let record = repository
+            .create(Record {
+                peer_os: Some(String::from("Linux")),
+                peer_addr: Some(String::from("192.168.1.2:48001")),
+                timestamp: chrono::Utc::now().timestamp() - 60,
+                ..Record::new(
+                    Direction::Incoming,
+                    SessionType::Desktop,
+                    String::from("mirrorx.cloud"),
+                    1,
+                )
+            })
+            .unwrap();
+
+        let read = repository.query(&HistoryFilter::default()).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].end_timestamp, None);
+        assert_eq!(read[0].end_reason, None);
+
+        repository
+            .finish(record.id, 1 << 40, EndReason::RemoteClosed)
+            .unwrap();
+
+        let read = repository.query(&HistoryFilter::default()).unwrap();
+        assert!(read[0].end_timestamp.unwrap() >= record.timestamp);
+        assert_eq!(read[0].transferred_bytes, 1 << 40);
+        assert_eq!(read[0].end_reason, Some(EndReason::RemoteClosed));
+        assert_eq!(read[0].peer_os.as_deref(), Some("Linux"));
+        assert_eq!(read[0].peer_addr.as_deref(), Some("192.168.1.2:48001"));
+    }
+
+    #[test]
+    fn test_retention_pruning() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.history();
+        let now = chrono::Utc::now().timestamp();
+
+        let old = create(
+            repository,
+            now - 400 * DAY,
+            Direction::Outgoing,
+            SessionType::Desktop,
+            "mirrorx.cloud",
+            1,
+        );
+        let expired = create(
+            repository,
+            now - 31 * DAY,
+            Direction::Outgoing,
+            SessionType::Desktop,
+            "mirrorx.cloud",
+            1,
+        );
+        let kept = create(
+            repository,
+            now - 29 * DAY,
+            Direction::Incoming,
+            SessionType::Desktop,
+            "mirrorx.cloud",
+            2,
+        );
+        let recent = create(
+            repository,
+            now - 60,
+            Direction::Outgoing,
+            SessionType::LAN,
+            "lan",
+            0,
+        );
+
+        // zero retention keeps records forever
+        repository.delete_expired(0).unwrap();
+        assert_eq!(ids(repository, &HistoryFilter::default()).len(), 4);
+
+        repository.delete_expired(365).unwrap();
+        assert_eq!(
+            ids(repository, &HistoryFilter::default()),
+            [recent.id, kept.id, expired.id]
+        );
+        assert!(!ids(repository, &HistoryFilter::default()).contains(&old.id));
+
+        repository.delete_expired(30).unwrap();
+        assert_eq!(
+            ids(repository, &HistoryFilter::default()),
+            [recent.id, kept.id]
+        );
+
+        repository.delete_expired(1).unwrap();
+        assert_eq!(ids(repository, &HistoryFilter::default()), [recent.id]);
+
+        repository.delete_domain_related("lan").unwrap();
+        assert!(ids(repository, &HistoryFilter::default()).is_empty());
+    }
+}
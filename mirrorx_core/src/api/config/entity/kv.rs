@@ -1,11 +1,22 @@
-use crate::{core_error, error::CoreResult};
-use r2d2::Pool;
-use r2d2_sqlite::SqliteConnectionManager;
+use crate::{
+    api::{
+        config::ConnectionSource,
+        endpoint::message::{ChromaFormat, VideoCodec},
+    },
+    core_error,
+    error::CoreResult,
+};
 use rusqlite::OptionalExtension;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::str::FromStr;
 
-#[derive(Debug, Serialize, Deserialize)]
+const SETTINGS_KEY: &str = "settings";
+
+// the encoder rate control buffer holds two seconds at the cap, which must fit in i32
+pub const MIN_BITRATE_KBPS: u32 = 100;
+pub const MAX_BITRATE_KBPS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Theme {
     Light,
@@ -36,12 +47,129 @@ impl FromStr for Theme {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoQuality {
+    Low,
+    Balanced,
+    High,
+}
+
+impl VideoQuality {
+    // the encoder targets a part of the bitrate cap, a higher quality spends more of it
+    pub fn target_bitrate(&self, max_bitrate: i64) -> i64 {
+        match self {
+            VideoQuality::Low => max_bitrate / 4,
+            VideoQuality::Balanced => max_bitrate / 2,
+            VideoQuality::High => max_bitrate,
+        }
+    }
+}
+
+// how long decoded frames are held back to absorb network jitter, low latency only
+// evens out small bursts while smooth covers most of the observed jitter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JitterBufferMode {
+    LowLatency,
+    Smooth,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl<'a> From<LogLevel> for &'a str {
+    fn from(val: LogLevel) -> Self {
+        match val {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        }
+    }
+}
+
+// missing fields fallback to their defaults, so settings saved by earlier versions
+// are still readable after new fields are added
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub video_quality: VideoQuality,
+    pub frame_rate: u8,
+    pub max_bitrate_kbps: u32,
+    pub audio_enabled: bool,
+    // kept for the clipboard channel, no endpoint message carries the clipboard yet so
+    // nothing reads it so far
+    #[serde(default = "default_clipboard_sync")]
+    pub clipboard_sync: bool,
+    pub lan_discoverable: bool,
+    pub log_level: LogLevel,
+    // preference order of video codecs, the first one both sides support is used
+    pub video_codecs: Vec<VideoCodec>,
+    // refresh the picture periodically so that a lost frame heals without a key frame request
+    pub intra_refresh: bool,
+    // requested chroma format as visitor, yuv444 falls back to yuv420 if the remote
+    // encoder can't encode it
+    pub chroma_format: ChromaFormat,
+    pub jitter_buffer: JitterBufferMode,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            video_quality: VideoQuality::High,
+            frame_rate: 60,
+            max_bitrate_kbps: 4000,
+            audio_enabled: true,
+            clipboard_sync: default_clipboard_sync(),
+            lan_discoverable: true,
+            log_level: LogLevel::Info,
+            video_codecs: vec![
+                VideoCodec::H264,
+                VideoCodec::Hevc,
+                VideoCodec::VP9,
+                VideoCodec::VP8,
+                VideoCodec::AV1,
+            ],
+            intra_refresh: false,
+            chroma_format: ChromaFormat::YUV420,
+            jitter_buffer: JitterBufferMode::LowLatency,
+        }
+    }
+}
+
+fn default_clipboard_sync() -> bool {
+    true
+}
+
+impl Settings {
+    pub fn max_bitrate(&self) -> i64 {
+        // settings saved by earlier versions weren't bounded
+        let max_bitrate_kbps = self
+            .max_bitrate_kbps
+            .clamp(MIN_BITRATE_KBPS, MAX_BITRATE_KBPS);
+        i64::from(max_bitrate_kbps) * 1000
+    }
+
+    pub fn target_bitrate(&self) -> i64 {
+        self.video_quality.target_bitrate(self.max_bitrate())
+    }
+}
+
 pub struct KVRepository {
-    pool: Pool<SqliteConnectionManager>,
+    pool: ConnectionSource,
 }
 
 impl KVRepository {
-    pub fn new(pool: Pool<SqliteConnectionManager>) -> Self {
+    pub fn new(pool: ConnectionSource) -> Self {
         Self { pool }
     }
 
@@ -82,6 +210,66 @@ impl KVRepository {
         }
     }
 
+    pub fn set_history_retention_days(&self, value: u32) -> CoreResult<()> {
+        self.set_typed("history_retention_days", &value)
+    }
+
+    pub fn get_history_retention_days(&self) -> CoreResult<Option<u32>> {
+        self.get_typed("history_retention_days")
+    }
+
+    pub fn set_settings(&self, settings: &Settings) -> CoreResult<()> {
+        if settings.frame_rate == 0 || settings.frame_rate > 120 {
+            return Err(core_error!("frame rate should between 1 and 120"));
+        }
+
+        if !(MIN_BITRATE_KBPS..=MAX_BITRATE_KBPS).contains(&settings.max_bitrate_kbps) {
+            return Err(core_error!(
+                "bitrate cap should between {} and {} kbps",
+                MIN_BITRATE_KBPS,
+                MAX_BITRATE_KBPS
+            ));
+        }
+
+        if settings.video_codecs.is_empty() {
+            return Err(core_error!("settings should contain at least one codec"));
+        }
+
+        self.set_typed(SETTINGS_KEY, settings)
+    }
+
+    pub fn get_settings(&self) -> CoreResult<Settings> {
+        self.get_typed_or_default(SETTINGS_KEY)
+    }
+
+    pub fn get_saved_settings(&self) -> CoreResult<Option<Settings>> {
+        self.get_typed(SETTINGS_KEY)
+    }
+
+    pub fn set_typed<T>(&self, key: &str, value: &T) -> CoreResult<()>
+    where
+        T: Serialize,
+    {
+        self.set(key, &serde_json::to_string(value)?)
+    }
+
+    pub fn get_typed<T>(&self, key: &str) -> CoreResult<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        match self.get(key)? {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_typed_or_default<T>(&self, key: &str) -> CoreResult<T>
+    where
+        T: DeserializeOwned + Default,
+    {
+        Ok(self.get_typed(key)?.unwrap_or_default())
+    }
+
     fn set(&self, key: &str, value: &str) -> CoreResult<()> {
         const COMMAND: &str =
             r"INSERT INTO kv(key, value) VALUES(?, ?) ON CONFLICT DO UPDATE SET value = ?";
@@ -103,3 +291,173 @@ impl KVRepository {
         Ok(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::config::TestStorage;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_typed_round_trip() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.kv();
+
+        assert_eq!(repository.get_typed::<u32>("missing").unwrap(), None);
+        assert_eq!(
+            repository.get_typed_or_default::<u32>("missing").unwrap(),
+            0
+        );
+        assert!(repository
+            .get_typed_or_default::<Vec<String>>("missing")
+            .unwrap()
+            .is_empty());
+
+        repository.set_typed("number", &42u32).unwrap();
+        assert_eq!(repository.get_typed::<u32>("number").unwrap(), Some(42));
+
+        // a value is replaced, not appended
+        repository.set_typed("number", &7u32).unwrap();
+        assert_eq!(repository.get_typed_or_default::<u32>("number").unwrap(), 7);
+
+        let map = BTreeMap::from([(String::from("a"), vec![1, 2]), (String::from("b"), vec![])]);
+        repository.set_typed("map", &map).unwrap();
+        assert_eq!(
+            repository
+                .get_typed::<BTreeMap<String, Vec<i32>>>("map")
+                .unwrap(),
+            Some(map)
+        );
+
+        // a value of another type is an error rather than a default
+        assert!(repository.get_typed::<u32>("map").is_err());
+
+        repository.set_history_retention_days(30).unwrap();
+        assert_eq!(repository.get_history_retention_days().unwrap(), Some(30));
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.kv();
+
+        assert!(repository.get_saved_settings().unwrap().is_none());
+        let settings = repository.get_settings().unwrap();
+        assert_eq!(settings.frame_rate, Settings::default().frame_rate);
+        assert!(settings.clipboard_sync);
+
+        let settings = Settings {
+            video_quality: VideoQuality::Low,
+            frame_rate: 30,
+            max_bitrate_kbps: 8000,
+            audio_enabled: false,
+            clipboard_sync: false,
+            lan_discoverable: false,
+            log_level: LogLevel::Debug,
+            video_codecs: vec![VideoCodec::VP9, VideoCodec::H264],
+            intra_refresh: true,
+            chroma_format: ChromaFormat::YUV444,
+            jitter_buffer: JitterBufferMode::Smooth,
+        };
+        repository.set_settings(&settings).unwrap();
+
+        let saved = repository.get_saved_settings().unwrap().unwrap();
+        assert_eq!(saved.video_quality, VideoQuality::Low);
+        assert_eq!(saved.frame_rate, 30);
+        assert_eq!(saved.max_bitrate_kbps, 8000);
+        assert!(!saved.audio_enabled);
+        assert!(!saved.clipboard_sync);
+        assert!(!saved.lan_discoverable);
+        assert_eq!(saved.log_level, LogLevel::Debug);
+        assert_eq!(saved.video_codecs, [VideoCodec::VP9, VideoCodec::H264]);
+        assert!(saved.intra_refresh);
+        assert_eq!(saved.chroma_format, ChromaFormat::YUV444);
+        assert_eq!(saved.jitter_buffer, JitterBufferMode::Smooth);
+    }
+
+    #[test]
+    fn test_settings_of_earlier_version() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.kv();
+
+        // saved before clipboard sync, codecs, chroma format and jitter buffer were added
+        repository
+            .set(
+                SETTINGS_KEY,
+                r#"{"video_quality":"balanced","frame_rate":30,"max_bitrate_kbps":500000,"audio_enabled":false,"lan_discoverable":true,"log_level":"warn"}"#,
+            )
+            .unwrap();
+
+        let settings = repository.get_settings().unwrap();
+        let default_settings = Settings::default();
+
+        assert_eq!(settings.video_quality, VideoQuality::Balanced);
+        assert_eq!(settings.frame_rate, 30);
+        assert!(!settings.audio_enabled);
+        assert_eq!(settings.log_level, LogLevel::Warn);
+        assert!(settings.clipboard_sync);
+        assert_eq!(settings.video_codecs, default_settings.video_codecs);
+        assert_eq!(settings.chroma_format, default_settings.chroma_format);
+        assert_eq!(settings.jitter_buffer, default_settings.jitter_buffer);
+
+        // the unbounded cap of earlier versions is clamped when used
+        assert_eq!(settings.max_bitrate(), i64::from(MAX_BITRATE_KBPS) * 1000);
+        assert_eq!(settings.target_bitrate(), settings.max_bitrate() / 2);
+    }
+
+    #[test]
+    fn test_set_settings_range_checks() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.kv();
+
+        let invalid_settings = [
+            Settings {
+                frame_rate: 0,
+                ..Settings::default()
+            },
+            Settings {
+                frame_rate: 121,
+                ..Settings::default()
+            },
+            Settings {
+                max_bitrate_kbps: MIN_BITRATE_KBPS - 1,
+                ..Settings::default()
+            },
+            Settings {
+                max_bitrate_kbps: MAX_BITRATE_KBPS + 1,
+                ..Settings::default()
+            },
+            Settings {
+                video_codecs: vec![],
+                ..Settings::default()
+            },
+        ];
+
+        for settings in invalid_settings.iter() {
+            assert!(repository.set_settings(settings).is_err(), "{settings:?}");
+        }
+
+        assert!(repository.get_saved_settings().unwrap().is_none());
+
+        let bound_settings = [
+            Settings {
+                frame_rate: 1,
+                max_bitrate_kbps: MIN_BITRATE_KBPS,
+                ..Settings::default()
+            },
+            Settings {
+                frame_rate: 120,
+                max_bitrate_kbps: MAX_BITRATE_KBPS,
+                ..Settings::default()
+            },
+        ];
+
+        for settings in bound_settings.iter() {
+            repository.set_settings(settings).unwrap();
+            assert_eq!(
+                repository.get_settings().unwrap().frame_rate,
+                settings.frame_rate
+            );
+        }
+    }
+}
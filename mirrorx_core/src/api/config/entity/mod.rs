@@ -1,3 +1,5 @@
+pub mod address_book;
 pub mod domain;
 pub mod history;
 pub mod kv;
+pub mod profile;
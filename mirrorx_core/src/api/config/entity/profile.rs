@@ -0,0 +1,326 @@
+use super::kv::{Settings, MAX_BITRATE_KBPS, MIN_BITRATE_KBPS};
+use crate::{
+    api::{config::ConnectionSource, endpoint::message::VideoCodec},
+    core_error,
+    error::CoreResult,
+};
+use rusqlite::{params, Row};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionProfile {
+    pub id: i64,
+    pub domain: String,
+    pub device_id: i64,
+    // codecs in order of preference
+    pub video_codecs: Vec<VideoCodec>,
+    pub frame_rate: u8,
+    pub max_bitrate_kbps: u32,
+    pub audio_enabled: bool,
+    pub monitor_id: Option<String>,
+    pub updated_at: i64,
+}
+
+impl ConnectionProfile {
+    // devices without a saved profile are visited with the global settings
+    pub fn from_settings(domain: String, device_id: i64, settings: &Settings) -> Self {
+        Self {
+            id: 0,
+            domain,
+            device_id,
+            video_codecs: settings.video_codecs.clone(),
+            frame_rate: settings.frame_rate,
+            max_bitrate_kbps: settings.max_bitrate_kbps,
+            audio_enabled: settings.audio_enabled,
+            monitor_id: None,
+            updated_at: 0,
+        }
+    }
+}
+
+pub struct ProfileRepository {
+    pool: ConnectionSource,
+}
+
+impl ProfileRepository {
+    pub fn new(pool: ConnectionSource) -> Self {
+        Self { pool }
+    }
+
+    pub fn ensure_table(&self) -> CoreResult<()> {
+        let conn = self.pool.get()?;
+
+        const COMMAND: &str = r"
+        CREATE TABLE IF NOT EXISTS profile(
+            id INTEGER PRIMARY KEY,
+            domain TEXT NOT NULL,
+            device_id INTEGER NOT NULL,
+            video_codecs TEXT NOT NULL,
+            frame_rate INTEGER NOT NULL,
+            max_bitrate_kbps INTEGER NOT NULL,
+            audio_enabled BOOLEAN NOT NULL,
+            monitor_id TEXT,
+            updated_at INTEGER NOT NULL,
+            UNIQUE(domain, device_id)
+        )";
+
+        conn.execute(COMMAND, [])?;
+
+        Ok(())
+    }
+
+    pub fn save_profile(&self, mut profile: ConnectionProfile) -> CoreResult<ConnectionProfile> {
+        const COMMAND: &str = r#"
+        INSERT INTO profile(
+            domain,
+            device_id,
+            video_codecs,
+            frame_rate,
+            max_bitrate_kbps,
+            audio_enabled,
+            monitor_id,
+            updated_at
+        )
+        VALUES(?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(domain, device_id) DO UPDATE SET
+            video_codecs = excluded.video_codecs,
+            frame_rate = excluded.frame_rate,
+            max_bitrate_kbps = excluded.max_bitrate_kbps,
+            audio_enabled = excluded.audio_enabled,
+            monitor_id = excluded.monitor_id,
+            updated_at = excluded.updated_at
+        RETURNING id"#;
+
+        if profile.video_codecs.is_empty() {
+            return Err(core_error!("profile should contain at least one codec"));
+        }
+
+        if profile.frame_rate == 0 || profile.frame_rate > 120 {
+            return Err(core_error!("frame rate should between 1 and 120"));
+        }
+
+        if !(MIN_BITRATE_KBPS..=MAX_BITRATE_KBPS).contains(&profile.max_bitrate_kbps) {
+            return Err(core_error!(
+                "bitrate cap should between {} and {} kbps",
+                MIN_BITRATE_KBPS,
+                MAX_BITRATE_KBPS
+            ));
+        }
+
+        profile.updated_at = chrono::Utc::now().timestamp();
+
+        profile.id = self.pool.get()?.query_row(
+            COMMAND,
+            params![
+                profile.domain,
+                profile.device_id,
+                serde_json::to_string(&profile.video_codecs)?,
+                profile.frame_rate,
+                profile.max_bitrate_kbps,
+                profile.audio_enabled,
+                profile.monitor_id,
+                profile.updated_at,
+            ],
+            |row| row.get(0),
+        )?;
+
+        Ok(profile)
+    }
+
+    pub fn get_profile(
+        &self,
+        domain: &str,
+        device_id: i64,
+    ) -> CoreResult<Option<ConnectionProfile>> {
+        const COMMAND: &str = r"SELECT * FROM profile WHERE domain = ? AND device_id = ? LIMIT 1";
+
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare(COMMAND)?;
+        let profile = stmt
+            .query_and_then(params![domain, device_id], parse_profile)?
+            .next()
+            .transpose()?;
+
+        Ok(profile)
+    }
+
+    pub fn get_profiles(&self) -> CoreResult<Vec<ConnectionProfile>> {
+        const COMMAND: &str = r"SELECT * FROM profile ORDER BY updated_at DESC";
+
+        let conn = self.pool.get()?;
+        let mut stmt = conn.prepare(COMMAND)?;
+        let rows = stmt.query_and_then([], parse_profile)?;
+
+        let mut profiles = Vec::new();
+        for row in rows {
+            profiles.push(row?);
+        }
+
+        Ok(profiles)
+    }
+
+    pub fn delete_profile(&self, id: i64) -> CoreResult<()> {
+        const COMMAND: &str = r"DELETE FROM profile WHERE id = ?";
+
+        self.pool.get()?.execute(COMMAND, [id])?;
+
+        Ok(())
+    }
+
+    pub fn delete_domain_related(&self, domain: &str) -> CoreResult<()> {
+        const COMMAND: &str = r"DELETE FROM profile WHERE domain = ?";
+
+        self.pool.get()?.execute(COMMAND, [domain])?;
+
+        Ok(())
+    }
+}
+
+fn parse_profile(row: &Row) -> CoreResult<ConnectionProfile> {
+    Ok(ConnectionProfile {
+        id: row.get(0)?,
+        domain: row.get(1)?,
+        device_id: row.get(2)?,
+        video_codecs: serde_json::from_str(&row.get::<_, String>(3)?)?,
+        frame_rate: row.get(4)?,
+        max_bitrate_kbps: row.get(5)?,
+        audio_enabled: row.get(6)?,
+        monitor_id: row.get(7)?,
+        updated_at: row.get(8)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::config::TestStorage;
+
+    fn new_profile(domain: &str, device_id: i64) -> ConnectionProfile {
+        ConnectionProfile::from_settings(domain.to_string(), device_id, &Settings::default())
+    }
+
+    #[test]
+    fn test_save_profile_upsert() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.profile();
+
+        assert!(repository
+            .get_profile("mirrorx.cloud", 1)
+            .unwrap()
+            .is_none());
+
+        let saved = repository
+            .save_profile(new_profile("mirrorx.cloud", 1))
+            .unwrap();
+        assert!(saved.id > 0);
+        assert!(saved.updated_at > 0);
+
+        // the same device of another domain is a distinct profile
+        let other_domain = repository
+            .save_profile(new_profile("lan.example", 1))
+            .unwrap();
+        assert_ne!(other_domain.id, saved.id);
+
+        // saving the same domain and device again updates the row in place
+        let updated = repository
+            .save_profile(ConnectionProfile {
+                id: 0,
+                video_codecs: vec![VideoCodec::AV1, VideoCodec::H264],
+                frame_rate: 30,
+                max_bitrate_kbps: 8000,
+                audio_enabled: false,
+                monitor_id: Some(String::from("DP-1")),
+                ..new_profile("mirrorx.cloud", 1)
+            })
+            .unwrap();
+        assert_eq!(updated.id, saved.id);
+
+        let loaded = repository.get_profile("mirrorx.cloud", 1).unwrap().unwrap();
+        assert_eq!(loaded.id, saved.id);
+        assert_eq!(loaded.video_codecs, [VideoCodec::AV1, VideoCodec::H264]);
+        assert_eq!(loaded.frame_rate, 30);
+        assert_eq!(loaded.max_bitrate_kbps, 8000);
+        assert!(!loaded.audio_enabled);
+        assert_eq!(loaded.monitor_id.as_deref(), Some("DP-1"));
+
+        assert_eq!(repository.get_profiles().unwrap().len(), 2);
+
+        repository.delete_profile(saved.id).unwrap();
+        assert!(repository
+            .get_profile("mirrorx.cloud", 1)
+            .unwrap()
+            .is_none());
+        assert!(repository.get_profile("lan.example", 1).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_save_profile_validation() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.profile();
+
+        let invalid_profiles = [
+            ConnectionProfile {
+                video_codecs: Vec::new(),
+                ..new_profile("mirrorx.cloud", 1)
+            },
+            ConnectionProfile {
+                frame_rate: 0,
+                ..new_profile("mirrorx.cloud", 1)
+            },
+            ConnectionProfile {
+                frame_rate: 121,
+                ..new_profile("mirrorx.cloud", 1)
+            },
+            ConnectionProfile {
+                max_bitrate_kbps: MIN_BITRATE_KBPS - 1,
+                ..new_profile("mirrorx.cloud", 1)
+            },
+            ConnectionProfile {
+                max_bitrate_kbps: MAX_BITRATE_KBPS + 1,
+                ..new_profile("mirrorx.cloud", 1)
+            },
+        ];
+
+        for profile in invalid_profiles {
+            assert!(repository.save_profile(profile).is_err());
+        }
+
+        assert!(repository.get_profiles().unwrap().is_empty());
+
+        // the bounds themselves are accepted
+        for (frame_rate, max_bitrate_kbps) in [(1, MIN_BITRATE_KBPS), (120, MAX_BITRATE_KBPS)] {
+            repository
+                .save_profile(ConnectionProfile {
+                    frame_rate,
+                    max_bitrate_kbps,
+                    ..new_profile("mirrorx.cloud", 1)
+                })
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_delete_domain_related() {
+        let test_storage = TestStorage::new();
+        let repository = test_storage.storage.profile();
+
+        for (domain, device_id) in [
+            ("mirrorx.cloud", 1),
+            ("mirrorx.cloud", 2),
+            ("lan.example", 1),
+        ] {
+            repository
+                .save_profile(new_profile(domain, device_id))
+                .unwrap();
+        }
+
+        repository.delete_domain_related("mirrorx.cloud").unwrap();
+
+        let profiles = repository.get_profiles().unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert_eq!(
+            (profiles[0].domain.as_str(), profiles[0].device_id),
+            ("lan.example", 1)
+        );
+    }
+}
@@ -1,39 +1,139 @@
+pub mod backup;
 pub mod entity;
+pub mod secret;
 
-use self::entity::{domain::DomainRepository, history::HistoryRepository, kv::KVRepository};
+use self::{
+    entity::{
+        address_book::AddressBookRepository,
+        domain::DomainRepository,
+        history::{HistoryRepository, DEFAULT_RETENTION_DAYS},
+        kv::KVRepository,
+        profile::ProfileRepository,
+    },
+    secret::SecretKey,
+};
 use crate::error::CoreResult;
+use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use r2d2::{Pool, PooledConnection};
 use r2d2_sqlite::SqliteConnectionManager;
-use std::{path::Path, sync::Arc};
+use rusqlite::{Connection, Transaction, TransactionBehavior};
+use std::{ops::Deref, path::Path, sync::Arc};
+
+// repositories take a connection from the shared pool for every statement, or all use
+// the connection of an open transaction. the transaction keeps its connection locked
+// while the repositories lock it again from the same thread
+#[derive(Clone)]
+pub enum ConnectionSource {
+    Pool(Pool<SqliteConnectionManager>),
+    Transaction(Arc<ReentrantMutex<PooledConnection<SqliteConnectionManager>>>),
+}
+
+pub enum SourceConnection<'a> {
+    Pooled(PooledConnection<SqliteConnectionManager>),
+    Transaction(ReentrantMutexGuard<'a, PooledConnection<SqliteConnectionManager>>),
+}
+
+impl ConnectionSource {
+    pub fn get(&self) -> CoreResult<SourceConnection<'_>> {
+        match self {
+            ConnectionSource::Pool(pool) => Ok(SourceConnection::Pooled(pool.get()?)),
+            ConnectionSource::Transaction(conn) => Ok(SourceConnection::Transaction(conn.lock())),
+        }
+    }
+}
+
+impl Deref for SourceConnection<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            SourceConnection::Pooled(conn) => conn,
+            SourceConnection::Transaction(conn) => conn,
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct LocalStorage {
+    pool: Pool<SqliteConnectionManager>,
+    secret_key: Arc<SecretKey>,
     domain: Arc<DomainRepository>,
     kv: Arc<KVRepository>,
     history: Arc<HistoryRepository>,
+    address_book: Arc<AddressBookRepository>,
+    profile: Arc<ProfileRepository>,
 }
 
 impl LocalStorage {
-    pub fn new<P>(db_path: P) -> CoreResult<LocalStorage>
+    // the secret key must be kept out of the database directory, so that a copy or a
+    // synced folder of the database alone doesn't leak saved passwords
+    pub fn new<P, K>(db_path: P, secret_key_path: K) -> CoreResult<LocalStorage>
     where
         P: AsRef<Path>,
+        K: AsRef<Path>,
     {
-        let manager = SqliteConnectionManager::file(db_path);
+        let secret_key = Arc::new(SecretKey::load_or_create(
+            secret_key_path,
+            db_path.as_ref().with_extension("key"),
+        )?);
+
+        let manager = SqliteConnectionManager::file(db_path.as_ref());
         let pool = r2d2::Pool::new(manager)?;
 
-        let domain_repository = DomainRepository::new(pool.clone());
-        domain_repository.ensure_table()?;
+        let storage = Self::with_source(pool.clone(), secret_key, ConnectionSource::Pool(pool));
+        storage.domain.ensure_table()?;
+        storage.kv.ensure_table()?;
+        storage.history.ensure_table()?;
+        storage.history.delete_expired(
+            storage
+                .kv
+                .get_history_retention_days()?
+                .unwrap_or(DEFAULT_RETENTION_DAYS),
+        )?;
+        storage.address_book.ensure_table()?;
+        storage.profile.ensure_table()?;
 
-        let kv_repository = KVRepository::new(pool.clone());
-        kv_repository.ensure_table()?;
+        Ok(storage)
+    }
 
-        let history_repository = HistoryRepository::new(pool);
-        history_repository.ensure_table()?;
+    fn with_source(
+        pool: Pool<SqliteConnectionManager>,
+        secret_key: Arc<SecretKey>,
+        source: ConnectionSource,
+    ) -> Self {
+        Self {
+            pool,
+            secret_key: secret_key.clone(),
+            domain: Arc::new(DomainRepository::new(source.clone())),
+            kv: Arc::new(KVRepository::new(source.clone())),
+            history: Arc::new(HistoryRepository::new(source.clone())),
+            address_book: Arc::new(AddressBookRepository::new(source.clone(), secret_key)),
+            profile: Arc::new(ProfileRepository::new(source)),
+        }
+    }
+
+    // the repositories handed to the closure share one connection of the pool, so all of
+    // their changes are committed together or rolled back if the closure fails
+    pub fn transaction<T, F>(&self, f: F) -> CoreResult<T>
+    where
+        F: FnOnce(&LocalStorage) -> CoreResult<T>,
+    {
+        let conn = Arc::new(ReentrantMutex::new(self.pool.get()?));
 
-        Ok(Self {
-            domain: Arc::new(domain_repository),
-            kv: Arc::new(kv_repository),
-            history: Arc::new(history_repository),
-        })
+        let storage = Self::with_source(
+            self.pool.clone(),
+            self.secret_key.clone(),
+            ConnectionSource::Transaction(conn.clone()),
+        );
+
+        let conn = conn.lock();
+
+        // the transaction is rolled back when it's dropped without commit
+        let tx = Transaction::new_unchecked(&conn, TransactionBehavior::Immediate)?;
+        let value = f(&storage)?;
+        tx.commit()?;
+
+        Ok(value)
     }
 
     pub fn domain(&self) -> &DomainRepository {
@@ -47,4 +147,38 @@ impl LocalStorage {
     pub fn history(&self) -> &HistoryRepository {
         &self.history
     }
+
+    pub fn address_book(&self) -> &AddressBookRepository {
+        &self.address_book
+    }
+
+    pub fn profile(&self) -> &ProfileRepository {
+        &self.profile
+    }
+}
+
+// a storage in a directory of its own, removed when dropped
+#[cfg(test)]
+pub(crate) struct TestStorage {
+    dir: std::path::PathBuf,
+    pub storage: LocalStorage,
+}
+
+#[cfg(test)]
+impl TestStorage {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!("mirrorx_storage_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let storage = LocalStorage::new(dir.join("mirrorx.db"), dir.join("secret.key")).unwrap();
+
+        TestStorage { dir, storage }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestStorage {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
 }
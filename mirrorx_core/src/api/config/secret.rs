@@ -0,0 +1,255 @@
+use crate::{core_error, error::CoreResult};
+use base64::{engine::general_purpose::STANDARD as base64_standard, Engine};
+use hmac::Hmac;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    rand::{SecureRandom, SystemRandom},
+};
+use sha2::Sha256;
+use std::path::Path;
+
+const SECRET_KEY_LENGTH: usize = 32;
+
+#[cfg(target_os = "macos")]
+const KEYCHAIN_SERVICE: &str = "MirrorX";
+
+#[cfg(target_os = "macos")]
+const ERR_SEC_ITEM_NOT_FOUND: i32 = -25300;
+
+// secrets saved in the database are sealed with a key kept out of the database
+// directory and protected by the os where possible, so that a copy of the database
+// directory alone doesn't leak them
+pub struct SecretKey {
+    key: LessSafeKey,
+    rng: SystemRandom,
+}
+
+impl SecretKey {
+    // a key left by older versions next to the database is moved to the key path
+    pub fn load_or_create<P, L>(key_path: P, legacy_key_path: L) -> CoreResult<Self>
+    where
+        P: AsRef<Path>,
+        L: AsRef<Path>,
+    {
+        let rng = SystemRandom::new();
+
+        let key_bytes = match read_key(key_path.as_ref())? {
+            Some(key_bytes) => key_bytes,
+            None => {
+                let key_bytes = match std::fs::read(legacy_key_path.as_ref()) {
+                    Ok(key_bytes) => key_bytes,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                        let mut key_bytes = vec![0u8; SECRET_KEY_LENGTH];
+                        rng.fill(&mut key_bytes)?;
+                        key_bytes
+                    }
+                    Err(err) => return Err(err.into()),
+                };
+
+                if key_bytes.len() != SECRET_KEY_LENGTH {
+                    return Err(core_error!("invalid secret key file"));
+                }
+
+                write_key(key_path.as_ref(), &key_bytes)?;
+
+                if let Err(err) = std::fs::remove_file(legacy_key_path.as_ref()) {
+                    if err.kind() != std::io::ErrorKind::NotFound {
+                        return Err(err.into());
+                    }
+                }
+
+                key_bytes
+            }
+        };
+
+        if key_bytes.len() != SECRET_KEY_LENGTH {
+            return Err(core_error!("invalid secret key file"));
+        }
+
+        let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key_bytes)?);
+
+        Ok(Self { key, rng })
+    }
+
+    pub fn derive(passphrase: &str, salt: &[u8], iterations: u32) -> CoreResult<Self> {
+        let mut key_bytes = [0u8; SECRET_KEY_LENGTH];
+        pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, iterations, &mut key_bytes);
+
+        let key = LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key_bytes)?);
+
+        Ok(Self {
+            key,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn seal(&self, plain_text: &str) -> CoreResult<String> {
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        self.rng.fill(&mut nonce_bytes)?;
+
+        let mut buffer = plain_text.as_bytes().to_vec();
+        self.key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce_bytes),
+            Aad::empty(),
+            &mut buffer,
+        )?;
+
+        let mut sealed = nonce_bytes.to_vec();
+        sealed.append(&mut buffer);
+
+        Ok(base64_standard.encode(sealed))
+    }
+
+    pub fn open(&self, sealed_text: &str) -> CoreResult<String> {
+        let mut sealed = base64_standard.decode(sealed_text)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(core_error!("invalid sealed secret"));
+        }
+
+        let mut buffer = sealed.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&sealed)?;
+        let plain_text = self.key.open_in_place(nonce, Aad::empty(), &mut buffer)?;
+
+        Ok(String::from_utf8(plain_text.to_vec())?)
+    }
+}
+
+// the key is kept in the login keychain, the key path only tells storages apart
+#[cfg(target_os = "macos")]
+fn read_key(key_path: &Path) -> CoreResult<Option<Vec<u8>>> {
+    match security_framework::passwords::get_generic_password(
+        KEYCHAIN_SERVICE,
+        &key_path.to_string_lossy(),
+    ) {
+        Ok(key_bytes) => Ok(Some(key_bytes)),
+        Err(err) if err.code() == ERR_SEC_ITEM_NOT_FOUND => Ok(None),
+        Err(err) => Err(core_error!(
+            "read secret key from keychain failed ({:?})",
+            err
+        )),
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn write_key(key_path: &Path, key_bytes: &[u8]) -> CoreResult<()> {
+    security_framework::passwords::set_generic_password(
+        KEYCHAIN_SERVICE,
+        &key_path.to_string_lossy(),
+        key_bytes,
+    )
+    .map_err(|err| core_error!("save secret key to keychain failed ({:?})", err))
+}
+
+// the key file is protected by dpapi, so it can't be opened out of the user account
+#[cfg(target_os = "windows")]
+fn read_key(key_path: &Path) -> CoreResult<Option<Vec<u8>>> {
+    match std::fs::read(key_path) {
+        Ok(protected_bytes) => Ok(Some(windows_data_protection::unprotect(&protected_bytes)?)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn write_key(key_path: &Path, key_bytes: &[u8]) -> CoreResult<()> {
+    write_key_file(key_path, &windows_data_protection::protect(key_bytes)?)
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn read_key(key_path: &Path) -> CoreResult<Option<Vec<u8>>> {
+    match std::fs::read(key_path) {
+        Ok(key_bytes) => Ok(Some(key_bytes)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+fn write_key(key_path: &Path, key_bytes: &[u8]) -> CoreResult<()> {
+    write_key_file(key_path, key_bytes)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn write_key_file(path: &Path, key_bytes: &[u8]) -> CoreResult<()> {
+    use std::io::Write;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(key_bytes)?;
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+mod windows_data_protection {
+    use crate::{error::CoreResult, HRESULT};
+    use windows::{
+        core::PCWSTR,
+        Win32::{
+            Security::Cryptography::{
+                CryptProtectData, CryptUnprotectData, CRYPTOAPI_BLOB, CRYPTPROTECT_UI_FORBIDDEN,
+            },
+            System::Memory::LocalFree,
+        },
+    };
+
+    pub fn protect(data: &[u8]) -> CoreResult<Vec<u8>> {
+        unsafe {
+            let data_in = CRYPTOAPI_BLOB {
+                cbData: data.len() as u32,
+                pbData: data.as_ptr() as *mut u8,
+            };
+            let mut data_out = CRYPTOAPI_BLOB::default();
+
+            HRESULT!(CryptProtectData(
+                &data_in,
+                PCWSTR::null(),
+                None,
+                None,
+                None,
+                CRYPTPROTECT_UI_FORBIDDEN,
+                &mut data_out,
+            )
+            .ok());
+
+            Ok(take_blob(data_out))
+        }
+    }
+
+    pub fn unprotect(data: &[u8]) -> CoreResult<Vec<u8>> {
+        unsafe {
+            let data_in = CRYPTOAPI_BLOB {
+                cbData: data.len() as u32,
+                pbData: data.as_ptr() as *mut u8,
+            };
+            let mut data_out = CRYPTOAPI_BLOB::default();
+
+            HRESULT!(CryptUnprotectData(
+                &data_in,
+                None,
+                None,
+                None,
+                None,
+                CRYPTPROTECT_UI_FORBIDDEN,
+                &mut data_out,
+            )
+            .ok());
+
+            Ok(take_blob(data_out))
+        }
+    }
+
+    // the output blob is allocated by the system and must be released with LocalFree
+    unsafe fn take_blob(blob: CRYPTOAPI_BLOB) -> Vec<u8> {
+        let data = std::slice::from_raw_parts(blob.pbData, blob.cbData as usize).to_vec();
+        LocalFree(blob.pbData as isize);
+        data
+    }
+}
@@ -1,27 +1,55 @@
 mod tcp;
 mod udp;
 
-use self::{tcp::serve_tcp, udp::serve_udp};
+pub use self::tcp::{connect_relay, EndPointFramed};
+
+use self::{
+    tcp::{serve_framed_tcp, serve_tcp},
+    udp::serve_udp,
+};
 use super::{
-    handlers::negotiate_desktop_params::handle_negotiate_desktop_params_request, id::EndPointID,
-    message::*, EndPointStream,
+    handlers::negotiate_desktop_params::{
+        common_video_codecs, handle_negotiate_desktop_params_request,
+    },
+    id::EndPointID,
+    message::*,
+    EndPointStream,
 };
 use crate::{
-    api::endpoint::handlers::{
-        fs_download_file::handle_download_file_request, fs_send_file::handle_send_file_request,
-        fs_visit_directory::handle_visit_directory_request, input::handle_input,
-        negotiate_finished::handle_negotiate_finished_request,
+    api::{
+        config::entity::{history::EndReason, kv::Settings, profile::ConnectionProfile},
+        endpoint::handlers::{
+            fs_download_file::handle_download_file_request,
+            fs_send_file::handle_send_file_request,
+            fs_visit_directory::handle_visit_directory_request,
+            input::handle_input,
+            negotiate_finished::handle_negotiate_finished_request,
+            receiver_report::handle_receiver_report,
+            time_sync::{handle_time_sync_request, handle_time_sync_response},
+            update_frame_rate::{
+                handle_update_frame_rate_request, handle_update_frame_rate_response,
+            },
+            update_resolution::handle_update_resolution_request,
+        },
     },
     call,
     component::{
         desktop::monitor::Monitor,
         fs::transfer::{append_file_block, delete_file_append_session},
+        recording::{RecordingEvent, SessionRecorder},
+        video_decoder::{
+            decoder::supported_decoders,
+            latency::{VideoStatistics, VideoStatisticsSnapshot},
+            statistics::ReceiverStatistics,
+        },
+        video_encoder::congestion::CongestionController,
     },
     core_error,
     error::{CoreError, CoreResult},
     utility::{
         bincode::{bincode_deserialize, bincode_serialize},
         nonce_value::NonceValue,
+        time::unix_micros,
     },
 };
 use bytes::Bytes;
@@ -31,12 +59,64 @@ use serde::de::DeserializeOwned;
 use std::{
     fmt::Display,
     ops::Deref,
-    sync::{atomic::AtomicU16, Arc},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU16, AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex,
+    },
     time::Duration,
 };
-use tokio::sync::{mpsc::Sender, RwLock};
+use tokio::sync::{
+    mpsc::{Sender, UnboundedReceiver, UnboundedSender},
+    Mutex as AsyncMutex, MutexGuard as AsyncMutexGuard, RwLock,
+};
+use tokio_util::sync::CancellationToken;
 
 const RECV_MESSAGE_TIMEOUT: Duration = Duration::from_secs(30);
+const RECEIVER_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+pub struct SessionState {
+    exit_token: CancellationToken,
+    transferred_bytes: AtomicU64,
+    end_reason: Mutex<Option<EndReason>>,
+}
+
+impl SessionState {
+    pub fn add_transferred_bytes(&self, len: usize) {
+        self.transferred_bytes
+            .fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub fn transferred_bytes(&self) -> u64 {
+        self.transferred_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn exit_token(&self) -> &CancellationToken {
+        &self.exit_token
+    }
+
+    // the first reason wins, both read and write loop exit after it
+    pub fn exit(&self, reason: EndReason) {
+        if let Ok(mut end_reason) = self.end_reason.lock() {
+            if end_reason.is_none() {
+                *end_reason = Some(reason);
+            }
+        }
+
+        self.exit_token.cancel();
+    }
+
+    pub async fn wait_exit(&self) -> EndReason {
+        self.exit_token.cancelled().await;
+
+        self.end_reason
+            .lock()
+            .ok()
+            .and_then(|end_reason| *end_reason)
+            .unwrap_or(EndReason::LocalClosed)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct EndPointClient {
@@ -45,6 +125,17 @@ pub struct EndPointClient {
     tx: Sender<Vec<u8>>,
     call_id: Arc<AtomicU16>,
     call_store: Arc<moka::sync::Cache<u16, Sender<Vec<u8>>>>,
+    session_state: Arc<SessionState>,
+    settings: Arc<Settings>,
+    frame_rate: Arc<AtomicU8>,
+    key_frame_requested: Arc<AtomicBool>,
+    congestion_controller: Arc<AsyncMutex<Option<CongestionController>>>,
+    // captured size divided by encoded size, maps visitor input back to the monitor
+    frame_scale: Arc<RwLock<(f32, f32)>>,
+    recorder: Arc<Mutex<Option<SessionRecorder>>>,
+    recording_event_tx: UnboundedSender<RecordingEvent>,
+    recording_event_rx: Arc<Mutex<UnboundedReceiver<RecordingEvent>>>,
+    video_statistics: Arc<Mutex<VideoStatistics>>,
 }
 
 impl EndPointClient {
@@ -55,6 +146,8 @@ impl EndPointClient {
         video_frame_tx: Sender<EndPointVideoFrame>,
         audio_frame_tx: Sender<EndPointAudioFrame>,
         visit_credentials: Option<Vec<u8>>,
+        settings: Settings,
+        profile: ConnectionProfile,
     ) -> CoreResult<Arc<EndPointClient>> {
         EndPointClient::create(
             true,
@@ -64,6 +157,8 @@ impl EndPointClient {
             Some(video_frame_tx),
             Some(audio_frame_tx),
             visit_credentials,
+            settings,
+            Some(profile),
         )
         .await
     }
@@ -82,6 +177,8 @@ impl EndPointClient {
             None,
             None,
             visit_credentials,
+            Settings::default(),
+            None,
         )
         .await
     }
@@ -91,8 +188,9 @@ impl EndPointClient {
         key_pair: Option<(OpeningKey<NonceValue>, SealingKey<NonceValue>)>,
         stream: EndPointStream,
         visit_credentials: Option<Vec<u8>>,
-    ) -> CoreResult<()> {
-        let _ = EndPointClient::create(
+        settings: Settings,
+    ) -> CoreResult<Arc<EndPointClient>> {
+        EndPointClient::create(
             false,
             endpoint_id,
             key_pair,
@@ -100,9 +198,10 @@ impl EndPointClient {
             None,
             None,
             visit_credentials,
+            settings,
+            None,
         )
-        .await?;
-        Ok(())
+        .await
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -114,13 +213,17 @@ impl EndPointClient {
         video_frame_tx: Option<Sender<EndPointVideoFrame>>,
         audio_frame_tx: Option<Sender<EndPointAudioFrame>>,
         visit_credentials: Option<Vec<u8>>,
+        settings: Settings,
+        profile: Option<ConnectionProfile>,
     ) -> CoreResult<Arc<EndPointClient>> {
         let (opening_key, sealing_key) = match key_pair {
             Some((opening_key, sealing_key)) => (Some(opening_key), Some(sealing_key)),
             None => (None, None),
         };
 
-        let (tx, mut rx) = match stream {
+        let session_state = Arc::new(SessionState::default());
+
+        let (tx, rx) = match stream {
             EndPointStream::ActiveTCP(addr) => {
                 let stream = tokio::time::timeout(
                     Duration::from_secs(10),
@@ -135,10 +238,10 @@ impl EndPointClient {
                     sealing_key,
                     opening_key,
                     visit_credentials,
+                    session_state.clone(),
                 )
                 .await?
             }
-            EndPointStream::ActiveUDP(_) => panic!("not support yet"),
             EndPointStream::PassiveTCP(stream) => {
                 serve_tcp(
                     stream,
@@ -146,39 +249,106 @@ impl EndPointClient {
                     sealing_key,
                     opening_key,
                     visit_credentials,
+                    session_state.clone(),
                 )
                 .await?
             }
-            EndPointStream::PassiveUDP { socket, .. } => {
+            EndPointStream::RelayTCP(framed) => serve_framed_tcp(
+                framed,
+                endpoint_id,
+                sealing_key,
+                opening_key,
+                session_state.clone(),
+            )?,
+            EndPointStream::ActiveUDP {
+                remote_addr,
+                socket,
+            }
+            | EndPointStream::PassiveUDP {
+                remote_addr,
+                socket,
+            } => {
                 serve_udp(
                     socket,
+                    remote_addr,
                     endpoint_id,
                     sealing_key,
                     opening_key,
                     visit_credentials,
+                    session_state.clone(),
                 )
                 .await?
             }
         };
 
-        // active endpoint should start negotiate with passive endpoint
-        let primary_monitor = if active && video_frame_tx.is_some() && audio_frame_tx.is_some() {
-            let params = serve_active_negotiate(&tx, &mut rx).await?;
-            Some(Arc::new(params.primary_monitor))
-        } else {
-            None
+        EndPointClient::serve(
+            active,
+            endpoint_id,
+            tx,
+            rx,
+            session_state,
+            video_frame_tx,
+            audio_frame_tx,
+            settings,
+            profile,
+        )
+        .await
+    }
+
+    // serves the session over the message channels of an established transport
+    #[allow(clippy::too_many_arguments)]
+    async fn serve(
+        active: bool,
+        endpoint_id: EndPointID,
+        tx: Sender<Vec<u8>>,
+        mut rx: tokio::sync::mpsc::Receiver<Bytes>,
+        session_state: Arc<SessionState>,
+        video_frame_tx: Option<Sender<EndPointVideoFrame>>,
+        audio_frame_tx: Option<Sender<EndPointAudioFrame>>,
+        settings: Settings,
+        profile: Option<ConnectionProfile>,
+    ) -> CoreResult<Arc<EndPointClient>> {
+        // active desktop endpoint should start negotiate with passive endpoint
+        let primary_monitor = match profile {
+            Some(ref profile) if active => {
+                match serve_active_negotiate(&tx, &mut rx, profile, settings.chroma_format).await {
+                    Ok(params) => Some(Arc::new(params.primary_monitor)),
+                    Err(err) => {
+                        session_state.exit(EndReason::ConnectionError);
+                        return Err(err);
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let frame_rate = match profile {
+            Some(ref profile) if active => profile.frame_rate,
+            _ => settings.frame_rate,
         };
 
         let call_store = moka::sync::CacheBuilder::new(32)
             .time_to_live(Duration::from_secs(60))
             .build();
 
+        let (recording_event_tx, recording_event_rx) = tokio::sync::mpsc::unbounded_channel();
+
         let client = Arc::new(EndPointClient {
             endpoint_id,
             monitor: Arc::new(RwLock::new(primary_monitor)),
             tx,
             call_id: Arc::new(AtomicU16::new(0)),
             call_store: Arc::new(call_store),
+            session_state,
+            settings: Arc::new(settings),
+            frame_rate: Arc::new(AtomicU8::new(frame_rate)),
+            key_frame_requested: Arc::new(AtomicBool::new(false)),
+            congestion_controller: Arc::new(AsyncMutex::new(None)),
+            frame_scale: Arc::new(RwLock::new((1.0, 1.0))),
+            recorder: Arc::new(Mutex::new(None)),
+            recording_event_tx,
+            recording_event_rx: Arc::new(Mutex::new(recording_event_rx)),
+            video_statistics: Arc::new(Mutex::new(VideoStatistics::default())),
         });
 
         handle_message(client.clone(), rx, video_frame_tx, audio_frame_tx);
@@ -195,6 +365,148 @@ impl EndPointClient {
     pub async fn set_monitor(&self, monitor: Monitor) {
         (*self.monitor.write().await) = Some(Arc::new(monitor))
     }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn frame_rate(&self) -> u8 {
+        self.frame_rate.load(Ordering::Relaxed)
+    }
+
+    // asks the passive endpoint to encode at a new frame rate, the applied frame rate is
+    // stored once the passive endpoint replies
+    pub fn update_frame_rate(&self, frame_rate: u8) -> CoreResult<()> {
+        self.try_send(&EndPointMessage::UpdateFrameRateRequest(
+            EndPointUpdateFrameRateRequest { frame_rate },
+        ))
+    }
+
+    pub fn set_frame_rate(&self, frame_rate: u8) {
+        self.frame_rate.store(frame_rate, Ordering::Relaxed);
+    }
+
+    // asks the passive endpoint to fit frames into the given size, zero means no limit
+    pub fn update_resolution(&self, max_width: u16, max_height: u16) -> CoreResult<()> {
+        self.try_send(&EndPointMessage::UpdateResolutionRequest(
+            EndPointUpdateResolutionRequest {
+                max_width,
+                max_height,
+            },
+        ))
+    }
+
+    pub async fn frame_scale(&self) -> (f32, f32) {
+        *self.frame_scale.read().await
+    }
+
+    pub fn blocking_set_frame_scale(&self, frame_scale: (f32, f32)) {
+        *self.frame_scale.blocking_write() = frame_scale;
+    }
+
+    // asks the passive endpoint to encode next frame as key frame
+    pub fn request_key_frame(&self) -> CoreResult<()> {
+        self.try_send(&EndPointMessage::KeyFrameRequest)
+    }
+
+    pub fn is_key_frame_requested(&self) -> bool {
+        self.key_frame_requested.load(Ordering::Relaxed)
+    }
+
+    // returns whether a key frame was requested since last call
+    pub fn take_key_frame_request(&self) -> bool {
+        self.key_frame_requested.swap(false, Ordering::Relaxed)
+    }
+
+    pub async fn set_congestion_controller(&self, controller: CongestionController) {
+        (*self.congestion_controller.lock().await) = Some(controller)
+    }
+
+    pub async fn congestion_controller(&self) -> AsyncMutexGuard<'_, Option<CongestionController>> {
+        self.congestion_controller.lock().await
+    }
+
+    // replaces the running recording, the new file begins at next key frame
+    pub fn start_recording(&self, path: PathBuf) -> CoreResult<()> {
+        let recorder_slot = Arc::downgrade(&self.recorder);
+        let recording_event_tx = self.recording_event_tx.clone();
+
+        let recorder = SessionRecorder::start(path, move |path, err| {
+            // a failed recorder is cleared unless a new recording has replaced it
+            if let Some(recorder_slot) = recorder_slot.upgrade() {
+                if let Ok(mut current) = recorder_slot.lock() {
+                    if current
+                        .as_ref()
+                        .map_or(false, |recorder| recorder.path() == path)
+                    {
+                        current.take();
+                    }
+                }
+            }
+
+            let _ = recording_event_tx.send(RecordingEvent::Failed {
+                path: path.to_path_buf(),
+                error: err.to_string(),
+            });
+        })?;
+
+        if let Ok(mut current) = self.recorder.lock() {
+            *current = Some(recorder);
+        }
+
+        self.request_key_frame()
+    }
+
+    // returns the paths of the finished recording once the files are finalized
+    pub async fn stop_recording(&self) -> CoreResult<Vec<PathBuf>> {
+        let recorder = self
+            .recorder
+            .lock()
+            .ok()
+            .and_then(|mut recorder| recorder.take())
+            .ok_or_else(|| core_error!("recording not started"))?;
+
+        recorder.stop().await
+    }
+
+    pub fn take_recording_event(&self) -> Option<RecordingEvent> {
+        self.recording_event_rx
+            .lock()
+            .ok()
+            .and_then(|mut rx| rx.try_recv().ok())
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder
+            .lock()
+            .map(|recorder| recorder.is_some())
+            .unwrap_or(false)
+    }
+
+    pub fn update_video_statistics(&self, f: impl FnOnce(&mut VideoStatistics)) {
+        if let Ok(mut video_statistics) = self.video_statistics.lock() {
+            f(&mut video_statistics)
+        }
+    }
+
+    pub fn video_statistics(&self) -> VideoStatisticsSnapshot {
+        self.video_statistics
+            .lock()
+            .map(|mut video_statistics| video_statistics.snapshot())
+            .unwrap_or_default()
+    }
+
+    pub fn transferred_bytes(&self) -> u64 {
+        self.session_state.transferred_bytes()
+    }
+
+    pub fn close(&self) {
+        self.session_state.exit(EndReason::LocalClosed);
+    }
+
+    pub async fn wait_exit(&self) -> EndReason {
+        self.session_state.wait_exit().await
+    }
 }
 
 impl EndPointClient {
@@ -254,26 +566,54 @@ impl Display for EndPointClient {
 async fn serve_active_negotiate(
     tx: &Sender<Vec<u8>>,
     rx: &mut tokio::sync::mpsc::Receiver<Bytes>,
+    profile: &ConnectionProfile,
+    chroma_format: ChromaFormat,
 ) -> CoreResult<EndPointNegotiateVisitDesktopParams> {
-    let negotiate_request_buffer = bincode_serialize(
-        &EndPointMessage::NegotiateDesktopParamsRequest(EndPointNegotiateDesktopParamsRequest {
-            video_codecs: vec![VideoCodec::H264],
-        }),
-    )?;
+    // offer the preferred codecs which can be decoded locally, the order is kept
+    let video_codecs = common_video_codecs(&profile.video_codecs, &supported_decoders());
 
-    tx.send(negotiate_request_buffer)
-        .await
-        .map_err(|_| CoreError::OutgoingMessageChannelDisconnect)?;
+    if video_codecs.is_empty() {
+        return Err(core_error!("no preferred video codec can be decoded"));
+    }
 
-    let negotiate_response_buffer = tokio::time::timeout(RECV_MESSAGE_TIMEOUT, rx.recv())
-        .await
-        .map_err(|_| CoreError::Timeout)?
-        .ok_or(CoreError::OutgoingMessageChannelDisconnect)?;
+    // the extension goes first so that a passive endpoint has it at hand when the
+    // request arrives
+    let (desktop_params_extension, desktop_params_request) =
+        EndPointNegotiateDesktopParamsRequest::with_extension(
+            video_codecs,
+            chroma_format,
+            profile.monitor_id.clone(),
+        );
+
+    let negotiate_messages = [
+        EndPointMessage::NegotiateDesktopParamsExtension(desktop_params_extension),
+        EndPointMessage::NegotiateDesktopParamsRequest(desktop_params_request),
+    ];
+
+    for message in negotiate_messages.iter() {
+        tx.send(bincode_serialize(message)?)
+            .await
+            .map_err(|_| CoreError::OutgoingMessageChannelDisconnect)?;
+    }
 
-    let EndPointMessage::NegotiateDesktopParamsResponse(negotiate_response) =
-        bincode_deserialize(negotiate_response_buffer.deref())? else {
-            return Err(core_error!("unexpected negotiate reply"));
-        };
+    // passive endpoints of older versions reply no extension and encode 4:2:0 only
+    let mut visit_extension = None;
+    let negotiate_response = loop {
+        let negotiate_response_buffer = tokio::time::timeout(RECV_MESSAGE_TIMEOUT, rx.recv())
+            .await
+            .map_err(|_| CoreError::Timeout)?
+            .ok_or(CoreError::OutgoingMessageChannelDisconnect)?;
+
+        match bincode_deserialize(negotiate_response_buffer.deref())? {
+            EndPointMessage::NegotiateVisitDesktopParamsExtension(extension) => {
+                visit_extension = Some(extension)
+            }
+            EndPointMessage::NegotiateDesktopParamsResponse(negotiate_response) => {
+                break negotiate_response
+            }
+            _ => return Err(core_error!("unexpected negotiate reply")),
+        }
+    };
 
     let params = match negotiate_response {
         EndPointNegotiateDesktopParamsResponse::VideoError(err) => {
@@ -285,20 +625,33 @@ async fn serve_active_negotiate(
             return Err(core_error!("negotiate failed ({})", err));
         }
         EndPointNegotiateDesktopParamsResponse::Params(params) => {
-            tracing::info!(?params, "negotiate success");
+            tracing::info!(?params, ?visit_extension, "negotiate success");
             params
         }
     };
 
-    let negotiate_request_buffer = bincode_serialize(&EndPointMessage::NegotiateFinishedRequest(
-        EndPointNegotiateFinishedRequest {
-            expected_frame_rate: 60,
-        },
-    ))?;
+    let chroma_format = visit_extension
+        .map(|extension| extension.chroma_format)
+        .unwrap_or(ChromaFormat::YUV420);
+
+    let negotiate_messages = [
+        EndPointMessage::NegotiateFinishedExtension(EndPointNegotiateFinishedExtension {
+            version: NEGOTIATE_EXTENSION_VERSION,
+            video_codec: params.video_codec,
+            chroma_format,
+            max_bitrate_kbps: profile.max_bitrate_kbps,
+            audio_enabled: profile.audio_enabled,
+        }),
+        EndPointMessage::NegotiateFinishedRequest(EndPointNegotiateFinishedRequest {
+            expected_frame_rate: profile.frame_rate,
+        }),
+    ];
 
-    tx.send(negotiate_request_buffer)
-        .await
-        .map_err(|_| CoreError::OutgoingMessageChannelDisconnect)?;
+    for message in negotiate_messages.iter() {
+        tx.send(bincode_serialize(message)?)
+            .await
+            .map_err(|_| CoreError::OutgoingMessageChannelDisconnect)?;
+    }
 
     Ok(params)
 }
@@ -310,12 +663,47 @@ fn handle_message(
     audio_frame_tx: Option<Sender<EndPointAudioFrame>>,
 ) {
     tokio::spawn(async move {
+        let mut receiver_statistics = ReceiverStatistics::default();
+        // extensions arrive right before the negotiate messages they belong to
+        let mut desktop_params_extension = None;
+        let mut finished_extension = None;
+        let mut report_interval = tokio::time::interval(RECEIVER_REPORT_INTERVAL);
+
         loop {
-            let buffer = match rx.recv().await {
-                Some(buffer) => buffer,
-                None => {
-                    tracing::info!("message handle channel is closed");
-                    break;
+            let buffer = tokio::select! {
+                buffer = rx.recv() => match buffer {
+                    Some(buffer) => buffer,
+                    None => {
+                        tracing::info!("message handle channel is closed");
+                        break;
+                    }
+                },
+                _ = report_interval.tick(), if video_frame_tx.is_some() => {
+                    if let Some(ref tx) = video_frame_tx {
+                        // frames in the decode channel which are not decoded yet
+                        let decode_queue_depth = tx.max_capacity() - tx.capacity();
+                        let report = receiver_statistics.report(decode_queue_depth);
+
+                        if let Err(err) =
+                            client.send(&EndPointMessage::ReceiverReport(report)).await
+                        {
+                            tracing::error!(?err, "send receiver report failed");
+                        }
+
+                        // clock offset drifts and round trip changes, so it is estimated all along
+                        let time_sync_request = EndPointTimeSyncRequest {
+                            originate_us: unix_micros(),
+                        };
+
+                        if let Err(err) = client
+                            .send(&EndPointMessage::TimeSyncRequest(time_sync_request))
+                            .await
+                        {
+                            tracing::error!(?err, "send time sync request failed");
+                        }
+                    }
+
+                    continue;
                 }
             };
 
@@ -331,18 +719,56 @@ fn handle_message(
                 EndPointMessage::Error => {
                     // handle_error(active_device_id, passive_device_id);
                 }
+                EndPointMessage::NegotiateDesktopParamsExtension(extension) => {
+                    desktop_params_extension = Some(extension);
+                }
                 EndPointMessage::NegotiateDesktopParamsRequest(req) => {
-                    handle_negotiate_desktop_params_request(client.clone(), req).await
+                    handle_negotiate_desktop_params_request(
+                        client.clone(),
+                        req,
+                        desktop_params_extension.take(),
+                    )
+                    .await
                 }
                 EndPointMessage::NegotiateDesktopParamsResponse(_) => {
                     // this message should not received at handle_message loop because it already handled
                     // at negotiate stage from active endpoint
                 }
-                EndPointMessage::NegotiateFinishedRequest(_) => {
-                    handle_negotiate_finished_request(client.clone());
+                EndPointMessage::NegotiateVisitDesktopParamsExtension(_) => {
+                    // this message is handled at negotiate stage from active endpoint as well
+                }
+                EndPointMessage::NegotiateFinishedExtension(extension) => {
+                    finished_extension = Some(extension);
+                }
+                EndPointMessage::NegotiateFinishedRequest(req) => {
+                    handle_negotiate_finished_request(
+                        client.clone(),
+                        req,
+                        finished_extension.take(),
+                    )
+                    .await;
                 }
                 EndPointMessage::VideoFrame(video_frame) => {
                     if let Some(ref tx) = video_frame_tx {
+                        receiver_statistics.on_video_frame(&video_frame);
+                        client.update_video_statistics(|statistics| {
+                            statistics.on_video_received(&video_frame)
+                        });
+
+                        let key_frame_wanted = match client.recorder.lock() {
+                            Ok(recorder) => recorder.as_ref().map_or(false, |recorder| {
+                                recorder.push_video(&video_frame, client.frame_rate())
+                            }),
+                            Err(_) => false,
+                        };
+
+                        // a new recording segment must begin with a key frame
+                        if key_frame_wanted {
+                            if let Err(err) = client.request_key_frame() {
+                                tracing::error!(?err, "request key frame for recording failed");
+                            }
+                        }
+
                         if let Err(err) = tx.send(video_frame).await {
                             tracing::error!(%err, "endpoint video frame message channel send failed");
                             return;
@@ -353,6 +779,12 @@ fn handle_message(
                 }
                 EndPointMessage::AudioFrame(audio_frame) => {
                     if let Some(ref tx) = audio_frame_tx {
+                        if let Ok(recorder) = client.recorder.lock() {
+                            if let Some(ref recorder) = *recorder {
+                                recorder.push_audio(&audio_frame);
+                            }
+                        }
+
                         if let Err(err) = tx.send(audio_frame).await {
                             tracing::error!(%err, "endpoint audio frame message channel send failed");
                             return;
@@ -408,9 +840,180 @@ fn handle_message(
                 EndPointMessage::FileTransferError(message) => {
                     delete_file_append_session(&message.id).await
                 }
+                EndPointMessage::ReceiverReport(report) => {
+                    handle_receiver_report(client.clone(), report).await
+                }
+                EndPointMessage::UpdateFrameRateRequest(req) => {
+                    handle_update_frame_rate_request(client.clone(), req).await
+                }
+                EndPointMessage::KeyFrameRequest => {
+                    client.key_frame_requested.store(true, Ordering::Relaxed)
+                }
+                EndPointMessage::UpdateResolutionRequest(req) => {
+                    handle_update_resolution_request(client.clone(), req).await
+                }
+                EndPointMessage::TimeSyncRequest(req) => {
+                    handle_time_sync_request(client.clone(), req).await
+                }
+                EndPointMessage::TimeSyncResponse(resp) => {
+                    handle_time_sync_response(client.clone(), resp).await
+                }
+                EndPointMessage::UpdateFrameRateResponse(resp) => {
+                    handle_update_frame_rate_response(client.clone(), resp).await
+                }
             }
         }
 
         tracing::info!("message handle loop exit");
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::endpoint::message::VideoCodec,
+        component::{
+            desktop::{synthetic_desktop_enabled, SYNTHETIC_DESKTOP_ENV},
+            video_decoder::decoder::VideoDecoder,
+        },
+    };
+    use tokio::sync::mpsc::Receiver;
+
+    type MessageChannels = (Sender<Vec<u8>>, Receiver<Bytes>);
+
+    // hands every message one endpoint sends to the other, like a transport which has
+    // finished its handshake
+    fn memory_transport() -> (MessageChannels, MessageChannels) {
+        fn forward() -> MessageChannels {
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<Vec<u8>>(32);
+            let (forward_tx, forward_rx) = tokio::sync::mpsc::channel(32);
+
+            tokio::spawn(async move {
+                while let Some(buffer) = rx.recv().await {
+                    if forward_tx.send(Bytes::from(buffer)).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            (tx, forward_rx)
+        }
+
+        let (active_tx, passive_rx) = forward();
+        let (passive_tx, active_rx) = forward();
+
+        ((active_tx, active_rx), (passive_tx, passive_rx))
+    }
+
+    // the active endpoint negotiates a synthetic monitor and frame rate, then frames of
+    // the passive endpoint's synthetic desktop are captured, encoded and decoded
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_synthetic_desktop_negotiate_round_trip() {
+        // the switch is read once per process, no other test reads it unset
+        std::env::set_var(SYNTHETIC_DESKTOP_ENV, "1");
+        assert!(synthetic_desktop_enabled());
+
+        let endpoint_id = EndPointID::DeviceID {
+            local_device_id: 1,
+            remote_device_id: 2,
+        };
+
+        let settings = Settings {
+            audio_enabled: false,
+            ..Settings::default()
+        };
+
+        let ((active_tx, active_rx), (passive_tx, passive_rx)) = memory_transport();
+
+        // the passive endpoint must serve the negotiate the active endpoint waits for
+        let passive_client = EndPointClient::serve(
+            false,
+            endpoint_id,
+            passive_tx,
+            passive_rx,
+            Arc::new(SessionState::default()),
+            None,
+            None,
+            settings.clone(),
+            None,
+        )
+        .await
+        .unwrap();
+
+        let mut profile = ConnectionProfile::from_settings(String::from("test"), 2, &settings);
+        profile.video_codecs = vec![VideoCodec::H264];
+        profile.monitor_id = Some(String::from("synthetic-1"));
+        profile.frame_rate = 15;
+
+        let (video_frame_tx, mut video_frame_rx) = tokio::sync::mpsc::channel(120);
+        let (audio_frame_tx, _audio_frame_rx) = tokio::sync::mpsc::channel(180);
+
+        let active_client = EndPointClient::serve(
+            true,
+            endpoint_id,
+            active_tx,
+            active_rx,
+            Arc::new(SessionState::default()),
+            Some(video_frame_tx),
+            Some(audio_frame_tx),
+            settings.clone(),
+            Some(profile),
+        )
+        .await
+        .unwrap();
+
+        let monitor = active_client.monitor().await.unwrap();
+        assert_eq!(monitor.id, "synthetic-1");
+        assert_eq!((monitor.width, monitor.height), (1280, 720));
+        assert_eq!(active_client.frame_rate(), 15);
+
+        let mut video_frames = Vec::new();
+        while video_frames.len() < 16 {
+            let video_frame = tokio::time::timeout(Duration::from_secs(10), video_frame_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(video_frame.codec, VideoCodec::H264);
+            assert_eq!(video_frame.sequence, video_frames.len() as u64);
+            video_frames.push(video_frame);
+        }
+
+        assert!(video_frames[0].key_frame);
+
+        let (decoded_frame_tx, mut decoded_frame_rx) = tokio::sync::mpsc::channel(32);
+        tokio::task::spawn_blocking(move || {
+            let mut decoder = VideoDecoder::new(decoded_frame_tx);
+            for video_frame in video_frames {
+                decoder.decode(video_frame).unwrap();
+            }
+        })
+        .await
+        .unwrap();
+
+        let mut decoded_frames = Vec::new();
+        while let Ok(Some(decoded_frame)) =
+            tokio::time::timeout(Duration::from_secs(1), decoded_frame_rx.recv()).await
+        {
+            assert_eq!((decoded_frame.width, decoded_frame.height), (1280, 720));
+            decoded_frames.push(decoded_frame);
+        }
+
+        // the decoder may still hold the last frames back
+        assert!(decoded_frames.len() >= 8, "{} frames", decoded_frames.len());
+
+        // the 30 fps synthetic monitor is encoded at the negotiated 15 fps
+        let first_capture_time_us = decoded_frames[0].capture_time_us;
+        let last_capture_time_us = decoded_frames[decoded_frames.len() - 1].capture_time_us;
+        let frame_interval_us =
+            (last_capture_time_us - first_capture_time_us) / (decoded_frames.len() as i64 - 1);
+        assert!(
+            (60_000..=75_000).contains(&frame_interval_us),
+            "frame interval {frame_interval_us}us"
+        );
+
+        active_client.close();
+        passive_client.close();
+    }
+}
@@ -1,8 +1,11 @@
-use super::RECV_MESSAGE_TIMEOUT;
+use super::{SessionState, RECV_MESSAGE_TIMEOUT};
 use crate::{
-    api::endpoint::{
-        id::EndPointID,
-        message::{EndPointHandshakeRequest, EndPointHandshakeResponse},
+    api::{
+        config::entity::history::EndReason,
+        endpoint::{
+            id::EndPointID,
+            message::{EndPointHandshakeRequest, EndPointHandshakeResponse},
+        },
     },
     core_error,
     error::{CoreError, CoreResult},
@@ -17,45 +20,82 @@ use futures::{
     SinkExt, StreamExt,
 };
 use ring::aead::{OpeningKey, SealingKey};
-use std::ops::Deref;
+use std::{net::SocketAddr, ops::Deref, sync::Arc, time::Duration};
 use tokio::{
     net::TcpStream,
     sync::mpsc::{Receiver, Sender},
 };
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
+pub type EndPointFramed = Framed<TcpStream, LengthDelimitedCodec>;
+
+// connects to the relay and finishes the handshake, so the relay can be prepared
+// while a direct path is tried
+pub async fn connect_relay(
+    addr: SocketAddr,
+    endpoint_id: EndPointID,
+    visit_credentials: Vec<u8>,
+) -> CoreResult<EndPointFramed> {
+    let stream = tokio::time::timeout(Duration::from_secs(10), TcpStream::connect(addr))
+        .await
+        .map_err(|_| CoreError::Timeout)??;
+
+    let mut framed = new_framed(stream);
+    serve_handshake(&mut framed, visit_credentials, endpoint_id).await?;
+    Ok(framed)
+}
+
 pub async fn serve_tcp(
     stream: TcpStream,
     endpoint_id: EndPointID,
     sealing_key: Option<SealingKey<NonceValue>>,
     opening_key: Option<OpeningKey<NonceValue>>,
     mut visit_credentials: Option<Vec<u8>>,
+    session_state: Arc<SessionState>,
 ) -> CoreResult<(Sender<Vec<u8>>, Receiver<Bytes>)> {
-    let mut framed = Framed::new(
-        stream,
-        LengthDelimitedCodec::builder()
-            .little_endian()
-            .max_frame_length(32 * 1024 * 1024)
-            .new_codec(),
-    );
+    let mut framed = new_framed(stream);
 
     if let Some(visit_credentials) = visit_credentials.take() {
         serve_handshake(&mut framed, visit_credentials, endpoint_id).await?;
     }
 
+    serve_framed_tcp(framed, endpoint_id, sealing_key, opening_key, session_state)
+}
+
+pub fn serve_framed_tcp(
+    framed: EndPointFramed,
+    endpoint_id: EndPointID,
+    sealing_key: Option<SealingKey<NonceValue>>,
+    opening_key: Option<OpeningKey<NonceValue>>,
+    session_state: Arc<SessionState>,
+) -> CoreResult<(Sender<Vec<u8>>, Receiver<Bytes>)> {
     let (tx, rx) = tokio::sync::mpsc::channel(32);
     let (sink, stream) = framed.split();
-    serve_tcp_write(endpoint_id, rx, sealing_key, sink);
-    let rx = serve_tcp_read(endpoint_id, opening_key, stream)?;
+    serve_tcp_write(endpoint_id, rx, sealing_key, sink, session_state.clone());
+    let rx = serve_tcp_read(endpoint_id, opening_key, stream, session_state)?;
     Ok((tx, rx))
 }
 
+fn new_framed(stream: TcpStream) -> EndPointFramed {
+    Framed::new(
+        stream,
+        LengthDelimitedCodec::builder()
+            .little_endian()
+            .max_frame_length(32 * 1024 * 1024)
+            .new_codec(),
+    )
+}
+
 async fn serve_handshake(
-    stream: &mut Framed<TcpStream, LengthDelimitedCodec>,
+    stream: &mut EndPointFramed,
     visit_credentials: Vec<u8>,
     endpoint_id: EndPointID,
 ) -> CoreResult<()> {
-    let EndPointID::DeviceID { local_device_id, remote_device_id } = endpoint_id else {
+    let EndPointID::DeviceID {
+        local_device_id,
+        remote_device_id,
+    } = endpoint_id
+    else {
         return Err(core_error!("lan connection needn't device id"));
     };
 
@@ -87,31 +127,39 @@ fn serve_tcp_read(
     endpoint_id: EndPointID,
     mut opening_key: Option<OpeningKey<NonceValue>>,
     mut stream: SplitStream<Framed<TcpStream, LengthDelimitedCodec>>,
+    session_state: Arc<SessionState>,
 ) -> CoreResult<tokio::sync::mpsc::Receiver<Bytes>> {
     let (tx, rx) = tokio::sync::mpsc::channel(1);
 
     tokio::spawn(async move {
-        loop {
-            let mut buffer = match stream.next().await {
+        let end_reason = loop {
+            let packet = tokio::select! {
+                _ = session_state.exit_token().cancelled() => break EndReason::LocalClosed,
+                packet = stream.next() => packet,
+            };
+
+            let mut buffer = match packet {
                 Some(packet) => match packet {
                     Ok(v) => v,
                     Err(err) => {
                         tracing::error!(?endpoint_id, ?err, "read stream failed");
-                        break;
+                        break EndReason::ConnectionError;
                     }
                 },
                 None => {
                     tracing::error!(?endpoint_id, "read stream is closed");
-                    break;
+                    break EndReason::RemoteClosed;
                 }
             };
 
+            session_state.add_transferred_bytes(buffer.len());
+
             let buffer_len = if let Some(ref mut opening_key) = opening_key {
                 match opening_key.open_in_place(ring::aead::Aad::empty(), buffer.as_mut()) {
                     Ok(output) => output.len(),
                     Err(err) => {
                         tracing::error!(?err, "open endpoint message packet failed");
-                        break;
+                        break EndReason::ConnectionError;
                     }
                 }
             } else {
@@ -122,9 +170,11 @@ fn serve_tcp_read(
 
             if tx.send(buffer.freeze()).await.is_err() {
                 tracing::error!(?endpoint_id, "output channel closed");
-                break;
+                break EndReason::LocalClosed;
             }
-        }
+        };
+
+        session_state.exit(end_reason);
 
         tracing::info!(?endpoint_id, "tcp read loop exit");
     });
@@ -137,31 +187,44 @@ fn serve_tcp_write(
     mut rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
     mut sealing_key: Option<SealingKey<NonceValue>>,
     mut sink: SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>,
+    session_state: Arc<SessionState>,
 ) {
     tokio::spawn(async move {
-        loop {
-            match rx.recv().await {
+        let end_reason = loop {
+            let buffer = tokio::select! {
+                _ = session_state.exit_token().cancelled() => break EndReason::LocalClosed,
+                buffer = rx.recv() => buffer,
+            };
+
+            match buffer {
                 Some(mut buffer) => {
                     if let Some(ref mut sealing_key) = sealing_key {
                         if let Err(err) = sealing_key
                             .seal_in_place_append_tag(ring::aead::Aad::empty(), &mut buffer)
                         {
                             tracing::error!(?err, "seal endpoint message packet failed");
-                            break;
+                            break EndReason::ConnectionError;
                         }
                     }
 
+                    session_state.add_transferred_bytes(buffer.len());
+
                     if sink.send(Bytes::from(buffer)).await.is_err() {
                         tracing::error!(?endpoint_id, "tcp write failed");
-                        break;
+                        break EndReason::ConnectionError;
                     }
                 }
                 None => {
                     tracing::error!(?endpoint_id, "input channel closed");
-                    break;
+                    break EndReason::LocalClosed;
                 }
             }
-        }
+        };
+
+        session_state.exit(end_reason);
+
+        // shutdown the connection so the remote is aware of the session exit
+        let _ = sink.close().await;
 
         tracing::info!(?endpoint_id, "tcp write loop exit");
     });
@@ -1,174 +1,703 @@
-use super::RECV_MESSAGE_TIMEOUT;
+use super::SessionState;
 use crate::{
-    api::endpoint::{
-        id::EndPointID,
-        message::{EndPointHandshakeRequest, EndPointHandshakeResponse},
-    },
+    api::{config::entity::history::EndReason, endpoint::id::EndPointID},
     core_error,
-    error::{CoreError, CoreResult},
-    utility::{
-        bincode::{bincode_deserialize, bincode_serialize},
-        nonce_value::NonceValue,
-    },
-};
-use bytes::Bytes;
-use futures::{
-    stream::{SplitSink, SplitStream},
-    SinkExt, StreamExt,
+    error::CoreResult,
+    utility::nonce_value::NonceValue,
 };
+use bytes::{BufMut, Bytes, BytesMut};
 use ring::aead::{OpeningKey, SealingKey};
-use std::{net::SocketAddr, ops::Deref};
-use tokio::{net::UdpSocket, sync::mpsc::Sender};
-use tokio_util::{codec::LengthDelimitedCodec, udp::UdpFramed};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{Receiver, Sender},
+    time::{Instant, MissedTickBehavior},
+};
+
+// datagrams are kept below the common path mtu, so frames are split into fragments
+// which are retransmitted when lost and delivered in order. the counter nonce of both
+// sides stays in step as long as every frame is opened in the order it was sealed
+const MAX_DATAGRAM_LENGTH: usize = 1200;
+const DATA_HEADER_LENGTH: usize = 1 + 8 + 1;
+const MAX_FRAGMENT_LENGTH: usize = MAX_DATAGRAM_LENGTH - DATA_HEADER_LENGTH;
+const MAX_FRAME_LENGTH: usize = 32 * 1024 * 1024;
+
+// fragments in flight, the receiver buffers the same amount ahead of a gap
+const WINDOW_SIZE: u64 = 512;
+const SACK_BITS: u64 = 64;
+const ACK_EVERY_FRAGMENTS: usize = 8;
+
+const PACKET_DATA: u8 = 0xA0;
+const PACKET_ACK: u8 = 0xA1;
+const PACKET_CLOSE: u8 = 0xA2;
+const FLAG_FRAME_END: u8 = 1;
+
+const INITIAL_RTT: Duration = Duration::from_millis(100);
+const MIN_RTO: Duration = Duration::from_millis(30);
+const MAX_RTO: Duration = Duration::from_secs(1);
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(2);
+const PEER_TIMEOUT: Duration = Duration::from_secs(15);
+const CLOSE_BURST_TIMES: usize = 3;
+const MAX_READY_FRAMES: usize = 32;
 
 pub async fn serve_udp(
     socket: UdpSocket,
+    remote_addr: SocketAddr,
     endpoint_id: EndPointID,
     sealing_key: Option<SealingKey<NonceValue>>,
     opening_key: Option<OpeningKey<NonceValue>>,
-    mut visit_credentials: Option<Vec<u8>>,
-) -> CoreResult<(Sender<Vec<u8>>, tokio::sync::mpsc::Receiver<Bytes>)> {
-    let remote_addr = socket.peer_addr()?;
-    let mut framed = UdpFramed::new(
-        socket,
-        LengthDelimitedCodec::builder()
-            .little_endian()
-            .max_frame_length(32 * 1024 * 1024)
-            .new_codec(),
-    );
-
-    if let Some(visit_credentials) = visit_credentials.take() {
-        serve_udp_handshake(remote_addr, &mut framed, visit_credentials, endpoint_id).await?;
+    visit_credentials: Option<Vec<u8>>,
+    session_state: Arc<SessionState>,
+) -> CoreResult<(Sender<Vec<u8>>, Receiver<Bytes>)> {
+    // a punched path reaches the remote endpoint itself, there's no relay which takes
+    // the visit credentials
+    if visit_credentials.is_some() {
+        return Err(core_error!("udp path needn't visit credentials"));
     }
 
     let (tx, rx) = tokio::sync::mpsc::channel(32);
-    let (sink, stream) = framed.split();
-    serve_udp_write(remote_addr, rx, sealing_key, sink);
-    let rx = serve_udp_read(remote_addr, opening_key, stream)?;
-    Ok((tx, rx))
+    let (frame_tx, frame_rx) = tokio::sync::mpsc::channel(1);
+
+    serve_udp_transport(
+        socket,
+        remote_addr,
+        endpoint_id,
+        sealing_key,
+        opening_key,
+        rx,
+        frame_tx,
+        session_state,
+    );
+
+    Ok((tx, frame_rx))
 }
 
-async fn serve_udp_handshake(
+#[allow(clippy::too_many_arguments)]
+fn serve_udp_transport(
+    socket: UdpSocket,
     remote_addr: SocketAddr,
-    stream: &mut UdpFramed<LengthDelimitedCodec>,
-    visit_credentials: Vec<u8>,
     endpoint_id: EndPointID,
-) -> CoreResult<()> {
-    let EndPointID::DeviceID { local_device_id, remote_device_id } = endpoint_id else {
-        return Err(core_error!("lan connection needn't device id"));
-    };
+    mut sealing_key: Option<SealingKey<NonceValue>>,
+    mut opening_key: Option<OpeningKey<NonceValue>>,
+    mut rx: Receiver<Vec<u8>>,
+    tx: Sender<Bytes>,
+    session_state: Arc<SessionState>,
+) {
+    tokio::spawn(async move {
+        let mut send_window = SendWindow::new();
+        let mut receive_window = ReceiveWindow::default();
+        let mut ready_frames: VecDeque<Bytes> = VecDeque::new();
+        let mut buffer = [0u8; 2048];
 
-    let handshake_request_buffer = bincode_serialize(&EndPointHandshakeRequest {
-        visit_credentials,
-        device_id: local_device_id,
-    })?;
+        let mut ticker = tokio::time::interval(TICK_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
-    stream
-        .send((Bytes::from(handshake_request_buffer), remote_addr))
-        .await
-        .map_err(|_| CoreError::OutgoingMessageChannelDisconnect)?;
+        let mut last_received_at = Instant::now();
+        let mut last_sent_at = Instant::now();
+        let mut unacked_fragments = 0;
 
-    // should we try receive 3 or more times because udp is connect less?
-    let (handshake_response_buffer, response_remote_addr) =
-        tokio::time::timeout(RECV_MESSAGE_TIMEOUT, stream.next())
-            .await
-            .map_err(|_| CoreError::Timeout)?
-            .ok_or(CoreError::OutgoingMessageChannelDisconnect)??;
+        // the first ack also tells the remote that this side took the punched path
+        let _ = socket.send_to(&receive_window.ack(), remote_addr).await;
 
-    if response_remote_addr != remote_addr {
-        return Err(core_error!("unexpected handshake reply addr"));
-    }
+        let end_reason = loop {
+            let mut send_ack = false;
 
-    let resp: EndPointHandshakeResponse = bincode_deserialize(handshake_response_buffer.deref())?;
+            tokio::select! {
+                _ = session_state.exit_token().cancelled() => break EndReason::LocalClosed,
+                message = rx.recv(), if send_window.can_push() => match message {
+                    Some(mut buffer) => {
+                        if let Some(ref mut sealing_key) = sealing_key {
+                            if let Err(err) = sealing_key
+                                .seal_in_place_append_tag(ring::aead::Aad::empty(), &mut buffer)
+                            {
+                                tracing::error!(?err, "seal endpoint message packet failed");
+                                break EndReason::ConnectionError;
+                            }
+                        }
 
-    if resp.remote_device_id != remote_device_id {
-        return Err(core_error!("endpoints server build mismatch tunnel"));
-    }
+                        if buffer.len() > MAX_FRAME_LENGTH {
+                            tracing::error!(len = buffer.len(), "endpoint message packet too large");
+                            break EndReason::ConnectionError;
+                        }
 
-    Ok(())
-}
+                        session_state.add_transferred_bytes(buffer.len());
+                        send_window.push_frame(&buffer);
+                    }
+                    None => {
+                        tracing::error!(?endpoint_id, "input channel closed");
+                        break EndReason::LocalClosed;
+                    }
+                },
+                permit = tx.reserve(), if !ready_frames.is_empty() => match permit {
+                    Ok(permit) => {
+                        if let Some(frame) = ready_frames.pop_front() {
+                            permit.send(frame);
+                        }
+                    }
+                    Err(_) => {
+                        tracing::error!(?endpoint_id, "output channel closed");
+                        break EndReason::LocalClosed;
+                    }
+                },
+                res = socket.recv_from(&mut buffer), if ready_frames.len() < MAX_READY_FRAMES => {
+                    // errors like icmp port unreachable are left to the peer timeout, and
+                    // stale hole punching packets are skipped as unknown packets
+                    match res {
+                        Ok((len, addr)) if addr == remote_addr => {
+                            last_received_at = Instant::now();
 
-fn serve_udp_read(
-    remote_addr: SocketAddr,
-    mut opening_key: Option<OpeningKey<NonceValue>>,
-    mut stream: SplitStream<UdpFramed<LengthDelimitedCodec>>,
-) -> CoreResult<tokio::sync::mpsc::Receiver<Bytes>> {
-    let (tx, rx) = tokio::sync::mpsc::channel(1);
+                            match decode_packet(&buffer[..len]) {
+                                Some(Packet::Data { seq, frame_end, payload }) => {
+                                    let frames = match receive_window.push_fragment(seq, frame_end, payload) {
+                                        Ok(frames) => frames,
+                                        Err(err) => {
+                                            tracing::error!(?err, "reassemble endpoint message packet failed");
+                                            break EndReason::ConnectionError;
+                                        }
+                                    };
 
-    tokio::spawn(async move {
-        loop {
-            let mut buffer = match stream.next().await {
-                Some(packet) => match packet {
-                    Ok((buffer, addr)) => {
-                        if addr != remote_addr {
-                            continue;
-                        }
+                                    if let Err(err) = open_frames(
+                                        frames,
+                                        &mut opening_key,
+                                        &mut ready_frames,
+                                        &session_state,
+                                    ) {
+                                        tracing::error!(?err, "open endpoint message packet failed");
+                                        break EndReason::ConnectionError;
+                                    }
 
-                        buffer
-                    }
-                    Err(err) => {
-                        tracing::error!(?remote_addr, ?err, "read stream failed");
-                        break;
+                                    unacked_fragments += 1;
+                                    send_ack = unacked_fragments >= ACK_EVERY_FRAGMENTS;
+                                }
+                                Some(Packet::Ack { next_seq, sack }) => {
+                                    send_window.on_ack(next_seq, sack, Instant::now());
+                                }
+                                Some(Packet::Close) => {
+                                    tracing::info!(?endpoint_id, "remote closed udp path");
+                                    break EndReason::RemoteClosed;
+                                }
+                                None => {}
+                            }
+                        }
+                        _ => {}
                     }
                 },
-                None => {
-                    tracing::error!(?remote_addr, "read stream is closed");
-                    break;
+                _ = ticker.tick() => {
+                    if last_received_at.elapsed() >= PEER_TIMEOUT {
+                        tracing::error!(?endpoint_id, "udp path timeout");
+                        break EndReason::ConnectionError;
+                    }
+
+                    send_ack = unacked_fragments > 0 || last_sent_at.elapsed() >= KEEPALIVE_INTERVAL;
                 }
-            };
+            }
+
+            if send_ack {
+                unacked_fragments = 0;
+                last_sent_at = Instant::now();
+                let _ = socket.send_to(&receive_window.ack(), remote_addr).await;
+            }
+
+            // lost datagrams are retransmitted, so send errors are ignored like losses
+            for datagram in send_window.poll_transmit(Instant::now()) {
+                last_sent_at = Instant::now();
+                let _ = socket.send_to(&datagram, remote_addr).await;
+            }
+        };
+
+        if end_reason == EndReason::LocalClosed {
+            for _ in 0..CLOSE_BURST_TIMES {
+                let _ = socket.send_to(&[PACKET_CLOSE], remote_addr).await;
+            }
+        }
+
+        session_state.exit(end_reason);
+
+        tracing::info!(?endpoint_id, ?remote_addr, "udp transport loop exit");
+    });
+}
+
+fn open_frames(
+    frames: Vec<BytesMut>,
+    opening_key: &mut Option<OpeningKey<NonceValue>>,
+    ready_frames: &mut VecDeque<Bytes>,
+    session_state: &SessionState,
+) -> CoreResult<()> {
+    for mut frame in frames {
+        session_state.add_transferred_bytes(frame.len());
+
+        if let Some(ref mut opening_key) = opening_key {
+            let output_len = opening_key
+                .open_in_place(ring::aead::Aad::empty(), frame.as_mut())?
+                .len();
+            frame.truncate(output_len);
+        }
+
+        ready_frames.push_back(frame.freeze());
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Packet {
+    Data {
+        seq: u64,
+        frame_end: bool,
+        payload: Bytes,
+    },
+    // every fragment before next_seq is received, bit n of sack tells whether
+    // fragment next_seq + 1 + n is received too
+    Ack {
+        next_seq: u64,
+        sack: u64,
+    },
+    Close,
+}
+
+fn decode_packet(buffer: &[u8]) -> Option<Packet> {
+    let (&packet_type, body) = buffer.split_first()?;
+
+    match packet_type {
+        PACKET_DATA if body.len() >= DATA_HEADER_LENGTH - 1 => Some(Packet::Data {
+            seq: u64::from_le_bytes(body[..8].try_into().ok()?),
+            frame_end: body[8] & FLAG_FRAME_END != 0,
+            payload: Bytes::copy_from_slice(&body[9..]),
+        }),
+        PACKET_ACK if body.len() == 16 => Some(Packet::Ack {
+            next_seq: u64::from_le_bytes(body[..8].try_into().ok()?),
+            sack: u64::from_le_bytes(body[8..].try_into().ok()?),
+        }),
+        PACKET_CLOSE if body.is_empty() => Some(Packet::Close),
+        _ => None,
+    }
+}
+
+struct InflightFragment {
+    datagram: Bytes,
+    sent_at: Instant,
+    retransmitted: bool,
+}
+
+struct SendWindow {
+    next_seq: u64,
+    queued: VecDeque<(u64, Bytes)>,
+    inflight: BTreeMap<u64, InflightFragment>,
+    highest_acked: Option<u64>,
+    srtt: Duration,
+    rttvar: Duration,
+}
+
+impl SendWindow {
+    fn new() -> Self {
+        Self {
+            next_seq: 0,
+            queued: VecDeque::new(),
+            inflight: BTreeMap::new(),
+            highest_acked: None,
+            srtt: INITIAL_RTT,
+            rttvar: INITIAL_RTT / 2,
+        }
+    }
+
+    // a new frame is taken only after the previous one is fully in flight
+    fn can_push(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    fn push_frame(&mut self, frame: &[u8]) {
+        let mut chunks = frame.chunks(MAX_FRAGMENT_LENGTH).peekable();
+
+        if chunks.peek().is_none() {
+            self.push_fragment(&[], true);
+            return;
+        }
+
+        while let Some(chunk) = chunks.next() {
+            self.push_fragment(chunk, chunks.peek().is_none());
+        }
+    }
+
+    fn push_fragment(&mut self, payload: &[u8], frame_end: bool) {
+        let mut datagram = BytesMut::with_capacity(DATA_HEADER_LENGTH + payload.len());
+        datagram.put_u8(PACKET_DATA);
+        datagram.put_u64_le(self.next_seq);
+        datagram.put_u8(if frame_end { FLAG_FRAME_END } else { 0 });
+        datagram.put_slice(payload);
+
+        self.queued.push_back((self.next_seq, datagram.freeze()));
+        self.next_seq += 1;
+    }
+
+    fn rto(&self) -> Duration {
+        (self.srtt + self.rttvar * 4).clamp(MIN_RTO, MAX_RTO)
+    }
+
+    fn on_ack(&mut self, next_seq: u64, sack: u64, now: Instant) {
+        let mut acked = self.inflight.split_off(&next_seq);
+        std::mem::swap(&mut acked, &mut self.inflight);
 
-            if let Some(ref mut opening_key) = opening_key {
-                if let Err(err) =
-                    opening_key.open_in_place(ring::aead::Aad::empty(), buffer.as_mut())
-                {
-                    tracing::error!(?err, "open endpoint message packet failed");
-                    break;
+        for bit in 0..SACK_BITS {
+            if sack & (1 << bit) != 0 {
+                let seq = next_seq + 1 + bit;
+                if let Some(fragment) = self.inflight.remove(&seq) {
+                    acked.insert(seq, fragment);
                 }
             }
+        }
+
+        let Some((&seq, fragment)) = acked.iter().next_back() else {
+            return;
+        };
+
+        self.highest_acked = self.highest_acked.max(Some(seq));
+
+        // retransmitted fragments are ambiguous about which transmission was acked
+        if !fragment.retransmitted {
+            let rtt = now.saturating_duration_since(fragment.sent_at);
+            let deviation = rtt.max(self.srtt) - rtt.min(self.srtt);
+
+            self.rttvar = (self.rttvar * 3 + deviation) / 4;
+            self.srtt = (self.srtt * 7 + rtt) / 8;
+        }
+    }
+
+    fn poll_transmit(&mut self, now: Instant) -> Vec<Bytes> {
+        let mut datagrams = Vec::new();
+        let rto = self.rto();
+
+        // a fragment older than an acked one is taken as lost after a round trip, the
+        // others after the retransmission timeout
+        for (&seq, fragment) in self.inflight.iter_mut() {
+            let elapsed = now.saturating_duration_since(fragment.sent_at);
+            let overtaken = self.highest_acked.is_some_and(|highest| seq < highest);
+
+            if elapsed >= rto || (overtaken && elapsed >= self.srtt) {
+                fragment.sent_at = now;
+                fragment.retransmitted = true;
+                datagrams.push(fragment.datagram.clone());
+            }
+        }
+
+        // the receiver only buffers a window ahead of the oldest fragment it lacks
+        let base = match self.inflight.keys().next() {
+            Some(&seq) => seq,
+            None => self
+                .queued
+                .front()
+                .map(|(seq, _)| *seq)
+                .unwrap_or(self.next_seq),
+        };
 
-            if tx.send(buffer.freeze()).await.is_err() {
-                tracing::error!(?remote_addr, "output channel closed");
+        while let Some((seq, _)) = self.queued.front() {
+            if *seq >= base + WINDOW_SIZE {
                 break;
             }
+
+            if let Some((seq, datagram)) = self.queued.pop_front() {
+                datagrams.push(datagram.clone());
+                self.inflight.insert(
+                    seq,
+                    InflightFragment {
+                        datagram,
+                        sent_at: now,
+                        retransmitted: false,
+                    },
+                );
+            }
         }
 
-        tracing::info!(?remote_addr, "tcp read loop exit");
-    });
+        datagrams
+    }
+}
 
-    Ok(rx)
+#[derive(Default)]
+struct ReceiveWindow {
+    next_seq: u64,
+    out_of_order: BTreeMap<u64, (bool, Bytes)>,
+    partial_frame: BytesMut,
 }
 
-fn serve_udp_write(
-    remote_addr: SocketAddr,
-    mut rx: tokio::sync::mpsc::Receiver<Vec<u8>>,
-    mut sealing_key: Option<SealingKey<NonceValue>>,
-    mut sink: SplitSink<UdpFramed<LengthDelimitedCodec>, (Bytes, SocketAddr)>,
-) {
-    tokio::spawn(async move {
-        loop {
-            match rx.recv().await {
-                Some(mut buffer) => {
-                    if let Some(ref mut sealing_key) = sealing_key {
-                        if let Err(err) = sealing_key
-                            .seal_in_place_append_tag(ring::aead::Aad::empty(), &mut buffer)
-                        {
-                            tracing::error!(?err, "seal endpoint message packet failed");
-                            break;
+impl ReceiveWindow {
+    fn push_fragment(
+        &mut self,
+        seq: u64,
+        frame_end: bool,
+        payload: Bytes,
+    ) -> CoreResult<Vec<BytesMut>> {
+        let mut frames = Vec::new();
+
+        // duplicates and fragments beyond the window are dropped, the next ack makes
+        // the sender transmit them again if they're still needed
+        if seq < self.next_seq || seq >= self.next_seq + WINDOW_SIZE {
+            return Ok(frames);
+        }
+
+        self.out_of_order.entry(seq).or_insert((frame_end, payload));
+
+        while let Some((frame_end, payload)) = self.out_of_order.remove(&self.next_seq) {
+            self.next_seq += 1;
+
+            if self.partial_frame.len() + payload.len() > MAX_FRAME_LENGTH {
+                return Err(core_error!("udp frame exceeds max length"));
+            }
+
+            self.partial_frame.extend_from_slice(&payload);
+
+            if frame_end {
+                frames.push(self.partial_frame.split());
+            }
+        }
+
+        Ok(frames)
+    }
+
+    fn ack(&self) -> Bytes {
+        let mut sack = 0u64;
+        for &seq in self
+            .out_of_order
+            .range(self.next_seq + 1..self.next_seq + 1 + SACK_BITS)
+            .map(|(seq, _)| seq)
+        {
+            sack |= 1 << (seq - self.next_seq - 1);
+        }
+
+        let mut datagram = BytesMut::with_capacity(17);
+        datagram.put_u8(PACKET_ACK);
+        datagram.put_u64_le(self.next_seq);
+        datagram.put_u64_le(sack);
+        datagram.freeze()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn test_frame(index: usize, len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + index) as u8).collect()
+    }
+
+    #[test]
+    fn test_fragments_reassembled_out_of_order() {
+        let mut send_window = SendWindow::new();
+        let frame = test_frame(0, MAX_FRAGMENT_LENGTH * 3 + 10);
+        send_window.push_frame(&frame);
+
+        let datagrams = send_window.poll_transmit(Instant::now());
+        assert_eq!(datagrams.len(), 4);
+        assert!(datagrams.iter().all(|d| d.len() <= MAX_DATAGRAM_LENGTH));
+
+        let mut receive_window = ReceiveWindow::default();
+        for datagram in datagrams.iter().rev() {
+            let Some(Packet::Data {
+                seq,
+                frame_end,
+                payload,
+            }) = decode_packet(datagram)
+            else {
+                panic!("unexpected packet");
+            };
+
+            let frames = receive_window
+                .push_fragment(seq, frame_end, payload)
+                .unwrap();
+
+            if seq == 0 {
+                assert_eq!(frames.len(), 1);
+                assert_eq!(frames[0].as_ref(), frame.as_slice());
+            } else {
+                assert!(frames.is_empty());
+                assert_eq!(
+                    decode_packet(&receive_window.ack()),
+                    Some(Packet::Ack {
+                        next_seq: 0,
+                        sack: match seq {
+                            3 => 0b100,
+                            2 => 0b110,
+                            _ => 0b111,
                         }
-                    }
+                    })
+                );
+            }
+        }
 
-                    if sink.send((Bytes::from(buffer), remote_addr)).await.is_err() {
-                        tracing::error!(?remote_addr, "tcp write failed");
-                        break;
-                    }
+        // a duplicate doesn't produce the frame again
+        let Some(Packet::Data { payload, .. }) = decode_packet(&datagrams[3]) else {
+            panic!("unexpected packet");
+        };
+        assert!(receive_window
+            .push_fragment(3, true, payload)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_lost_fragments_retransmitted() {
+        let start = Instant::now();
+        let mut send_window = SendWindow::new();
+        let mut receive_window = ReceiveWindow::default();
+
+        let frames: Vec<Vec<u8>> = (0..40).map(|i| test_frame(i, 100 + i * 900)).collect();
+        let mut pending_frames = frames.iter();
+        let mut received_frames = Vec::new();
+
+        // every third datagram is lost and each batch arrives reversed
+        let mut datagram_count = 0usize;
+        for step in 0..2000u64 {
+            let now = start + TICK_INTERVAL * step as u32;
+
+            if send_window.can_push() {
+                if let Some(frame) = pending_frames.next() {
+                    send_window.push_frame(frame);
                 }
-                None => {
-                    tracing::error!(?remote_addr, "input channel closed");
-                    break;
+            }
+
+            let mut datagrams = send_window.poll_transmit(now);
+            datagrams.reverse();
+
+            for datagram in datagrams {
+                datagram_count += 1;
+                if datagram_count % 3 == 2 {
+                    continue;
                 }
+
+                let Some(Packet::Data {
+                    seq,
+                    frame_end,
+                    payload,
+                }) = decode_packet(&datagram)
+                else {
+                    panic!("unexpected packet");
+                };
+
+                received_frames.extend(
+                    receive_window
+                        .push_fragment(seq, frame_end, payload)
+                        .unwrap(),
+                );
+            }
+
+            if let Some(Packet::Ack { next_seq, sack }) = decode_packet(&receive_window.ack()) {
+                send_window.on_ack(next_seq, sack, now + TICK_INTERVAL);
+            }
+
+            if received_frames.len() == frames.len() {
+                break;
             }
         }
 
-        tracing::info!(?remote_addr, "tcp write loop exit");
-    });
+        assert_eq!(received_frames.len(), frames.len());
+        for (received_frame, frame) in received_frames.iter().zip(frames.iter()) {
+            assert_eq!(received_frame.as_ref(), frame.as_slice());
+        }
+        assert!(send_window.inflight.is_empty());
+    }
+
+    #[test]
+    fn test_send_window_limit() {
+        let mut send_window = SendWindow::new();
+        send_window.push_frame(&test_frame(
+            0,
+            MAX_FRAGMENT_LENGTH * (WINDOW_SIZE as usize + 5),
+        ));
+
+        let now = Instant::now();
+        assert_eq!(send_window.poll_transmit(now).len(), WINDOW_SIZE as usize);
+        assert!(!send_window.can_push());
+
+        // acking the oldest fragment moves the window by one
+        send_window.on_ack(1, 0, now);
+        assert_eq!(send_window.poll_transmit(now).len(), 1);
+    }
+
+    #[test]
+    fn test_decode_rejects_unknown_packets() {
+        assert_eq!(decode_packet(&[]), None);
+        assert_eq!(decode_packet(&[PACKET_ACK, 0, 0]), None);
+        assert_eq!(decode_packet(&[PACKET_CLOSE]), Some(Packet::Close));
+
+        // stale hole punching packets start with their length
+        let mut punch_packet = vec![24, 0, 0, 0];
+        punch_packet.extend_from_slice(b"MXPUNCH");
+        assert_eq!(decode_packet(&punch_packet), None);
+    }
+
+    #[tokio::test]
+    async fn test_serve_udp_over_loopback() {
+        let active_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let passive_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let active_addr = active_socket.local_addr().unwrap();
+        let passive_addr = passive_socket.local_addr().unwrap();
+
+        let endpoint_id = EndPointID::DeviceID {
+            local_device_id: 1,
+            remote_device_id: 2,
+        };
+
+        let active_state = Arc::new(SessionState::default());
+        let passive_state = Arc::new(SessionState::default());
+
+        let (active_tx, mut active_rx) = serve_udp(
+            active_socket,
+            passive_addr,
+            endpoint_id,
+            None,
+            None,
+            None,
+            active_state.clone(),
+        )
+        .await
+        .unwrap();
+
+        let (passive_tx, mut passive_rx) = serve_udp(
+            passive_socket,
+            active_addr,
+            endpoint_id,
+            None,
+            None,
+            None,
+            passive_state.clone(),
+        )
+        .await
+        .unwrap();
+
+        // a key frame is far larger than a datagram
+        let frames: Vec<Vec<u8>> = (0..8).map(|i| test_frame(i, 300 * 1024 + i)).collect();
+
+        for frame in frames.iter() {
+            active_tx.send(frame.clone()).await.unwrap();
+        }
+        passive_tx.send(b"reply".to_vec()).await.unwrap();
+
+        for frame in frames.iter() {
+            let received = tokio::time::timeout(Duration::from_secs(10), passive_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(received.as_ref(), frame.as_slice());
+        }
+
+        let reply = tokio::time::timeout(Duration::from_secs(10), active_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply.as_ref(), b"reply");
+
+        // closing one side ends the other
+        active_state.exit(EndReason::LocalClosed);
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(5), passive_state.wait_exit())
+                .await
+                .unwrap(),
+            EndReason::RemoteClosed
+        );
+    }
 }
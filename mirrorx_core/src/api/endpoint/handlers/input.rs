@@ -12,6 +12,7 @@ pub async fn handle_input(client: Arc<EndPointClient>, input_event: EndPointInpu
         match event {
             InputEvent::Mouse(event) => {
                 if let Some(monitor) = client.monitor().await {
+                    let event = scale_mouse_event(event, client.frame_scale().await);
                     handle_mouse(&event, &monitor);
                 }
             }
@@ -20,6 +21,16 @@ pub async fn handle_input(client: Arc<EndPointClient>, input_event: EndPointInpu
     }
 }
 
+// the visitor sends positions on the encoded frame, which may be smaller than the monitor
+fn scale_mouse_event(event: MouseEvent, (scale_x, scale_y): (f32, f32)) -> MouseEvent {
+    match event {
+        MouseEvent::Up(key, x, y) => MouseEvent::Up(key, x * scale_x, y * scale_y),
+        MouseEvent::Down(key, x, y) => MouseEvent::Down(key, x * scale_x, y * scale_y),
+        MouseEvent::Move(key, x, y) => MouseEvent::Move(key, x * scale_x, y * scale_y),
+        MouseEvent::ScrollWheel(delta) => MouseEvent::ScrollWheel(delta),
+    }
+}
+
 pub fn handle_mouse(event: &MouseEvent, monitor: &Monitor) {
     match event {
         MouseEvent::Up(key, x, y) => {
@@ -51,3 +62,49 @@ pub fn handle_keyboard(event: &KeyboardEvent) {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::video_encoder::congestion::EncoderTarget;
+
+    #[test]
+    fn test_scale_mouse_event() {
+        let target = EncoderTarget {
+            bitrate: 0,
+            max_bitrate: 0,
+            scale: 100,
+            frame_rate: 30,
+            max_width: 1280,
+            max_height: 720,
+        };
+
+        // the encoder publishes the scale from the encoded size back to the monitor
+        let (width, height) = target.scaled_size(3840, 2160);
+        assert_eq!((width, height), (1280, 720));
+        let frame_scale = (3840.0 / width as f32, 2160.0 / height as f32);
+
+        assert_eq!(
+            scale_mouse_event(MouseEvent::Move(MouseKey::None, 640.0, 360.0), frame_scale),
+            MouseEvent::Move(MouseKey::None, 1920.0, 1080.0)
+        );
+        assert_eq!(
+            scale_mouse_event(MouseEvent::Down(MouseKey::Left, 1279.0, 0.0), frame_scale),
+            MouseEvent::Down(MouseKey::Left, 3837.0, 0.0)
+        );
+        assert_eq!(
+            scale_mouse_event(MouseEvent::Up(MouseKey::Right, 0.5, 719.0), frame_scale),
+            MouseEvent::Up(MouseKey::Right, 1.5, 2157.0)
+        );
+        assert_eq!(
+            scale_mouse_event(MouseEvent::ScrollWheel(-2.5), frame_scale),
+            MouseEvent::ScrollWheel(-2.5)
+        );
+
+        // an unscaled frame keeps the positions
+        assert_eq!(
+            scale_mouse_event(MouseEvent::Move(MouseKey::None, 10.0, 20.0), (1.0, 1.0)),
+            MouseEvent::Move(MouseKey::None, 10.0, 20.0)
+        );
+    }
+}
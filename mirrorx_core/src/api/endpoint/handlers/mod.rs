@@ -6,4 +6,8 @@ pub mod fs_visit_directory;
 pub mod input;
 pub mod negotiate_desktop_params;
 pub mod negotiate_finished;
+pub mod receiver_report;
+pub mod time_sync;
+pub mod update_frame_rate;
+pub mod update_resolution;
 pub mod video_frame;
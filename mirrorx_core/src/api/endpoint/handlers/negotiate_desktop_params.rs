@@ -2,20 +2,46 @@ use crate::{
     api::endpoint::{
         client::EndPointClient,
         message::{
-            EndPointMessage, EndPointNegotiateDesktopParamsRequest,
-            EndPointNegotiateDesktopParamsResponse, EndPointNegotiateVisitDesktopParams,
-            VideoCodec,
+            ChromaFormat, EndPointMessage, EndPointNegotiateDesktopParamsExtension,
+            EndPointNegotiateDesktopParamsRequest, EndPointNegotiateDesktopParamsResponse,
+            EndPointNegotiateVisitDesktopParams, EndPointNegotiateVisitDesktopParamsExtension,
+            VideoCodec, NEGOTIATE_EXTENSION_VERSION,
         },
     },
-    component::desktop::monitor::get_primary_monitor_params,
+    component::{
+        desktop::monitor::get_active_monitors,
+        video_encoder::config::{new_encoder_config, supported_encoders},
+    },
 };
 use std::sync::Arc;
 
 pub async fn handle_negotiate_desktop_params_request(
     client: Arc<EndPointClient>,
     req: EndPointNegotiateDesktopParamsRequest,
+    extension: Option<EndPointNegotiateDesktopParamsExtension>,
 ) {
-    let resp = negotiate_media_params(&client, req).await;
+    let with_extension = extension.is_some();
+    let (resp, chroma_format) = negotiate_media_params(&client, req, extension).await;
+
+    // the extension is only replied to an active endpoint which understands it
+    if with_extension {
+        if let EndPointNegotiateDesktopParamsResponse::Params(_) = resp {
+            if let Err(err) = client
+                .send(&EndPointMessage::NegotiateVisitDesktopParamsExtension(
+                    EndPointNegotiateVisitDesktopParamsExtension {
+                        version: NEGOTIATE_EXTENSION_VERSION,
+                        chroma_format,
+                    },
+                ))
+                .await
+            {
+                tracing::error!(
+                    ?err,
+                    "handle_negotiate_desktop_params_request: reply extension failed"
+                );
+            }
+        }
+    }
 
     if let Err(err) = client
         .send(&EndPointMessage::NegotiateDesktopParamsResponse(resp))
@@ -30,26 +56,212 @@ pub async fn handle_negotiate_desktop_params_request(
 
 async fn negotiate_media_params(
     client: &EndPointClient,
-    _req: EndPointNegotiateDesktopParamsRequest,
-) -> EndPointNegotiateDesktopParamsResponse {
-    // todo: check support video and audio properties
+    req: EndPointNegotiateDesktopParamsRequest,
+    extension: Option<EndPointNegotiateDesktopParamsExtension>,
+) -> (EndPointNegotiateDesktopParamsResponse, ChromaFormat) {
+    // active endpoints of older versions send no extension, they decode 4:2:0 only
+    // and always visit the primary monitor
+    let (video_codecs, requested_chroma_format, requested_monitor_id) = match extension {
+        Some(extension) => (
+            extension.video_codecs,
+            extension.chroma_format,
+            extension.monitor_id,
+        ),
+        None => (req.video_codecs, ChromaFormat::YUV420, None),
+    };
+
+    let supported_encoders = supported_encoders();
+
+    // only probed for a 4:4:4 request, the encoder config is built for each codec
+    let yuv444_encoders: Vec<VideoCodec> = match requested_chroma_format {
+        ChromaFormat::YUV444 => supported_encoders
+            .iter()
+            .filter(|codec| {
+                new_encoder_config(**codec)
+                    .map(|encoder_config| encoder_config.yuv444_supported())
+                    .unwrap_or(false)
+            })
+            .copied()
+            .collect(),
+        ChromaFormat::YUV420 => Vec::new(),
+    };
+
+    let Some((video_codec, chroma_format)) = select_video_codec(
+        &video_codecs,
+        &supported_encoders,
+        requested_chroma_format,
+        &yuv444_encoders,
+    ) else {
+        tracing::error!(
+            offered = ?video_codecs,
+            supported = ?supported_encoders,
+            "no common video codec"
+        );
+        return (
+            EndPointNegotiateDesktopParamsResponse::VideoError(String::from(
+                "no common video codec",
+            )),
+            ChromaFormat::YUV420,
+        );
+    };
 
-    let primary_monitor = match get_primary_monitor_params() {
-        Ok(monitor) => monitor,
+    let monitors = match get_active_monitors(false) {
+        Ok(monitors) => monitors,
         Err(err) => {
-            tracing::error!(?err, "get primary monitor params failed at negotiate stage");
-            return EndPointNegotiateDesktopParamsResponse::MonitorError(err.to_string());
+            tracing::error!(?err, "get active monitors failed at negotiate stage");
+            return (
+                EndPointNegotiateDesktopParamsResponse::MonitorError(err.to_string()),
+                chroma_format,
+            );
         }
     };
 
+    // the requested monitor may be disconnected since the profile was saved
+    let requested_monitor = requested_monitor_id
+        .and_then(|monitor_id| monitors.iter().find(|monitor| monitor.id == monitor_id));
+
+    let primary_monitor =
+        match requested_monitor.or_else(|| monitors.iter().find(|monitor| monitor.is_primary)) {
+            Some(monitor) => monitor.clone(),
+            None => {
+                tracing::error!("no available monitor at negotiate stage");
+                return (
+                    EndPointNegotiateDesktopParamsResponse::MonitorError(String::from(
+                        "no available monitor",
+                    )),
+                    chroma_format,
+                );
+            }
+        };
+
     client.set_monitor(primary_monitor.clone()).await;
 
     let params = EndPointNegotiateVisitDesktopParams {
-        video_codec: VideoCodec::H264,
+        video_codec,
         os_type: String::from(""),
         os_version: String::from(""),
         primary_monitor,
     };
 
-    EndPointNegotiateDesktopParamsResponse::Params(params)
+    (
+        EndPointNegotiateDesktopParamsResponse::Params(params),
+        chroma_format,
+    )
+}
+
+// the preferred codecs which are also supported locally, in the order of preference
+pub fn common_video_codecs(preferred: &[VideoCodec], supported: &[VideoCodec]) -> Vec<VideoCodec> {
+    preferred
+        .iter()
+        .filter(|codec| supported.contains(codec))
+        .copied()
+        .collect()
+}
+
+// picks the first offered codec that can be encoded locally. full resolution chrominance
+// is only sent if the selected encoder can encode it, otherwise the same codec falls
+// back to 4:2:0 rather than switching to a less preferred one
+pub fn select_video_codec(
+    offered: &[VideoCodec],
+    supported_encoders: &[VideoCodec],
+    requested_chroma_format: ChromaFormat,
+    yuv444_encoders: &[VideoCodec],
+) -> Option<(VideoCodec, ChromaFormat)> {
+    let video_codec = *common_video_codecs(offered, supported_encoders).first()?;
+
+    let chroma_format = match requested_chroma_format {
+        ChromaFormat::YUV444 if yuv444_encoders.contains(&video_codec) => ChromaFormat::YUV444,
+        _ => ChromaFormat::YUV420,
+    };
+
+    Some((video_codec, chroma_format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_video_codec_order() {
+        let supported = [VideoCodec::H264, VideoCodec::Hevc, VideoCodec::AV1];
+
+        // the visitor's preference wins over the local order
+        assert_eq!(
+            select_video_codec(
+                &[VideoCodec::VP9, VideoCodec::AV1, VideoCodec::H264],
+                &supported,
+                ChromaFormat::YUV420,
+                &[],
+            ),
+            Some((VideoCodec::AV1, ChromaFormat::YUV420))
+        );
+
+        assert_eq!(
+            common_video_codecs(
+                &[
+                    VideoCodec::AV1,
+                    VideoCodec::VP8,
+                    VideoCodec::Hevc,
+                    VideoCodec::H264
+                ],
+                &[VideoCodec::H264, VideoCodec::Hevc],
+            ),
+            [VideoCodec::Hevc, VideoCodec::H264]
+        );
+    }
+
+    #[test]
+    fn test_select_video_codec_without_common_codec() {
+        assert_eq!(
+            select_video_codec(
+                &[VideoCodec::VP8, VideoCodec::VP9],
+                &[VideoCodec::H264],
+                ChromaFormat::YUV444,
+                &[VideoCodec::H264],
+            ),
+            None
+        );
+        assert_eq!(
+            select_video_codec(&[], &[VideoCodec::H264], ChromaFormat::YUV420, &[]),
+            None
+        );
+        assert!(common_video_codecs(&[VideoCodec::AV1], &[]).is_empty());
+    }
+
+    #[test]
+    fn test_select_video_codec_yuv444_fallback() {
+        let supported = [VideoCodec::H264, VideoCodec::Hevc];
+
+        assert_eq!(
+            select_video_codec(
+                &[VideoCodec::Hevc, VideoCodec::H264],
+                &supported,
+                ChromaFormat::YUV444,
+                &[VideoCodec::Hevc],
+            ),
+            Some((VideoCodec::Hevc, ChromaFormat::YUV444))
+        );
+
+        // the preferred codec is kept with 4:2:0 instead of a 4:4:4 capable one
+        assert_eq!(
+            select_video_codec(
+                &[VideoCodec::H264, VideoCodec::Hevc],
+                &supported,
+                ChromaFormat::YUV444,
+                &[VideoCodec::Hevc],
+            ),
+            Some((VideoCodec::H264, ChromaFormat::YUV420))
+        );
+
+        // 4:4:4 is never picked unless requested
+        assert_eq!(
+            select_video_codec(
+                &[VideoCodec::Hevc],
+                &supported,
+                ChromaFormat::YUV420,
+                &[VideoCodec::Hevc],
+            ),
+            Some((VideoCodec::Hevc, ChromaFormat::YUV420))
+        );
+    }
 }
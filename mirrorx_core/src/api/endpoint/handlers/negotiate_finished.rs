@@ -1,15 +1,29 @@
 use crate::{
-    api::endpoint::{client::EndPointClient, message::EndPointMessage},
+    api::endpoint::{
+        client::EndPointClient,
+        message::{
+            ChromaFormat, EndPointMessage, EndPointNegotiateFinishedExtension,
+            EndPointNegotiateFinishedRequest, EndPointUpdateFrameRateResponse, VideoCodec,
+        },
+    },
     component::{
         audio::{encoder::AudioEncoder, recorder::new_record_stream_and_rx},
-        desktop::{monitor::get_active_monitors, Duplicator},
-        video_encoder::{config::*, encoder::VideoEncoder},
+        desktop::{synthetic, synthetic_desktop_enabled, Duplicator},
+        frame::DesktopEncodeFrame,
+        video_encoder::{
+            congestion::{CongestionController, EncoderTarget},
+            encoder::VideoEncoder,
+        },
     },
-    error::CoreError,
+    error::{CoreError, CoreResult},
 };
 use cpal::traits::StreamTrait;
 use scopeguard::defer;
 use std::sync::Arc;
+use tokio::sync::watch::Receiver;
+
+#[cfg(target_os = "macos")]
+use crate::component::desktop::monitor::get_active_monitors;
 
 pub struct NegotiateFinishedRequest {
     pub active_device_id: i64,
@@ -18,13 +32,80 @@ pub struct NegotiateFinishedRequest {
     pub texture_id: i64,
 }
 
-pub fn handle_negotiate_finished_request(client: Arc<EndPointClient>) {
-    spawn_desktop_capture_and_encode_process(client.clone());
-    spawn_audio_capture_and_encode_process(client);
+pub async fn handle_negotiate_finished_request(
+    client: Arc<EndPointClient>,
+    req: EndPointNegotiateFinishedRequest,
+    extension: Option<EndPointNegotiateFinishedExtension>,
+) {
+    // active endpoints of older versions send no extension, they decode h264 only and
+    // always take audio
+    let extension = extension.unwrap_or(EndPointNegotiateFinishedExtension {
+        version: 0,
+        video_codec: VideoCodec::H264,
+        chroma_format: ChromaFormat::YUV420,
+        max_bitrate_kbps: u32::MAX,
+        audio_enabled: true,
+    });
+
+    let monitor_id = client.monitor().await.map(|monitor| monitor.id.clone());
+
+    // the visitor can only lower the bitrate cap and frame rate of local settings
+    let mut settings = client.settings().clone();
+    settings.max_bitrate_kbps = settings.max_bitrate_kbps.min(extension.max_bitrate_kbps);
+    settings.frame_rate = settings.frame_rate.min(req.expected_frame_rate.max(1));
+
+    // the encoder starts from the settings and follows receiver reports afterwards
+    let (congestion_controller, target_rx) = CongestionController::new(&settings);
+    client
+        .set_congestion_controller(congestion_controller)
+        .await;
+
+    // the visitor may have asked for more than the local settings allow
+    if let Err(err) = client
+        .send(&EndPointMessage::UpdateFrameRateResponse(
+            EndPointUpdateFrameRateResponse {
+                frame_rate: settings.frame_rate,
+            },
+        ))
+        .await
+    {
+        tracing::error!(?err, "reply negotiated frame rate failed");
+    }
+
+    if synthetic_desktop_enabled() {
+        spawn_polling_desktop_capture_and_encode_process(
+            client.clone(),
+            extension.video_codec,
+            extension.chroma_format,
+            monitor_id,
+            target_rx,
+            synthetic::Duplicator::new,
+            synthetic::Duplicator::capture,
+        );
+    } else {
+        spawn_desktop_capture_and_encode_process(
+            client.clone(),
+            extension.video_codec,
+            extension.chroma_format,
+            monitor_id,
+            target_rx,
+        );
+    }
+
+    // audio is shared only if both the visitor and the local settings allow it
+    if extension.audio_enabled && client.settings().audio_enabled {
+        spawn_audio_capture_and_encode_process(client);
+    }
 }
 
 #[cfg(target_os = "macos")]
-fn spawn_desktop_capture_and_encode_process(client: Arc<EndPointClient>) {
+fn spawn_desktop_capture_and_encode_process(
+    client: Arc<EndPointClient>,
+    video_codec: VideoCodec,
+    chroma_format: ChromaFormat,
+    monitor_id: Option<String>,
+    target_rx: Receiver<EncoderTarget>,
+) {
     let (capture_frame_tx, mut capture_frame_rx) = tokio::sync::mpsc::channel(180);
 
     tokio::task::spawn_blocking(move || {
@@ -42,27 +123,14 @@ fn spawn_desktop_capture_and_encode_process(client: Arc<EndPointClient>) {
             }
         };
 
-        let mut encoder = match VideoEncoder::new(libx264::Libx264Config::default(), client.clone())
-        {
-            Ok(encoder) => encoder,
-            Err(err) => {
-                tracing::error!(?err, "initialize encoder failed");
-                return;
-            }
-        };
-
-        let primary_monitor = monitors.iter().find(|monitor| monitor.is_primary);
-
-        let (duplicator, monitor_id) = match Duplicator::new(
-            primary_monitor.map(|monitor| monitor.id.to_owned()),
-            capture_frame_tx,
-        ) {
-            Ok(duplicator) => duplicator,
-            Err(err) => {
-                tracing::error!(?err, "initialize encoder failed");
-                return;
-            }
-        };
+        let (duplicator, monitor_id) =
+            match Duplicator::new(monitor_id, chroma_format, capture_frame_tx) {
+                Ok(duplicator) => duplicator,
+                Err(err) => {
+                    tracing::error!(?err, "desktop duplicator initialize failed");
+                    return;
+                }
+            };
 
         let select_monitor = match monitors
             .into_iter()
@@ -88,58 +156,86 @@ fn spawn_desktop_capture_and_encode_process(client: Arc<EndPointClient>) {
             let _ = duplicator.stop();
         }
 
+        // an encoder that failed is dropped and a new one starts from the next frame
         loop {
-            match capture_frame_rx.blocking_recv() {
-                Some(capture_frame) => {
-                    if let Err(err) = encoder.encode(capture_frame) {
-                        if let CoreError::OutgoingMessageChannelDisconnect = err {
-                            tracing::info!("desktop capture and encode process exit");
-                            return;
-                        } else {
-                            tracing::error!("video encode failed");
-                            break;
+            let mut encoder =
+                match VideoEncoder::new(video_codec, client.clone(), target_rx.clone()) {
+                    Ok(encoder) => encoder,
+                    Err(err) => {
+                        tracing::error!(?err, "video encoder initialize failed");
+                        return;
+                    }
+                };
+
+            loop {
+                match capture_frame_rx.blocking_recv() {
+                    Some(capture_frame) => {
+                        if let Err(err) = encoder.encode(capture_frame) {
+                            if let CoreError::OutgoingMessageChannelDisconnect = err {
+                                tracing::info!("desktop capture and encode process exit");
+                                return;
+                            } else {
+                                tracing::error!(?err, "video encode failed, recreate encoder");
+                                break;
+                            }
                         }
                     }
-                }
-                None => {
-                    tracing::error!("capture frame rx recv error");
-                    break;
+                    None => {
+                        tracing::error!("capture frame channel closed");
+                        return;
+                    }
                 }
             }
         }
     });
 }
 
-#[cfg(target_os = "windows")]
-fn spawn_desktop_capture_and_encode_process(client: Arc<EndPointClient>) {
-    let monitors = match get_active_monitors(false) {
-        Ok(params) => params,
-        Err(err) => {
-            tracing::error!(?err, "get_active_monitors failed");
-            return;
-        }
-    };
+#[cfg(not(target_os = "macos"))]
+fn spawn_desktop_capture_and_encode_process(
+    client: Arc<EndPointClient>,
+    video_codec: VideoCodec,
+    chroma_format: ChromaFormat,
+    monitor_id: Option<String>,
+    target_rx: Receiver<EncoderTarget>,
+) {
+    spawn_polling_desktop_capture_and_encode_process(
+        client,
+        video_codec,
+        chroma_format,
+        monitor_id,
+        target_rx,
+        Duplicator::new,
+        Duplicator::capture,
+    );
+}
 
+// the duplicator is created on the capture thread and polled for frames there
+fn spawn_polling_desktop_capture_and_encode_process<D: 'static>(
+    client: Arc<EndPointClient>,
+    video_codec: VideoCodec,
+    chroma_format: ChromaFormat,
+    monitor_id: Option<String>,
+    target_rx: Receiver<EncoderTarget>,
+    new_duplicator: fn(Option<String>, ChromaFormat) -> CoreResult<(D, String)>,
+    capture: fn(&mut D) -> CoreResult<DesktopEncodeFrame>,
+) {
     let (capture_frame_tx, mut capture_frame_rx) = tokio::sync::mpsc::channel(180);
 
     tokio::task::spawn_blocking(move || {
         defer! {
-            tracing::info!( "desktop capture process exit");
+            tracing::info!("desktop capture process exit");
         }
 
-        let primary_monitor = monitors.iter().find(|monitor| monitor.is_primary);
-
-        let (mut duplicator, _) =
-            match Duplicator::new(primary_monitor.map(|monitor| monitor.id.to_owned())) {
-                Ok(duplicator) => duplicator,
-                Err(err) => {
-                    tracing::error!(?err, "initialize encoder failed");
-                    return;
-                }
-            };
+        let (mut duplicator, _) = match new_duplicator(monitor_id, chroma_format) {
+            Ok(duplicator) => duplicator,
+            Err(err) => {
+                tracing::error!(?err, "desktop duplicator initialize failed");
+                return;
+            }
+        };
 
         loop {
-            match duplicator.capture() {
+            match capture(&mut duplicator) {
                 Ok(capture_frame) => {
                     if capture_frame_tx.blocking_send(capture_frame).is_err() {
                         return;
@@ -153,37 +249,32 @@ fn spawn_desktop_capture_and_encode_process(client: Arc<EndPointClient>) {
         }
     });
 
-    tokio::task::spawn_blocking(move || {
-        loop {
-            // defer! {
-            //     tracing::info!(?active_device_id, ?passive_device_id, "video encode process exit");
-            // }
-
-            let mut encoder =
-                match VideoEncoder::new(libx264::Libx264Config::default(), client.clone()) {
-                    Ok(encoder) => encoder,
-                    Err(err) => {
-                        tracing::error!(?err, "video encoder initialize failed");
-                        return;
-                    }
-                };
+    // an encoder that failed is dropped and a new one starts from the next frame
+    tokio::task::spawn_blocking(move || loop {
+        let mut encoder = match VideoEncoder::new(video_codec, client.clone(), target_rx.clone()) {
+            Ok(encoder) => encoder,
+            Err(err) => {
+                tracing::error!(?err, "video encoder initialize failed");
+                return;
+            }
+        };
 
-            loop {
-                match capture_frame_rx.blocking_recv() {
-                    Some(capture_frame) => {
-                        if let Err(err) = encoder.encode(capture_frame) {
-                            if let CoreError::OutgoingMessageChannelDisconnect = err {
-                                tracing::info!("desktop capture and encode process exit");
-                                return;
-                            } else {
-                                tracing::error!(?err, "video encode failed");
-                            }
+        loop {
+            match capture_frame_rx.blocking_recv() {
+                Some(capture_frame) => {
+                    if let Err(err) = encoder.encode(capture_frame) {
+                        if let CoreError::OutgoingMessageChannelDisconnect = err {
+                            tracing::info!("desktop capture and encode process exit");
+                            return;
+                        } else {
+                            tracing::error!(?err, "video encode failed, recreate encoder");
+                            break;
                         }
                     }
-                    None => {
-                        tracing::error!("capture frame channel closed");
-                        return;
-                    }
+                }
+                None => {
+                    tracing::error!("capture frame channel closed");
+                    return;
                 }
             }
         }
@@ -0,0 +1,9 @@
+use crate::api::endpoint::{client::EndPointClient, message::EndPointReceiverReport};
+use std::sync::Arc;
+
+pub async fn handle_receiver_report(client: Arc<EndPointClient>, report: EndPointReceiverReport) {
+    match *client.congestion_controller().await {
+        Some(ref mut controller) => controller.on_receiver_report(&report),
+        None => tracing::warn!("receive receiver report before negotiate finished"),
+    }
+}
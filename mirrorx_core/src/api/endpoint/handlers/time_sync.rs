@@ -0,0 +1,32 @@
+use crate::{
+    api::endpoint::{
+        client::EndPointClient,
+        message::{EndPointMessage, EndPointTimeSyncRequest, EndPointTimeSyncResponse},
+    },
+    utility::time::unix_micros,
+};
+use std::sync::Arc;
+
+pub async fn handle_time_sync_request(client: Arc<EndPointClient>, req: EndPointTimeSyncRequest) {
+    let receive_us = unix_micros();
+
+    let response = EndPointTimeSyncResponse {
+        originate_us: req.originate_us,
+        receive_us,
+        transmit_us: unix_micros(),
+    };
+
+    if let Err(err) = client
+        .send(&EndPointMessage::TimeSyncResponse(response))
+        .await
+    {
+        tracing::error!(?err, "send time sync response failed");
+    }
+}
+
+pub async fn handle_time_sync_response(
+    client: Arc<EndPointClient>,
+    resp: EndPointTimeSyncResponse,
+) {
+    client.update_video_statistics(|statistics| statistics.on_time_sync_response(&resp));
+}
@@ -0,0 +1,37 @@
+use crate::api::endpoint::{
+    client::EndPointClient,
+    message::{EndPointMessage, EndPointUpdateFrameRateRequest, EndPointUpdateFrameRateResponse},
+};
+use std::sync::Arc;
+
+pub async fn handle_update_frame_rate_request(
+    client: Arc<EndPointClient>,
+    req: EndPointUpdateFrameRateRequest,
+) {
+    // the visitor can't raise the frame rate over local settings
+    let frame_rate = req.frame_rate.clamp(1, client.settings().frame_rate);
+
+    match *client.congestion_controller().await {
+        Some(ref controller) => controller.set_frame_rate(frame_rate),
+        None => {
+            tracing::warn!("receive update frame rate request before negotiate finished");
+            return;
+        }
+    }
+
+    if let Err(err) = client
+        .send(&EndPointMessage::UpdateFrameRateResponse(
+            EndPointUpdateFrameRateResponse { frame_rate },
+        ))
+        .await
+    {
+        tracing::error!(?err, "reply update frame rate failed");
+    }
+}
+
+pub async fn handle_update_frame_rate_response(
+    client: Arc<EndPointClient>,
+    resp: EndPointUpdateFrameRateResponse,
+) {
+    client.set_frame_rate(resp.frame_rate);
+}
@@ -0,0 +1,22 @@
+use crate::api::endpoint::{client::EndPointClient, message::EndPointUpdateResolutionRequest};
+use std::sync::Arc;
+
+// smaller sizes would make the remote desktop unusable
+const MIN_WIDTH: u16 = 320;
+const MIN_HEIGHT: u16 = 240;
+
+pub async fn handle_update_resolution_request(
+    client: Arc<EndPointClient>,
+    req: EndPointUpdateResolutionRequest,
+) {
+    let (max_width, max_height) = if req.max_width == 0 || req.max_height == 0 {
+        (0, 0)
+    } else {
+        (req.max_width.max(MIN_WIDTH), req.max_height.max(MIN_HEIGHT))
+    };
+
+    match *client.congestion_controller().await {
+        Some(ref controller) => controller.set_max_size(max_width, max_height),
+        None => tracing::warn!("receive update resolution request before negotiate finished"),
+    }
+}
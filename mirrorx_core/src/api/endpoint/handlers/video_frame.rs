@@ -1,32 +1,208 @@
 use crate::{
-    api::endpoint::{message::EndPointVideoFrame, EndPointID},
-    component::{frame::DesktopDecodeFrame, video_decoder::decoder::VideoDecoder},
+    api::endpoint::{client::EndPointClient, message::EndPointVideoFrame},
+    component::{
+        frame::DesktopDecodeFrame,
+        video_decoder::{decoder::VideoDecoder, jitter_buffer::JitterBuffer},
+    },
 };
-use tokio::sync::mpsc::Sender;
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+// the key frame request is sent again if the key frame doesn't arrive in time
+const KEY_FRAME_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
 
 pub fn serve_video_decode(
-    id: EndPointID,
+    client: Arc<EndPointClient>,
+    mut rx: Receiver<EndPointVideoFrame>,
     render_tx: Sender<DesktopDecodeFrame>,
-) -> Sender<EndPointVideoFrame> {
-    let (tx, mut rx) = tokio::sync::mpsc::channel(120);
+) {
+    let (decoded_tx, decoded_rx) = tokio::sync::mpsc::channel(180);
+    serve_frame_pacing(
+        client.clone(),
+        JitterBuffer::new(client.settings().jitter_buffer),
+        decoded_rx,
+        render_tx,
+    );
 
     tokio::task::spawn_blocking(move || {
-        tracing::info!(?id, "video decode process");
+        tracing::info!(?client, "video decode process");
 
-        let mut decoder = VideoDecoder::new(render_tx);
+        let mut decoder = VideoDecoder::new(decoded_tx);
+        let mut key_frame_requester = KeyFrameRequester::default();
 
         while let Some(video_frame) = rx.blocking_recv() {
+            let sequence = video_frame.sequence;
+
             // let instant = std::time::Instant::now();
             if let Err(err) = decoder.decode(video_frame) {
-                tracing::error!(?err, "decode video frame failed");
-                break;
+                tracing::error!(?err, "decode video frame failed, wait for next key frame");
+                decoder.reset();
+                key_frame_requester.reset();
+                client.update_video_statistics(|statistics| statistics.on_video_dropped(sequence));
+            } else if decoder.is_waiting_key_frame() {
+                // frames are dropped until next key frame after an error or a sequence gap
+                client.update_video_statistics(|statistics| statistics.on_video_dropped(sequence));
+            } else {
+                client.update_video_statistics(|statistics| statistics.on_video_decoded(sequence));
             }
             // let elapsed = instant.elapsed();
             // tracing::info!(?elapsed, "instant");
+
+            if key_frame_requester.should_request(decoder.is_waiting_key_frame(), Instant::now()) {
+                if let Err(err) = client.request_key_frame() {
+                    tracing::error!(?err, "request key frame failed");
+                }
+            }
         }
 
         tracing::info!("video decode process exit");
     });
+}
+
+// requests a key frame once the decoder begins waiting for it, and again each interval
+// until it arrives
+#[derive(Default)]
+struct KeyFrameRequester {
+    last_request: Option<Instant>,
+}
+
+impl KeyFrameRequester {
+    fn reset(&mut self) {
+        self.last_request = None;
+    }
+
+    fn should_request(&mut self, waiting_key_frame: bool, now: Instant) -> bool {
+        if !waiting_key_frame {
+            self.last_request = None;
+            return false;
+        }
+
+        if self.last_request.map_or(false, |last_request| {
+            now.saturating_duration_since(last_request) < KEY_FRAME_REQUEST_INTERVAL
+        }) {
+            return false;
+        }
+
+        self.last_request = Some(now);
+        true
+    }
+}
+
+fn serve_frame_pacing(
+    client: Arc<EndPointClient>,
+    mut jitter_buffer: JitterBuffer,
+    mut rx: Receiver<DesktopDecodeFrame>,
+    render_tx: Sender<DesktopDecodeFrame>,
+) {
+    tokio::spawn(async move {
+        let mut last_resolution = None;
+
+        loop {
+            while let Some(frame) = jitter_buffer.pop_ready() {
+                if render_tx.send(frame).await.is_err() {
+                    tracing::info!("frame pacing process exit");
+                    return;
+                }
+            }
+
+            let next_release = jitter_buffer.next_release();
+
+            tokio::select! {
+                _ = sleep_until_release(next_release) => {}
+                frame = rx.recv() => match frame {
+                    // frames from remotes which don't send capture time are not paced
+                    Some(frame) if frame.capture_time_us == 0 => {
+                        if render_tx.send(frame).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(frame) => {
+                        // the remote encoder starts over with a new resolution, frames of
+                        // the old one are stale and its jitter doesn't apply
+                        if last_resolution.map_or(false, |resolution| {
+                            resolution != (frame.width, frame.height)
+                        }) {
+                            for sequence in jitter_buffer.reset() {
+                                client.update_video_statistics(|statistics| {
+                                    statistics.on_video_dropped(sequence)
+                                });
+                            }
+                        }
+
+                        last_resolution = Some((frame.width, frame.height));
+
+                        let sequence = frame.sequence;
+                        if !jitter_buffer.push(frame) {
+                            client.update_video_statistics(|statistics| {
+                                statistics.on_video_dropped(sequence)
+                            });
+                        }
+                    }
+                    None => break,
+                },
+            }
+        }
+
+        tracing::info!("frame pacing process exit");
+    });
+}
+
+async fn sleep_until_release(release: Option<Instant>) {
+    match release {
+        Some(release) => tokio::time::sleep_until(release.into()).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::endpoint::message::{EndPointVideoFrameTiming, VideoCodec};
+
+    fn video_frame(sequence: u64, key_frame: bool) -> EndPointVideoFrame {
+        EndPointVideoFrame {
+            codec: VideoCodec::H264,
+            sequence,
+            key_frame,
+            width: 1920,
+            height: 1080,
+            pts: sequence as i64,
+            timing: EndPointVideoFrameTiming::default(),
+            buffer: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_dropped_frames_request_one_key_frame() {
+        let (decoded_tx, _decoded_rx) = tokio::sync::mpsc::channel(1);
+        let mut decoder = VideoDecoder::new(decoded_tx);
+        let mut key_frame_requester = KeyFrameRequester::default();
+        let now = Instant::now();
+
+        // frames dropped while the decoder waits for a key frame, after a gap or at the
+        // beginning, ask for it once within the interval
+        let mut requests = 0;
+        for (index, sequence) in [0, 1, 3, 4, 5, 2, 6].into_iter().enumerate() {
+            decoder.decode(video_frame(sequence, false)).unwrap();
+            assert!(decoder.is_waiting_key_frame());
+
+            let now = now + Duration::from_millis(index as u64 * 100);
+            if key_frame_requester.should_request(decoder.is_waiting_key_frame(), now) {
+                requests += 1;
+            }
+        }
+
+        assert_eq!(requests, 1);
+
+        // the request is repeated when the key frame doesn't arrive in time
+        assert!(key_frame_requester.should_request(true, now + KEY_FRAME_REQUEST_INTERVAL));
+        assert!(!key_frame_requester.should_request(true, now + KEY_FRAME_REQUEST_INTERVAL));
 
-    tx
+        // and starts over once decoding recovers
+        assert!(!key_frame_requester.should_request(false, now + KEY_FRAME_REQUEST_INTERVAL));
+        assert!(key_frame_requester.should_request(true, now + KEY_FRAME_REQUEST_INTERVAL));
+    }
 }
@@ -1,5 +1,6 @@
 use crate::component::{desktop::monitor::Monitor, fs::Directory, input::key::MouseKey};
 use cpal::SampleFormat;
+use mirrorx_native::ffmpeg::codecs::codec_id::*;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
@@ -28,6 +29,16 @@ pub enum EndPointMessage {
     InputCommand(EndPointInput),
     FileTransferBlock(EndPointFileTransferBlock),
     FileTransferError(EndPointFileTransferError),
+    ReceiverReport(EndPointReceiverReport),
+    UpdateFrameRateRequest(EndPointUpdateFrameRateRequest),
+    KeyFrameRequest,
+    UpdateResolutionRequest(EndPointUpdateResolutionRequest),
+    TimeSyncRequest(EndPointTimeSyncRequest),
+    TimeSyncResponse(EndPointTimeSyncResponse),
+    UpdateFrameRateResponse(EndPointUpdateFrameRateResponse),
+    NegotiateDesktopParamsExtension(EndPointNegotiateDesktopParamsExtension),
+    NegotiateVisitDesktopParamsExtension(EndPointNegotiateVisitDesktopParamsExtension),
+    NegotiateFinishedExtension(EndPointNegotiateFinishedExtension),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -42,6 +53,32 @@ pub struct EndPointNegotiateDesktopParamsRequest {
     pub video_codecs: Vec<VideoCodec>,
 }
 
+impl EndPointNegotiateDesktopParamsRequest {
+    // splits the offer into the extension and the request which older peers decode
+    pub fn with_extension(
+        video_codecs: Vec<VideoCodec>,
+        chroma_format: ChromaFormat,
+        monitor_id: Option<String>,
+    ) -> (EndPointNegotiateDesktopParamsExtension, Self) {
+        let request = EndPointNegotiateDesktopParamsRequest {
+            video_codecs: video_codecs
+                .iter()
+                .filter(|codec| codec.is_legacy())
+                .copied()
+                .collect(),
+        };
+
+        let extension = EndPointNegotiateDesktopParamsExtension {
+            version: NEGOTIATE_EXTENSION_VERSION,
+            chroma_format,
+            monitor_id,
+            video_codecs,
+        };
+
+        (extension, request)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct EndPointNegotiateVisitDesktopParams {
     pub video_codec: VideoCodec,
@@ -57,12 +94,39 @@ pub enum EndPointNegotiateDesktopParamsResponse {
     Params(EndPointNegotiateVisitDesktopParams),
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
 pub enum VideoCodec {
     H264,
     Hevc,
     VP8,
     VP9,
+    AV1,
+}
+
+impl VideoCodec {
+    pub const ALL: [VideoCodec; 5] = [
+        VideoCodec::H264,
+        VideoCodec::Hevc,
+        VideoCodec::VP8,
+        VideoCodec::VP9,
+        VideoCodec::AV1,
+    ];
+
+    // peers of the first release decode no other codec, later ones are only offered in
+    // the negotiate extension
+    pub fn is_legacy(&self) -> bool {
+        !matches!(self, VideoCodec::AV1)
+    }
+
+    pub fn av_codec_id(&self) -> AVCodecID {
+        match self {
+            VideoCodec::H264 => AV_CODEC_ID_H264,
+            VideoCodec::Hevc => AV_CODEC_ID_HEVC,
+            VideoCodec::VP8 => AV_CODEC_ID_VP8,
+            VideoCodec::VP9 => AV_CODEC_ID_VP9,
+            VideoCodec::AV1 => AV_CODEC_ID_AV1,
+        }
+    }
 }
 
 impl Default for VideoCodec {
@@ -71,6 +135,20 @@ impl Default for VideoCodec {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ChromaFormat {
+    // chrominance at half resolution in both directions
+    YUV420,
+    // chrominance at full resolution, keeps colored text sharp at a higher bitrate
+    YUV444,
+}
+
+impl Default for ChromaFormat {
+    fn default() -> Self {
+        ChromaFormat::YUV420
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum AudioSampleFormat {
     I8,
@@ -132,16 +210,102 @@ pub struct EndPointNegotiateFinishedRequest {
     pub expected_frame_rate: u8,
 }
 
+// the negotiate messages keep the layout of the first release. fields added later are
+// sent in an extension message right before the message they extend, peers of older
+// versions can't decode the extension and skip it, fields are only appended to the
+// extensions in later versions
+pub const NEGOTIATE_EXTENSION_VERSION: u8 = 1;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EndPointNegotiateDesktopParamsExtension {
+    pub version: u8,
+    pub chroma_format: ChromaFormat,
+    pub monitor_id: Option<String>,
+    // the whole offer in order of preference, the request carries legacy codecs alone
+    pub video_codecs: Vec<VideoCodec>,
+}
+
+// only replied to an active endpoint which sent its extension
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EndPointNegotiateVisitDesktopParamsExtension {
+    pub version: u8,
+    pub chroma_format: ChromaFormat,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EndPointNegotiateFinishedExtension {
+    pub version: u8,
+    pub video_codec: VideoCodec,
+    pub chroma_format: ChromaFormat,
+    pub max_bitrate_kbps: u32,
+    pub audio_enabled: bool,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct EndPointVideoFrame {
+    pub codec: VideoCodec,
+    // increases by one for every encoded frame, gaps are counted as lost frames
+    pub sequence: u64,
+    pub key_frame: bool,
     pub width: i32,
     pub height: i32,
     pub pts: i64,
+    pub timing: EndPointVideoFrameTiming,
 
     #[serde(with = "serde_bytes")]
     pub buffer: Vec<u8>,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct EndPointVideoFrameTiming {
+    // wall clock of the passive endpoint in microseconds
+    pub capture_time_us: i64,
+    // microseconds from capture until the frame was encoded
+    pub encode_cost_us: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EndPointUpdateFrameRateRequest {
+    pub frame_rate: u8,
+}
+
+// the frame rate the passive endpoint actually encodes at, after the local limit applied
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EndPointUpdateFrameRateResponse {
+    pub frame_rate: u8,
+}
+
+// frames are scaled down to fit in the given size, zero means no limit
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EndPointUpdateResolutionRequest {
+    pub max_width: u16,
+    pub max_height: u16,
+}
+
+// sent periodically by the active endpoint to let the passive endpoint adapt bitrate
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EndPointReceiverReport {
+    pub decode_queue_depth: u32,
+    pub jitter_ms: u32,
+    pub received_frames: u32,
+    pub lost_frames: u32,
+    pub throughput_kbps: u32,
+}
+
+// ntp like exchange, all times are wall clock in microseconds, the active endpoint
+// estimates clock offset and round trip time from the response
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EndPointTimeSyncRequest {
+    pub originate_us: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct EndPointTimeSyncResponse {
+    pub originate_us: i64,
+    pub receive_us: i64,
+    pub transmit_us: i64,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub struct EndPointAudioFrame {
     pub channels: u8,
@@ -219,3 +383,213 @@ pub struct EndPointFileTransferBlock {
 pub struct EndPointFileTransferError {
     pub id: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::bincode::{bincode_deserialize, bincode_serialize};
+
+    // the negotiate messages as the first release encodes them, other messages are kept
+    // for their variant index only
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum LegacyEndPointMessage {
+        Error,
+        CallRequest,
+        CallReply,
+        NegotiateDesktopParamsRequest(LegacyNegotiateDesktopParamsRequest),
+        NegotiateDesktopParamsResponse(LegacyNegotiateDesktopParamsResponse),
+        NegotiateFinishedRequest(LegacyNegotiateFinishedRequest),
+        VideoFrame,
+        AudioFrame,
+        InputCommand,
+        FileTransferBlock,
+        FileTransferError,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum LegacyVideoCodec {
+        H264,
+        Hevc,
+        VP8,
+        VP9,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct LegacyNegotiateDesktopParamsRequest {
+        video_codecs: Vec<LegacyVideoCodec>,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum LegacyNegotiateDesktopParamsResponse {
+        VideoError(String),
+        MonitorError(String),
+        Params(LegacyNegotiateVisitDesktopParams),
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct LegacyNegotiateVisitDesktopParams {
+        video_codec: LegacyVideoCodec,
+        os_type: String,
+        os_version: String,
+        primary_monitor: Monitor,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct LegacyNegotiateFinishedRequest {
+        expected_frame_rate: u8,
+    }
+
+    fn monitor() -> Monitor {
+        Monitor {
+            id: String::from("1"),
+            name: String::from("monitor"),
+            refresh_rate: 60,
+            width: 1920,
+            height: 1080,
+            is_primary: true,
+            screen_shot: None,
+            left: 0,
+            top: 0,
+        }
+    }
+
+    #[test]
+    fn test_legacy_peer_skips_negotiate_extensions() {
+        let (desktop_params_extension, desktop_params_request) =
+            EndPointNegotiateDesktopParamsRequest::with_extension(
+                vec![VideoCodec::AV1, VideoCodec::Hevc, VideoCodec::H264],
+                ChromaFormat::YUV444,
+                Some(String::from("2")),
+            );
+
+        assert_eq!(
+            desktop_params_extension.video_codecs,
+            [VideoCodec::AV1, VideoCodec::Hevc, VideoCodec::H264]
+        );
+
+        let extensions = [
+            EndPointMessage::NegotiateDesktopParamsExtension(desktop_params_extension),
+            EndPointMessage::NegotiateVisitDesktopParamsExtension(
+                EndPointNegotiateVisitDesktopParamsExtension {
+                    version: NEGOTIATE_EXTENSION_VERSION,
+                    chroma_format: ChromaFormat::YUV444,
+                },
+            ),
+            EndPointMessage::NegotiateFinishedExtension(EndPointNegotiateFinishedExtension {
+                version: NEGOTIATE_EXTENSION_VERSION,
+                video_codec: VideoCodec::AV1,
+                chroma_format: ChromaFormat::YUV444,
+                max_bitrate_kbps: 8000,
+                audio_enabled: false,
+            }),
+        ];
+
+        for extension in extensions.iter() {
+            let buffer = bincode_serialize(extension).unwrap();
+            assert!(bincode_deserialize::<LegacyEndPointMessage>(&buffer).is_err());
+        }
+
+        // codecs unknown to older peers are left out of the request
+        let buffer = bincode_serialize(&EndPointMessage::NegotiateDesktopParamsRequest(
+            desktop_params_request,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            bincode_deserialize::<LegacyEndPointMessage>(&buffer).unwrap(),
+            LegacyEndPointMessage::NegotiateDesktopParamsRequest(
+                LegacyNegotiateDesktopParamsRequest {
+                    video_codecs: vec![LegacyVideoCodec::Hevc, LegacyVideoCodec::H264],
+                }
+            )
+        );
+
+        // every codec but the newer ones is known to older peers
+        for video_codec in VideoCodec::ALL {
+            let buffer = bincode_serialize(&video_codec).unwrap();
+            assert_eq!(
+                bincode_deserialize::<LegacyVideoCodec>(&buffer).is_ok(),
+                video_codec.is_legacy()
+            );
+        }
+
+        let buffer = bincode_serialize(&EndPointMessage::NegotiateDesktopParamsResponse(
+            EndPointNegotiateDesktopParamsResponse::Params(EndPointNegotiateVisitDesktopParams {
+                video_codec: VideoCodec::H264,
+                os_type: String::new(),
+                os_version: String::new(),
+                primary_monitor: monitor(),
+            }),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            bincode_deserialize::<LegacyEndPointMessage>(&buffer).unwrap(),
+            LegacyEndPointMessage::NegotiateDesktopParamsResponse(
+                LegacyNegotiateDesktopParamsResponse::Params(LegacyNegotiateVisitDesktopParams {
+                    video_codec: LegacyVideoCodec::H264,
+                    os_type: String::new(),
+                    os_version: String::new(),
+                    primary_monitor: monitor(),
+                })
+            )
+        );
+
+        let buffer = bincode_serialize(&EndPointMessage::NegotiateFinishedRequest(
+            EndPointNegotiateFinishedRequest {
+                expected_frame_rate: 30,
+            },
+        ))
+        .unwrap();
+
+        assert_eq!(
+            bincode_deserialize::<LegacyEndPointMessage>(&buffer).unwrap(),
+            LegacyEndPointMessage::NegotiateFinishedRequest(LegacyNegotiateFinishedRequest {
+                expected_frame_rate: 30,
+            })
+        );
+    }
+
+    #[test]
+    fn test_legacy_negotiate_messages_decoded() {
+        let buffer = bincode_serialize(&LegacyEndPointMessage::NegotiateDesktopParamsRequest(
+            LegacyNegotiateDesktopParamsRequest {
+                video_codecs: vec![LegacyVideoCodec::H264],
+            },
+        ))
+        .unwrap();
+
+        assert_eq!(
+            bincode_deserialize::<EndPointMessage>(&buffer).unwrap(),
+            EndPointMessage::NegotiateDesktopParamsRequest(EndPointNegotiateDesktopParamsRequest {
+                video_codecs: vec![VideoCodec::H264],
+            })
+        );
+
+        let buffer = bincode_serialize(&LegacyEndPointMessage::NegotiateDesktopParamsResponse(
+            LegacyNegotiateDesktopParamsResponse::MonitorError(String::from("no monitor")),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            bincode_deserialize::<EndPointMessage>(&buffer).unwrap(),
+            EndPointMessage::NegotiateDesktopParamsResponse(
+                EndPointNegotiateDesktopParamsResponse::MonitorError(String::from("no monitor"))
+            )
+        );
+
+        let buffer = bincode_serialize(&LegacyEndPointMessage::NegotiateFinishedRequest(
+            LegacyNegotiateFinishedRequest {
+                expected_frame_rate: 60,
+            },
+        ))
+        .unwrap();
+
+        assert_eq!(
+            bincode_deserialize::<EndPointMessage>(&buffer).unwrap(),
+            EndPointMessage::NegotiateFinishedRequest(EndPointNegotiateFinishedRequest {
+                expected_frame_rate: 60,
+            })
+        );
+    }
+}
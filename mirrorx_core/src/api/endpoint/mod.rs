@@ -4,19 +4,32 @@ pub mod id;
 pub mod message;
 
 use self::{
-    client::EndPointClient,
+    client::{EndPointClient, EndPointFramed},
     handlers::{audio_frame::serve_audio_decode, video_frame::serve_video_decode},
     id::EndPointID,
 };
-use crate::{error::CoreResult, utility::nonce_value::NonceValue, DesktopDecodeFrame};
+use crate::{
+    api::config::{
+        entity::{history::Record, kv::Settings, profile::ConnectionProfile},
+        LocalStorage,
+    },
+    error::CoreResult,
+    utility::nonce_value::NonceValue,
+    DesktopDecodeFrame,
+};
 use ring::aead::{OpeningKey, SealingKey};
 use std::{net::SocketAddr, sync::Arc};
 use tokio::net::{TcpStream, UdpSocket};
 
 pub enum EndPointStream {
     ActiveTCP(SocketAddr),
-    ActiveUDP(SocketAddr),
+    ActiveUDP {
+        remote_addr: SocketAddr,
+        socket: UdpSocket,
+    },
     PassiveTCP(TcpStream),
+    // a relay connection which finished the handshake
+    RelayTCP(EndPointFramed),
     PassiveUDP {
         remote_addr: SocketAddr,
         socket: UdpSocket,
@@ -28,14 +41,16 @@ pub async fn create_desktop_active_endpoint_client(
     key_pair: Option<(OpeningKey<NonceValue>, SealingKey<NonceValue>)>,
     stream: EndPointStream,
     visit_credentials: Option<Vec<u8>>,
+    settings: Settings,
+    profile: ConnectionProfile,
 ) -> CoreResult<(
     Arc<EndPointClient>,
     tokio::sync::mpsc::Receiver<DesktopDecodeFrame>,
 )> {
     let (render_frame_tx, render_frame_rx) = tokio::sync::mpsc::channel(180);
+    let (video_frame_tx, video_frame_rx) = tokio::sync::mpsc::channel(120);
     let (audio_frame_tx, audio_frame_rx) = tokio::sync::mpsc::channel(180);
 
-    let video_frame_tx = serve_video_decode(endpoint_id, render_frame_tx);
     serve_audio_decode(endpoint_id, audio_frame_rx);
 
     let client = EndPointClient::new_desktop_active(
@@ -45,9 +60,14 @@ pub async fn create_desktop_active_endpoint_client(
         video_frame_tx,
         audio_frame_tx,
         visit_credentials,
+        settings,
+        profile,
     )
     .await?;
 
+    // the decoder asks for key frames through the client to recover from errors
+    serve_video_decode(client.clone(), video_frame_rx, render_frame_tx);
+
     Ok((client, render_frame_rx))
 }
 
@@ -69,7 +89,30 @@ pub async fn create_passive_endpoint_client(
     key_pair: Option<(OpeningKey<NonceValue>, SealingKey<NonceValue>)>,
     stream: EndPointStream,
     visit_credentials: Option<Vec<u8>>,
-) -> CoreResult<()> {
-    EndPointClient::new_passive(endpoint_id, key_pair, stream, visit_credentials).await?;
-    Ok(())
+    settings: Settings,
+) -> CoreResult<Arc<EndPointClient>> {
+    EndPointClient::new_passive(endpoint_id, key_pair, stream, visit_credentials, settings).await
+}
+
+// the record is saved when session starts and completed after the session exits
+pub fn record_session(storage: LocalStorage, client: Arc<EndPointClient>, record: Record) {
+    tokio::spawn(async move {
+        let record = match storage.history().create(record) {
+            Ok(record) => record,
+            Err(err) => {
+                tracing::error!(?err, "create session history record failed");
+                return;
+            }
+        };
+
+        let end_reason = client.wait_exit().await;
+
+        if let Err(err) =
+            storage
+                .history()
+                .finish(record.id, client.transferred_bytes(), end_reason)
+        {
+            tracing::error!(?err, "finish session history record failed");
+        }
+    });
 }
@@ -1,25 +1,42 @@
 pub mod http_message;
 pub mod subscribe_message;
+pub mod transport;
 
 use self::{
     http_message::{
         IdentityResponse, RegisterRequest, RegisterResponse, Response, VisitRequest, VisitResponse,
     },
     subscribe_message::{
-        ActiveEndpointKeyExchangeSecret, ClientMessage, PassiveEndpointKeyExchangeSecret,
-        ServerMessage, Subscription, VisitFailureReason,
+        ActiveEndpointKeyExchangeExtension, ActiveEndpointKeyExchangeSecret, ClientMessage,
+        PassiveEndpointKeyExchangeExtension, PassiveEndpointKeyExchangeSecret, ServerMessage,
+        Subscription, VisitFailureReason, KEY_EXCHANGE_EXTENSION_VERSION,
     },
+    transport::{build_http_client, connect_subscribe_stream, parse_proxy, SubscribeStream},
 };
 use super::{
-    config::LocalStorage,
-    endpoint::{create_passive_endpoint_client, id::EndPointID},
+    config::{
+        entity::{
+            history::{Direction, Record, SessionType},
+            kv::Settings,
+        },
+        LocalStorage,
+    },
+    endpoint::{
+        client::connect_relay, create_passive_endpoint_client, id::EndPointID, record_session,
+        EndPointStream,
+    },
 };
 use crate::{
+    component::{
+        lan::visit::{LANVisitOffer, LANVisits, PendingVisit},
+        punch::{resolve_reflector_addr, PunchSession, Puncher, PUNCH_TOKEN_LENGTH},
+    },
     core_error,
-    error::CoreResult,
+    error::{CoreError, CoreResult},
     utility::{
-        bincode::{bincode_deserialize, bincode_serialize},
+        bincode::{bincode_deserialize, bincode_deserialize_with_extension, bincode_serialize},
         nonce_value::NonceValue,
+        os::os_description,
         rand::generate_random_ping_value,
     },
 };
@@ -37,32 +54,109 @@ use ring::aead::{BoundKey, OpeningKey, SealingKey, UnboundKey};
 use rsa::{rand_core::OsRng, BigUint, PublicKey, PublicKeyParts};
 use sha2::Sha256;
 use std::{net::SocketAddr, time::Duration};
-use tokio::net::TcpStream;
+use tokio::time::Instant;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use url::Url;
 
+// renew the domain registration this long before it expires
+const REGISTRATION_RENEW_AHEAD_SECS: i64 = 10 * 60;
+const REGISTRATION_RENEW_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const RESUBSCRIBE_MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+// the client version is the one of the application, which is versioned apart from
+// this crate
+pub fn ensure_client_version(client_version: &str, min_client_version: &str) -> CoreResult<()> {
+    let current = semver::Version::parse(client_version)?;
+    let required = semver::Version::parse(min_client_version.trim_start_matches('v'))?;
+
+    if current < required {
+        return Err(CoreError::ClientTooOld {
+            current: current.to_string(),
+            required: required.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+pub type VisitResult = Response<
+    Result<
+        (
+            String,
+            Vec<u8>,
+            OpeningKey<NonceValue>,
+            SealingKey<NonceValue>,
+            DirectPaths,
+            Option<String>,
+        ),
+        VisitFailureReason,
+    >,
+>;
+
+pub struct DirectPaths {
+    lan_offer: Option<LANVisitOffer>,
+    punch_session: Option<PunchSession>,
+}
+
+impl DirectPaths {
+    // the lan path is tried first, then the path selected by hole punching
+    pub async fn select(
+        self,
+        endpoint_id: EndPointID,
+        endpoint_addr: SocketAddr,
+        visit_credentials: Vec<u8>,
+    ) -> CoreResult<EndPointStream> {
+        if let Some(lan_offer) = self.lan_offer {
+            match lan_offer.connect().await {
+                // the stream has been authenticated, so it's served as an accepted one
+                Ok(stream) => return Ok(EndPointStream::PassiveTCP(stream)),
+                Err(err) => tracing::warn!(?err, "direct lan connection failed"),
+            }
+        }
+
+        punch_or_relay(
+            self.punch_session,
+            true,
+            endpoint_id,
+            endpoint_addr,
+            visit_credentials,
+        )
+        .await
+    }
+}
+
 pub struct SignalingClient {
     url: Url,
+    proxy: Option<Url>,
     http_client: reqwest::Client,
     subscribe_tx: Option<tokio::sync::mpsc::Sender<Bytes>>,
 }
 
 impl SignalingClient {
-    pub fn new<U: IntoUrl>(domain: U) -> CoreResult<Self> {
+    pub fn new<U: IntoUrl>(domain: U, proxy: Option<&str>) -> CoreResult<Self> {
         let url = domain.into_url()?;
-
-        let http_client = reqwest::Client::builder()
-            .connect_timeout(Duration::from_secs(10))
-            .timeout(Duration::from_secs(10))
-            .build()?;
+        let proxy = proxy.map(parse_proxy).transpose()?;
+        let http_client = build_http_client(proxy.as_ref())?;
 
         Ok(Self {
             url,
+            proxy,
             http_client,
             subscribe_tx: None,
         })
     }
 
+    // a copy for http requests like visits, it doesn't keep the subscription alive so
+    // callers may hold it without the lock of the subscribed clients
+    pub fn detached(&self) -> SignalingClient {
+        SignalingClient {
+            url: self.url.clone(),
+            proxy: self.proxy.clone(),
+            http_client: self.http_client.clone(),
+            subscribe_tx: None,
+        }
+    }
+
     #[tracing::instrument(skip(self))]
     pub async fn identity(&self) -> CoreResult<Response<IdentityResponse>> {
         let url = self.url.join("/api/identity")?;
@@ -83,49 +177,77 @@ impl SignalingClient {
         device_id: i64,
         device_finger_print: &str,
     ) -> CoreResult<Response<RegisterResponse>> {
-        let url = self.url.join("/api/domain/register")?;
+        domain_register(&self.http_client, &self.url, device_id, device_finger_print).await
+    }
+
+    #[tracing::instrument(skip(self, lan_visits))]
+    pub async fn visit(
+        &self,
+        local_device_id: i64,
+        remote_device_id: i64,
+        password: String,
+        visit_desktop: bool,
+        lan_visits: Option<&LANVisits>,
+    ) -> CoreResult<VisitResult> {
         let resp = self
-            .http_client
-            .post(url)
-            .json(&RegisterRequest {
-                device_id,
-                device_finger_print: device_finger_print.to_string(),
-            })
-            .send()
-            .await?
-            .json::<Response<RegisterResponse>>()
+            .visit_once(
+                local_device_id,
+                remote_device_id,
+                &password,
+                visit_desktop,
+                lan_visits,
+                true,
+            )
             .await?;
 
+        // peers of older versions refuse the trailing extension as invalid args, they're
+        // visited again with the secret alone
+        if let Response::Message(Err(VisitFailureReason::InvalidArgs)) = resp {
+            tracing::info!("visit again without key exchange extension");
+            return self
+                .visit_once(
+                    local_device_id,
+                    remote_device_id,
+                    &password,
+                    visit_desktop,
+                    lan_visits,
+                    false,
+                )
+                .await;
+        }
+
         Ok(resp)
     }
 
-    #[allow(clippy::type_complexity)]
-    #[tracing::instrument(skip(self))]
-    pub async fn visit(
+    async fn visit_once(
         &self,
         local_device_id: i64,
         remote_device_id: i64,
-        password: String,
+        password: &str,
         visit_desktop: bool,
-    ) -> CoreResult<
-        Response<
-            Result<
-                (
-                    String,
-                    Vec<u8>,
-                    OpeningKey<NonceValue>,
-                    SealingKey<NonceValue>,
-                ),
-                VisitFailureReason,
-            >,
-        >,
-    > {
+        lan_visits: Option<&LANVisits>,
+        with_extension: bool,
+    ) -> CoreResult<VisitResult> {
         let url = self.url.join("/api/visit")?;
 
         let secure_random = ring::rand::SystemRandom::new();
 
-        // generate key pair for passive device key exchange reply
-        let reply_private_key = rsa::RsaPrivateKey::new(&mut OsRng, 4096)?;
+        // generate key pair for passive device key exchange reply while the candidates
+        // for hole punching are gathered, a restricted network which needs proxy is
+        // unlikely to pass udp, so it goes through the relay directly
+        let (reply_private_key, puncher) = tokio::join!(
+            tokio::task::spawn_blocking(|| rsa::RsaPrivateKey::new(&mut OsRng, 4096)),
+            async {
+                if with_extension && self.proxy.is_none() {
+                    bind_puncher(&self.url).await
+                } else {
+                    None
+                }
+            }
+        );
+
+        let reply_private_key =
+            reply_private_key.map_err(|_| core_error!("generate reply key pair failed"))??;
         let reply_public_key = reply_private_key.to_public_key();
 
         // generate exchange key pair and nonce
@@ -141,6 +263,10 @@ impl SignalingClient {
         let mut visit_credentials_buffer = [0u8; 16];
         OsRng.fill_bytes(&mut visit_credentials_buffer);
 
+        // the token also authenticates the direct lan connection
+        let mut punch_token = [0u8; PUNCH_TOKEN_LENGTH];
+        OsRng.fill_bytes(&mut punch_token);
+
         // generate and sealing active device key exchange secret
         let active_device_secret = ActiveEndpointKeyExchangeSecret {
             exchange_reply_public_key_n: &reply_public_key.n().to_bytes_le(),
@@ -149,6 +275,19 @@ impl SignalingClient {
             active_exchange_nonce: &active_exchange_nonce,
         };
 
+        let active_device_extension = ActiveEndpointKeyExchangeExtension {
+            version: KEY_EXCHANGE_EXTENSION_VERSION,
+            candidates: puncher
+                .as_ref()
+                .map(|puncher| puncher.candidates().to_vec())
+                .unwrap_or_default(),
+            lan_addrs: lan_visits
+                .map(|lan_visits| lan_visits.local_addrs())
+                .unwrap_or_default(),
+            punch_token: punch_token.to_vec(),
+            os: os_description(),
+        };
+
         // generate secret sealing key with salt
         let mut active_device_secret_salt = [0u8; 16];
         OsRng.fill_bytes(&mut active_device_secret_salt);
@@ -162,6 +301,9 @@ impl SignalingClient {
         );
 
         let mut active_device_secret_buffer = bincode_serialize(&active_device_secret)?;
+        if with_extension {
+            active_device_secret_buffer.extend(bincode_serialize(&active_device_extension)?);
+        }
 
         let active_device_secret_sealing_unbound_key = ring::aead::UnboundKey::new(
             &ring::aead::AES_256_GCM,
@@ -210,8 +352,11 @@ impl SignalingClient {
                 let passive_device_secret_buffer =
                     reply_private_key.decrypt(rsa::Pkcs1v15Encrypt::default(), &secret)?;
 
-                let passive_device_secret: PassiveEndpointKeyExchangeSecret =
-                    bincode_deserialize(&passive_device_secret_buffer)?;
+                let (passive_device_secret, passive_device_extension) =
+                    bincode_deserialize_with_extension::<
+                        PassiveEndpointKeyExchangeSecret,
+                        PassiveEndpointKeyExchangeExtension,
+                    >(&passive_device_secret_buffer)?;
 
                 let passive_exchange_public_key = ring::agreement::UnparsedPublicKey::new(
                     &ring::agreement::X25519,
@@ -267,69 +412,287 @@ impl SignalingClient {
                 let opening_key =
                     ring::aead::OpeningKey::new(unbound_opening_key, NonceValue::new(nonce));
 
+                // a passive endpoint without the extension only supports the relay
+                let (direct_paths, passive_os) = match passive_device_extension {
+                    Some(passive_device_extension) => {
+                        // both sides prefer the lan path only if they have discovered each other
+                        let lan_addrs = match lan_visits {
+                            Some(lan_visits) => {
+                                lan_visits
+                                    .discovered_addrs(&passive_device_extension.lan_addrs)
+                                    .await
+                            }
+                            None => Vec::new(),
+                        };
+                        let lan_offer = if lan_addrs.is_empty() {
+                            None
+                        } else {
+                            Some(LANVisitOffer {
+                                addrs: lan_addrs,
+                                token: punch_token.to_vec(),
+                                local_device_id,
+                                remote_device_id,
+                            })
+                        };
+
+                        // both sides try hole punching only if both of them offered candidates
+                        let punch_session = match puncher {
+                            Some(puncher) if !passive_device_extension.candidates.is_empty() => {
+                                Some(PunchSession {
+                                    puncher,
+                                    token: punch_token.to_vec(),
+                                    remote_candidates: passive_device_extension.candidates,
+                                })
+                            }
+                            _ => None,
+                        };
+
+                        (
+                            DirectPaths {
+                                lan_offer,
+                                punch_session,
+                            },
+                            Some(passive_device_extension.os),
+                        )
+                    }
+                    None => (
+                        DirectPaths {
+                            lan_offer: None,
+                            punch_session: None,
+                        },
+                        None,
+                    ),
+                };
+
                 Ok(Response::Message(Ok((
                     resp.endpoint_addr,
                     visit_credentials,
                     opening_key,
                     sealing_key,
+                    direct_paths,
+                    passive_os,
                 ))))
             }
             Response::Error(err) => Ok(Response::Error(err)),
         }
     }
 
-    // see https://github.com/rust-lang/rust-clippy/pull/9496, which was merged but not release
-    #[allow(clippy::never_loop)]
+    #[allow(clippy::too_many_arguments)]
     pub async fn subscribe(
         &mut self,
-        addrs: Vec<SocketAddr>,
+        subscribe_port: u16,
+        subscribe_tls: bool,
+        domain_id: i64,
         device_id: i64,
         device_finger_print: &str,
+        expire: i64,
         storage: LocalStorage,
+        lan_visits: Option<LANVisits>,
     ) -> CoreResult<()> {
+        let mut registration = Registration {
+            http_client: self.http_client.clone(),
+            url: self.url.clone(),
+            domain_id,
+            device_id,
+            device_finger_print: device_finger_print.to_string(),
+            expire,
+            retry_at: None,
+        };
+
+        // the server refuses subscriptions of lapsed registrations
+        if registration.expire <= chrono::Utc::now().timestamp() {
+            registration.renew(&storage).await;
+            if registration.retry_at.is_some() {
+                return Err(core_error!("renew domain registration failed"));
+            }
+        }
+
+        let Some(host) = self.url.host_str() else {
+            return Err(core_error!("invalid domain addr"));
+        };
+
+        let target = SubscribeTarget {
+            // ipv6 host is bracketed in url
+            host: host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port: subscribe_port,
+            proxy: self.proxy.clone(),
+            tls: subscribe_tls,
+        };
+
+        let framed_stream = target
+            .subscribe(registration.device_id, &registration.device_finger_print)
+            .await?;
+
+        let (sink, stream) = framed_stream.split();
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
+
+        tokio::spawn(serve_connection(
+            registration,
+            target,
+            rx,
+            sink,
+            stream,
+            storage,
+            lan_visits,
+        ));
+
+        self.subscribe_tx = Some(tx);
+
+        Ok(())
+    }
+}
+
+async fn domain_register(
+    http_client: &reqwest::Client,
+    url: &Url,
+    device_id: i64,
+    device_finger_print: &str,
+) -> CoreResult<Response<RegisterResponse>> {
+    let url = url.join("/api/domain/register")?;
+    let resp = http_client
+        .post(url)
+        .json(&RegisterRequest {
+            device_id,
+            device_finger_print: device_finger_print.to_string(),
+        })
+        .send()
+        .await?
+        .json::<Response<RegisterResponse>>()
+        .await?;
+
+    Ok(resp)
+}
+
+type SubscribeFramed = Framed<Box<dyn SubscribeStream>, LengthDelimitedCodec>;
+
+struct SubscribeTarget {
+    host: String,
+    port: u16,
+    proxy: Option<Url>,
+    tls: bool,
+}
+
+impl SubscribeTarget {
+    async fn subscribe(
+        &self,
+        device_id: i64,
+        device_finger_print: &str,
+    ) -> CoreResult<SubscribeFramed> {
         let subscription_bytes = Bytes::from(bincode_serialize(&Subscription {
             device_id,
             device_finger_print: device_finger_print.to_string(),
         })?);
 
-        for addr in addrs {
-            let Ok(Ok(stream)) = tokio::time::timeout(
-                Duration::from_secs(10),
-                tokio::net::TcpStream::connect(addr),
-            )
-            .await else {
-                continue;
-            };
+        let stream =
+            connect_subscribe_stream(&self.host, self.port, self.proxy.as_ref(), self.tls).await?;
 
-            let mut framed_stream = Framed::new(
-                stream,
-                LengthDelimitedCodec::builder()
-                    .length_field_length(2)
-                    .little_endian()
-                    .new_codec(),
-            );
+        let mut framed_stream = Framed::new(
+            stream,
+            LengthDelimitedCodec::builder()
+                .length_field_length(2)
+                .little_endian()
+                .new_codec(),
+        );
+
+        framed_stream.send(subscription_bytes).await?;
+
+        Ok(framed_stream)
+    }
+}
+
+struct Registration {
+    http_client: reqwest::Client,
+    url: Url,
+    domain_id: i64,
+    device_id: i64,
+    device_finger_print: String,
+    expire: i64,
+    retry_at: Option<Instant>,
+}
+
+impl Registration {
+    fn renew_at(&self) -> Instant {
+        if let Some(retry_at) = self.retry_at {
+            return retry_at;
+        }
+
+        let remain_secs =
+            self.expire - REGISTRATION_RENEW_AHEAD_SECS - chrono::Utc::now().timestamp();
+
+        Instant::now() + Duration::from_secs(remain_secs.max(0) as u64)
+    }
 
-            framed_stream.send(subscription_bytes.clone()).await?;
+    async fn renew(&mut self, storage: &LocalStorage) {
+        let resp = match domain_register(
+            &self.http_client,
+            &self.url,
+            self.device_id,
+            &self.device_finger_print,
+        )
+        .await
+        {
+            Ok(Response::Message(resp)) => resp,
+            Ok(Response::Error(err)) => {
+                tracing::error!(
+                    ?err,
+                    domain_id = self.domain_id,
+                    "renew registration failed"
+                );
+                self.retry_at = Some(Instant::now() + REGISTRATION_RENEW_RETRY_INTERVAL);
+                return;
+            }
+            Err(err) => {
+                tracing::error!(
+                    ?err,
+                    domain_id = self.domain_id,
+                    "renew registration failed"
+                );
+                self.retry_at = Some(Instant::now() + REGISTRATION_RENEW_RETRY_INTERVAL);
+                return;
+            }
+        };
 
-            let (sink, stream) = framed_stream.split();
-            let (tx, rx) = tokio::sync::mpsc::channel(1);
+        if resp.device_id != self.device_id {
+            tracing::warn!(
+                domain_id = self.domain_id,
+                old_device_id = self.device_id,
+                new_device_id = resp.device_id,
+                "renew registration assigned new device id"
+            );
 
-            tokio::spawn(serve_connection(rx, sink, stream, storage.clone()));
+            if let Err(err) = storage
+                .domain()
+                .set_domain_device_id(self.domain_id, resp.device_id)
+            {
+                tracing::error!(?err, "save renewed device id failed");
+            }
 
-            self.subscribe_tx = Some(tx);
+            self.device_id = resp.device_id;
+        }
 
-            return Ok(());
+        if let Err(err) = storage
+            .domain()
+            .set_domain_expire(self.domain_id, resp.expire)
+        {
+            tracing::error!(?err, "save renewed registration expire failed");
         }
 
-        Err(core_error!("non addr usable"))
+        self.expire = resp.expire;
+        self.retry_at = None;
     }
 }
 
 async fn serve_connection(
+    mut registration: Registration,
+    target: SubscribeTarget,
     mut rx: tokio::sync::mpsc::Receiver<Bytes>,
-    mut sink: SplitSink<Framed<TcpStream, LengthDelimitedCodec>, Bytes>,
-    mut stream: SplitStream<Framed<TcpStream, LengthDelimitedCodec>>,
+    mut sink: SplitSink<SubscribeFramed, Bytes>,
+    mut stream: SplitStream<SubscribeFramed>,
     storage: LocalStorage,
+    lan_visits: Option<LANVisits>,
 ) {
     let mut ticker = tokio::time::interval(Duration::from_secs(60));
     let mut last_ping = None;
@@ -337,6 +700,23 @@ async fn serve_connection(
 
     loop {
         let buffer = tokio::select! {
+            _ = tokio::time::sleep_until(registration.renew_at()) => {
+                let device_id = registration.device_id;
+                registration.renew(&storage).await;
+
+                // the server routes visits by the subscribed device id, so the
+                // subscription is replaced when the renew assigned a new one
+                if registration.device_id != device_id {
+                    let Some(framed_stream) = resubscribe(&target, &registration, &mut rx).await else {
+                        return;
+                    };
+
+                    (sink, stream) = framed_stream.split();
+                    last_ping = None;
+                }
+
+                continue;
+            }
             _ = ticker.tick() => {
                 if last_ping.is_some() {
                     return;
@@ -389,22 +769,26 @@ async fn serve_connection(
             ServerMessage::VisitRequest {
                 active_device_id,
                 passive_device_id,
-                visit_desktop: _,
+                visit_desktop,
                 endpoint_addr,
                 password_salt,
                 secret,
                 secret_nonce,
                 passive_visit_credentials,
             } => {
+                let domain_id = registration.domain_id;
                 let storage = storage.clone();
+                let lan_visits = lan_visits.clone();
                 let (tx, rx) = tokio::sync::oneshot::channel();
                 tokio::spawn(async move {
                     let result = serve_visit_request(
                         storage,
+                        lan_visits,
+                        domain_id,
                         active_device_id,
                         passive_device_id,
                         endpoint_addr,
-                        // visit_desktop,
+                        visit_desktop,
                         password_salt,
                         secret,
                         secret_nonce,
@@ -447,66 +831,247 @@ async fn serve_connection(
     }
 }
 
+// retries the subscription with the renewed device id until it succeeds, the old one
+// can't receive visits anymore. returns none once the signaling client was dropped
+async fn resubscribe(
+    target: &SubscribeTarget,
+    registration: &Registration,
+    rx: &mut tokio::sync::mpsc::Receiver<Bytes>,
+) -> Option<SubscribeFramed> {
+    let mut backoff = RESUBSCRIBE_MIN_BACKOFF;
+
+    loop {
+        match target
+            .subscribe(registration.device_id, &registration.device_finger_print)
+            .await
+        {
+            Ok(framed_stream) => return Some(framed_stream),
+            Err(err) => {
+                tracing::error!(
+                    ?err,
+                    domain_id = registration.domain_id,
+                    ?backoff,
+                    "resubscribe with renewed device id failed"
+                );
+            }
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            buffer = rx.recv() => {
+                if buffer.is_none() {
+                    return None;
+                }
+            }
+        }
+
+        backoff = (backoff * 2).min(REGISTRATION_RENEW_RETRY_INTERVAL);
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn serve_visit_request(
     storage: LocalStorage,
+    lan_visits: Option<LANVisits>,
+    domain_id: i64,
     active_device_id: i64,
     passive_device_id: i64,
     endpoint_addr: String,
+    visit_desktop: bool,
     password_salt: Vec<u8>,
     secret: Vec<u8>,
     secret_nonce: Vec<u8>,
     passive_visit_credentials: Vec<u8>,
 ) -> Result<Vec<u8>, VisitFailureReason> {
-    let Ok(domain) = storage.domain().get_primary_domain() else {
+    // each subscription serves exactly one domain, so the visit must be verified
+    // with the password of the domain it arrived on
+    let Ok(domain) = storage.domain().get_domain_by_id(domain_id) else {
         return Err(VisitFailureReason::InternalError);
     };
 
+    if domain.device_id != passive_device_id {
+        tracing::error!(
+            domain = domain.name,
+            ?passive_device_id,
+            "visit request passive device id mismatch"
+        );
+        return Err(VisitFailureReason::InvalidArgs);
+    }
+
     let Ok(endpoint_addr) = endpoint_addr.parse::<SocketAddr>() else {
         return Err(VisitFailureReason::InternalError);
     };
 
-    let (secret, sealing_key, opening_key) = match key_agreement(
+    // the reflexive candidate is queried while the secret is opened
+    let puncher_task = match (domain.proxy.is_none(), Url::parse(&domain.addr)) {
+        (true, Ok(url)) => Some(tokio::spawn(async move { bind_puncher(&url).await })),
+        _ => None,
+    };
+
+    let active_device_secret_buffer = open_active_device_secret(
         &domain.password,
         active_device_id,
         password_salt,
         secret,
         secret_nonce,
-    )
-    .await
-    {
-        Ok(v) => v,
-        Err(err) => {
-            return Err(err);
+    )?;
+
+    let Ok((active_device_secret, active_device_extension)) = bincode_deserialize_with_extension::<
+        ActiveEndpointKeyExchangeSecret,
+        ActiveEndpointKeyExchangeExtension,
+    >(&active_device_secret_buffer) else {
+        return Err(VisitFailureReason::InvalidArgs);
+    };
+
+    let puncher = match puncher_task {
+        Some(puncher_task) => puncher_task.await.ok().flatten(),
+        None => None,
+    };
+
+    let passive_lan_addrs = lan_visits
+        .as_ref()
+        .map(|lan_visits| lan_visits.listening_addrs())
+        .unwrap_or_default();
+    let lan_advertised = !passive_lan_addrs.is_empty();
+
+    // the extension is only replied to an active endpoint which understands it
+    let passive_device_extension =
+        active_device_extension
+            .as_ref()
+            .map(|_| PassiveEndpointKeyExchangeExtension {
+                version: KEY_EXCHANGE_EXTENSION_VERSION,
+                candidates: puncher
+                    .as_ref()
+                    .map(|puncher| puncher.candidates().to_vec())
+                    .unwrap_or_default(),
+                lan_addrs: passive_lan_addrs,
+                os: os_description(),
+            });
+
+    let (secret, sealing_key, opening_key) =
+        key_agreement(&active_device_secret, passive_device_extension.as_ref())?;
+
+    let mut session_record = Record::new(
+        Direction::Incoming,
+        if visit_desktop {
+            SessionType::Desktop
+        } else {
+            SessionType::FileManager
+        },
+        domain.name,
+        active_device_id,
+    );
+
+    let (active_lan_addrs, punch_token, punch_session) = match active_device_extension {
+        Some(active_device_extension) => {
+            session_record.peer_os = Some(active_device_extension.os);
+
+            let punch_session = match puncher {
+                Some(puncher) if !active_device_extension.candidates.is_empty() => {
+                    Some(PunchSession {
+                        puncher,
+                        token: active_device_extension.punch_token.clone(),
+                        remote_candidates: active_device_extension.candidates,
+                    })
+                }
+                _ => None,
+            };
+
+            (
+                active_device_extension.lan_addrs,
+                active_device_extension.punch_token,
+                punch_session,
+            )
+        }
+        None => (Vec::new(), Vec::new(), None),
+    };
+
+    // the active side dials the lan server first if both sides have discovered each other
+    let lan_visit = match lan_visits {
+        Some(lan_visits) if lan_advertised => {
+            if lan_visits
+                .discovered_addrs(&active_lan_addrs)
+                .await
+                .is_empty()
+            {
+                None
+            } else {
+                Some(lan_visits)
+            }
         }
+        _ => None,
     };
 
     tokio::spawn(async move {
-        if let Err(err) = create_passive_endpoint_client(
-            EndPointID::DeviceID {
-                local_device_id: passive_device_id,
-                remote_device_id: active_device_id,
-            },
-            Some((opening_key, sealing_key)),
-            crate::api::endpoint::EndPointStream::ActiveTCP(endpoint_addr),
-            Some(passive_visit_credentials),
-        )
-        .await
+        let endpoint_id = EndPointID::DeviceID {
+            local_device_id: passive_device_id,
+            remote_device_id: active_device_id,
+        };
+
+        let connect_active = punch_or_relay(
+            punch_session,
+            false,
+            endpoint_id,
+            endpoint_addr,
+            passive_visit_credentials,
+        );
+
+        let (stream, key_pair) = match lan_visit {
+            Some(lan_visits) => {
+                let mut ticket = lan_visits.register(
+                    punch_token,
+                    PendingVisit {
+                        local_device_id: passive_device_id,
+                        remote_device_id: active_device_id,
+                        key_pair: (opening_key, sealing_key),
+                        session_record: session_record.clone(),
+                    },
+                );
+
+                // the other paths are connected meanwhile, so the visit is handed over as
+                // soon as the lan server claims it or the active side turns to them
+                tokio::select! {
+                    true = ticket.claimed() => return,
+                    result = connect_active => match ticket.withdraw() {
+                        Some(visit) => (result, visit.key_pair),
+                        None => return,
+                    },
+                }
+            }
+            None => (connect_active.await, (opening_key, sealing_key)),
+        };
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                tracing::error!(?err, "connect active endpoint failed");
+                return;
+            }
+        };
+
+        let settings = storage.kv().get_settings().unwrap_or_else(|err| {
+            tracing::warn!(?err, "read settings failed, use default settings");
+            Settings::default()
+        });
+
+        match create_passive_endpoint_client(endpoint_id, Some(key_pair), stream, None, settings)
+            .await
         {
-            tracing::error!(?err, "create passive endpoint client failed");
+            Ok(client) => record_session(storage, client, session_record),
+            Err(err) => tracing::error!(?err, "create passive endpoint client failed"),
         }
     });
 
     Ok(secret)
 }
 
-async fn key_agreement(
+fn open_active_device_secret(
     domain_password: &str,
     active_device_id: i64,
     password_salt: Vec<u8>,
     mut secret: Vec<u8>,
     secret_nonce: Vec<u8>,
-) -> Result<(Vec<u8>, SealingKey<NonceValue>, OpeningKey<NonceValue>), VisitFailureReason> {
+) -> Result<Vec<u8>, VisitFailureReason> {
     if secret_nonce.len() != ring::aead::NONCE_LEN {
         return Err(VisitFailureReason::InternalError);
     }
@@ -540,23 +1105,23 @@ async fn key_agreement(
         NonceValue::new(active_device_secret_opening_nonce),
     );
 
-    let active_device_secret_buffer = match active_device_secret_opening_key.open_in_place(
+    let secret_length = match active_device_secret_opening_key.open_in_place(
         ring::aead::Aad::from(active_device_id.to_le_bytes()),
         &mut secret,
     ) {
-        Ok(buffer) => buffer,
+        Ok(buffer) => buffer.len(),
         Err(_) => return Err(VisitFailureReason::InvalidPassword),
     };
 
-    let active_device_secret =
-        match bincode_deserialize::<ActiveEndpointKeyExchangeSecret>(&*active_device_secret_buffer)
-        {
-            Ok(secret) => secret,
-            Err(_) => {
-                return Err(VisitFailureReason::InvalidArgs);
-            }
-        };
+    secret.truncate(secret_length);
+
+    Ok(secret)
+}
 
+fn key_agreement(
+    active_device_secret: &ActiveEndpointKeyExchangeSecret,
+    passive_device_extension: Option<&PassiveEndpointKeyExchangeExtension>,
+) -> Result<(Vec<u8>, SealingKey<NonceValue>, OpeningKey<NonceValue>), VisitFailureReason> {
     if active_device_secret.active_exchange_nonce.len() != ring::aead::NONCE_LEN {
         return Err(VisitFailureReason::InvalidArgs);
     }
@@ -669,11 +1234,18 @@ async fn key_agreement(
         passive_exchange_nonce: &passive_exchange_nonce,
     };
 
-    let passive_device_secret_buffer = match bincode_serialize(&passive_device_secret) {
+    let mut passive_device_secret_buffer = match bincode_serialize(&passive_device_secret) {
         Ok(buffer) => buffer,
         Err(_) => return Err(VisitFailureReason::InternalError),
     };
 
+    if let Some(passive_device_extension) = passive_device_extension {
+        match bincode_serialize(passive_device_extension) {
+            Ok(buffer) => passive_device_secret_buffer.extend(buffer),
+            Err(_) => return Err(VisitFailureReason::InternalError),
+        }
+    }
+
     let active_exchange_reply_public_key = match rsa::RsaPublicKey::new(
         BigUint::from_bytes_le(active_device_secret.exchange_reply_public_key_n),
         BigUint::from_bytes_le(active_device_secret.exchange_reply_public_key_e),
@@ -699,3 +1271,72 @@ async fn key_agreement(
 
     Ok((secret_buffer, sealing_key, opening_key))
 }
+
+// the active side selects the path while punching and the passive side follows it,
+// so the relay is only dialed after both of them gave up the punched path
+async fn punch_or_relay(
+    punch_session: Option<PunchSession>,
+    active: bool,
+    endpoint_id: EndPointID,
+    endpoint_addr: SocketAddr,
+    visit_credentials: Vec<u8>,
+) -> CoreResult<EndPointStream> {
+    if let Some(punch_session) = punch_session {
+        match punch_session.punch(active).await {
+            Ok((socket, remote_addr)) if active => {
+                return Ok(EndPointStream::ActiveUDP {
+                    remote_addr,
+                    socket,
+                })
+            }
+            Ok((socket, remote_addr)) => {
+                return Ok(EndPointStream::PassiveUDP {
+                    remote_addr,
+                    socket,
+                })
+            }
+            Err(err) => tracing::warn!(?err, "hole punching failed, fallback to relay"),
+        }
+    }
+
+    Ok(EndPointStream::RelayTCP(
+        connect_relay(endpoint_addr, endpoint_id, visit_credentials).await?,
+    ))
+}
+
+async fn bind_puncher(url: &Url) -> Option<Puncher> {
+    let reflector_addr = match url.host_str() {
+        Some(host) => {
+            resolve_reflector_addr(host.trim_start_matches('[').trim_end_matches(']')).await
+        }
+        None => None,
+    };
+
+    match Puncher::bind(reflector_addr).await {
+        Ok(puncher) => Some(puncher),
+        Err(err) => {
+            tracing::warn!(?err, "bind hole punching socket failed");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ensure_client_version() {
+        assert!(ensure_client_version("0.1.5", "0.1.5").is_ok());
+        assert!(ensure_client_version("0.1.5", "v0.1.0").is_ok());
+
+        match ensure_client_version("0.1.5", "v0.2.0") {
+            Err(CoreError::ClientTooOld { current, required }) => {
+                assert_eq!((current.as_str(), required.as_str()), ("0.1.5", "0.2.0"))
+            }
+            ret => panic!("unexpected result {ret:?}"),
+        }
+
+        assert!(ensure_client_version("0.1.5", "latest").is_err());
+    }
+}
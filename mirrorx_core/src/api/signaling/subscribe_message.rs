@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Subscription {
@@ -58,3 +59,26 @@ pub struct PassiveEndpointKeyExchangeSecret<'a> {
     pub passive_exchange_public_key: &'a [u8],
     pub passive_exchange_nonce: &'a [u8],
 }
+
+// the extensions trail the key exchange secrets so that the secrets keep their
+// layout, fields are only appended to them in later versions
+pub const KEY_EXCHANGE_EXTENSION_VERSION: u8 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ActiveEndpointKeyExchangeExtension {
+    pub version: u8,
+    pub candidates: Vec<SocketAddr>,
+    pub lan_addrs: Vec<IpAddr>,
+    #[serde(with = "serde_bytes")]
+    pub punch_token: Vec<u8>,
+    pub os: String,
+}
+
+// only replied to an active endpoint which sent its extension
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PassiveEndpointKeyExchangeExtension {
+    pub version: u8,
+    pub candidates: Vec<SocketAddr>,
+    pub lan_addrs: Vec<IpAddr>,
+    pub os: String,
+}
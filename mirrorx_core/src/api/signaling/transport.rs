@@ -0,0 +1,208 @@
+use crate::{core_error, error::CoreResult};
+use base64::{engine::general_purpose::STANDARD as base64_standard, Engine};
+use percent_encoding::percent_decode_str;
+use std::{net::Ipv6Addr, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use url::Url;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_PROXY_RESPONSE_HEADER_LENGTH: usize = 8 * 1024;
+
+pub trait SubscribeStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> SubscribeStream for T {}
+
+pub fn parse_proxy(proxy: &str) -> CoreResult<Url> {
+    let url = Url::parse(proxy).map_err(|_| core_error!("invalid proxy url"))?;
+
+    match url.scheme() {
+        "http" | "socks5" | "socks5h" => {}
+        scheme => return Err(core_error!("unsupported proxy scheme ({})", scheme)),
+    }
+
+    if url.host_str().is_none() || url.port_or_known_default().is_none() {
+        return Err(core_error!("proxy url must contains host and port"));
+    }
+
+    Ok(url)
+}
+
+pub fn build_http_client(proxy: Option<&Url>) -> CoreResult<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(Duration::from_secs(10));
+
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.as_str())?);
+    }
+
+    Ok(builder.build()?)
+}
+
+pub async fn connect_subscribe_stream(
+    host: &str,
+    port: u16,
+    proxy: Option<&Url>,
+    tls: bool,
+) -> CoreResult<Box<dyn SubscribeStream>> {
+    let stream: Box<dyn SubscribeStream> = match proxy {
+        Some(proxy) => match proxy.scheme() {
+            "http" => Box::new(http_connect(proxy, host, port).await?),
+            _ => Box::new(socks5_connect(proxy, host, port).await?),
+        },
+        None => Box::new(tcp_connect(host, port).await?),
+    };
+
+    if !tls {
+        return Ok(stream);
+    }
+
+    let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+
+    let Ok(tls_stream) =
+        tokio::time::timeout(CONNECT_TIMEOUT, connector.connect(host, stream)).await
+    else {
+        return Err(core_error!("tls handshake timeout"));
+    };
+
+    Ok(Box::new(tls_stream?))
+}
+
+async fn tcp_connect(host: &str, port: u16) -> CoreResult<TcpStream> {
+    for addr in tokio::net::lookup_host((host, port)).await? {
+        if let Ok(Ok(stream)) =
+            tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await
+        {
+            return Ok(stream);
+        }
+    }
+
+    Err(core_error!("non addr usable"))
+}
+
+async fn proxy_tcp_connect(proxy: &Url) -> CoreResult<TcpStream> {
+    let (Some(proxy_host), Some(proxy_port)) = (proxy.host_str(), proxy.port_or_known_default())
+    else {
+        return Err(core_error!("proxy url must contains host and port"));
+    };
+
+    // ipv6 proxy host is bracketed in url
+    let proxy_host = proxy_host.trim_start_matches('[').trim_end_matches(']');
+
+    tcp_connect(proxy_host, proxy_port).await
+}
+
+async fn http_connect(proxy: &Url, host: &str, port: u16) -> CoreResult<TcpStream> {
+    let mut stream = proxy_tcp_connect(proxy).await?;
+
+    // ipv6 literal must be bracketed in the authority form
+    let authority = if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    };
+
+    let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+
+    if let Some((username, password)) = proxy_credentials(proxy)? {
+        let credentials = format!("{username}:{password}");
+
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64_standard.encode(credentials)
+        ));
+    }
+
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes()).await?;
+
+    // read byte by byte so that no tunneled bytes are consumed with the header
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > MAX_PROXY_RESPONSE_HEADER_LENGTH {
+            return Err(core_error!("proxy response header too long"));
+        }
+
+        let Ok(byte) = tokio::time::timeout(CONNECT_TIMEOUT, stream.read_u8()).await else {
+            return Err(core_error!("proxy response timeout"));
+        };
+
+        response.push(byte?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_code = response
+        .lines()
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1));
+
+    if status_code != Some("200") {
+        return Err(core_error!(
+            "proxy refused tunnel ({})",
+            response.lines().next().unwrap_or_default()
+        ));
+    }
+
+    Ok(stream)
+}
+
+async fn socks5_connect(
+    proxy: &Url,
+    host: &str,
+    port: u16,
+) -> CoreResult<tokio_socks::tcp::Socks5Stream<TcpStream>> {
+    let stream = proxy_tcp_connect(proxy).await?;
+
+    // the target is always sent as domain name, so the proxy resolves it for both
+    // 'socks5' and 'socks5h' which keeps it usable in networks without public dns
+    let result = match proxy_credentials(proxy)? {
+        Some((username, password)) => {
+            tokio::time::timeout(
+                CONNECT_TIMEOUT,
+                tokio_socks::tcp::Socks5Stream::connect_with_password_and_socket(
+                    stream,
+                    (host, port),
+                    &username,
+                    &password,
+                ),
+            )
+            .await
+        }
+        None => {
+            tokio::time::timeout(
+                CONNECT_TIMEOUT,
+                tokio_socks::tcp::Socks5Stream::connect_with_socket(stream, (host, port)),
+            )
+            .await
+        }
+    };
+
+    match result {
+        Ok(Ok(stream)) => Ok(stream),
+        Ok(Err(err)) => Err(core_error!("socks5 proxy connect failed ({})", err)),
+        Err(_) => Err(core_error!("socks5 proxy connect timeout")),
+    }
+}
+
+// credentials are kept percent-encoded in the url
+fn proxy_credentials(proxy: &Url) -> CoreResult<Option<(String, String)>> {
+    if proxy.username().is_empty() {
+        return Ok(None);
+    }
+
+    let decode = |value: &str| {
+        percent_decode_str(value)
+            .decode_utf8()
+            .map(|value| value.into_owned())
+            .map_err(|_| core_error!("invalid proxy credentials encoding"))
+    };
+
+    let username = decode(proxy.username())?;
+    let password = decode(proxy.password().unwrap_or_default())?;
+
+    Ok(Some((username, password)))
+}
@@ -0,0 +1,373 @@
+use super::util::XDisplay;
+use crate::{
+    api::endpoint::message::ChromaFormat,
+    component::{
+        desktop::{
+            monitor::linux::get_display_monitors, nv12::NV12Converter, yuv444::YUV444Converter,
+        },
+        frame::{capture_time_now, DesktopEncodeFrame},
+    },
+    core_error,
+    error::CoreResult,
+};
+use scopeguard::defer;
+use std::time::{Duration, Instant};
+use x11_dl::{
+    xlib::{XImage, ZPixmap},
+    xshm::{XShmSegmentInfo, Xext},
+};
+
+enum FrameConverter {
+    NV12(NV12Converter),
+    YUV444(YUV444Converter),
+}
+
+// captures the area of one monitor from the root window, through a shared memory
+// image when the x server is local and by XGetImage otherwise
+pub struct Duplicator {
+    display: XDisplay,
+    xext: Option<Xext>,
+    shm_image: *mut XImage,
+    // boxed because the shared memory image keeps a pointer to it
+    shm_segment: Box<XShmSegmentInfo>,
+    converter: FrameConverter,
+    left: i32,
+    top: i32,
+    width: i32,
+    height: i32,
+    frame_interval: Duration,
+    next_capture_instant: Instant,
+}
+
+unsafe impl Send for Duplicator {}
+
+impl Duplicator {
+    pub fn new(
+        monitor_id: Option<String>,
+        chroma_format: ChromaFormat,
+    ) -> CoreResult<(Duplicator, String)> {
+        Duplicator::with_display(XDisplay::open()?, monitor_id, chroma_format)
+    }
+
+    pub fn with_display(
+        display: XDisplay,
+        monitor_id: Option<String>,
+        chroma_format: ChromaFormat,
+    ) -> CoreResult<(Duplicator, String)> {
+        let monitors = get_display_monitors(&display, false)?;
+
+        let monitor = match monitor_id {
+            Some(monitor_id) => monitors
+                .into_iter()
+                .find(|monitor| monitor.id == monitor_id),
+            None => monitors.into_iter().find(|monitor| monitor.is_primary),
+        }
+        .ok_or_else(|| core_error!("can't find selected monitor"))?;
+
+        // nv12 chrominance is subsampled in both directions
+        let width = monitor.width as i32 & !1;
+        let height = monitor.height as i32 & !1;
+
+        let converter = match chroma_format {
+            ChromaFormat::YUV420 => FrameConverter::NV12(NV12Converter::new(width, height)?),
+            ChromaFormat::YUV444 => FrameConverter::YUV444(YUV444Converter::new(width, height)?),
+        };
+
+        let mut duplicator = Duplicator {
+            display,
+            xext: None,
+            shm_image: std::ptr::null_mut(),
+            shm_segment: Box::new(XShmSegmentInfo {
+                shmseg: 0,
+                shmid: -1,
+                shmaddr: std::ptr::null_mut(),
+                readOnly: 0,
+            }),
+            converter,
+            left: monitor.left as i32,
+            top: monitor.top as i32,
+            width,
+            height,
+            frame_interval: Duration::from_secs(1) / monitor.refresh_rate.max(1) as u32,
+            next_capture_instant: Instant::now(),
+        };
+
+        unsafe {
+            if let Err(err) = duplicator.init_shm_image() {
+                tracing::warn!(
+                    ?err,
+                    "x11 shared memory unavailable, fall back to XGetImage"
+                );
+                duplicator.release_shm_image();
+            }
+        }
+
+        Ok((duplicator, monitor.id))
+    }
+
+    // x11 doesn't signal new frames without the damage extension, so frames are taken
+    // at the monitor refresh rate
+    pub fn capture(&mut self) -> CoreResult<DesktopEncodeFrame> {
+        let now = Instant::now();
+        if self.next_capture_instant > now {
+            std::thread::sleep(self.next_capture_instant - now);
+        }
+
+        let capture_instant = Instant::now();
+        while self.next_capture_instant <= capture_instant {
+            self.next_capture_instant += self.frame_interval;
+        }
+
+        unsafe {
+            if self.shm_image.is_null() {
+                self.capture_by_get_image()
+            } else {
+                self.capture_by_shm()
+            }
+        }
+    }
+
+    unsafe fn init_shm_image(&mut self) -> CoreResult<()> {
+        let xext = Xext::open().map_err(|err| core_error!("load libXext failed ({})", err))?;
+
+        if (xext.XShmQueryExtension)(self.display.display) == 0 {
+            return Err(core_error!("x server doesn't support MIT-SHM"));
+        }
+
+        let screen = (self.display.xlib.XDefaultScreen)(self.display.display);
+
+        self.shm_image = (xext.XShmCreateImage)(
+            self.display.display,
+            (self.display.xlib.XDefaultVisual)(self.display.display, screen),
+            (self.display.xlib.XDefaultDepth)(self.display.display, screen) as u32,
+            ZPixmap,
+            std::ptr::null_mut(),
+            self.shm_segment.as_mut(),
+            self.width as u32,
+            self.height as u32,
+        );
+
+        if self.shm_image.is_null() {
+            return Err(core_error!("XShmCreateImage returns null pointer"));
+        }
+
+        let size = (*self.shm_image).bytes_per_line as usize * self.height as usize;
+
+        self.shm_segment.shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+        if self.shm_segment.shmid == -1 {
+            return Err(core_error!(
+                "shmget failed ({})",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        let shmaddr = libc::shmat(self.shm_segment.shmid, std::ptr::null(), 0);
+        if shmaddr as isize == -1 {
+            return Err(core_error!(
+                "shmat failed ({})",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        self.shm_segment.shmaddr = shmaddr as *mut _;
+        (*self.shm_image).data = shmaddr as *mut _;
+
+        let attached = (xext.XShmAttach)(self.display.display, self.shm_segment.as_mut());
+
+        // attaching fails asynchronously when the x server runs on another host
+        if attached == 0 || self.display.sync_and_check_error() {
+            self.shm_segment.shmseg = 0;
+            return Err(core_error!("XShmAttach failed"));
+        }
+
+        // the segment is destroyed once both sides detached from it
+        libc::shmctl(self.shm_segment.shmid, libc::IPC_RMID, std::ptr::null_mut());
+        self.shm_segment.shmid = -1;
+        self.xext = Some(xext);
+
+        Ok(())
+    }
+
+    unsafe fn release_shm_image(&mut self) {
+        if let Some(xext) = &self.xext {
+            if self.shm_segment.shmseg != 0 {
+                (xext.XShmDetach)(self.display.display, self.shm_segment.as_mut());
+                self.display.sync_and_check_error();
+            }
+        }
+
+        if !self.shm_image.is_null() {
+            // the data pointer belongs to the shared memory segment
+            (*self.shm_image).data = std::ptr::null_mut();
+            (self.display.xlib.XDestroyImage)(self.shm_image);
+            self.shm_image = std::ptr::null_mut();
+        }
+
+        if !self.shm_segment.shmaddr.is_null() {
+            libc::shmdt(self.shm_segment.shmaddr as *const _);
+            self.shm_segment.shmaddr = std::ptr::null_mut();
+        }
+
+        if self.shm_segment.shmid != -1 {
+            libc::shmctl(self.shm_segment.shmid, libc::IPC_RMID, std::ptr::null_mut());
+            self.shm_segment.shmid = -1;
+        }
+
+        self.shm_segment.shmseg = 0;
+    }
+
+    unsafe fn capture_by_shm(&mut self) -> CoreResult<DesktopEncodeFrame> {
+        let xext = self
+            .xext
+            .as_ref()
+            .ok_or_else(|| core_error!("libXext isn't loaded"))?;
+
+        if (xext.XShmGetImage)(
+            self.display.display,
+            self.display.root_window(),
+            self.shm_image,
+            self.left,
+            self.top,
+            (self.display.xlib.XAllPlanes)() as u32,
+        ) == 0
+        {
+            return Err(core_error!("XShmGetImage failed"));
+        }
+
+        self.convert(self.shm_image)
+    }
+
+    unsafe fn capture_by_get_image(&mut self) -> CoreResult<DesktopEncodeFrame> {
+        let image = (self.display.xlib.XGetImage)(
+            self.display.display,
+            self.display.root_window(),
+            self.left,
+            self.top,
+            self.width as u32,
+            self.height as u32,
+            (self.display.xlib.XAllPlanes)(),
+            ZPixmap,
+        );
+
+        if image.is_null() {
+            return Err(core_error!("XGetImage returns null pointer"));
+        }
+
+        defer! {
+            (self.display.xlib.XDestroyImage)(image);
+        }
+
+        self.convert(image)
+    }
+
+    unsafe fn convert(&self, image: *mut XImage) -> CoreResult<DesktopEncodeFrame> {
+        // 24 and 32 bit depth visuals are stored as bgrx in little endian
+        if (*image).bits_per_pixel != 32 {
+            return Err(core_error!(
+                "unsupported bits per pixel: {}",
+                (*image).bits_per_pixel
+            ));
+        }
+
+        let bgra_stride = (*image).bytes_per_line;
+        let bgra_bytes = std::slice::from_raw_parts(
+            (*image).data as *const u8,
+            bgra_stride as usize * self.height as usize,
+        );
+
+        match &self.converter {
+            FrameConverter::NV12(converter) => {
+                converter.convert(capture_time_now(), bgra_bytes, bgra_stride)
+            }
+            FrameConverter::YUV444(converter) => {
+                converter.convert(capture_time_now(), bgra_bytes, bgra_stride)
+            }
+        }
+    }
+}
+
+impl Drop for Duplicator {
+    fn drop(&mut self) {
+        unsafe {
+            self.release_shm_image();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::frame::DesktopEncodeFrameFormat;
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+    };
+
+    struct Xvfb {
+        child: Child,
+        display_name: String,
+    }
+
+    impl Xvfb {
+        // starts a 640x480 black screen on a free display
+        fn start() -> Xvfb {
+            let mut child = Command::new("Xvfb")
+                .args(["-displayfd", "1", "-screen", "0", "640x480x24", "-br"])
+                .args(["-nolisten", "tcp"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("spawn Xvfb failed");
+
+            let mut display_number = String::new();
+            BufReader::new(child.stdout.take().unwrap())
+                .read_line(&mut display_number)
+                .unwrap();
+
+            Xvfb {
+                child,
+                display_name: format!(":{}", display_number.trim()),
+            }
+        }
+
+        fn open_display(&self) -> XDisplay {
+            XDisplay::connect(Some(&self.display_name)).unwrap()
+        }
+    }
+
+    impl Drop for Xvfb {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    #[test]
+    #[ignore = "requires Xvfb"]
+    fn test_capture_under_xvfb() {
+        let xvfb = Xvfb::start();
+
+        let monitors = get_display_monitors(&xvfb.open_display(), true).unwrap();
+        let primary_monitor = monitors.iter().find(|monitor| monitor.is_primary).unwrap();
+        assert_eq!((primary_monitor.width, primary_monitor.height), (640, 480));
+        assert!(primary_monitor.screen_shot.is_some());
+
+        let (mut duplicator, monitor_id) =
+            Duplicator::with_display(xvfb.open_display(), None, ChromaFormat::YUV420).unwrap();
+        assert_eq!(monitor_id, primary_monitor.id);
+
+        // the black background converts to full range black without color
+        let frame = duplicator.capture().unwrap();
+        assert_eq!(frame.format, DesktopEncodeFrameFormat::NV12);
+        assert_eq!((frame.width, frame.height), (640, 480));
+        assert!(frame.luminance_bytes.iter().all(|value| *value == 0));
+        assert!(frame.chrominance_bytes.iter().all(|value| *value == 128));
+
+        let (mut duplicator, _) =
+            Duplicator::with_display(xvfb.open_display(), Some(monitor_id), ChromaFormat::YUV444)
+                .unwrap();
+        let frame = duplicator.capture().unwrap();
+        assert_eq!(frame.format, DesktopEncodeFrameFormat::YUV444P);
+        assert_eq!(frame.chrominance_bytes.len(), 640 * 480 * 2);
+    }
+}
@@ -0,0 +1,5 @@
+mod duplicator;
+
+pub mod util;
+
+pub use duplicator::Duplicator;
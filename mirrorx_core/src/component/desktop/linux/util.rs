@@ -0,0 +1,85 @@
+use crate::{core_error, error::CoreResult};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Once,
+};
+use x11_dl::xlib::{Display, Window, XErrorEvent, Xlib};
+
+static X_ERROR_OCCURRED: AtomicBool = AtomicBool::new(false);
+static SET_ERROR_HANDLER: Once = Once::new();
+
+// the default xlib error handler terminates the process, failed requests like
+// attaching shared memory to a remote x server are only recorded instead
+unsafe extern "C" fn x_error_handler(_: *mut Display, event: *mut XErrorEvent) -> i32 {
+    X_ERROR_OCCURRED.store(true, Ordering::SeqCst);
+    tracing::error!(
+        error_code = (*event).error_code,
+        request_code = (*event).request_code,
+        "x11 request failed"
+    );
+    0
+}
+
+pub struct XDisplay {
+    pub xlib: Xlib,
+    pub display: *mut Display,
+}
+
+unsafe impl Send for XDisplay {}
+
+impl XDisplay {
+    // connects to the x server named by the DISPLAY environment variable
+    pub fn open() -> CoreResult<XDisplay> {
+        XDisplay::connect(None)
+    }
+
+    // connects to the named x server like ":1", or the DISPLAY one without a name
+    pub fn connect(display_name: Option<&str>) -> CoreResult<XDisplay> {
+        let display_name = display_name
+            .map(std::ffi::CString::new)
+            .transpose()
+            .map_err(|_| core_error!("x display name contains nul byte"))?;
+
+        let xlib = Xlib::open().map_err(|err| core_error!("load libX11 failed ({})", err))?;
+
+        unsafe {
+            SET_ERROR_HANDLER.call_once(|| {
+                (xlib.XSetErrorHandler)(Some(x_error_handler));
+            });
+
+            let display = (xlib.XOpenDisplay)(
+                display_name
+                    .as_ref()
+                    .map_or(std::ptr::null(), |name| name.as_ptr()),
+            );
+            if display.is_null() {
+                return Err(core_error!(
+                    "XOpenDisplay returns null pointer, check the DISPLAY environment variable"
+                ));
+            }
+
+            Ok(XDisplay { xlib, display })
+        }
+    }
+
+    pub fn root_window(&self) -> Window {
+        unsafe { (self.xlib.XDefaultRootWindow)(self.display) }
+    }
+
+    // waits until the server processed all requests and tells whether any of them failed
+    pub fn sync_and_check_error(&self) -> bool {
+        unsafe {
+            (self.xlib.XSync)(self.display, 0);
+        }
+
+        X_ERROR_OCCURRED.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Drop for XDisplay {
+    fn drop(&mut self) {
+        unsafe {
+            (self.xlib.XCloseDisplay)(self.display);
+        }
+    }
+}
@@ -1,14 +1,17 @@
 use crate::{
-    component::{desktop::monitor::NSScreen, frame::DesktopEncodeFrame},
+    api::endpoint::message::ChromaFormat,
+    component::{
+        desktop::{monitor::NSScreen, yuv444::YUV444Converter},
+        frame::{capture_time_now, DesktopEncodeFrame, DesktopEncodeFrameFormat},
+    },
     core_error,
     error::CoreResult,
 };
 use block::ConcreteBlock;
 use dispatch::ffi::{dispatch_queue_create, dispatch_release, DISPATCH_QUEUE_SERIAL};
 use mirrorx_native::os::macos::{core_graphics::*, core_video::*, io_surface::*};
-use once_cell::unsync::OnceCell;
 use scopeguard::defer;
-use std::{ffi::CString, ops::Deref, time::Duration};
+use std::{cell::RefCell, ffi::CString, ops::Deref, time::Duration};
 use tokio::sync::mpsc::Sender;
 
 pub struct Duplicator {
@@ -21,6 +24,7 @@ unsafe impl Sync for Duplicator {}
 impl Duplicator {
     pub fn new(
         monitor_id: Option<String>,
+        chroma_format: ChromaFormat,
         capture_frame_tx: Sender<DesktopEncodeFrame>,
     ) -> CoreResult<(Self, String)> {
         unsafe {
@@ -52,23 +56,23 @@ impl Duplicator {
 
             let capture_frame_tx_ptr = Box::into_raw(Box::new(capture_frame_tx));
 
-            let epoch: OnceCell<std::time::Instant> = OnceCell::new();
+            // display stream only outputs 4:2:0 yuv, yuv444 frames are converted from bgra
+            let pixel_format = match chroma_format {
+                ChromaFormat::YUV420 => kCVPixelFormatType_420YpCbCr8BiPlanarFullRange,
+                ChromaFormat::YUV444 => kCVPixelFormatType_32BGRA,
+            };
+
+            let yuv444_converter: RefCell<Option<YUV444Converter>> = RefCell::new(None);
 
             let block = ConcreteBlock::new(
                 move |status: CGDisplayStreamFrameStatus,
                       display_time: u64,
                       frame_surface: IOSurfaceRef,
                       update_ref: CGDisplayStreamUpdateRef| {
-                    let capture_time = if let Some(instant) = epoch.get() {
-                        instant.elapsed()
-                    } else {
-                        let _ = epoch.set(std::time::Instant::now());
-                        Duration::ZERO
-                    };
-
                     frame_available_handler(
-                        capture_time,
+                        capture_time_now(),
                         capture_frame_tx_ptr,
+                        &yuv444_converter,
                         status,
                         display_time,
                         frame_surface,
@@ -83,7 +87,7 @@ impl Duplicator {
                 screen.screenNumber(),
                 screen_size.width as usize,
                 screen_size.height as usize,
-                kCVPixelFormatType_420YpCbCr8BiPlanarFullRange as i32,
+                pixel_format as i32,
                 std::ptr::null_mut(),
                 dispatch_queue,
                 block.deref(),
@@ -128,6 +132,7 @@ impl Duplicator {
 unsafe fn frame_available_handler(
     capture_time: Duration,
     capture_frame_tx: *mut Sender<DesktopEncodeFrame>,
+    yuv444_converter: &RefCell<Option<YUV444Converter>>,
     status: CGDisplayStreamFrameStatus,
     _display_time: u64,
     frame_surface: IOSurfaceRef,
@@ -163,6 +168,37 @@ unsafe fn frame_available_handler(
 
     CVPixelBufferLockBaseAddress(pixel_buffer, 0);
 
+    let capture_frame =
+        if CVPixelBufferGetPixelFormatType(pixel_buffer) == kCVPixelFormatType_32BGRA {
+            create_yuv444_capture_frame(capture_time, pixel_buffer, yuv444_converter)
+        } else {
+            create_capture_frame(capture_time, pixel_buffer)
+        };
+
+    CVPixelBufferUnlockBaseAddress(pixel_buffer, 0);
+
+    let capture_frame = match capture_frame {
+        Ok(capture_frame) => capture_frame,
+        Err(err) => {
+            tracing::error!(?err, "create capture frame failed");
+            return;
+        }
+    };
+
+    if (*capture_frame_tx).blocking_send(capture_frame).is_err() {
+        tracing::error!("desktop capture frame tx send failed");
+    }
+
+    let dropped_frames = CGDisplayStreamUpdateGetDropCount(update_ref);
+    if dropped_frames > 0 {
+        tracing::warn!(count = dropped_frames, "drop frames");
+    }
+}
+
+unsafe fn create_capture_frame(
+    capture_time: Duration,
+    pixel_buffer: CVPixelBufferRef,
+) -> CoreResult<DesktopEncodeFrame> {
     let width = CVPixelBufferGetWidth(pixel_buffer);
     let height = CVPixelBufferGetHeight(pixel_buffer);
     let luminance_bytes_address = CVPixelBufferGetBaseAddressOfPlane(pixel_buffer, 0);
@@ -180,24 +216,40 @@ unsafe fn frame_available_handler(
     )
     .to_vec();
 
-    CVPixelBufferUnlockBaseAddress(pixel_buffer, 0);
-
-    let capture_frame = DesktopEncodeFrame {
+    Ok(DesktopEncodeFrame {
         capture_time,
+        format: DesktopEncodeFrameFormat::NV12,
         width: width as i32,
         height: height as i32,
         luminance_bytes,
         luminance_stride: luminance_stride as i32,
         chrominance_bytes,
         chrominance_stride: chrominance_stride as i32,
+    })
+}
+
+unsafe fn create_yuv444_capture_frame(
+    capture_time: Duration,
+    pixel_buffer: CVPixelBufferRef,
+    yuv444_converter: &RefCell<Option<YUV444Converter>>,
+) -> CoreResult<DesktopEncodeFrame> {
+    let width = CVPixelBufferGetWidth(pixel_buffer) as i32;
+    let height = CVPixelBufferGetHeight(pixel_buffer) as i32;
+    let bgra_stride = CVPixelBufferGetBytesPerRow(pixel_buffer);
+    let bgra_bytes = std::slice::from_raw_parts(
+        CVPixelBufferGetBaseAddress(pixel_buffer) as *const u8,
+        height as usize * bgra_stride,
+    );
+
+    let mut yuv444_converter = yuv444_converter.borrow_mut();
+
+    let converter = match yuv444_converter.take() {
+        Some(converter) if converter.is_match(width, height) => converter,
+        _ => YUV444Converter::new(width, height)?,
     };
 
-    if (*capture_frame_tx).blocking_send(capture_frame).is_err() {
-        tracing::error!("desktop capture frame tx send failed");
-    }
+    let capture_frame = converter.convert(capture_time, bgra_bytes, bgra_stride as i32);
+    *yuv444_converter = Some(converter);
 
-    let dropped_frames = CGDisplayStreamUpdateGetDropCount(update_ref);
-    if dropped_frames > 0 {
-        tracing::warn!(count = dropped_frames, "drop frames");
-    }
+    capture_frame
 }
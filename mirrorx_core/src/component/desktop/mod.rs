@@ -1,4 +1,9 @@
 pub mod monitor;
+pub mod nv12;
+pub mod synthetic;
+pub mod yuv444;
+
+use once_cell::sync::Lazy;
 
 #[cfg(target_os = "macos")]
 mod macos;
@@ -9,3 +14,39 @@ pub use macos::Duplicator;
 mod windows;
 #[cfg(target_os = "windows")]
 pub use self::windows::Duplicator;
+
+#[cfg(target_os = "linux")]
+pub(crate) mod linux;
+#[cfg(target_os = "linux")]
+pub use linux::Duplicator;
+
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+pub use synthetic::Duplicator;
+
+// setting it to 1 or true serves the synthetic test pattern and monitors instead of the
+// real display, for headless machines and end to end tests
+pub const SYNTHETIC_DESKTOP_ENV: &str = "MIRRORX_SYNTHETIC_DESKTOP";
+
+pub fn synthetic_desktop_enabled() -> bool {
+    static ENABLED: Lazy<bool> = Lazy::new(|| {
+        if cfg!(not(any(
+            target_os = "windows",
+            target_os = "macos",
+            target_os = "linux"
+        ))) {
+            return true;
+        }
+
+        let enabled = std::env::var(SYNTHETIC_DESKTOP_ENV)
+            .map(|value| value == "1" || value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        if enabled {
+            tracing::info!("synthetic desktop enabled");
+        }
+
+        enabled
+    });
+
+    *ENABLED
+}
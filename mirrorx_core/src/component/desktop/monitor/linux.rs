@@ -0,0 +1,251 @@
+use super::Monitor;
+use crate::{component::desktop::linux::util::XDisplay, core_error, error::CoreResult};
+use image::ColorType;
+use scopeguard::defer;
+use std::{ffi::CStr, io::Cursor};
+use x11_dl::{
+    xlib::{Atom, ZPixmap},
+    xrandr::{XRRMonitorInfo, XRRScreenResources, Xrandr},
+};
+
+// used when the refresh rate of a monitor can't be read from its crtc mode
+const DEFAULT_REFRESH_RATE: u8 = 60;
+
+pub fn get_primary_monitor_params() -> CoreResult<Monitor> {
+    let monitors = get_active_monitors(false)?;
+    for monitor in monitors.into_iter() {
+        if monitor.is_primary {
+            return Ok(monitor);
+        }
+    }
+
+    Err(core_error!("no primary display"))
+}
+
+pub fn get_active_monitors(take_screen_shot: bool) -> CoreResult<Vec<Monitor>> {
+    let display = XDisplay::open()?;
+    get_display_monitors(&display, take_screen_shot)
+}
+
+pub fn get_display_monitors(
+    display: &XDisplay,
+    take_screen_shot: bool,
+) -> CoreResult<Vec<Monitor>> {
+    let xrandr = Xrandr::open().map_err(|err| core_error!("load libXrandr failed ({})", err))?;
+
+    unsafe {
+        let root_window = display.root_window();
+
+        let mut monitors_count = 0;
+        let monitor_infos =
+            (xrandr.XRRGetMonitors)(display.display, root_window, 1, &mut monitors_count);
+
+        defer! {
+            if !monitor_infos.is_null() {
+                (xrandr.XRRFreeMonitors)(monitor_infos);
+            }
+        }
+
+        let resources = (xrandr.XRRGetScreenResourcesCurrent)(display.display, root_window);
+
+        defer! {
+            if !resources.is_null() {
+                (xrandr.XRRFreeScreenResources)(resources);
+            }
+        }
+
+        let mut displays = Vec::new();
+
+        if !monitor_infos.is_null() {
+            let monitor_infos = std::slice::from_raw_parts(monitor_infos, monitors_count as usize);
+
+            for (index, monitor_info) in monitor_infos.iter().enumerate() {
+                let name = atom_name(display, monitor_info.name)
+                    .unwrap_or_else(|| format!("monitor-{}", index));
+
+                let screen_shot = if take_screen_shot {
+                    Some(take_screen_shot_as_png(
+                        display,
+                        monitor_info.x,
+                        monitor_info.y,
+                        monitor_info.width,
+                        monitor_info.height,
+                    )?)
+                } else {
+                    None
+                };
+
+                displays.push(Monitor {
+                    id: name.clone(),
+                    name,
+                    refresh_rate: refresh_rate(display, &xrandr, resources, monitor_info)
+                        .unwrap_or(DEFAULT_REFRESH_RATE),
+                    width: monitor_info.width as u16,
+                    height: monitor_info.height as u16,
+                    is_primary: monitor_info.primary != 0,
+                    screen_shot,
+                    left: monitor_info.x as u16,
+                    top: monitor_info.y as u16,
+                });
+            }
+        }
+
+        // servers without randr 1.5, such as old Xvfb builds, expose the whole screen
+        // as the only monitor
+        if displays.is_empty() {
+            let screen = (display.xlib.XDefaultScreen)(display.display);
+            let width = (display.xlib.XDisplayWidth)(display.display, screen);
+            let height = (display.xlib.XDisplayHeight)(display.display, screen);
+
+            let screen_shot = if take_screen_shot {
+                Some(take_screen_shot_as_png(display, 0, 0, width, height)?)
+            } else {
+                None
+            };
+
+            displays.push(Monitor {
+                id: String::from("screen"),
+                name: String::from("screen"),
+                refresh_rate: DEFAULT_REFRESH_RATE,
+                width: width as u16,
+                height: height as u16,
+                is_primary: true,
+                screen_shot,
+                left: 0,
+                top: 0,
+            });
+        }
+
+        // a monitor is primary only if it's set by the user, the first one is picked
+        // otherwise so that the visitor has a default
+        if !displays.iter().any(|monitor| monitor.is_primary) {
+            displays[0].is_primary = true;
+        }
+
+        Ok(displays)
+    }
+}
+
+unsafe fn atom_name(display: &XDisplay, atom: Atom) -> Option<String> {
+    let name_ptr = (display.xlib.XGetAtomName)(display.display, atom);
+    if name_ptr.is_null() {
+        return None;
+    }
+
+    let name = CStr::from_ptr(name_ptr).to_string_lossy().into_owned();
+    (display.xlib.XFree)(name_ptr as *mut _);
+
+    Some(name)
+}
+
+// the rate of the mode driving the first output of the monitor
+unsafe fn refresh_rate(
+    display: &XDisplay,
+    xrandr: &Xrandr,
+    resources: *mut XRRScreenResources,
+    monitor_info: &XRRMonitorInfo,
+) -> Option<u8> {
+    if resources.is_null() || monitor_info.noutput <= 0 || monitor_info.outputs.is_null() {
+        return None;
+    }
+
+    let output_info = (xrandr.XRRGetOutputInfo)(display.display, resources, *monitor_info.outputs);
+    if output_info.is_null() {
+        return None;
+    }
+
+    defer! {
+        (xrandr.XRRFreeOutputInfo)(output_info);
+    }
+
+    if (*output_info).crtc == 0 {
+        return None;
+    }
+
+    let crtc_info = (xrandr.XRRGetCrtcInfo)(display.display, resources, (*output_info).crtc);
+    if crtc_info.is_null() {
+        return None;
+    }
+
+    defer! {
+        (xrandr.XRRFreeCrtcInfo)(crtc_info);
+    }
+
+    let modes = std::slice::from_raw_parts((*resources).modes, (*resources).nmode as usize);
+    let mode = modes.iter().find(|mode| mode.id == (*crtc_info).mode)?;
+
+    let total_pixels = mode.hTotal as u64 * mode.vTotal as u64;
+    if total_pixels == 0 {
+        return None;
+    }
+
+    let rate = (mode.dotClock as f64 / total_pixels as f64).round();
+    Some(rate.clamp(1.0, u8::MAX as f64) as u8)
+}
+
+unsafe fn take_screen_shot_as_png(
+    display: &XDisplay,
+    left: i32,
+    top: i32,
+    width: i32,
+    height: i32,
+) -> CoreResult<Vec<u8>> {
+    let x_image = (display.xlib.XGetImage)(
+        display.display,
+        display.root_window(),
+        left,
+        top,
+        width as u32,
+        height as u32,
+        (display.xlib.XAllPlanes)(),
+        ZPixmap,
+    );
+
+    if x_image.is_null() {
+        return Err(core_error!("XGetImage returns null pointer"));
+    }
+
+    defer! {
+        (display.xlib.XDestroyImage)(x_image);
+    }
+
+    if (*x_image).bits_per_pixel != 32 {
+        return Err(core_error!(
+            "unsupported bits per pixel: {}",
+            (*x_image).bits_per_pixel
+        ));
+    }
+
+    let bytes_per_line = (*x_image).bytes_per_line as usize;
+    let bgra_bytes = std::slice::from_raw_parts(
+        (*x_image).data as *const u8,
+        bytes_per_line * height as usize,
+    );
+
+    // rows are copied without padding, blue and red are swapped and the unused fourth
+    // byte of the 24 bit visual is made opaque
+    let mut rgba_bytes = Vec::with_capacity((width * height * 4) as usize);
+    for row in bgra_bytes.chunks(bytes_per_line) {
+        for pixel in row[..width as usize * 4].chunks(4) {
+            rgba_bytes.extend_from_slice(&[pixel[2], pixel[1], pixel[0], 255]);
+        }
+    }
+
+    let mut png_bytes: Vec<u8> = Vec::with_capacity(rgba_bytes.len());
+
+    if let Err(err) = image::write_buffer_with_format(
+        &mut Cursor::new(&mut png_bytes),
+        &rgba_bytes,
+        width as u32,
+        height as u32,
+        ColorType::Rgba8,
+        image::ImageOutputFormat::Png,
+    ) {
+        return Err(core_error!(
+            "write desktop screenshot image buffer failed ({})",
+            err
+        ));
+    }
+
+    Ok(png_bytes)
+}
@@ -1,16 +1,49 @@
+use super::synthetic_desktop_enabled;
+use crate::error::CoreResult;
 use serde::{Deserialize, Serialize};
 
+pub mod synthetic;
+
 #[cfg(target_os = "windows")]
 mod windows;
 
 #[cfg(target_os = "windows")]
-pub use self::windows::{get_active_monitors, get_primary_monitor_params};
+use self::windows as platform;
 
 #[cfg(target_os = "macos")]
 mod macos;
 
 #[cfg(target_os = "macos")]
-pub use macos::{get_active_monitors, get_primary_monitor_params, NSScreen};
+use self::macos as platform;
+
+#[cfg(target_os = "macos")]
+pub use macos::NSScreen;
+
+// the x11 duplicator reads the real monitors even with the synthetic desktop enabled
+#[cfg(target_os = "linux")]
+pub(crate) mod linux;
+
+#[cfg(target_os = "linux")]
+use self::linux as platform;
+
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
+use self::synthetic as platform;
+
+pub fn get_active_monitors(take_screen_shot: bool) -> CoreResult<Vec<Monitor>> {
+    if synthetic_desktop_enabled() {
+        synthetic::get_active_monitors(take_screen_shot)
+    } else {
+        platform::get_active_monitors(take_screen_shot)
+    }
+}
+
+pub fn get_primary_monitor_params() -> CoreResult<Monitor> {
+    if synthetic_desktop_enabled() {
+        synthetic::get_primary_monitor_params()
+    } else {
+        platform::get_primary_monitor_params()
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Monitor {
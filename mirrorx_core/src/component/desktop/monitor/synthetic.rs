@@ -0,0 +1,42 @@
+use super::Monitor;
+use crate::{core_error, error::CoreResult};
+
+pub fn get_primary_monitor_params() -> CoreResult<Monitor> {
+    let monitors = get_active_monitors(false)?;
+    for monitor in monitors.into_iter() {
+        if monitor.is_primary {
+            return Ok(monitor);
+        }
+    }
+
+    Err(core_error!("no primary display"))
+}
+
+// fixed monitors served by the synthetic duplicator, there is no screen shot to
+// preview them
+pub fn get_active_monitors(_take_screen_shot: bool) -> CoreResult<Vec<Monitor>> {
+    Ok(vec![
+        Monitor {
+            id: String::from("synthetic-0"),
+            name: String::from("Synthetic 1920x1080"),
+            refresh_rate: 60,
+            width: 1920,
+            height: 1080,
+            is_primary: true,
+            screen_shot: None,
+            left: 0,
+            top: 0,
+        },
+        Monitor {
+            id: String::from("synthetic-1"),
+            name: String::from("Synthetic 1280x720"),
+            refresh_rate: 30,
+            width: 1280,
+            height: 720,
+            is_primary: false,
+            screen_shot: None,
+            left: 1920,
+            top: 0,
+        },
+    ])
+}
@@ -0,0 +1,219 @@
+use crate::{
+    component::frame::{DesktopEncodeFrame, DesktopEncodeFrameFormat},
+    core_error,
+    error::CoreResult,
+};
+use mirrorx_native::ffmpeg::{
+    swscale::*,
+    utils::pixfmt::{AV_PIX_FMT_BGRA, AV_PIX_FMT_NV12},
+};
+use std::time::Duration;
+
+// converts captured bgra frames to nv12 frames for backends without a gpu color
+// conversion. the chrominance is averaged over each 2x2 block and the planes are full
+// range bt.709 like the frames from the capture shaders, which is how the encoder tags
+// every stream
+pub struct NV12Converter {
+    sws_ctx: *mut SwsContext,
+    width: i32,
+    height: i32,
+}
+
+unsafe impl Send for NV12Converter {}
+
+impl NV12Converter {
+    pub fn new(width: i32, height: i32) -> CoreResult<NV12Converter> {
+        if width <= 0 || height <= 0 || width % 2 != 0 || height % 2 != 0 {
+            return Err(core_error!(
+                "nv12 resolution must be even and positive: {}x{}",
+                width,
+                height
+            ));
+        }
+
+        unsafe {
+            let sws_ctx = sws_getContext(
+                width,
+                height,
+                AV_PIX_FMT_BGRA,
+                width,
+                height,
+                AV_PIX_FMT_NV12,
+                SWS_BILINEAR,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null(),
+            );
+
+            if sws_ctx.is_null() {
+                return Err(core_error!("sws_getContext returns null pointer"));
+            }
+
+            let converter = NV12Converter {
+                sws_ctx,
+                width,
+                height,
+            };
+
+            let ret = sws_setColorspaceDetails(
+                sws_ctx,
+                sws_getCoefficients(SWS_CS_DEFAULT),
+                1,
+                sws_getCoefficients(SWS_CS_ITU709),
+                1,
+                0,
+                1 << 16,
+                1 << 16,
+            );
+
+            if ret < 0 {
+                return Err(core_error!(
+                    "sws_setColorspaceDetails returns error code: {}",
+                    ret
+                ));
+            }
+
+            Ok(converter)
+        }
+    }
+
+    pub fn is_match(&self, width: i32, height: i32) -> bool {
+        self.width == width && self.height == height
+    }
+
+    pub fn convert(
+        &self,
+        capture_time: Duration,
+        bgra_bytes: &[u8],
+        bgra_stride: i32,
+    ) -> CoreResult<DesktopEncodeFrame> {
+        if bgra_stride < self.width * 4
+            || bgra_bytes.len() < (bgra_stride * (self.height - 1) + self.width * 4) as usize
+        {
+            return Err(core_error!("bgra buffer doesn't match frame size"));
+        }
+
+        let mut luminance_bytes = vec![0u8; (self.width * self.height) as usize];
+        let mut chrominance_bytes = vec![0u8; (self.width * self.height / 2) as usize];
+
+        let src_slice = [bgra_bytes.as_ptr()];
+        let src_stride = [bgra_stride];
+        let dst = [luminance_bytes.as_mut_ptr(), chrominance_bytes.as_mut_ptr()];
+        let dst_stride = [self.width, self.width];
+
+        unsafe {
+            let ret = sws_scale(
+                self.sws_ctx,
+                src_slice.as_ptr(),
+                src_stride.as_ptr(),
+                0,
+                self.height,
+                dst.as_ptr(),
+                dst_stride.as_ptr(),
+            );
+
+            if ret != self.height {
+                return Err(core_error!("sws_scale returns unexpected height: {}", ret));
+            }
+        }
+
+        Ok(DesktopEncodeFrame {
+            capture_time,
+            format: DesktopEncodeFrameFormat::NV12,
+            width: self.width,
+            height: self.height,
+            luminance_bytes,
+            luminance_stride: self.width,
+            chrominance_bytes,
+            chrominance_stride: self.width,
+        })
+    }
+}
+
+impl Drop for NV12Converter {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.sws_ctx.is_null() {
+                sws_freeContext(self.sws_ctx);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_new_rejects_odd_resolution() {
+        assert!(NV12Converter::new(0, 2).is_err());
+        assert!(NV12Converter::new(3, 2).is_err());
+        assert!(NV12Converter::new(2, 3).is_err());
+    }
+
+    #[test]
+    fn test_convert_bgra_pattern() {
+        // left 2x2 block black, right 2x2 block white, rows padded to 24 bytes
+        let stride = 24;
+        let mut bgra_bytes = vec![0xAAu8; stride * 2];
+        for row in 0..2 {
+            for column in 0..4 {
+                let value = if column < 2 { 0 } else { 255 };
+                let offset = row * stride + column * 4;
+                bgra_bytes[offset..offset + 4].copy_from_slice(&[value, value, value, 255]);
+            }
+        }
+
+        let converter = NV12Converter::new(4, 2).unwrap();
+        let frame = converter
+            .convert(Duration::ZERO, &bgra_bytes, stride as i32)
+            .unwrap();
+
+        assert_eq!(frame.format, DesktopEncodeFrameFormat::NV12);
+        assert_eq!((frame.width, frame.height), (4, 2));
+        // swscale rounds in fixed point, so the extremes may be one step off
+        for (index, y) in frame.luminance_bytes.iter().enumerate() {
+            if index % 4 < 2 {
+                assert!(*y <= 1, "{:?}", frame.luminance_bytes);
+            } else {
+                assert!(*y >= 254, "{:?}", frame.luminance_bytes);
+            }
+        }
+        assert!(
+            frame
+                .chrominance_bytes
+                .iter()
+                .all(|value| (127..=129).contains(value)),
+            "{:?}",
+            frame.chrominance_bytes
+        );
+
+        // full range bt.709 puts pure blue at y 18, u 255 and v 116
+        let blue = [255u8, 0, 0, 255].repeat(4);
+        let converter = NV12Converter::new(2, 2).unwrap();
+        let frame = converter.convert(Duration::ZERO, &blue, 8).unwrap();
+
+        assert!(
+            frame.luminance_bytes.iter().all(|y| (17..=19).contains(y)),
+            "{:?}",
+            frame.luminance_bytes
+        );
+        assert!(
+            frame.chrominance_bytes[0] >= 254,
+            "{:?}",
+            frame.chrominance_bytes
+        );
+        assert!(
+            (115..=117).contains(&frame.chrominance_bytes[1]),
+            "{:?}",
+            frame.chrominance_bytes
+        );
+    }
+
+    #[test]
+    fn test_convert_rejects_short_buffer() {
+        let converter = NV12Converter::new(4, 2).unwrap();
+        assert!(converter.convert(Duration::ZERO, &[0; 31], 16).is_err());
+        assert!(converter.convert(Duration::ZERO, &[0; 32], 12).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use super::pattern::Canvas;
+use crate::{
+    api::endpoint::message::ChromaFormat,
+    component::{
+        desktop::monitor::synthetic::get_active_monitors,
+        frame::{capture_time_now, DesktopEncodeFrame, DesktopEncodeFrameFormat},
+    },
+    core_error,
+    error::CoreResult,
+};
+use std::time::{Duration, Instant};
+
+// produces animated test patterns instead of capturing a real display, so the whole
+// session pipeline can run on platforms without a capture backend and in headless tests
+pub struct Duplicator {
+    format: DesktopEncodeFrameFormat,
+    width: i32,
+    height: i32,
+    fps: u8,
+    frame_interval: Duration,
+    start_instant: Instant,
+    next_capture_instant: Instant,
+    frame_index: u64,
+}
+
+impl Duplicator {
+    pub fn new(
+        monitor_id: Option<String>,
+        chroma_format: ChromaFormat,
+    ) -> CoreResult<(Duplicator, String)> {
+        let monitors = get_active_monitors(false)?;
+
+        let monitor = match monitor_id {
+            Some(monitor_id) => monitors
+                .into_iter()
+                .find(|monitor| monitor.id == monitor_id),
+            None => monitors.into_iter().find(|monitor| monitor.is_primary),
+        }
+        .ok_or_else(|| core_error!("can't find synthetic monitor"))?;
+
+        let duplicator = Duplicator::with_mode(
+            monitor.width as i32,
+            monitor.height as i32,
+            monitor.refresh_rate,
+            chroma_format,
+        )?;
+
+        Ok((duplicator, monitor.id))
+    }
+
+    pub fn with_mode(
+        width: i32,
+        height: i32,
+        fps: u8,
+        chroma_format: ChromaFormat,
+    ) -> CoreResult<Duplicator> {
+        // nv12 chrominance is subsampled in both directions
+        if width <= 0 || height <= 0 || width % 2 != 0 || height % 2 != 0 {
+            return Err(core_error!(
+                "synthetic resolution must be even and positive: {}x{}",
+                width,
+                height
+            ));
+        }
+
+        if fps == 0 {
+            return Err(core_error!("synthetic frame rate must be positive"));
+        }
+
+        let format = match chroma_format {
+            ChromaFormat::YUV420 => DesktopEncodeFrameFormat::NV12,
+            ChromaFormat::YUV444 => DesktopEncodeFrameFormat::YUV444P,
+        };
+
+        let now = Instant::now();
+
+        Ok(Duplicator {
+            format,
+            width,
+            height,
+            fps,
+            frame_interval: Duration::from_secs(1) / fps as u32,
+            start_instant: now,
+            next_capture_instant: now,
+            frame_index: 0,
+        })
+    }
+
+    // blocks until the next frame is due, frames are skipped rather than produced in a
+    // burst when the caller falls behind
+    pub fn capture(&mut self) -> CoreResult<DesktopEncodeFrame> {
+        let now = Instant::now();
+        if self.next_capture_instant > now {
+            std::thread::sleep(self.next_capture_instant - now);
+        }
+
+        let capture_instant = Instant::now();
+        while self.next_capture_instant <= capture_instant {
+            self.next_capture_instant += self.frame_interval;
+            self.frame_index += 1;
+        }
+
+        let (chrominance_stride, chrominance_size) = match self.format {
+            DesktopEncodeFrameFormat::NV12 => (self.width, self.width * self.height / 2),
+            DesktopEncodeFrameFormat::YUV444P => (self.width, self.width * self.height * 2),
+        };
+
+        let mut luminance_bytes = vec![0u8; (self.width * self.height) as usize];
+        let mut chrominance_bytes = vec![0u8; chrominance_size as usize];
+
+        let mut canvas = Canvas::new(
+            self.format,
+            self.width,
+            self.height,
+            &mut luminance_bytes,
+            &mut chrominance_bytes,
+        );
+
+        canvas.draw_test_pattern(
+            capture_instant.duration_since(self.start_instant),
+            self.frame_index,
+            self.fps,
+        );
+
+        Ok(DesktopEncodeFrame {
+            capture_time: capture_time_now(),
+            format: self.format,
+            width: self.width,
+            height: self.height,
+            luminance_bytes,
+            luminance_stride: self.width,
+            chrominance_bytes,
+            chrominance_stride,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_mode_rejects_invalid_mode() {
+        assert!(Duplicator::with_mode(0, 240, 30, ChromaFormat::YUV420).is_err());
+        assert!(Duplicator::with_mode(321, 240, 30, ChromaFormat::YUV420).is_err());
+        assert!(Duplicator::with_mode(320, -240, 30, ChromaFormat::YUV420).is_err());
+        assert!(Duplicator::with_mode(320, 240, 0, ChromaFormat::YUV420).is_err());
+    }
+
+    #[test]
+    fn test_new_selects_monitor() {
+        let (duplicator, monitor_id) = Duplicator::new(None, ChromaFormat::YUV420).unwrap();
+        assert_eq!(monitor_id, "synthetic-0");
+        assert_eq!((duplicator.width, duplicator.height), (1920, 1080));
+
+        let (duplicator, monitor_id) =
+            Duplicator::new(Some(String::from("synthetic-1")), ChromaFormat::YUV444).unwrap();
+        assert_eq!(monitor_id, "synthetic-1");
+        assert_eq!((duplicator.width, duplicator.height), (1280, 720));
+
+        assert!(Duplicator::new(Some(String::from("missing")), ChromaFormat::YUV420).is_err());
+    }
+
+    #[test]
+    fn test_capture_frame_layout() {
+        let mut duplicator = Duplicator::with_mode(64, 48, 120, ChromaFormat::YUV420).unwrap();
+        let frame = duplicator.capture().unwrap();
+        assert_eq!(frame.format, DesktopEncodeFrameFormat::NV12);
+        assert_eq!((frame.width, frame.height), (64, 48));
+        assert_eq!(frame.luminance_bytes.len(), 64 * 48);
+        assert_eq!(frame.chrominance_bytes.len(), 64 * 48 / 2);
+        assert_eq!(frame.chrominance_stride, 64);
+
+        let mut duplicator = Duplicator::with_mode(64, 48, 120, ChromaFormat::YUV444).unwrap();
+        let frame = duplicator.capture().unwrap();
+        assert_eq!(frame.format, DesktopEncodeFrameFormat::YUV444P);
+        assert_eq!(frame.chrominance_bytes.len(), 64 * 48 * 2);
+    }
+
+    #[test]
+    fn test_capture_paces_frames() {
+        let mut duplicator = Duplicator::with_mode(64, 48, 50, ChromaFormat::YUV420).unwrap();
+
+        let instant = Instant::now();
+        let first = duplicator.capture().unwrap();
+        for _ in 0..4 {
+            duplicator.capture().unwrap();
+        }
+        let last = duplicator.capture().unwrap();
+
+        // five intervals of 20ms, and the pattern keeps moving meanwhile
+        assert!(instant.elapsed() >= Duration::from_millis(95));
+        assert!(last.capture_time > first.capture_time);
+        assert_ne!(first.luminance_bytes, last.luminance_bytes);
+    }
+}
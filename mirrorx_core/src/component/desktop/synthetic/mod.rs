@@ -0,0 +1,4 @@
+mod duplicator;
+mod pattern;
+
+pub use duplicator::Duplicator;
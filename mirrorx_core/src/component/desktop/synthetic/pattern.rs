@@ -0,0 +1,413 @@
+use crate::component::frame::DesktopEncodeFrameFormat;
+use std::time::Duration;
+
+const BACKGROUND: Rgb = Rgb(24, 24, 32);
+const TEXT_BAND: Rgb = Rgb(48, 48, 64);
+const WHITE: Rgb = Rgb(235, 235, 235);
+
+const COLOR_BARS: [Rgb; 8] = [
+    Rgb(235, 235, 235),
+    Rgb(235, 235, 16),
+    Rgb(16, 235, 235),
+    Rgb(16, 235, 16),
+    Rgb(235, 16, 235),
+    Rgb(235, 16, 16),
+    Rgb(16, 16, 235),
+    Rgb(16, 16, 16),
+];
+
+// color, size in fractions of the frame height and horizontal and vertical speed in
+// travelled ranges per second
+const MOVING_RECTANGLES: [(Rgb, f64, f64, f64); 3] = [
+    (Rgb(230, 80, 60), 0.12, 0.31, 0.23),
+    (Rgb(60, 200, 90), 0.08, 0.47, 0.37),
+    (Rgb(70, 120, 240), 0.16, 0.19, 0.29),
+];
+
+const GLYPH_WIDTH: i32 = 5;
+const GLYPH_HEIGHT: i32 = 7;
+
+#[derive(Clone, Copy)]
+struct Rgb(u8, u8, u8);
+
+impl Rgb {
+    // full range bt.709, the same as frames from the capture backends
+    fn to_yuv(self) -> (u8, u8, u8) {
+        let (r, g, b) = (self.0 as f32, self.1 as f32, self.2 as f32);
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let u = (b - y) / 1.8556 + 128.0;
+        let v = (r - y) / 1.5748 + 128.0;
+
+        (
+            y.round().clamp(0.0, 255.0) as u8,
+            u.round().clamp(0.0, 255.0) as u8,
+            v.round().clamp(0.0, 255.0) as u8,
+        )
+    }
+}
+
+pub struct Canvas<'a> {
+    format: DesktopEncodeFrameFormat,
+    width: i32,
+    height: i32,
+    luminance_bytes: &'a mut [u8],
+    chrominance_bytes: &'a mut [u8],
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(
+        format: DesktopEncodeFrameFormat,
+        width: i32,
+        height: i32,
+        luminance_bytes: &'a mut [u8],
+        chrominance_bytes: &'a mut [u8],
+    ) -> Self {
+        Self {
+            format,
+            width,
+            height,
+            luminance_bytes,
+            chrominance_bytes,
+        }
+    }
+
+    // color bars on the top, bouncing rectangles in the middle and a scrolling line
+    // of text with the frame index on the bottom
+    pub fn draw_test_pattern(&mut self, elapsed: Duration, frame_index: u64, fps: u8) {
+        let (width, height) = (self.width, self.height);
+        let elapsed_secs = elapsed.as_secs_f64();
+
+        self.fill_rect(0, 0, width, height, BACKGROUND);
+
+        let bars_height = height / 4;
+        for (index, color) in COLOR_BARS.iter().enumerate() {
+            let left = width * index as i32 / COLOR_BARS.len() as i32;
+            let right = width * (index as i32 + 1) / COLOR_BARS.len() as i32;
+            self.fill_rect(left, 0, right - left, bars_height, *color);
+        }
+
+        let band_height = height / 6;
+        let band_top = height - band_height;
+        let field_height = band_top - bars_height;
+
+        for (color, size, speed_x, speed_y) in MOVING_RECTANGLES {
+            let rect_size = (height as f64 * size) as i32;
+            let left = bounce(elapsed_secs * speed_x, width - rect_size);
+            let top = bars_height + bounce(elapsed_secs * speed_y, field_height - rect_size);
+            self.fill_rect(left, top, rect_size, rect_size, color);
+        }
+
+        self.fill_rect(0, band_top, width, band_height, TEXT_BAND);
+
+        let text = format!(
+            "MIRRORX TEST PATTERN   {}X{} @ {} FPS   FRAME {:06}   ",
+            width, height, fps, frame_index
+        );
+
+        let scale = (band_height / (GLYPH_HEIGHT * 2)).max(1);
+        let text_width = text_width(&text, scale);
+        let text_top = band_top + (band_height - GLYPH_HEIGHT * scale) / 2;
+
+        // scrolls a quarter of the frame width per second and wraps around seamlessly
+        let offset = (elapsed_secs * width as f64 / 4.0) as i64 % text_width as i64;
+        let mut left = -(offset as i32);
+        while left < width {
+            self.draw_text(left, text_top, scale, &text, WHITE);
+            left += text_width;
+        }
+    }
+
+    fn draw_text(&mut self, left: i32, top: i32, scale: i32, text: &str, color: Rgb) {
+        for (index, ch) in text.chars().enumerate() {
+            let glyph_left = left + index as i32 * (GLYPH_WIDTH + 1) * scale;
+            if glyph_left >= self.width || glyph_left + GLYPH_WIDTH * scale < 0 {
+                continue;
+            }
+
+            for (row, bits) in glyph(ch).iter().enumerate() {
+                for column in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0 {
+                        self.fill_rect(
+                            glyph_left + column * scale,
+                            top + row as i32 * scale,
+                            scale,
+                            scale,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn fill_rect(&mut self, left: i32, top: i32, width: i32, height: i32, color: Rgb) {
+        let x0 = left.clamp(0, self.width);
+        let y0 = top.clamp(0, self.height);
+        let x1 = (left + width).clamp(0, self.width);
+        let y1 = (top + height).clamp(0, self.height);
+
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+
+        let (y, u, v) = color.to_yuv();
+
+        for row in y0..y1 {
+            let offset = (row * self.width) as usize;
+            self.luminance_bytes[offset + x0 as usize..offset + x1 as usize].fill(y);
+        }
+
+        match self.format {
+            DesktopEncodeFrameFormat::NV12 => {
+                // a chrominance sample covering any pixel of the rectangle takes its color
+                for row in y0 / 2..(y1 + 1) / 2 {
+                    let offset = (row * self.width) as usize;
+                    for column in x0 / 2..(x1 + 1) / 2 {
+                        self.chrominance_bytes[offset + column as usize * 2] = u;
+                        self.chrominance_bytes[offset + column as usize * 2 + 1] = v;
+                    }
+                }
+            }
+            DesktopEncodeFrameFormat::YUV444P => {
+                let (u_plane, v_plane) = self
+                    .chrominance_bytes
+                    .split_at_mut((self.width * self.height) as usize);
+
+                for row in y0..y1 {
+                    let offset = (row * self.width) as usize;
+                    u_plane[offset + x0 as usize..offset + x1 as usize].fill(u);
+                    v_plane[offset + x0 as usize..offset + x1 as usize].fill(v);
+                }
+            }
+        }
+    }
+}
+
+// moves back and forth between 0 and range, position is in fractions of the range
+// travelled since the start
+fn bounce(position: f64, range: i32) -> i32 {
+    if range <= 0 {
+        return 0;
+    }
+
+    let phase = position.rem_euclid(2.0);
+    let position = if phase > 1.0 { 2.0 - phase } else { phase };
+    (position * range as f64) as i32
+}
+
+fn text_width(text: &str, scale: i32) -> i32 {
+    text.chars().count() as i32 * (GLYPH_WIDTH + 1) * scale
+}
+
+// 5x7 bitmaps of the characters used by the pattern, one byte per row with the
+// leftmost pixel in the highest of the five low bits
+fn glyph(ch: char) -> [u8; 7] {
+    match ch {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        '@' => [0x0E, 0x11, 0x17, 0x15, 0x17, 0x10, 0x0F],
+        _ => [0; 7],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_planes(format: DesktopEncodeFrameFormat, width: i32, height: i32) -> (Vec<u8>, Vec<u8>) {
+        let chrominance_size = match format {
+            DesktopEncodeFrameFormat::NV12 => width * height / 2,
+            DesktopEncodeFrameFormat::YUV444P => width * height * 2,
+        };
+
+        (
+            vec![0u8; (width * height) as usize],
+            vec![0u8; chrominance_size as usize],
+        )
+    }
+
+    #[test]
+    fn test_bounce() {
+        assert_eq!(bounce(0.0, 100), 0);
+        assert_eq!(bounce(0.25, 100), 25);
+        assert_eq!(bounce(1.0, 100), 100);
+        assert_eq!(bounce(1.25, 100), 75);
+        assert_eq!(bounce(2.0, 100), 0);
+        assert_eq!(bounce(2.5, 100), 50);
+        assert_eq!(bounce(-0.25, 100), 25);
+        assert_eq!(bounce(0.7, 0), 0);
+        assert_eq!(bounce(0.7, -10), 0);
+    }
+
+    #[test]
+    fn test_bounce_stays_in_range() {
+        for step in 0..1000 {
+            let position = bounce(step as f64 * 0.037, 57);
+            assert!((0..=57).contains(&position), "{position}");
+        }
+    }
+
+    #[test]
+    fn test_rgb_to_yuv() {
+        assert_eq!(Rgb(0, 0, 0).to_yuv(), (0, 128, 128));
+        assert_eq!(Rgb(255, 255, 255).to_yuv(), (255, 128, 128));
+
+        let (_, u, v) = Rgb(255, 0, 0).to_yuv();
+        assert!(u < 128 && v == 255, "{u} {v}");
+
+        let (_, u, v) = Rgb(0, 0, 255).to_yuv();
+        assert!(u == 255 && v < 128, "{u} {v}");
+    }
+
+    #[test]
+    fn test_fill_rect_nv12() {
+        let (mut luminance, mut chrominance) = new_planes(DesktopEncodeFrameFormat::NV12, 8, 4);
+        let (y, u, v) = WHITE.to_yuv();
+
+        Canvas::new(
+            DesktopEncodeFrameFormat::NV12,
+            8,
+            4,
+            &mut luminance,
+            &mut chrominance,
+        )
+        .fill_rect(1, 1, 2, 2, WHITE);
+
+        for row in 0..4 {
+            for column in 0..8 {
+                let inside = (1..3).contains(&row) && (1..3).contains(&column);
+                let expected = if inside { y } else { 0 };
+                assert_eq!(luminance[row * 8 + column], expected, "{row} {column}");
+            }
+        }
+
+        // the rectangle touches the first two columns of both chrominance rows
+        for row in 0..2 {
+            for column in 0..4 {
+                let expected = if column < 2 { (u, v) } else { (0, 0) };
+                let offset = row * 8 + column * 2;
+                assert_eq!(
+                    (chrominance[offset], chrominance[offset + 1]),
+                    expected,
+                    "{row} {column}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill_rect_yuv444_clipped() {
+        let (mut luminance, mut chrominance) = new_planes(DesktopEncodeFrameFormat::YUV444P, 4, 4);
+        let (y, u, v) = WHITE.to_yuv();
+
+        Canvas::new(
+            DesktopEncodeFrameFormat::YUV444P,
+            4,
+            4,
+            &mut luminance,
+            &mut chrominance,
+        )
+        .fill_rect(-2, 2, 4, 10, WHITE);
+
+        let (u_plane, v_plane) = chrominance.split_at(16);
+        for row in 0..4 {
+            for column in 0..4 {
+                let inside = row >= 2 && column < 2;
+                let offset = row * 4 + column;
+                assert_eq!(luminance[offset], if inside { y } else { 0 });
+                assert_eq!(u_plane[offset], if inside { u } else { 0 });
+                assert_eq!(v_plane[offset], if inside { v } else { 0 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill_rect_outside() {
+        let (mut luminance, mut chrominance) = new_planes(DesktopEncodeFrameFormat::NV12, 4, 4);
+
+        let mut canvas = Canvas::new(
+            DesktopEncodeFrameFormat::NV12,
+            4,
+            4,
+            &mut luminance,
+            &mut chrominance,
+        );
+
+        canvas.fill_rect(4, 0, 2, 2, WHITE);
+        canvas.fill_rect(0, -3, 2, 3, WHITE);
+        canvas.fill_rect(1, 1, 0, 2, WHITE);
+
+        assert!(luminance.iter().all(|value| *value == 0));
+        assert!(chrominance.iter().all(|value| *value == 0));
+    }
+
+    #[test]
+    fn test_draw_test_pattern() {
+        let (width, height) = (320, 240);
+
+        let draw = |elapsed: Duration, frame_index: u64| {
+            let (mut luminance, mut chrominance) =
+                new_planes(DesktopEncodeFrameFormat::NV12, width, height);
+
+            Canvas::new(
+                DesktopEncodeFrameFormat::NV12,
+                width,
+                height,
+                &mut luminance,
+                &mut chrominance,
+            )
+            .draw_test_pattern(elapsed, frame_index, 30);
+
+            luminance
+        };
+
+        let first = draw(Duration::ZERO, 0);
+
+        // color bars stay at the top left and right corners
+        assert_eq!(first[0], COLOR_BARS[0].to_yuv().0);
+        assert_eq!(first[width as usize - 1], COLOR_BARS[7].to_yuv().0);
+
+        // the same moment is drawn the same, time moves the pattern
+        assert_eq!(first, draw(Duration::ZERO, 0));
+        assert_ne!(first, draw(Duration::from_millis(500), 15));
+    }
+
+    #[test]
+    fn test_text_width() {
+        assert_eq!(text_width("", 2), 0);
+        assert_eq!(text_width("MIRRORX", 1), 7 * (GLYPH_WIDTH + 1));
+        assert_eq!(text_width("42", 3), 2 * (GLYPH_WIDTH + 1) * 3);
+    }
+
+    #[test]
+    fn test_glyph() {
+        assert_eq!(glyph(' '), [0; 7]);
+        assert_eq!(glyph('?'), [0; 7]);
+
+        for ch in "0123456789AEFIMNOPRSTX@".chars() {
+            let bitmap = glyph(ch);
+            assert!(bitmap.iter().any(|row| *row != 0), "{ch}");
+            assert!(bitmap.iter().all(|row| *row < 1 << GLYPH_WIDTH), "{ch}");
+        }
+    }
+}
@@ -4,7 +4,11 @@ use super::{
     util::{init_directx, prepare_desktop},
 };
 use crate::{
-    component::{desktop::windows::dx_math::Vertex, frame::DesktopEncodeFrame},
+    api::endpoint::message::ChromaFormat,
+    component::{
+        desktop::{windows::dx_math::Vertex, yuv444::YUV444Converter},
+        frame::{capture_time_now, DesktopEncodeFrame, DesktopEncodeFrameFormat},
+    },
     core_error,
     error::{CoreError, CoreResult},
     HRESULT,
@@ -41,6 +45,7 @@ pub struct Duplicator {
     dxgi_outdupl_desc: DXGI_OUTDUPL_DESC,
 
     backend_texture: ID3D11Texture2D,
+    backend_staging_texture: ID3D11Texture2D,
     backend_viewport: [D3D11_VIEWPORT; 1],
     backend_rtv: [Option<ID3D11RenderTargetView>; 1],
 
@@ -54,6 +59,10 @@ pub struct Duplicator {
     chrominance_viewport: [D3D11_VIEWPORT; 1],
     chrominance_rtv: [Option<ID3D11RenderTargetView>; 1],
 
+    // yuv444 frames are converted from the bgra backend texture instead of drawn
+    // by the nv12 shaders
+    yuv444_converter: Option<YUV444Converter>,
+
     sampler_state: [Option<ID3D11SamplerState>; 1],
     blend_state: ID3D11BlendState,
 
@@ -63,14 +72,15 @@ pub struct Duplicator {
     mouse_visible: bool,
     mouse_shape_buffer: Vec<u8>,
     mouse_shape_info: DXGI_OUTDUPL_POINTER_SHAPE_INFO,
-
-    epoch: once_cell::unsync::OnceCell<std::time::Instant>,
 }
 
 unsafe impl Send for Duplicator {}
 
 impl Duplicator {
-    pub fn new(monitor_id: Option<String>) -> CoreResult<(Duplicator, String)> {
+    pub fn new(
+        monitor_id: Option<String>,
+        chroma_format: ChromaFormat,
+    ) -> CoreResult<(Duplicator, String)> {
         unsafe {
             prepare_desktop()?;
 
@@ -89,7 +99,7 @@ impl Duplicator {
             let mut dxgi_outdupl_desc = std::mem::zeroed();
             duplication.GetDesc(&mut dxgi_outdupl_desc);
 
-            let (backend_texture, backend_rtv, backend_viewport) =
+            let (backend_texture, backend_staging_texture, backend_rtv, backend_viewport) =
                 init_backend_resources(&device, &dxgi_outdupl_desc)?;
 
             let (lumina_render_texture, lumina_staging_texture, lumina_viewport, lumina_rtv) =
@@ -102,6 +112,14 @@ impl Duplicator {
                 chrominance_rtv,
             ) = init_chrominance_resources(&device, &dxgi_outdupl_desc)?;
 
+            let yuv444_converter = match chroma_format {
+                ChromaFormat::YUV420 => None,
+                ChromaFormat::YUV444 => Some(YUV444Converter::new(
+                    dxgi_outdupl_desc.ModeDesc.Width as i32,
+                    dxgi_outdupl_desc.ModeDesc.Height as i32,
+                )?),
+            };
+
             let sampler_state = init_sampler_state(&device)?;
 
             let blend_state = init_blend_state(&device)?;
@@ -122,6 +140,7 @@ impl Duplicator {
                     duplication,
                     dxgi_outdupl_desc,
                     backend_texture,
+                    backend_staging_texture,
                     backend_viewport: [backend_viewport],
                     backend_rtv: [Some(backend_rtv)],
                     luminance_render_texture: lumina_render_texture,
@@ -132,6 +151,7 @@ impl Duplicator {
                     chrominance_staging_texture,
                     chrominance_viewport: [chrominance_viewport],
                     chrominance_rtv: [Some(chrominance_rtv)],
+                    yuv444_converter,
                     sampler_state: [Some(sampler_state)],
                     blend_state,
                     mouse_position_x: 0,
@@ -140,7 +160,6 @@ impl Duplicator {
                     mouse_visible: false,
                     mouse_shape_buffer: Vec::new(),
                     mouse_shape_info: std::mem::zeroed(),
-                    epoch: once_cell::unsync::OnceCell::new(),
                 },
                 monitor_id,
             ))
@@ -164,6 +183,10 @@ impl Duplicator {
                 return Err(err);
             }
 
+            if let Some(ref yuv444_converter) = self.yuv444_converter {
+                return self.create_yuv444_capture_frame(yuv444_converter);
+            }
+
             self.draw_lumina_and_chrominance_texture()?;
             self.create_capture_frame()
         }
@@ -318,15 +341,9 @@ impl Duplicator {
         self.device_context
             .Unmap(&self.chrominance_staging_texture, 0);
 
-        let capture_time = if let Some(instant) = self.epoch.get() {
-            instant.elapsed()
-        } else {
-            let _ = self.epoch.set(std::time::Instant::now());
-            std::time::Duration::ZERO
-        };
-
         Ok(DesktopEncodeFrame {
-            capture_time,
+            capture_time: capture_time_now(),
+            format: DesktopEncodeFrameFormat::NV12,
             width: self.dxgi_outdupl_desc.ModeDesc.Width as i32,
             height: self.dxgi_outdupl_desc.ModeDesc.Height as i32,
             luminance_bytes,
@@ -336,6 +353,32 @@ impl Duplicator {
         })
     }
 
+    unsafe fn create_yuv444_capture_frame(
+        &self,
+        yuv444_converter: &YUV444Converter,
+    ) -> CoreResult<DesktopEncodeFrame> {
+        self.device_context
+            .CopyResource(&self.backend_staging_texture, &self.backend_texture);
+
+        let backend_mapped_resource =
+            HRESULT!(self
+                .device_context
+                .Map(&self.backend_staging_texture, 0, D3D11_MAP_READ, 0));
+
+        defer! {
+            self.device_context.Unmap(&self.backend_staging_texture, 0);
+        }
+
+        let bgra_stride = backend_mapped_resource.RowPitch;
+
+        let bgra_bytes = std::slice::from_raw_parts(
+            backend_mapped_resource.pData as *const u8,
+            (self.dxgi_outdupl_desc.ModeDesc.Height * bgra_stride) as usize,
+        );
+
+        yuv444_converter.convert(capture_time_now(), bgra_bytes, bgra_stride as i32)
+    }
+
     unsafe fn update_mouse(
         &mut self,
         desktop_frame_info: &DXGI_OUTDUPL_FRAME_INFO,
@@ -871,7 +914,12 @@ unsafe fn init_shaders(
 unsafe fn init_backend_resources(
     device: &ID3D11Device,
     dxgi_outdupl_desc: &DXGI_OUTDUPL_DESC,
-) -> CoreResult<(ID3D11Texture2D, ID3D11RenderTargetView, D3D11_VIEWPORT)> {
+) -> CoreResult<(
+    ID3D11Texture2D,
+    ID3D11Texture2D,
+    ID3D11RenderTargetView,
+    D3D11_VIEWPORT,
+)> {
     let mut texture_desc: D3D11_TEXTURE2D_DESC = std::mem::zeroed();
     texture_desc.Width = dxgi_outdupl_desc.ModeDesc.Width;
     texture_desc.Height = dxgi_outdupl_desc.ModeDesc.Height;
@@ -889,6 +937,8 @@ unsafe fn init_backend_resources(
     texture_desc.Usage = D3D11_USAGE_STAGING;
     texture_desc.BindFlags = D3D11_BIND_FLAG::default();
 
+    let staging_texture = HRESULT!(device.CreateTexture2D(&texture_desc, None));
+
     let rtv = HRESULT!(device.CreateRenderTargetView(&texture, None));
 
     let viewport = D3D11_VIEWPORT {
@@ -900,7 +950,7 @@ unsafe fn init_backend_resources(
         MaxDepth: 1.0,
     };
 
-    Ok((texture, rtv, viewport))
+    Ok((texture, staging_texture, rtv, viewport))
 }
 
 unsafe fn init_lumina_resources(
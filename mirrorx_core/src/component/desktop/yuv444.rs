@@ -0,0 +1,131 @@
+use crate::{
+    component::frame::{DesktopEncodeFrame, DesktopEncodeFrameFormat},
+    core_error,
+    error::CoreResult,
+};
+use mirrorx_native::ffmpeg::{
+    swscale::*,
+    utils::pixfmt::{AV_PIX_FMT_BGRA, AV_PIX_FMT_YUV444P},
+};
+use std::time::Duration;
+
+// converts captured bgra frames to planar yuv444 frames, the planes are full range
+// bt.709 like the nv12 frames from the capture shaders
+pub struct YUV444Converter {
+    sws_ctx: *mut SwsContext,
+    width: i32,
+    height: i32,
+}
+
+unsafe impl Send for YUV444Converter {}
+
+impl YUV444Converter {
+    pub fn new(width: i32, height: i32) -> CoreResult<YUV444Converter> {
+        unsafe {
+            let sws_ctx = sws_getContext(
+                width,
+                height,
+                AV_PIX_FMT_BGRA,
+                width,
+                height,
+                AV_PIX_FMT_YUV444P,
+                SWS_POINT,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null(),
+            );
+
+            if sws_ctx.is_null() {
+                return Err(core_error!("sws_getContext returns null pointer"));
+            }
+
+            let converter = YUV444Converter {
+                sws_ctx,
+                width,
+                height,
+            };
+
+            let ret = sws_setColorspaceDetails(
+                sws_ctx,
+                sws_getCoefficients(SWS_CS_DEFAULT),
+                1,
+                sws_getCoefficients(SWS_CS_ITU709),
+                1,
+                0,
+                1 << 16,
+                1 << 16,
+            );
+
+            if ret < 0 {
+                return Err(core_error!(
+                    "sws_setColorspaceDetails returns error code: {}",
+                    ret
+                ));
+            }
+
+            Ok(converter)
+        }
+    }
+
+    pub fn is_match(&self, width: i32, height: i32) -> bool {
+        self.width == width && self.height == height
+    }
+
+    pub fn convert(
+        &self,
+        capture_time: Duration,
+        bgra_bytes: &[u8],
+        bgra_stride: i32,
+    ) -> CoreResult<DesktopEncodeFrame> {
+        let plane_size = (self.width * self.height) as usize;
+        let mut luminance_bytes = vec![0u8; plane_size];
+        let mut chrominance_bytes = vec![0u8; plane_size * 2];
+        let (u_plane, v_plane) = chrominance_bytes.split_at_mut(plane_size);
+
+        let src_slice = [bgra_bytes.as_ptr()];
+        let src_stride = [bgra_stride];
+        let dst = [
+            luminance_bytes.as_mut_ptr(),
+            u_plane.as_mut_ptr(),
+            v_plane.as_mut_ptr(),
+        ];
+        let dst_stride = [self.width, self.width, self.width];
+
+        unsafe {
+            let ret = sws_scale(
+                self.sws_ctx,
+                src_slice.as_ptr(),
+                src_stride.as_ptr(),
+                0,
+                self.height,
+                dst.as_ptr(),
+                dst_stride.as_ptr(),
+            );
+
+            if ret != self.height {
+                return Err(core_error!("sws_scale returns unexpected height: {}", ret));
+            }
+        }
+
+        Ok(DesktopEncodeFrame {
+            capture_time,
+            format: DesktopEncodeFrameFormat::YUV444P,
+            width: self.width,
+            height: self.height,
+            luminance_bytes,
+            luminance_stride: self.width,
+            chrominance_bytes,
+            chrominance_stride: self.width,
+        })
+    }
+}
+
+impl Drop for YUV444Converter {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.sws_ctx.is_null() {
+                sws_freeContext(self.sws_ctx);
+            }
+        }
+    }
+}
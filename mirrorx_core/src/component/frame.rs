@@ -1,8 +1,32 @@
+use crate::utility::time::unix_micros;
 use cpal::SampleFormat;
-use std::time::Duration;
+use once_cell::sync::Lazy;
+use std::time::{Duration, Instant};
+
+// capture time of all duplicators is measured from this epoch, it is anchored to the
+// wall clock so the visitor can tell when a frame was captured
+static CAPTURE_EPOCH: Lazy<(Instant, i64)> = Lazy::new(|| (Instant::now(), unix_micros()));
+
+pub fn capture_time_now() -> Duration {
+    CAPTURE_EPOCH.0.elapsed()
+}
+
+pub fn capture_time_to_unix_micros(capture_time: Duration) -> i64 {
+    CAPTURE_EPOCH.1 + capture_time.as_micros() as i64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DesktopEncodeFrameFormat {
+    // chrominance bytes are interleaved u and v at half resolution
+    NV12,
+    // chrominance bytes are a full resolution u plane followed by a v plane,
+    // both with chrominance stride
+    YUV444P,
+}
 
 pub struct DesktopEncodeFrame {
     pub capture_time: Duration,
+    pub format: DesktopEncodeFrameFormat,
     pub width: i32,
     pub height: i32,
     pub luminance_bytes: Vec<u8>,
@@ -13,15 +37,20 @@ pub struct DesktopEncodeFrame {
 
 unsafe impl Send for DesktopEncodeFrame {}
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum DesktopDecodeFrameFormat {
     NV12,
     YUV420P,
+    YUV444P,
 }
 
 // todo: remove clone after stable
 #[derive(Clone)]
 pub struct DesktopDecodeFrame {
+    pub sequence: u64,
+    // remote wall clock in microseconds when the frame was captured, frames are
+    // paced by it
+    pub capture_time_us: i64,
     pub width: i32,
     pub height: i32,
     pub plane_data: Vec<Vec<u8>>,
@@ -32,6 +61,8 @@ pub struct DesktopDecodeFrame {
 impl Default for DesktopDecodeFrame {
     fn default() -> Self {
         Self {
+            sequence: 0,
+            capture_time_us: 0,
             width: 0,
             height: 0,
             plane_data: Vec::new(),
@@ -63,9 +63,13 @@ pub fn read_root_directory() -> CoreResult<Directory> {
 }
 
 fn read_icon(path: &Path) -> CoreResult<Vec<u8>> {
-    #[cfg(not(target_os = "windows"))]
+    #[cfg(target_os = "macos")]
     return self::macos::NSWorkspace::sharedWorkspace()?.iconForFile(path);
 
+    // entries are listed without icons until a linux icon theme lookup exists
+    #[cfg(target_os = "linux")]
+    return Err(crate::core_error!("file icon isn't supported on linux ({:?})", path));
+
     #[cfg(target_os = "windows")]
     return self::windows::read_icon(path);
 }
@@ -0,0 +1,310 @@
+use super::key::MouseKey;
+use crate::{
+    component::desktop::{linux::util::XDisplay, monitor::Monitor},
+    core_error,
+    error::CoreResult,
+};
+use once_cell::sync::Lazy;
+use std::sync::Mutex;
+use tao::keyboard::KeyCode;
+use x11_dl::{keysym::*, xtest::Xf86vmode};
+
+// a wheel notch of the visitor, every notch is a click of the x11 scroll buttons
+const WHEEL_DELTA: f32 = 120.0;
+
+// events are injected by the XTest extension through one connection shared by all
+// input handlers, it's opened by the first event
+static INPUT_CONNECTION: Lazy<Mutex<Option<InputConnection>>> = Lazy::new(|| Mutex::new(None));
+
+struct InputConnection {
+    display: XDisplay,
+    xtest: Xf86vmode,
+}
+
+unsafe impl Send for InputConnection {}
+
+pub fn mouse_up(monitor: &Monitor, key: &MouseKey, x: f32, y: f32) -> CoreResult<()> {
+    let button = map_mouse_button(key)?;
+
+    with_input_connection(|connection| unsafe {
+        connection.move_to(monitor, x, y);
+        connection.button(button, false);
+    })
+}
+
+pub fn mouse_down(monitor: &Monitor, key: &MouseKey, x: f32, y: f32) -> CoreResult<()> {
+    let button = map_mouse_button(key)?;
+
+    with_input_connection(|connection| unsafe {
+        connection.move_to(monitor, x, y);
+        connection.button(button, true);
+    })
+}
+
+// a pressed button stays down from mouse_down, so dragging is only a move
+pub fn mouse_move(monitor: &Monitor, _: &MouseKey, x: f32, y: f32) -> CoreResult<()> {
+    with_input_connection(|connection| unsafe {
+        connection.move_to(monitor, x, y);
+    })
+}
+
+pub fn mouse_scroll_wheel(_: &Monitor, delta: f32) -> CoreResult<()> {
+    if delta == 0.0 {
+        return Ok(());
+    }
+
+    let button = if delta > 0.0 { 4 } else { 5 };
+    let clicks = (delta.abs() / WHEEL_DELTA).round().max(1.0) as usize;
+
+    with_input_connection(|connection| unsafe {
+        for _ in 0..clicks {
+            connection.button(button, true);
+            connection.button(button, false);
+        }
+    })
+}
+
+pub fn mouse_double_click(monitor: &Monitor, key: &MouseKey, x: f32, y: f32) -> CoreResult<()> {
+    let button = map_mouse_button(key)?;
+
+    with_input_connection(|connection| unsafe {
+        connection.move_to(monitor, x, y);
+
+        for _ in 0..2 {
+            connection.button(button, true);
+            connection.button(button, false);
+        }
+    })
+}
+
+pub fn keyboard_up(key: &KeyCode) -> CoreResult<()> {
+    post_keyboard_event(key, false)
+}
+
+pub fn keyboard_down(key: &KeyCode) -> CoreResult<()> {
+    post_keyboard_event(key, true)
+}
+
+fn post_keyboard_event(key: &KeyCode, press: bool) -> CoreResult<()> {
+    let Some(key_sym) = map_key_code(key) else {
+        return Ok(());
+    };
+
+    with_input_connection(|connection| unsafe {
+        let key_code =
+            (connection.display.xlib.XKeysymToKeycode)(connection.display.display, key_sym.into());
+
+        // the keyboard mapping of the server has no key producing this symbol
+        if key_code != 0 {
+            (connection.xtest.XTestFakeKeyEvent)(
+                connection.display.display,
+                key_code.into(),
+                press.into(),
+                0,
+            );
+        }
+    })
+}
+
+fn with_input_connection(f: impl FnOnce(&InputConnection)) -> CoreResult<()> {
+    let mut input_connection = INPUT_CONNECTION
+        .lock()
+        .map_err(|_| core_error!("input connection lock poisoned"))?;
+
+    if input_connection.is_none() {
+        let display = XDisplay::open()?;
+        let xtest =
+            Xf86vmode::open().map_err(|err| core_error!("load libXtst failed ({})", err))?;
+
+        *input_connection = Some(InputConnection { display, xtest });
+    }
+
+    if let Some(connection) = input_connection.as_ref() {
+        f(connection);
+
+        unsafe {
+            (connection.display.xlib.XFlush)(connection.display.display);
+        }
+    }
+
+    Ok(())
+}
+
+impl InputConnection {
+    unsafe fn move_to(&self, monitor: &Monitor, x: f32, y: f32) {
+        (self.xtest.XTestFakeMotionEvent)(
+            self.display.display,
+            -1,
+            monitor.left as i32 + x.round() as i32,
+            monitor.top as i32 + y.round() as i32,
+            0,
+        );
+    }
+
+    unsafe fn button(&self, button: u32, press: bool) {
+        (self.xtest.XTestFakeButtonEvent)(self.display.display, button, press.into(), 0);
+    }
+}
+
+fn map_mouse_button(key: &MouseKey) -> CoreResult<u32> {
+    match key {
+        MouseKey::None => Err(core_error!("unsupport key")),
+        MouseKey::Left => Ok(1),
+        MouseKey::Wheel => Ok(2),
+        MouseKey::Right => Ok(3),
+        MouseKey::SideBack => Ok(8),
+        MouseKey::SideForward => Ok(9),
+    }
+}
+
+const fn map_key_code(key: &KeyCode) -> Option<u32> {
+    match key {
+        KeyCode::Backquote => Some(XK_grave),
+        KeyCode::Backslash => Some(XK_backslash),
+        KeyCode::BracketLeft => Some(XK_bracketleft),
+        KeyCode::BracketRight => Some(XK_bracketright),
+        KeyCode::Comma => Some(XK_comma),
+        KeyCode::Digit0 => Some(XK_0),
+        KeyCode::Digit1 => Some(XK_1),
+        KeyCode::Digit2 => Some(XK_2),
+        KeyCode::Digit3 => Some(XK_3),
+        KeyCode::Digit4 => Some(XK_4),
+        KeyCode::Digit5 => Some(XK_5),
+        KeyCode::Digit6 => Some(XK_6),
+        KeyCode::Digit7 => Some(XK_7),
+        KeyCode::Digit8 => Some(XK_8),
+        KeyCode::Digit9 => Some(XK_9),
+        KeyCode::Equal => Some(XK_equal),
+        KeyCode::IntlBackslash => Some(XK_backslash),
+        KeyCode::KeyA => Some(XK_a),
+        KeyCode::KeyB => Some(XK_b),
+        KeyCode::KeyC => Some(XK_c),
+        KeyCode::KeyD => Some(XK_d),
+        KeyCode::KeyE => Some(XK_e),
+        KeyCode::KeyF => Some(XK_f),
+        KeyCode::KeyG => Some(XK_g),
+        KeyCode::KeyH => Some(XK_h),
+        KeyCode::KeyI => Some(XK_i),
+        KeyCode::KeyJ => Some(XK_j),
+        KeyCode::KeyK => Some(XK_k),
+        KeyCode::KeyL => Some(XK_l),
+        KeyCode::KeyM => Some(XK_m),
+        KeyCode::KeyN => Some(XK_n),
+        KeyCode::KeyO => Some(XK_o),
+        KeyCode::KeyP => Some(XK_p),
+        KeyCode::KeyQ => Some(XK_q),
+        KeyCode::KeyR => Some(XK_r),
+        KeyCode::KeyS => Some(XK_s),
+        KeyCode::KeyT => Some(XK_t),
+        KeyCode::KeyU => Some(XK_u),
+        KeyCode::KeyV => Some(XK_v),
+        KeyCode::KeyW => Some(XK_w),
+        KeyCode::KeyX => Some(XK_x),
+        KeyCode::KeyY => Some(XK_y),
+        KeyCode::KeyZ => Some(XK_z),
+        KeyCode::Minus => Some(XK_minus),
+        KeyCode::Plus => Some(XK_equal),
+        KeyCode::Period => Some(XK_period),
+        KeyCode::Quote => Some(XK_apostrophe),
+        KeyCode::Semicolon => Some(XK_semicolon),
+        KeyCode::Slash => Some(XK_slash),
+        KeyCode::AltLeft => Some(XK_Alt_L),
+        KeyCode::AltRight => Some(XK_Alt_R),
+        KeyCode::Backspace => Some(XK_BackSpace),
+        KeyCode::CapsLock => Some(XK_Caps_Lock),
+        KeyCode::ContextMenu => Some(XK_Menu),
+        KeyCode::ControlLeft => Some(XK_Control_L),
+        KeyCode::ControlRight => Some(XK_Control_R),
+        KeyCode::Enter => Some(XK_Return),
+        KeyCode::SuperLeft => Some(XK_Super_L),
+        KeyCode::SuperRight => Some(XK_Super_R),
+        KeyCode::ShiftLeft => Some(XK_Shift_L),
+        KeyCode::ShiftRight => Some(XK_Shift_R),
+        KeyCode::Space => Some(XK_space),
+        KeyCode::Tab => Some(XK_Tab),
+        KeyCode::Convert => Some(XK_Henkan),
+        KeyCode::KanaMode => Some(XK_Katakana),
+        KeyCode::NonConvert => Some(XK_Muhenkan),
+        KeyCode::Delete => Some(XK_Delete),
+        KeyCode::End => Some(XK_End),
+        KeyCode::Help => Some(XK_Help),
+        KeyCode::Home => Some(XK_Home),
+        KeyCode::Insert => Some(XK_Insert),
+        KeyCode::PageDown => Some(XK_Page_Down),
+        KeyCode::PageUp => Some(XK_Page_Up),
+        KeyCode::ArrowDown => Some(XK_Down),
+        KeyCode::ArrowLeft => Some(XK_Left),
+        KeyCode::ArrowRight => Some(XK_Right),
+        KeyCode::ArrowUp => Some(XK_Up),
+        KeyCode::NumLock => Some(XK_Num_Lock),
+        KeyCode::Numpad0 => Some(XK_KP_0),
+        KeyCode::Numpad1 => Some(XK_KP_1),
+        KeyCode::Numpad2 => Some(XK_KP_2),
+        KeyCode::Numpad3 => Some(XK_KP_3),
+        KeyCode::Numpad4 => Some(XK_KP_4),
+        KeyCode::Numpad5 => Some(XK_KP_5),
+        KeyCode::Numpad6 => Some(XK_KP_6),
+        KeyCode::Numpad7 => Some(XK_KP_7),
+        KeyCode::Numpad8 => Some(XK_KP_8),
+        KeyCode::Numpad9 => Some(XK_KP_9),
+        KeyCode::NumpadAdd => Some(XK_KP_Add),
+        KeyCode::NumpadBackspace => Some(XK_BackSpace),
+        KeyCode::NumpadClear => Some(XK_Clear),
+        KeyCode::NumpadComma => Some(XK_KP_Separator),
+        KeyCode::NumpadDecimal => Some(XK_KP_Decimal),
+        KeyCode::NumpadDivide => Some(XK_KP_Divide),
+        KeyCode::NumpadEnter => Some(XK_KP_Enter),
+        KeyCode::NumpadEqual => Some(XK_KP_Equal),
+        KeyCode::NumpadMultiply => Some(XK_KP_Multiply),
+        KeyCode::NumpadStar => Some(XK_KP_Multiply),
+        KeyCode::NumpadSubtract => Some(XK_KP_Subtract),
+        KeyCode::Escape => Some(XK_Escape),
+        KeyCode::PrintScreen => Some(XK_Print),
+        KeyCode::ScrollLock => Some(XK_Scroll_Lock),
+        KeyCode::Pause => Some(XK_Pause),
+        KeyCode::BrowserBack => Some(XF86XK_Back),
+        KeyCode::BrowserFavorites => Some(XF86XK_Favorites),
+        KeyCode::BrowserForward => Some(XF86XK_Forward),
+        KeyCode::BrowserHome => Some(XF86XK_HomePage),
+        KeyCode::BrowserRefresh => Some(XF86XK_Refresh),
+        KeyCode::BrowserSearch => Some(XF86XK_Search),
+        KeyCode::BrowserStop => Some(XF86XK_Stop),
+        KeyCode::LaunchApp1 => Some(XF86XK_Launch0),
+        KeyCode::LaunchApp2 => Some(XF86XK_Launch1),
+        KeyCode::LaunchMail => Some(XF86XK_Mail),
+        KeyCode::MediaPlayPause => Some(XF86XK_AudioPlay),
+        KeyCode::MediaSelect => Some(XF86XK_AudioMedia),
+        KeyCode::MediaStop => Some(XF86XK_AudioStop),
+        KeyCode::MediaTrackNext => Some(XF86XK_AudioNext),
+        KeyCode::MediaTrackPrevious => Some(XF86XK_AudioPrev),
+        KeyCode::Sleep => Some(XF86XK_Sleep),
+        KeyCode::AudioVolumeDown => Some(XF86XK_AudioLowerVolume),
+        KeyCode::AudioVolumeMute => Some(XF86XK_AudioMute),
+        KeyCode::AudioVolumeUp => Some(XF86XK_AudioRaiseVolume),
+        KeyCode::F1 => Some(XK_F1),
+        KeyCode::F2 => Some(XK_F2),
+        KeyCode::F3 => Some(XK_F3),
+        KeyCode::F4 => Some(XK_F4),
+        KeyCode::F5 => Some(XK_F5),
+        KeyCode::F6 => Some(XK_F6),
+        KeyCode::F7 => Some(XK_F7),
+        KeyCode::F8 => Some(XK_F8),
+        KeyCode::F9 => Some(XK_F9),
+        KeyCode::F10 => Some(XK_F10),
+        KeyCode::F11 => Some(XK_F11),
+        KeyCode::F12 => Some(XK_F12),
+        KeyCode::F13 => Some(XK_F13),
+        KeyCode::F14 => Some(XK_F14),
+        KeyCode::F15 => Some(XK_F15),
+        KeyCode::F16 => Some(XK_F16),
+        KeyCode::F17 => Some(XK_F17),
+        KeyCode::F18 => Some(XK_F18),
+        KeyCode::F19 => Some(XK_F19),
+        KeyCode::F20 => Some(XK_F20),
+        KeyCode::F21 => Some(XK_F21),
+        KeyCode::F22 => Some(XK_F22),
+        KeyCode::F23 => Some(XK_F23),
+        KeyCode::F24 => Some(XK_F24),
+        _ => None,
+    }
+}
@@ -6,8 +6,14 @@ mod macos;
 #[cfg(target_os = "windows")]
 mod windows;
 
+#[cfg(target_os = "linux")]
+mod linux;
+
 #[cfg(target_os = "macos")]
 pub use macos::*;
 
 #[cfg(target_os = "windows")]
 pub use self::windows::*;
+
+#[cfg(target_os = "linux")]
+pub use self::linux::*;
@@ -1,8 +1,11 @@
 mod discover;
 mod server;
+pub mod visit;
 
-use self::discover::BroadcastPacket;
-use crate::{error::CoreResult, utility::os::enum_broadcast_network_interfaces};
+use self::{discover::BroadcastPacket, visit::LANVisits};
+use crate::{
+    api::config::LocalStorage, error::CoreResult, utility::os::enum_broadcast_network_interfaces,
+};
 use fxhash::FxHashMap;
 use serde::Serialize;
 use std::{
@@ -26,15 +29,20 @@ pub struct Node {
 pub struct LANProvider {
     nodes_cache: Arc<RwLock<FxHashMap<String, Node>>>,
     discoverable: Arc<AtomicBool>,
+    visits: LANVisits,
     _discovers: Vec<discover::Discover>,
     _server: server::Server,
 }
 
 impl LANProvider {
-    pub async fn new() -> CoreResult<Self> {
+    pub async fn new(storage: Option<LocalStorage>) -> CoreResult<Self> {
         let hostname = format!("{}.mirrorx.lan", get_hostname()?);
         let mut discovers = Vec::new();
-        let discoverable = Arc::new(AtomicBool::new(true));
+        let discoverable = match storage {
+            Some(ref storage) => storage.kv().get_settings()?.lan_discoverable,
+            None => true,
+        };
+        let discoverable = Arc::new(AtomicBool::new(discoverable));
         let (packet_tx, packet_rx) = tokio::sync::mpsc::channel(64);
 
         if cfg!(target_os = "windows") {
@@ -64,14 +72,16 @@ impl LANProvider {
             );
         }
 
-        let server = server::Server::new().await?;
         let nodes_cache = Arc::new(RwLock::new(FxHashMap::default()));
+        let visits = LANVisits::new(nodes_cache.clone());
+        let server = server::Server::new(storage, visits.clone()).await?;
 
         serve_discover_nodes(hostname, nodes_cache.clone(), packet_rx);
 
         Ok(LANProvider {
             nodes_cache,
             discoverable,
+            visits,
             _discovers: discovers,
             _server: server,
         })
@@ -90,6 +100,10 @@ impl LANProvider {
     pub fn set_discoverable(&self, discoverable: bool) {
         self.discoverable.store(discoverable, Ordering::SeqCst)
     }
+
+    pub fn visits(&self) -> LANVisits {
+        self.visits.clone()
+    }
 }
 
 fn serve_discover_nodes(
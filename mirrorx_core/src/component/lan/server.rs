@@ -1,55 +1,137 @@
+use super::visit::{accept_visit, LANVisits, LAN_SERVER_PORT, LAN_VISIT_PORT};
 use crate::{
-    api::endpoint::{create_passive_endpoint_client, EndPointStream},
+    api::{
+        config::{
+            entity::{
+                history::{Direction, Record, SessionType},
+                kv::Settings,
+            },
+            LocalStorage,
+        },
+        endpoint::{
+            create_passive_endpoint_client, id::EndPointID, record_session, EndPointStream,
+        },
+    },
     error::CoreResult,
 };
 use std::net::{IpAddr, Ipv4Addr};
 
 pub struct Server {
+    visits: LANVisits,
     exit_tx: Option<tokio::sync::oneshot::Sender<()>>,
 }
 
 impl Server {
-    pub async fn new() -> CoreResult<Self> {
-        let listener = tokio::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, 48001)).await?;
+    pub async fn new(storage: Option<LocalStorage>, visits: LANVisits) -> CoreResult<Self> {
+        let listener =
+            tokio::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, LAN_SERVER_PORT)).await?;
+        let visit_listener =
+            tokio::net::TcpListener::bind((Ipv4Addr::UNSPECIFIED, LAN_VISIT_PORT)).await?;
         let local_addr = listener.local_addr()?;
         let (exit_tx, mut exit_rx) = tokio::sync::oneshot::channel();
         tracing::info!(?local_addr, "local lan server listen");
+        visits.set_listening(true);
 
+        let server_visits = visits.clone();
         tokio::spawn(async move {
             loop {
-                let (stream, addr) = tokio::select! {
+                let (mut stream, addr, is_visit) = tokio::select! {
                     _ = &mut exit_rx => {
                         tracing::info!("local lan server exit");
                         return;
                     },
                     res = listener.accept() => match res {
-                        Ok(stream) => stream,
+                        Ok((stream, addr)) => (stream, addr, false),
                         Err(err) => {
                             tracing::error!(?err, "local lan server accept stream failed");
                             continue;
                         }
+                    },
+                    res = visit_listener.accept() => match res {
+                        Ok((stream, addr)) => (stream, addr, true),
+                        Err(err) => {
+                            tracing::error!(?err, "local lan server accept visit stream failed");
+                            continue;
+                        }
                     }
                 };
 
-                if let Err(err) = create_passive_endpoint_client(
-                    crate::api::endpoint::id::EndPointID::LANID {
-                        local_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-                        remote_ip: addr.ip(),
-                    },
-                    None,
-                    EndPointStream::PassiveTCP(stream),
-                    None,
-                )
-                .await
-                {
-                    tracing::error!(?err, "create passive endpoint client from lan failed");
-                }
+                tracing::info!(?addr, is_visit, "local lan server accept stream");
+
+                let storage = storage.clone();
+                let visits = server_visits.clone();
+                tokio::spawn(async move {
+                    // a signaling visit which prefers the direct lan path carries its own keys
+                    let visit = if is_visit {
+                        match accept_visit(&visits, &mut stream).await {
+                            Some(visit) => Some(visit),
+                            None => {
+                                tracing::warn!(?addr, "lan visit handshake failed");
+                                return;
+                            }
+                        }
+                    } else {
+                        None
+                    };
+
+                    let (endpoint_id, key_pair, mut session_record) = match visit {
+                        Some(visit) => (
+                            EndPointID::DeviceID {
+                                local_device_id: visit.local_device_id,
+                                remote_device_id: visit.remote_device_id,
+                            },
+                            Some(visit.key_pair),
+                            visit.session_record,
+                        ),
+                        None => (
+                            EndPointID::LANID {
+                                local_ip: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                                remote_ip: addr.ip(),
+                            },
+                            None,
+                            Record::new(
+                                Direction::Incoming,
+                                SessionType::LAN,
+                                String::default(),
+                                0,
+                            ),
+                        ),
+                    };
 
-                tracing::info!(?addr, "local lan server accept stream");
+                    session_record.peer_addr = Some(addr.ip().to_string());
+
+                    let settings = match storage {
+                        Some(ref storage) => storage.kv().get_settings().unwrap_or_else(|err| {
+                            tracing::warn!(?err, "read settings failed, use default settings");
+                            Settings::default()
+                        }),
+                        None => Settings::default(),
+                    };
+
+                    match create_passive_endpoint_client(
+                        endpoint_id,
+                        key_pair,
+                        EndPointStream::PassiveTCP(stream),
+                        None,
+                        settings,
+                    )
+                    .await
+                    {
+                        Ok(client) => {
+                            if let Some(storage) = storage {
+                                record_session(storage, client, session_record);
+                            }
+                        }
+                        Err(err) => {
+                            tracing::error!(?err, "create passive endpoint client from lan failed")
+                        }
+                    }
+                });
             }
         });
 
         Ok(Self {
+            visits,
             exit_tx: Some(exit_tx),
         })
     }
@@ -57,6 +139,8 @@ impl Server {
 
 impl Drop for Server {
     fn drop(&mut self) {
+        self.visits.set_listening(false);
+
         if let Some(exit_ex) = self.exit_tx.take() {
             let _ = exit_ex.send(());
         }
@@ -0,0 +1,287 @@
+use super::Node;
+use crate::{
+    api::{
+        config::entity::history::Record,
+        endpoint::message::{EndPointHandshakeRequest, EndPointHandshakeResponse},
+    },
+    core_error,
+    error::{CoreError, CoreResult},
+    utility::{
+        bincode::{bincode_deserialize, bincode_serialize},
+        nonce_value::NonceValue,
+        os::enum_host_networks,
+    },
+};
+use fxhash::FxHashMap;
+use ring::aead::{OpeningKey, SealingKey};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{oneshot, RwLock},
+};
+
+pub const LAN_SERVER_PORT: u16 = 48001;
+
+// signaling visits are handed over on their own port, so sessions accepted on the
+// lan server port never wait for a handshake frame
+pub const LAN_VISIT_PORT: u16 = 48002;
+
+// keep the advertised addresses small enough to fit in the rsa sealed key exchange reply
+const MAX_LAN_ADDRS: usize = 8;
+const MAX_HANDSHAKE_FRAME_LENGTH: usize = 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct PendingVisit {
+    pub local_device_id: i64,
+    pub remote_device_id: i64,
+    pub key_pair: (OpeningKey<NonceValue>, SealingKey<NonceValue>),
+    pub session_record: Record,
+}
+
+type PendingVisits = Arc<Mutex<HashMap<Vec<u8>, (PendingVisit, oneshot::Sender<()>)>>>;
+
+// the lan state shared by signaling visits, it's owned by the lan provider
+#[derive(Clone)]
+pub struct LANVisits {
+    nodes_cache: Arc<RwLock<FxHashMap<String, Node>>>,
+    pending_visits: PendingVisits,
+    listening: Arc<AtomicBool>,
+}
+
+impl LANVisits {
+    pub(super) fn new(nodes_cache: Arc<RwLock<FxHashMap<String, Node>>>) -> Self {
+        Self {
+            nodes_cache,
+            pending_visits: Arc::new(Mutex::new(HashMap::new())),
+            listening: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub(super) fn set_listening(&self, listening: bool) {
+        self.listening.store(listening, Ordering::SeqCst);
+    }
+
+    // only advertise local addresses when the lan server is able to accept the direct connection
+    pub fn listening_addrs(&self) -> Vec<IpAddr> {
+        if !self.listening.load(Ordering::SeqCst) {
+            return Vec::new();
+        }
+
+        self.local_addrs()
+    }
+
+    pub fn local_addrs(&self) -> Vec<IpAddr> {
+        match enum_host_networks() {
+            Ok(networks) => networks
+                .into_iter()
+                .map(|(ip, _)| IpAddr::V4(ip))
+                .take(MAX_LAN_ADDRS)
+                .collect(),
+            Err(err) => {
+                tracing::warn!(?err, "enum host networks failed");
+                Vec::new()
+            }
+        }
+    }
+
+    // the peer is only dialed on the addrs it has been discovered from, a subnet match
+    // alone can't tell apart two private networks using the same range
+    pub async fn discovered_addrs(&self, remote_addrs: &[IpAddr]) -> Vec<IpAddr> {
+        let nodes = self.nodes_cache.read().await;
+
+        remote_addrs
+            .iter()
+            .filter(|remote_addr| {
+                nodes
+                    .values()
+                    .any(|node| node.addrs.contains_key(remote_addr))
+            })
+            .cloned()
+            .collect()
+    }
+
+    pub fn register(&self, token: Vec<u8>, visit: PendingVisit) -> VisitTicket {
+        let (claimed_tx, claimed_rx) = oneshot::channel();
+
+        if let Ok(mut pending_visits) = self.pending_visits.lock() {
+            pending_visits.insert(token.clone(), (visit, claimed_tx));
+        }
+
+        VisitTicket {
+            token,
+            pending_visits: self.pending_visits.clone(),
+            claimed_rx,
+        }
+    }
+
+    fn claim(&self, token: &[u8], device_id: i64) -> Option<PendingVisit> {
+        let (visit, claimed_tx) = {
+            let mut pending_visits = self.pending_visits.lock().ok()?;
+            let (visit, _) = pending_visits.get(token)?;
+
+            if visit.remote_device_id != device_id {
+                tracing::error!(device_id, "lan visit handshake device id mismatch");
+                return None;
+            }
+
+            pending_visits.remove(token)?
+        };
+
+        let _ = claimed_tx.send(());
+
+        Some(visit)
+    }
+}
+
+// a visit waiting for the active side on the lan visit port, it's withdrawn on drop
+pub struct VisitTicket {
+    token: Vec<u8>,
+    pending_visits: PendingVisits,
+    claimed_rx: oneshot::Receiver<()>,
+}
+
+impl VisitTicket {
+    // resolves once the lan server has taken the visit over and served the session
+    pub async fn claimed(&mut self) -> bool {
+        (&mut self.claimed_rx).await.is_ok()
+    }
+
+    // returns the visit back unless the lan server has claimed it already
+    pub fn withdraw(&mut self) -> Option<PendingVisit> {
+        self.pending_visits
+            .lock()
+            .ok()?
+            .remove(&self.token)
+            .map(|(visit, _)| visit)
+    }
+}
+
+impl Drop for VisitTicket {
+    fn drop(&mut self) {
+        let _ = self.withdraw();
+    }
+}
+
+pub struct LANVisitOffer {
+    pub addrs: Vec<IpAddr>,
+    pub token: Vec<u8>,
+    pub local_device_id: i64,
+    pub remote_device_id: i64,
+}
+
+impl LANVisitOffer {
+    pub async fn connect(self) -> CoreResult<TcpStream> {
+        // the passive side only waits for a while, so all addrs share one deadline
+        let deadline = tokio::time::Instant::now() + CONNECT_TIMEOUT + HANDSHAKE_TIMEOUT;
+
+        for ip in self.addrs {
+            let addr = SocketAddr::new(ip, LAN_VISIT_PORT);
+            let Ok(result) = tokio::time::timeout_at(
+                deadline,
+                connect_and_handshake(
+                    addr,
+                    &self.token,
+                    self.local_device_id,
+                    self.remote_device_id,
+                ),
+            )
+            .await
+            else {
+                break;
+            };
+
+            match result {
+                Ok(stream) => {
+                    tracing::info!(?addr, "direct lan connection established");
+                    return Ok(stream);
+                }
+                Err(err) => tracing::warn!(?err, ?addr, "direct lan connection failed"),
+            }
+        }
+
+        Err(core_error!("non lan addr usable"))
+    }
+}
+
+pub(super) async fn accept_visit(
+    visits: &LANVisits,
+    stream: &mut TcpStream,
+) -> Option<PendingVisit> {
+    let request_buffer = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_frame(stream))
+        .await
+        .ok()?
+        .ok()?;
+
+    let request: EndPointHandshakeRequest = bincode_deserialize(&request_buffer).ok()?;
+    let visit = visits.claim(&request.visit_credentials, request.device_id)?;
+
+    let response_buffer = bincode_serialize(&EndPointHandshakeResponse {
+        remote_device_id: visit.local_device_id,
+    })
+    .ok()?;
+
+    write_frame(stream, &response_buffer).await.ok()?;
+
+    Some(visit)
+}
+
+async fn connect_and_handshake(
+    addr: SocketAddr,
+    token: &[u8],
+    local_device_id: i64,
+    remote_device_id: i64,
+) -> CoreResult<TcpStream> {
+    let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+        .await
+        .map_err(|_| CoreError::Timeout)??;
+
+    let request_buffer = bincode_serialize(&EndPointHandshakeRequest {
+        visit_credentials: token.to_vec(),
+        device_id: local_device_id,
+    })?;
+
+    write_frame(&mut stream, &request_buffer).await?;
+
+    let response_buffer = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_frame(&mut stream))
+        .await
+        .map_err(|_| CoreError::Timeout)??;
+
+    let resp: EndPointHandshakeResponse = bincode_deserialize(&response_buffer)?;
+
+    if resp.remote_device_id != remote_device_id {
+        return Err(core_error!("lan visit handshake device id mismatch"));
+    }
+
+    Ok(stream)
+}
+
+// frames are length delimited in the same way as endpoint sessions, so the stream
+// can be handed over to the endpoint client after handshake
+async fn write_frame(stream: &mut TcpStream, buffer: &[u8]) -> CoreResult<()> {
+    stream
+        .write_all(&(buffer.len() as u32).to_le_bytes())
+        .await?;
+    stream.write_all(buffer).await?;
+    Ok(())
+}
+
+async fn read_frame(stream: &mut TcpStream) -> CoreResult<Vec<u8>> {
+    let frame_length = stream.read_u32_le().await? as usize;
+    if frame_length > MAX_HANDSHAKE_FRAME_LENGTH {
+        return Err(core_error!("lan visit handshake frame too long"));
+    }
+
+    let mut buffer = vec![0u8; frame_length];
+    stream.read_exact(&mut buffer).await?;
+    Ok(buffer)
+}
@@ -6,5 +6,8 @@ pub mod frame;
 pub mod fs;
 pub mod input;
 pub mod lan;
+pub mod punch;
+pub mod recording;
+pub mod screenshot;
 pub mod video_decoder;
 pub mod video_encoder;
@@ -0,0 +1,339 @@
+pub mod reflector;
+
+use crate::{core_error, error::CoreResult, utility::os::enum_host_addrs};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+
+pub const PUNCH_TOKEN_LENGTH: usize = 16;
+pub const DEFAULT_REFLECTOR_PORT: u16 = 3478;
+
+// a device with a public address can serve the stand-in reflector for a signaling
+// server which doesn't deploy one, and the other devices are pointed to it
+const REFLECTOR_LISTEN_ADDR_ENV: &str = "MIRRORX_REFLECTOR_LISTEN_ADDR";
+const REFLECTOR_ADDR_ENV: &str = "MIRRORX_REFLECTOR_ADDR";
+
+// keep the candidates small enough to fit in the rsa sealed key exchange reply
+const MAX_CANDIDATES: usize = 16;
+const PUNCH_TIMEOUT: Duration = Duration::from_secs(5);
+// the passive side starts punching before the active side receives the visit reply,
+// so it waits longer for the selection
+const SELECT_TIMEOUT: Duration = Duration::from_secs(2);
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+const ACK_BURST_TIMES: usize = 3;
+
+const PUNCH_MAGIC: &[u8; 7] = b"MXPUNCH";
+const PACKET_PROBE: u8 = 0;
+const PACKET_ACK: u8 = 1;
+// the active side selects the path and the passive side follows it
+const PACKET_SELECT_PUNCHED: u8 = 2;
+const PACKET_SELECT_RELAY: u8 = 3;
+const PACKET_SELECT_ACK: u8 = 4;
+const PACKET_PAYLOAD_LENGTH: usize = PUNCH_MAGIC.len() + 1 + PUNCH_TOKEN_LENGTH;
+
+pub struct Puncher {
+    socket: UdpSocket,
+    candidates: Vec<SocketAddr>,
+}
+
+impl Puncher {
+    pub async fn bind(reflector_addr: Option<SocketAddr>) -> CoreResult<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        let local_port = socket.local_addr()?.port();
+
+        let mut candidates: Vec<SocketAddr> = enum_host_addrs()?
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, local_port))
+            .collect();
+
+        if let Some(reflector_addr) = reflector_addr {
+            match reflector::query_reflexive_addr(&socket, reflector_addr).await {
+                Ok(reflexive_addr) => {
+                    if !candidates.contains(&reflexive_addr) {
+                        candidates.insert(0, reflexive_addr);
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(?err, ?reflector_addr, "gather reflexive candidate failed")
+                }
+            }
+        }
+
+        candidates.truncate(MAX_CANDIDATES);
+
+        Ok(Self { socket, candidates })
+    }
+
+    pub fn candidates(&self) -> &[SocketAddr] {
+        &self.candidates
+    }
+
+    // both sides probe the candidates of each other. the active side selects the
+    // punched path once it's acked and takes it as soon as the passive side confirms,
+    // an error means both sides fall back to the relay
+    pub async fn punch(
+        self,
+        token: &[u8],
+        remote_candidates: &[SocketAddr],
+        active: bool,
+    ) -> CoreResult<(UdpSocket, SocketAddr)> {
+        if token.len() != PUNCH_TOKEN_LENGTH {
+            return Err(core_error!("invalid punch token"));
+        }
+
+        let remote_candidates: Vec<SocketAddr> = remote_candidates
+            .iter()
+            .filter(|addr| addr.is_ipv4() && !addr.ip().is_unspecified() && addr.port() != 0)
+            .take(MAX_CANDIDATES)
+            .cloned()
+            .collect();
+
+        if remote_candidates.is_empty() {
+            return Err(core_error!("no remote candidate usable"));
+        }
+
+        let probe_packet = encode_packet(PACKET_PROBE, token);
+        let ack_packet = encode_packet(PACKET_ACK, token);
+        let select_packet = encode_packet(PACKET_SELECT_PUNCHED, token);
+
+        let mut deadline = tokio::time::Instant::now()
+            + if active {
+                PUNCH_TIMEOUT
+            } else {
+                PUNCH_TIMEOUT + SELECT_TIMEOUT * 2
+            };
+        let mut ticker = tokio::time::interval(PROBE_INTERVAL);
+        let mut buffer = [0u8; 64];
+        let mut selected_addr: Option<SocketAddr> = None;
+        let mut probed_addrs: Vec<SocketAddr> = Vec::new();
+
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(deadline) => {
+                    if selected_addr.is_some() {
+                        return Err(core_error!("path selection timeout"));
+                    }
+
+                    if active {
+                        // the passive side may still hear this side, tell it not to wait
+                        let relay_packet = encode_packet(PACKET_SELECT_RELAY, token);
+                        for remote_addr in remote_candidates.iter().chain(probed_addrs.iter()) {
+                            let _ = self.socket.send_to(&relay_packet, remote_addr).await;
+                        }
+                    }
+
+                    return Err(core_error!("hole punching timeout"));
+                }
+                _ = ticker.tick() => {
+                    match selected_addr {
+                        Some(remote_addr) => {
+                            let _ = self.socket.send_to(&select_packet, remote_addr).await;
+                        }
+                        None => {
+                            for remote_addr in remote_candidates.iter() {
+                                let _ = self.socket.send_to(&probe_packet, remote_addr).await;
+                            }
+                        }
+                    }
+                }
+                res = self.socket.recv_from(&mut buffer) => {
+                    // errors like icmp port unreachable are expected while punching
+                    let Ok((len, remote_addr)) = res else {
+                        continue;
+                    };
+
+                    let packet_type = decode_packet(&buffer[..len], token);
+
+                    // the passive side took the path and already serves the session on it
+                    if active
+                        && selected_addr == Some(remote_addr)
+                        && (packet_type == Some(PACKET_SELECT_ACK)
+                            || !buffer.get(4..len).is_some_and(is_punch_packet))
+                    {
+                        tracing::info!(?remote_addr, "hole punching success");
+                        return Ok((self.socket, remote_addr));
+                    }
+
+                    match packet_type {
+                        Some(PACKET_PROBE) => {
+                            if !probed_addrs.contains(&remote_addr) {
+                                probed_addrs.push(remote_addr);
+                            }
+
+                            let _ = self.socket.send_to(&ack_packet, remote_addr).await;
+                        }
+                        Some(PACKET_ACK) if active && selected_addr.is_none() => {
+                            selected_addr = Some(remote_addr);
+                            deadline = tokio::time::Instant::now() + SELECT_TIMEOUT;
+                            let _ = self.socket.send_to(&select_packet, remote_addr).await;
+                        }
+                        Some(PACKET_SELECT_PUNCHED) if !active => {
+                            let select_ack_packet = encode_packet(PACKET_SELECT_ACK, token);
+                            for _ in 0..ACK_BURST_TIMES {
+                                let _ = self.socket.send_to(&select_ack_packet, remote_addr).await;
+                            }
+
+                            tracing::info!(?remote_addr, "hole punching success");
+                            return Ok((self.socket, remote_addr));
+                        }
+                        Some(PACKET_SELECT_RELAY) if !active => {
+                            return Err(core_error!("remote endpoint selected relay"));
+                        }
+                        _ => continue,
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct PunchSession {
+    pub puncher: Puncher,
+    pub token: Vec<u8>,
+    pub remote_candidates: Vec<SocketAddr>,
+}
+
+impl PunchSession {
+    pub async fn punch(self, active: bool) -> CoreResult<(UdpSocket, SocketAddr)> {
+        self.puncher
+            .punch(&self.token, &self.remote_candidates, active)
+            .await
+    }
+}
+
+pub async fn resolve_reflector_addr(host: &str) -> Option<SocketAddr> {
+    let mut addrs: Vec<SocketAddr> = match std::env::var(REFLECTOR_ADDR_ENV) {
+        Ok(reflector_addr) => tokio::net::lookup_host(reflector_addr)
+            .await
+            .ok()?
+            .collect(),
+        Err(_) => tokio::net::lookup_host((host, DEFAULT_REFLECTOR_PORT))
+            .await
+            .ok()?
+            .collect(),
+    };
+
+    addrs.retain(|addr| addr.is_ipv4());
+    addrs.into_iter().next()
+}
+
+pub fn spawn_reflector_from_env() {
+    let Ok(listen_addr) = std::env::var(REFLECTOR_LISTEN_ADDR_ENV) else {
+        return;
+    };
+
+    tokio::spawn(async move {
+        let socket = match UdpSocket::bind(&listen_addr).await {
+            Ok(socket) => socket,
+            Err(err) => {
+                tracing::error!(?err, ?listen_addr, "bind reflector socket failed");
+                return;
+            }
+        };
+
+        tracing::info!(?listen_addr, "reflector listening");
+
+        if let Err(err) = reflector::serve_reflector(socket).await {
+            tracing::error!(?err, "reflector exit");
+        }
+    });
+}
+
+// stale punch packets may still arrive after the session took over the socket,
+// they're framed like session packets so the session reader can skip them
+pub(crate) fn is_punch_packet(payload: &[u8]) -> bool {
+    payload.len() == PACKET_PAYLOAD_LENGTH && payload.starts_with(PUNCH_MAGIC)
+}
+
+fn encode_packet(packet_type: u8, token: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(4 + PACKET_PAYLOAD_LENGTH);
+    buffer.extend_from_slice(&(PACKET_PAYLOAD_LENGTH as u32).to_le_bytes());
+    buffer.extend_from_slice(PUNCH_MAGIC);
+    buffer.push(packet_type);
+    buffer.extend_from_slice(token);
+    buffer
+}
+
+fn decode_packet(buffer: &[u8], token: &[u8]) -> Option<u8> {
+    let payload = buffer.get(4..)?;
+
+    if !is_punch_packet(payload) || &payload[PUNCH_MAGIC.len() + 1..] != token {
+        return None;
+    }
+
+    Some(payload[PUNCH_MAGIC.len()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_punch_packet_round_trip() {
+        let token = [9u8; PUNCH_TOKEN_LENGTH];
+        let packet = encode_packet(PACKET_ACK, &token);
+
+        assert!(is_punch_packet(&packet[4..]));
+        assert_eq!(decode_packet(&packet, &token), Some(PACKET_ACK));
+        assert_eq!(decode_packet(&packet, &[0u8; PUNCH_TOKEN_LENGTH]), None);
+    }
+
+    #[tokio::test]
+    async fn test_punch_over_loopback() {
+        let token = [5u8; PUNCH_TOKEN_LENGTH];
+
+        let active = Puncher::bind(None).await.unwrap();
+        let passive = Puncher::bind(None).await.unwrap();
+
+        let active_addr = SocketAddr::new(
+            Ipv4Addr::LOCALHOST.into(),
+            active.socket.local_addr().unwrap().port(),
+        );
+        let passive_addr = SocketAddr::new(
+            Ipv4Addr::LOCALHOST.into(),
+            passive.socket.local_addr().unwrap().port(),
+        );
+
+        let active_candidates = [active_addr];
+        let passive_candidates = [passive_addr];
+
+        let (active_result, passive_result) = tokio::join!(
+            active.punch(&token, &passive_candidates, true),
+            passive.punch(&token, &active_candidates, false)
+        );
+
+        assert_eq!(active_result.unwrap().1, passive_addr);
+        assert_eq!(passive_result.unwrap().1, active_addr);
+    }
+
+    #[tokio::test]
+    async fn test_passive_follows_relay_selection() {
+        let token = [6u8; PUNCH_TOKEN_LENGTH];
+
+        let passive = Puncher::bind(None).await.unwrap();
+        let passive_addr = SocketAddr::new(
+            Ipv4Addr::LOCALHOST.into(),
+            passive.socket.local_addr().unwrap().port(),
+        );
+
+        let active_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let active_addr = active_socket.local_addr().unwrap();
+
+        // an ack doesn't make the passive side take the path, only the selection does
+        tokio::spawn(async move {
+            let _ = active_socket
+                .send_to(&encode_packet(PACKET_ACK, &token), passive_addr)
+                .await;
+            tokio::time::sleep(PROBE_INTERVAL * 3).await;
+            let _ = active_socket
+                .send_to(&encode_packet(PACKET_SELECT_RELAY, &token), passive_addr)
+                .await;
+        });
+
+        let started_at = tokio::time::Instant::now();
+        assert!(passive.punch(&token, &[active_addr], false).await.is_err());
+        assert!(started_at.elapsed() < PUNCH_TIMEOUT);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::{core_error, error::CoreResult};
+use rand::RngCore;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+
+// a minimal subset of STUN (RFC 5389) binding, which is enough to learn the
+// server reflexive address and compatible with public STUN servers
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS_RESPONSE: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const HEADER_LENGTH: usize = 20;
+const ATTRIBUTE_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTRIBUTE_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const FAMILY_IPV4: u8 = 0x01;
+const FAMILY_IPV6: u8 = 0x02;
+
+const BINDING_RETRY_TIMES: usize = 2;
+const BINDING_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub async fn query_reflexive_addr(
+    socket: &UdpSocket,
+    reflector_addr: SocketAddr,
+) -> CoreResult<SocketAddr> {
+    let mut transaction_id = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut transaction_id);
+
+    let request = encode_message(BINDING_REQUEST, &transaction_id, &[]);
+    let mut buffer = [0u8; 512];
+
+    for _ in 0..BINDING_RETRY_TIMES {
+        socket.send_to(&request, reflector_addr).await?;
+
+        let deadline = tokio::time::Instant::now() + BINDING_TIMEOUT;
+        loop {
+            let Ok(res) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await
+            else {
+                break;
+            };
+
+            // ignore packets from elsewhere and errors like icmp port unreachable
+            let Ok((len, addr)) = res else {
+                continue;
+            };
+
+            if addr != reflector_addr {
+                continue;
+            }
+
+            if let Some(reflexive_addr) = decode_binding_response(&buffer[..len], &transaction_id) {
+                return Ok(reflexive_addr);
+            }
+        }
+    }
+
+    Err(core_error!("query reflexive addr timeout"))
+}
+
+// a stand-in reflector answers binding requests with the observed source
+// address, it's used when the signaling server doesn't deploy one
+pub async fn serve_reflector(socket: UdpSocket) -> CoreResult<()> {
+    let mut buffer = [0u8; 512];
+
+    loop {
+        let (len, addr) = match socket.recv_from(&mut buffer).await {
+            Ok(v) => v,
+            Err(err) => {
+                tracing::warn!(?err, "reflector receive failed");
+                continue;
+            }
+        };
+
+        let Some(transaction_id) = decode_binding_request(&buffer[..len]) else {
+            continue;
+        };
+
+        let response = encode_message(
+            BINDING_SUCCESS_RESPONSE,
+            &transaction_id,
+            &encode_xor_mapped_address(addr, &transaction_id),
+        );
+
+        if let Err(err) = socket.send_to(&response, addr).await {
+            tracing::warn!(?err, ?addr, "reflector reply failed");
+        }
+    }
+}
+
+fn encode_message(message_type: u16, transaction_id: &[u8; 12], attributes: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(HEADER_LENGTH + attributes.len());
+    buffer.extend_from_slice(&message_type.to_be_bytes());
+    buffer.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    buffer.extend_from_slice(transaction_id);
+    buffer.extend_from_slice(attributes);
+    buffer
+}
+
+fn decode_header(buffer: &[u8]) -> Option<(u16, [u8; 12], &[u8])> {
+    if buffer.len() < HEADER_LENGTH {
+        return None;
+    }
+
+    let message_type = u16::from_be_bytes([buffer[0], buffer[1]]);
+    let length = u16::from_be_bytes([buffer[2], buffer[3]]) as usize;
+    let cookie = u32::from_be_bytes([buffer[4], buffer[5], buffer[6], buffer[7]]);
+
+    if cookie != MAGIC_COOKIE || buffer.len() < HEADER_LENGTH + length {
+        return None;
+    }
+
+    let mut transaction_id = [0u8; 12];
+    transaction_id.copy_from_slice(&buffer[8..HEADER_LENGTH]);
+
+    Some((
+        message_type,
+        transaction_id,
+        &buffer[HEADER_LENGTH..HEADER_LENGTH + length],
+    ))
+}
+
+fn decode_binding_request(buffer: &[u8]) -> Option<[u8; 12]> {
+    match decode_header(buffer)? {
+        (BINDING_REQUEST, transaction_id, _) => Some(transaction_id),
+        _ => None,
+    }
+}
+
+fn decode_binding_response(
+    buffer: &[u8],
+    expected_transaction_id: &[u8; 12],
+) -> Option<SocketAddr> {
+    let (message_type, transaction_id, mut attributes) = decode_header(buffer)?;
+
+    if message_type != BINDING_SUCCESS_RESPONSE || &transaction_id != expected_transaction_id {
+        return None;
+    }
+
+    let mut mapped_address = None;
+
+    while attributes.len() >= 4 {
+        let attribute_type = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let attribute_length = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let padded_length = (attribute_length + 3) & !3;
+
+        if attributes.len() < 4 + attribute_length {
+            return None;
+        }
+
+        let value = &attributes[4..4 + attribute_length];
+
+        match attribute_type {
+            ATTRIBUTE_XOR_MAPPED_ADDRESS => {
+                return decode_address(value, Some(&transaction_id));
+            }
+            ATTRIBUTE_MAPPED_ADDRESS => mapped_address = decode_address(value, None),
+            _ => {}
+        }
+
+        attributes = &attributes[(4 + padded_length).min(attributes.len())..];
+    }
+
+    mapped_address
+}
+
+fn decode_address(value: &[u8], xor_transaction_id: Option<&[u8; 12]>) -> Option<SocketAddr> {
+    if value.len() < 4 {
+        return None;
+    }
+
+    let cookie = MAGIC_COOKIE.to_be_bytes();
+    let mut port = u16::from_be_bytes([value[2], value[3]]);
+    if xor_transaction_id.is_some() {
+        port ^= (MAGIC_COOKIE >> 16) as u16;
+    }
+
+    let ip = match value[1] {
+        FAMILY_IPV4 if value.len() >= 8 => {
+            let mut octets = [0u8; 4];
+            octets.copy_from_slice(&value[4..8]);
+            if xor_transaction_id.is_some() {
+                for (octet, mask) in octets.iter_mut().zip(cookie.iter()) {
+                    *octet ^= mask;
+                }
+            }
+
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        FAMILY_IPV6 if value.len() >= 20 => {
+            let mut octets = [0u8; 16];
+            octets.copy_from_slice(&value[4..20]);
+            if let Some(transaction_id) = xor_transaction_id {
+                let mask = cookie.iter().chain(transaction_id.iter());
+                for (octet, mask) in octets.iter_mut().zip(mask) {
+                    *octet ^= mask;
+                }
+            }
+
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => return None,
+    };
+
+    Some(SocketAddr::new(ip, port))
+}
+
+fn encode_xor_mapped_address(addr: SocketAddr, transaction_id: &[u8; 12]) -> Vec<u8> {
+    let cookie = MAGIC_COOKIE.to_be_bytes();
+    let port = addr.port() ^ (MAGIC_COOKIE >> 16) as u16;
+
+    let (family, mut octets) = match addr.ip() {
+        IpAddr::V4(ip) => (FAMILY_IPV4, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (FAMILY_IPV6, ip.octets().to_vec()),
+    };
+
+    let mask = cookie.iter().chain(transaction_id.iter());
+    for (octet, mask) in octets.iter_mut().zip(mask) {
+        *octet ^= mask;
+    }
+
+    let mut value = vec![0, family];
+    value.extend_from_slice(&port.to_be_bytes());
+    value.extend_from_slice(&octets);
+
+    let mut attribute = Vec::with_capacity(4 + value.len());
+    attribute.extend_from_slice(&ATTRIBUTE_XOR_MAPPED_ADDRESS.to_be_bytes());
+    attribute.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    attribute.extend_from_slice(&value);
+    attribute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xor_mapped_address_round_trip() {
+        let transaction_id = [7u8; 12];
+
+        for addr in [
+            "203.0.113.9:40123".parse().unwrap(),
+            "[2001:db8::1]:3478".parse().unwrap(),
+        ] {
+            let response = encode_message(
+                BINDING_SUCCESS_RESPONSE,
+                &transaction_id,
+                &encode_xor_mapped_address(addr, &transaction_id),
+            );
+
+            assert_eq!(
+                decode_binding_response(&response, &transaction_id),
+                Some(addr)
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_binding_response_rejects_other_transaction() {
+        let addr = "203.0.113.9:40123".parse().unwrap();
+        let response = encode_message(
+            BINDING_SUCCESS_RESPONSE,
+            &[1u8; 12],
+            &encode_xor_mapped_address(addr, &[1u8; 12]),
+        );
+
+        assert_eq!(decode_binding_response(&response, &[2u8; 12]), None);
+        assert_eq!(decode_binding_request(&response), None);
+    }
+
+    #[test]
+    fn test_decode_mapped_address() {
+        let transaction_id = [3u8; 12];
+
+        let mut attribute = Vec::new();
+        attribute.extend_from_slice(&ATTRIBUTE_MAPPED_ADDRESS.to_be_bytes());
+        attribute.extend_from_slice(&8u16.to_be_bytes());
+        attribute.extend_from_slice(&[0, FAMILY_IPV4]);
+        attribute.extend_from_slice(&40123u16.to_be_bytes());
+        attribute.extend_from_slice(&[203, 0, 113, 9]);
+
+        let response = encode_message(BINDING_SUCCESS_RESPONSE, &transaction_id, &attribute);
+
+        assert_eq!(
+            decode_binding_response(&response, &transaction_id),
+            Some("203.0.113.9:40123".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_query_reflexive_addr_from_reflector() {
+        let reflector_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let reflector_addr = reflector_socket.local_addr().unwrap();
+        let reflector = tokio::spawn(serve_reflector(reflector_socket));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let reflexive_addr = query_reflexive_addr(&socket, reflector_addr).await.unwrap();
+
+        assert_eq!(reflexive_addr, socket.local_addr().unwrap());
+
+        reflector.abort();
+    }
+
+    #[tokio::test]
+    async fn test_query_reflexive_addr_timeout() {
+        // nothing answers on the bound but unread socket
+        let silent_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let result = query_reflexive_addr(&socket, silent_socket.local_addr().unwrap()).await;
+
+        assert!(result.is_err());
+    }
+}
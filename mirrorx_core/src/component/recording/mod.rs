@@ -0,0 +1,502 @@
+mod muxer;
+
+use self::muxer::Muxer;
+use crate::{
+    api::endpoint::message::{EndPointAudioFrame, EndPointVideoFrame, VideoCodec},
+    core_error,
+    error::{CoreError, CoreResult},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc::{error::TrySendError, Receiver, Sender};
+
+#[derive(Debug, Clone)]
+pub enum RecordingEvent {
+    Failed { path: PathBuf, error: String },
+}
+
+// a container holds a single video stream layout, a new segment file is started
+// whenever it changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct VideoSignature {
+    codec: VideoCodec,
+    width: i32,
+    height: i32,
+    frame_rate: u8,
+}
+
+enum RecordingFrame {
+    Video(Instant, VideoSignature, EndPointVideoFrame),
+    Audio(Instant, EndPointAudioFrame),
+}
+
+// writes received packets into a container without re-encoding, the file is
+// finalized by stop or when the recorder is dropped
+#[derive(Debug)]
+pub struct SessionRecorder {
+    path: PathBuf,
+    tx: Sender<RecordingFrame>,
+    writer: Option<JoinHandle<CoreResult<Vec<PathBuf>>>>,
+    last_signature: Mutex<Option<VideoSignature>>,
+}
+
+impl SessionRecorder {
+    // the failure callback runs on the writer thread after the writer stopped
+    pub fn start<F>(path: PathBuf, on_failed: F) -> CoreResult<SessionRecorder>
+    where
+        F: FnOnce(&Path, &CoreError) + Send + 'static,
+    {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+
+        if !matches!(extension.as_deref(), Some("mkv" | "mp4")) {
+            return Err(core_error!("recording only supports mkv or mp4 file"));
+        }
+
+        let (tx, rx) = tokio::sync::mpsc::channel(240);
+        let writer_path = path.clone();
+
+        // callers may start recording outside of the runtime, such as the ui thread
+        let writer = std::thread::spawn(move || {
+            let ret = serve_writer::<Muxer>(&writer_path, rx);
+            if let Err(ref err) = ret {
+                tracing::error!(?err, path = ?writer_path, "write recording failed");
+                on_failed(&writer_path, err);
+            }
+            ret
+        });
+
+        Ok(SessionRecorder {
+            path,
+            tx,
+            writer: Some(writer),
+            last_signature: Mutex::new(None),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // returns true when the video stream layout changed at a non key frame, the new
+    // segment can't begin until next key frame
+    pub fn push_video(&self, video_frame: &EndPointVideoFrame, frame_rate: u8) -> bool {
+        let signature = VideoSignature {
+            codec: video_frame.codec,
+            width: video_frame.width,
+            height: video_frame.height,
+            frame_rate,
+        };
+
+        let changed = match self.last_signature.lock() {
+            Ok(mut last_signature) => last_signature.replace(signature) != Some(signature),
+            Err(_) => false,
+        };
+
+        self.push(RecordingFrame::Video(
+            Instant::now(),
+            signature,
+            video_frame.clone(),
+        ));
+
+        changed && !video_frame.key_frame
+    }
+
+    pub fn push_audio(&self, audio_frame: &EndPointAudioFrame) {
+        self.push(RecordingFrame::Audio(Instant::now(), audio_frame.clone()));
+    }
+
+    // waits until the writer has written the trailer of last segment, returns the
+    // paths of all segments
+    pub async fn stop(mut self) -> CoreResult<Vec<PathBuf>> {
+        let Some(writer) = self.writer.take() else {
+            return Err(core_error!("recording writer already stopped"));
+        };
+
+        // the writer exits once every sender is dropped
+        drop(self);
+
+        tokio::task::spawn_blocking(move || writer.join())
+            .await
+            .map_err(|err| core_error!("join recording writer failed ({})", err))?
+            .map_err(|_| core_error!("recording writer panicked"))?
+    }
+
+    fn push(&self, frame: RecordingFrame) {
+        // the session must never wait for disk io, a dropped video frame only
+        // corrupts the recording until next key frame
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(frame) {
+            tracing::warn!("recording channel is full, frame dropped");
+        }
+    }
+}
+
+// the part of the muxer the writer depends on, so segment handling works without a
+// real container
+trait SegmentWriter: Sized {
+    fn create(
+        path: &Path,
+        key_frame: &EndPointVideoFrame,
+        audio_channels: Option<u8>,
+    ) -> CoreResult<Self>;
+
+    fn has_audio(&self) -> bool;
+
+    fn write_video(
+        &mut self,
+        elapsed: Duration,
+        video_frame: &mut EndPointVideoFrame,
+    ) -> CoreResult<()>;
+
+    fn write_audio(
+        &mut self,
+        elapsed: Duration,
+        audio_frame: &mut EndPointAudioFrame,
+    ) -> CoreResult<()>;
+}
+
+impl SegmentWriter for Muxer {
+    fn create(
+        path: &Path,
+        key_frame: &EndPointVideoFrame,
+        audio_channels: Option<u8>,
+    ) -> CoreResult<Self> {
+        Muxer::new(path, key_frame, audio_channels)
+    }
+
+    fn has_audio(&self) -> bool {
+        Muxer::has_audio(self)
+    }
+
+    fn write_video(
+        &mut self,
+        elapsed: Duration,
+        video_frame: &mut EndPointVideoFrame,
+    ) -> CoreResult<()> {
+        Muxer::write_video(self, elapsed, video_frame)
+    }
+
+    fn write_audio(
+        &mut self,
+        elapsed: Duration,
+        audio_frame: &mut EndPointAudioFrame,
+    ) -> CoreResult<()> {
+        Muxer::write_audio(self, elapsed, audio_frame)
+    }
+}
+
+// the timeline of a segment starts at its first key frame. video follows the remote
+// capture clock so network jitter doesn't reach the recording, frames of peers that
+// don't send capture times and audio, which carries none, use the arrival time
+#[derive(Debug, Clone, Copy)]
+struct SegmentClock {
+    start_arrival: Instant,
+    start_capture_time_us: i64,
+}
+
+impl SegmentClock {
+    fn new(arrival: Instant, key_frame: &EndPointVideoFrame) -> SegmentClock {
+        SegmentClock {
+            start_arrival: arrival,
+            start_capture_time_us: key_frame.timing.capture_time_us,
+        }
+    }
+
+    fn video_elapsed(&self, arrival: Instant, video_frame: &EndPointVideoFrame) -> Duration {
+        let capture_time_us = video_frame.timing.capture_time_us;
+        if capture_time_us == 0 || self.start_capture_time_us == 0 {
+            return self.audio_elapsed(arrival);
+        }
+
+        Duration::from_micros((capture_time_us - self.start_capture_time_us).max(0) as u64)
+    }
+
+    fn audio_elapsed(&self, arrival: Instant) -> Duration {
+        arrival.saturating_duration_since(self.start_arrival)
+    }
+}
+
+fn serve_writer<W: SegmentWriter>(
+    path: &Path,
+    mut rx: Receiver<RecordingFrame>,
+) -> CoreResult<Vec<PathBuf>> {
+    let mut segments = Vec::new();
+    let mut writer: Option<(W, SegmentClock, VideoSignature)> = None;
+    let mut audio_channels = None;
+
+    while let Some(frame) = rx.blocking_recv() {
+        match frame {
+            RecordingFrame::Video(arrival, signature, mut video_frame) => {
+                // dropping the muxer writes the trailer of current segment
+                if matches!(writer, Some((_, _, current)) if current != signature) {
+                    tracing::info!(?signature, "video stream changed, finish recording segment");
+                    writer = None;
+                }
+
+                match writer {
+                    Some((ref mut writer, clock, _)) => writer.write_video(
+                        clock.video_elapsed(arrival, &video_frame),
+                        &mut video_frame,
+                    )?,
+                    None => {
+                        if !video_frame.key_frame {
+                            continue;
+                        }
+
+                        let segment_path = segment_path(path, segments.len());
+                        let mut new_writer =
+                            W::create(&segment_path, &video_frame, audio_channels)?;
+
+                        tracing::info!(
+                            path = ?segment_path,
+                            audio = new_writer.has_audio(),
+                            "recording segment started"
+                        );
+
+                        new_writer.write_video(Duration::ZERO, &mut video_frame)?;
+                        writer = Some((
+                            new_writer,
+                            SegmentClock::new(arrival, &video_frame),
+                            signature,
+                        ));
+                        segments.push(segment_path);
+                    }
+                }
+            }
+            RecordingFrame::Audio(arrival, mut audio_frame) => match writer {
+                Some((ref mut writer, clock, _)) => {
+                    writer.write_audio(clock.audio_elapsed(arrival), &mut audio_frame)?
+                }
+                None => {
+                    // audio before first key frame is only used to setup audio stream
+                    audio_channels = Some(audio_frame.channels).filter(|channels| *channels > 0);
+                }
+            },
+        }
+    }
+
+    drop(writer);
+
+    tracing::info!(?segments, "recording stopped");
+
+    Ok(segments)
+}
+
+// the first segment takes the requested path, later ones get a numeric suffix
+fn segment_path(path: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        return path.to_path_buf();
+    }
+
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut segment_path = path.with_file_name(format!("{}_{}", stem, index + 1));
+    if let Some(extension) = path.extension() {
+        segment_path.set_extension(extension);
+    }
+
+    segment_path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::endpoint::message::{AudioSampleFormat, EndPointVideoFrameTiming};
+    use std::cell::RefCell;
+
+    #[derive(Debug, PartialEq, Eq)]
+    enum WriterEvent {
+        Create(PathBuf, Option<u8>),
+        Video(u64, Duration),
+        Audio(Duration),
+    }
+
+    thread_local! {
+        static WRITER_EVENTS: RefCell<Vec<WriterEvent>> = RefCell::new(Vec::new());
+    }
+
+    struct FakeWriter;
+
+    impl SegmentWriter for FakeWriter {
+        fn create(
+            path: &Path,
+            _: &EndPointVideoFrame,
+            audio_channels: Option<u8>,
+        ) -> CoreResult<Self> {
+            WRITER_EVENTS.with(|events| {
+                events
+                    .borrow_mut()
+                    .push(WriterEvent::Create(path.to_path_buf(), audio_channels))
+            });
+            Ok(FakeWriter)
+        }
+
+        fn has_audio(&self) -> bool {
+            false
+        }
+
+        fn write_video(
+            &mut self,
+            elapsed: Duration,
+            video_frame: &mut EndPointVideoFrame,
+        ) -> CoreResult<()> {
+            WRITER_EVENTS.with(|events| {
+                events
+                    .borrow_mut()
+                    .push(WriterEvent::Video(video_frame.sequence, elapsed))
+            });
+            Ok(())
+        }
+
+        fn write_audio(&mut self, elapsed: Duration, _: &mut EndPointAudioFrame) -> CoreResult<()> {
+            WRITER_EVENTS.with(|events| events.borrow_mut().push(WriterEvent::Audio(elapsed)));
+            Ok(())
+        }
+    }
+
+    fn new_video_frame(
+        sequence: u64,
+        key_frame: bool,
+        width: i32,
+        capture_time_us: i64,
+    ) -> EndPointVideoFrame {
+        EndPointVideoFrame {
+            codec: VideoCodec::H264,
+            sequence,
+            key_frame,
+            width,
+            height: 720,
+            pts: sequence as i64,
+            timing: EndPointVideoFrameTiming {
+                capture_time_us,
+                encode_cost_us: 0,
+            },
+            buffer: Vec::new(),
+        }
+    }
+
+    fn video(arrival: Instant, video_frame: EndPointVideoFrame) -> RecordingFrame {
+        let signature = VideoSignature {
+            codec: video_frame.codec,
+            width: video_frame.width,
+            height: video_frame.height,
+            frame_rate: 30,
+        };
+        RecordingFrame::Video(arrival, signature, video_frame)
+    }
+
+    fn audio(arrival: Instant) -> RecordingFrame {
+        RecordingFrame::Audio(
+            arrival,
+            EndPointAudioFrame {
+                channels: 2,
+                sample_format: AudioSampleFormat::F32,
+                sample_rate: 48000,
+                buffer: Vec::new(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_segment_path() {
+        let path = Path::new("/records/session.mkv");
+        assert_eq!(segment_path(path, 0), path);
+        assert_eq!(segment_path(path, 1), Path::new("/records/session_2.mkv"));
+        assert_eq!(segment_path(path, 2), Path::new("/records/session_3.mkv"));
+        assert_eq!(
+            segment_path(Path::new("/records/session"), 1),
+            Path::new("/records/session_2")
+        );
+    }
+
+    #[test]
+    fn test_segment_clock() {
+        let arrival = Instant::now();
+        let clock = SegmentClock::new(arrival, &new_video_frame(0, true, 1280, 1_000_000));
+
+        // the capture clock is used even when the frame arrived late
+        assert_eq!(
+            clock.video_elapsed(
+                arrival + Duration::from_millis(90),
+                &new_video_frame(1, false, 1280, 1_033_000)
+            ),
+            Duration::from_millis(33)
+        );
+
+        // frames captured before the key frame stay at the segment start
+        assert_eq!(
+            clock.video_elapsed(arrival, &new_video_frame(1, false, 1280, 900_000)),
+            Duration::ZERO
+        );
+
+        // without capture times the arrival time is used
+        let later = arrival + Duration::from_millis(40);
+        assert_eq!(
+            clock.video_elapsed(later, &new_video_frame(1, false, 1280, 0)),
+            Duration::from_millis(40)
+        );
+        let clock = SegmentClock::new(arrival, &new_video_frame(0, true, 1280, 0));
+        assert_eq!(
+            clock.video_elapsed(later, &new_video_frame(1, false, 1280, 1_033_000)),
+            Duration::from_millis(40)
+        );
+        assert_eq!(clock.audio_elapsed(later), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_serve_writer_segments() {
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        // audio before the first key frame only sets up the audio stream, and video
+        // can't start a segment until a key frame
+        tx.try_send(audio(at(0))).unwrap();
+        tx.try_send(video(at(10), new_video_frame(1, false, 1280, 10_000)))
+            .unwrap();
+        tx.try_send(video(at(20), new_video_frame(2, true, 1280, 20_000)))
+            .unwrap();
+        tx.try_send(video(at(70), new_video_frame(3, false, 1280, 53_000)))
+            .unwrap();
+        tx.try_send(audio(at(80))).unwrap();
+
+        // a resolution change finishes the segment, the next one waits for a key frame
+        tx.try_send(video(at(90), new_video_frame(4, false, 1920, 86_000)))
+            .unwrap();
+        tx.try_send(video(at(100), new_video_frame(5, true, 1920, 100_000)))
+            .unwrap();
+        tx.try_send(video(at(130), new_video_frame(6, false, 1920, 133_000)))
+            .unwrap();
+        drop(tx);
+
+        let path = Path::new("/records/session.mp4");
+        let segments = serve_writer::<FakeWriter>(path, rx).unwrap();
+        assert_eq!(
+            segments,
+            [path.to_path_buf(), PathBuf::from("/records/session_2.mp4")]
+        );
+
+        let events = WRITER_EVENTS.with(|events| events.take());
+        assert_eq!(
+            events,
+            [
+                WriterEvent::Create(path.to_path_buf(), Some(2)),
+                WriterEvent::Video(2, Duration::ZERO),
+                WriterEvent::Video(3, Duration::from_millis(33)),
+                WriterEvent::Audio(Duration::from_millis(60)),
+                WriterEvent::Create(PathBuf::from("/records/session_2.mp4"), Some(2)),
+                WriterEvent::Video(5, Duration::ZERO),
+                WriterEvent::Video(6, Duration::from_millis(33)),
+            ]
+        );
+    }
+}
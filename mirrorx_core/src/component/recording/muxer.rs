@@ -0,0 +1,280 @@
+use crate::{
+    api::endpoint::message::{EndPointAudioFrame, EndPointVideoFrame, VideoCodec},
+    core_error,
+    error::CoreResult,
+};
+use mirrorx_native::ffmpeg::{
+    codecs::{
+        codec_id::AV_CODEC_ID_OPUS, codec_par::AVCodecParameters,
+        defs::AV_INPUT_BUFFER_PADDING_SIZE, packet::*,
+    },
+    format::{avformat::*, avio::*},
+    utils::{
+        avutil::{AVMEDIA_TYPE_AUDIO, AVMEDIA_TYPE_VIDEO},
+        channel_layout::av_channel_layout_default,
+        mathematics::av_rescale_q,
+        mem::av_mallocz,
+        rational::AVRational,
+    },
+};
+use std::{ffi::CString, path::Path, time::Duration};
+
+const MILLISECOND_TIME_BASE: AVRational = AVRational { num: 1, den: 1000 };
+const OPUS_SAMPLE_RATE: i32 = 48000;
+const OPUS_TIME_BASE: AVRational = AVRational {
+    num: 1,
+    den: OPUS_SAMPLE_RATE,
+};
+
+// audio timestamps advance by packet duration, they are moved to the arrival time
+// only when the remote stopped sending audio for a while
+const AUDIO_RESYNC_THRESHOLD: i64 = OPUS_SAMPLE_RATE as i64 / 5;
+
+pub struct Muxer {
+    format_ctx: *mut AVFormatContext,
+    packet: *mut AVPacket,
+    video_stream: *mut AVStream,
+    audio_stream: *mut AVStream,
+    last_video_pts: i64,
+    next_audio_pts: Option<i64>,
+    header_written: bool,
+}
+
+unsafe impl Send for Muxer {}
+
+impl Muxer {
+    // the container is guessed from the path extension, streams are created from the
+    // first video key frame, and an audio stream is only added when the channel count
+    // of remote audio is already known
+    pub fn new(
+        path: &Path,
+        key_frame: &EndPointVideoFrame,
+        audio_channels: Option<u8>,
+    ) -> CoreResult<Muxer> {
+        let path = path
+            .to_str()
+            .ok_or_else(|| core_error!("recording path is not valid unicode"))?;
+        let path = CString::new(path)?;
+
+        unsafe {
+            let mut muxer = Muxer {
+                format_ctx: std::ptr::null_mut(),
+                packet: std::ptr::null_mut(),
+                video_stream: std::ptr::null_mut(),
+                audio_stream: std::ptr::null_mut(),
+                last_video_pts: -1,
+                next_audio_pts: None,
+                header_written: false,
+            };
+
+            let ret = avformat_alloc_output_context2(
+                &mut muxer.format_ctx,
+                std::ptr::null(),
+                std::ptr::null(),
+                path.as_ptr(),
+            );
+
+            if ret < 0 || muxer.format_ctx.is_null() {
+                return Err(core_error!(
+                    "avformat_alloc_output_context2 returns error code: {}",
+                    ret
+                ));
+            }
+
+            muxer.packet = av_packet_alloc();
+            if muxer.packet.is_null() {
+                return Err(core_error!("av_packet_alloc returns null pointer"));
+            }
+
+            muxer.video_stream = avformat_new_stream(muxer.format_ctx, std::ptr::null());
+            if muxer.video_stream.is_null() {
+                return Err(core_error!("avformat_new_stream returns null pointer"));
+            }
+
+            let codecpar = (*muxer.video_stream).codecpar;
+            (*codecpar).codec_type = AVMEDIA_TYPE_VIDEO;
+            (*codecpar).codec_id = key_frame.codec.av_codec_id();
+            (*codecpar).width = key_frame.width;
+            (*codecpar).height = key_frame.height;
+            (*muxer.video_stream).time_base = MILLISECOND_TIME_BASE;
+
+            // mkv and mp4 muxers build avcC / hvcC / av1C from the parameter sets found in
+            // extradata, other units of the key frame are skipped by them
+            if matches!(
+                key_frame.codec,
+                VideoCodec::H264 | VideoCodec::Hevc | VideoCodec::AV1
+            ) {
+                set_extradata(codecpar, &key_frame.buffer)?;
+            }
+
+            if let Some(channels) = audio_channels {
+                muxer.audio_stream = avformat_new_stream(muxer.format_ctx, std::ptr::null());
+                if muxer.audio_stream.is_null() {
+                    return Err(core_error!("avformat_new_stream returns null pointer"));
+                }
+
+                let codecpar = (*muxer.audio_stream).codecpar;
+                (*codecpar).codec_type = AVMEDIA_TYPE_AUDIO;
+                (*codecpar).codec_id = AV_CODEC_ID_OPUS;
+                (*codecpar).sample_rate = OPUS_SAMPLE_RATE;
+                (*codecpar).channels = channels as i32;
+                av_channel_layout_default(&mut (*codecpar).ch_layout, channels as i32);
+                (*muxer.audio_stream).time_base = OPUS_TIME_BASE;
+
+                set_extradata(codecpar, &opus_head(channels))?;
+            }
+
+            if (*(*muxer.format_ctx).oformat).flags & AVFMT_NOFILE == 0 {
+                let ret = avio_open(&mut (*muxer.format_ctx).pb, path.as_ptr(), AVIO_FLAG_WRITE);
+                if ret < 0 {
+                    return Err(core_error!("avio_open returns error code: {}", ret));
+                }
+            }
+
+            let ret = avformat_write_header(muxer.format_ctx, std::ptr::null_mut());
+            if ret < 0 {
+                return Err(core_error!(
+                    "avformat_write_header returns error code: {}",
+                    ret
+                ));
+            }
+
+            muxer.header_written = true;
+
+            Ok(muxer)
+        }
+    }
+
+    pub fn has_audio(&self) -> bool {
+        !self.audio_stream.is_null()
+    }
+
+    // video frames are stamped with the elapsed time since the segment began rather
+    // than the remote pts, whose unit follows the encoder frame rate that may change
+    // during the session
+    pub fn write_video(
+        &mut self,
+        elapsed: Duration,
+        video_frame: &mut EndPointVideoFrame,
+    ) -> CoreResult<()> {
+        let pts = (elapsed.as_millis() as i64).max(self.last_video_pts + 1);
+        self.last_video_pts = pts;
+
+        unsafe {
+            let pts = av_rescale_q(pts, MILLISECOND_TIME_BASE, (*self.video_stream).time_base);
+
+            self.write_packet(
+                self.video_stream,
+                &mut video_frame.buffer,
+                pts,
+                video_frame.key_frame,
+            )
+        }
+    }
+
+    pub fn write_audio(
+        &mut self,
+        elapsed: Duration,
+        audio_frame: &mut EndPointAudioFrame,
+    ) -> CoreResult<()> {
+        if self.audio_stream.is_null() || audio_frame.channels == 0 {
+            return Ok(());
+        }
+
+        let arrival_pts = elapsed.as_micros() as i64 * OPUS_SAMPLE_RATE as i64 / 1_000_000;
+        let pts = match self.next_audio_pts {
+            Some(next_audio_pts) if arrival_pts - next_audio_pts < AUDIO_RESYNC_THRESHOLD => {
+                next_audio_pts
+            }
+            _ => arrival_pts,
+        };
+
+        // every opus packet from the remote encoder carries 960 interleaved samples
+        self.next_audio_pts = Some(pts + 960 / audio_frame.channels as i64);
+
+        unsafe {
+            let pts = av_rescale_q(pts, OPUS_TIME_BASE, (*self.audio_stream).time_base);
+            self.write_packet(self.audio_stream, &mut audio_frame.buffer, pts, true)
+        }
+    }
+
+    unsafe fn write_packet(
+        &mut self,
+        stream: *mut AVStream,
+        buffer: &mut [u8],
+        pts: i64,
+        key_frame: bool,
+    ) -> CoreResult<()> {
+        (*self.packet).data = buffer.as_mut_ptr();
+        (*self.packet).size = buffer.len() as i32;
+        (*self.packet).pts = pts;
+        (*self.packet).dts = pts;
+        (*self.packet).stream_index = (*stream).index;
+        (*self.packet).flags = if key_frame { AV_PKT_FLAG_KEY } else { 0 };
+
+        // the packet isn't reference counted, so the muxer copies the buffer before
+        // interleaving and resets the packet afterwards
+        let ret = av_interleaved_write_frame(self.format_ctx, self.packet);
+        if ret < 0 {
+            return Err(core_error!(
+                "av_interleaved_write_frame returns error code: {}",
+                ret
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Muxer {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.format_ctx.is_null() {
+                if self.header_written {
+                    let ret = av_write_trailer(self.format_ctx);
+                    if ret < 0 {
+                        tracing::error!(ret, "av_write_trailer returns error code");
+                    }
+                }
+
+                if !(*self.format_ctx).pb.is_null() {
+                    avio_closep(&mut (*self.format_ctx).pb);
+                }
+
+                avformat_free_context(self.format_ctx);
+            }
+
+            if !self.packet.is_null() {
+                av_packet_free(&mut self.packet);
+            }
+        }
+    }
+}
+
+unsafe fn set_extradata(codecpar: *mut AVCodecParameters, data: &[u8]) -> CoreResult<()> {
+    // extradata is released by libavformat, so it must be allocated by av_malloc
+    let extradata = av_mallocz(data.len() + AV_INPUT_BUFFER_PADDING_SIZE) as *mut u8;
+    if extradata.is_null() {
+        return Err(core_error!("av_mallocz returns null pointer"));
+    }
+
+    std::ptr::copy_nonoverlapping(data.as_ptr(), extradata, data.len());
+    (*codecpar).extradata = extradata;
+    (*codecpar).extradata_size = data.len() as i32;
+
+    Ok(())
+}
+
+// RFC 7845 identification header, no pre-skip is signalled since the remote
+// encoder lookahead isn't transferred
+fn opus_head(channels: u8) -> Vec<u8> {
+    let mut head = Vec::with_capacity(19);
+    head.extend_from_slice(b"OpusHead");
+    head.push(1);
+    head.push(channels);
+    head.extend_from_slice(&0u16.to_le_bytes());
+    head.extend_from_slice(&(OPUS_SAMPLE_RATE as u32).to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    head
+}
@@ -0,0 +1,237 @@
+use crate::{
+    component::frame::{DesktopDecodeFrame, DesktopDecodeFrameFormat},
+    core_error,
+    error::CoreResult,
+};
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use mirrorx_native::libyuv::{
+    kYvuF709Constants, I420ToARGBMatrix, I444ToARGBMatrix, NV21ToARGBMatrix,
+};
+use std::path::Path;
+
+// saves the frame as png or jpeg, the image format is guessed from the path extension
+pub fn save_screenshot(frame: &DesktopDecodeFrame, path: &Path) -> CoreResult<()> {
+    let format = ImageFormat::from_path(path)?;
+    if !matches!(format, ImageFormat::Png | ImageFormat::Jpeg) {
+        return Err(core_error!("screenshot only supports png or jpeg file"));
+    }
+
+    let rgba_bytes = convert_to_rgba(frame)?;
+    let image = RgbaImage::from_raw(frame.width as u32, frame.height as u32, rgba_bytes)
+        .ok_or_else(|| core_error!("rgba buffer doesn't match frame size"))?;
+
+    // jpeg has no alpha channel
+    if format == ImageFormat::Jpeg {
+        DynamicImage::ImageRgba8(image)
+            .into_rgb8()
+            .save_with_format(path, format)?;
+    } else {
+        image.save_with_format(path, format)?;
+    }
+
+    Ok(())
+}
+
+// libyuv names pixel formats by little endian words, so rgba in memory is its abgr. it
+// has no abgr output with a custom matrix, the argb conversions produce abgr instead when
+// u and v are swapped and the yvu constants are given
+pub fn convert_to_rgba(frame: &DesktopDecodeFrame) -> CoreResult<Vec<u8>> {
+    if frame.width <= 0 || frame.height <= 0 {
+        return Err(core_error!("desktop frame is empty"));
+    }
+
+    let planes = match frame.format {
+        DesktopDecodeFrameFormat::NV12 => 2,
+        DesktopDecodeFrameFormat::YUV420P | DesktopDecodeFrameFormat::YUV444P => 3,
+    };
+
+    if frame.plane_data.len() < planes || frame.line_sizes.len() < planes {
+        return Err(core_error!("desktop frame planes are incomplete"));
+    }
+
+    // libyuv reads every row of every plane without bounds, so the planes must cover
+    // the frame. 4:2:0 chrominance has half the rows and columns, rounded up
+    let width = frame.width as usize;
+    let height = frame.height as usize;
+    let chroma_width = (width + 1) / 2;
+    let chroma_height = (height + 1) / 2;
+
+    for index in 0..planes {
+        let (row_bytes, rows) = match (frame.format, index) {
+            (_, 0) | (DesktopDecodeFrameFormat::YUV444P, _) => (width, height),
+            (DesktopDecodeFrameFormat::NV12, _) => (chroma_width * 2, chroma_height),
+            (DesktopDecodeFrameFormat::YUV420P, _) => (chroma_width, chroma_height),
+        };
+
+        let line_size = frame.line_sizes[index];
+        if line_size < 0 || (line_size as usize) < row_bytes {
+            return Err(core_error!(
+                "desktop frame plane {} stride {} is less than its width {}",
+                index,
+                line_size,
+                row_bytes
+            ));
+        }
+
+        let plane_len = line_size as usize * (rows - 1) + row_bytes;
+        if frame.plane_data[index].len() < plane_len {
+            return Err(core_error!(
+                "desktop frame plane {} has {} bytes, expect at least {}",
+                index,
+                frame.plane_data[index].len(),
+                plane_len
+            ));
+        }
+    }
+
+    let mut rgba_bytes = vec![0u8; (frame.width * frame.height * 4) as usize];
+    let rgba_stride = frame.width * 4;
+    let plane = |index: usize| frame.plane_data[index].as_ptr();
+    let stride = |index: usize| frame.line_sizes[index];
+
+    // decoded frames are full range bt.709, the same as the render shader expects
+    let ret = unsafe {
+        match frame.format {
+            DesktopDecodeFrameFormat::NV12 => NV21ToARGBMatrix(
+                plane(0),
+                stride(0),
+                plane(1),
+                stride(1),
+                rgba_bytes.as_mut_ptr(),
+                rgba_stride,
+                &kYvuF709Constants,
+                frame.width,
+                frame.height,
+            ),
+            DesktopDecodeFrameFormat::YUV420P => I420ToARGBMatrix(
+                plane(0),
+                stride(0),
+                plane(2),
+                stride(2),
+                plane(1),
+                stride(1),
+                rgba_bytes.as_mut_ptr(),
+                rgba_stride,
+                &kYvuF709Constants,
+                frame.width,
+                frame.height,
+            ),
+            DesktopDecodeFrameFormat::YUV444P => I444ToARGBMatrix(
+                plane(0),
+                stride(0),
+                plane(2),
+                stride(2),
+                plane(1),
+                stride(1),
+                rgba_bytes.as_mut_ptr(),
+                rgba_stride,
+                &kYvuF709Constants,
+                frame.width,
+                frame.height,
+            ),
+        }
+    };
+
+    if ret != 0 {
+        return Err(core_error!("libyuv conversion returns error code: {}", ret));
+    }
+
+    Ok(rgba_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_frame(format: DesktopDecodeFrameFormat, y: u8, u: u8, v: u8) -> DesktopDecodeFrame {
+        let (width, height) = (4, 2);
+
+        let (plane_data, line_sizes) = match format {
+            DesktopDecodeFrameFormat::NV12 => {
+                (vec![vec![y; 8], [u, v].repeat(2)], vec![width, width])
+            }
+            DesktopDecodeFrameFormat::YUV420P => {
+                (vec![vec![y; 8], vec![u; 2], vec![v; 2]], vec![width, 2, 2])
+            }
+            DesktopDecodeFrameFormat::YUV444P => (
+                vec![vec![y; 8], vec![u; 8], vec![v; 8]],
+                vec![width, width, width],
+            ),
+        };
+
+        DesktopDecodeFrame {
+            width,
+            height,
+            plane_data,
+            line_sizes,
+            format,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_convert_to_rgba() {
+        for format in [
+            DesktopDecodeFrameFormat::NV12,
+            DesktopDecodeFrameFormat::YUV420P,
+            DesktopDecodeFrameFormat::YUV444P,
+        ] {
+            // full range white stays white
+            let rgba_bytes = convert_to_rgba(&new_frame(format, 255, 128, 128)).unwrap();
+            assert_eq!(rgba_bytes.len(), 4 * 2 * 4);
+            assert!(rgba_bytes.iter().all(|value| *value == 255));
+
+            // bt.709 red, the channels are stored in rgba order
+            let rgba_bytes = convert_to_rgba(&new_frame(format, 54, 99, 255)).unwrap();
+            for pixel in rgba_bytes.chunks(4) {
+                assert!(
+                    pixel[0] > 230 && pixel[1] < 30 && pixel[2] < 30,
+                    "{pixel:?}"
+                );
+                assert_eq!(pixel[3], 255);
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert_to_rgba_rejects_incomplete_frame() {
+        let mut frame = new_frame(DesktopDecodeFrameFormat::YUV420P, 0, 128, 128);
+        frame.plane_data.pop();
+        assert!(convert_to_rgba(&frame).is_err());
+
+        let frame = DesktopDecodeFrame::default();
+        assert!(convert_to_rgba(&frame).is_err());
+    }
+
+    #[test]
+    fn test_convert_to_rgba_rejects_truncated_plane() {
+        for format in [
+            DesktopDecodeFrameFormat::NV12,
+            DesktopDecodeFrameFormat::YUV420P,
+            DesktopDecodeFrameFormat::YUV444P,
+        ] {
+            let mut frame = new_frame(format, 0, 128, 128);
+            frame.plane_data[1].pop();
+            assert!(convert_to_rgba(&frame).is_err());
+
+            let mut frame = new_frame(format, 0, 128, 128);
+            frame.line_sizes[1] -= 1;
+            assert!(convert_to_rgba(&frame).is_err());
+
+            let mut frame = new_frame(format, 0, 128, 128);
+            frame.line_sizes[0] = -frame.width;
+            assert!(convert_to_rgba(&frame).is_err());
+        }
+
+        // odd sizes round the chrominance up, 3x3 needs 2x2 chrominance samples
+        let frame = DesktopDecodeFrame {
+            width: 3,
+            height: 3,
+            plane_data: vec![vec![0; 9], vec![128; 4], vec![128; 3]],
+            line_sizes: vec![3, 2, 2],
+            format: DesktopDecodeFrameFormat::YUV420P,
+            ..Default::default()
+        };
+        assert!(convert_to_rgba(&frame).is_err());
+    }
+}
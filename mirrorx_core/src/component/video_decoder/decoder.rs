@@ -1,17 +1,28 @@
 use crate::{
-    api::endpoint::message::EndPointVideoFrame,
+    api::endpoint::message::{EndPointVideoFrame, VideoCodec},
     component::frame::{DesktopDecodeFrame, DesktopDecodeFrameFormat},
     core_error,
     error::CoreResult,
 };
 use mirrorx_native::ffmpeg::{
-    codecs::{avcodec::*, codec::*, codec_id::*, packet::*},
+    codecs::{avcodec::*, codec::*, packet::*},
     utils::{buffer::*, error::*, frame::*, hwcontext::*, pixfmt::*, rational::AVRational},
 };
 use tokio::sync::mpsc::Sender;
 
+pub fn supported_decoders() -> Vec<VideoCodec> {
+    VideoCodec::ALL
+        .into_iter()
+        .filter(|codec| unsafe { !avcodec_find_decoder(codec.av_codec_id()).is_null() })
+        .collect()
+}
+
 pub struct VideoDecoder {
     decode_context: Option<DecodeContext>,
+    // frames after a decode error depend on lost references, so they are dropped
+    // until next key frame
+    wait_key_frame: bool,
+    next_sequence: Option<u64>,
     render_frame_tx: Sender<DesktopDecodeFrame>,
     _last_pts: i64,
 }
@@ -25,15 +36,50 @@ impl VideoDecoder {
 
         VideoDecoder {
             decode_context: None,
+            wait_key_frame: true,
+            next_sequence: None,
             render_frame_tx,
             _last_pts: 0,
         }
     }
 
+    pub fn reset(&mut self) {
+        self.decode_context = None;
+        self.wait_key_frame = true;
+    }
+
+    pub fn is_waiting_key_frame(&self) -> bool {
+        self.wait_key_frame
+    }
+
     pub fn decode(&mut self, mut video_frame: EndPointVideoFrame) -> CoreResult<()> {
+        // a gap in sequence means lost frames which the following frames may reference
+        if let Some(next_sequence) = self.next_sequence {
+            if video_frame.sequence != next_sequence && !video_frame.key_frame {
+                tracing::warn!(
+                    expected = next_sequence,
+                    received = video_frame.sequence,
+                    "video frame sequence gap, wait for next key frame"
+                );
+
+                self.wait_key_frame = true;
+            }
+        }
+
+        self.next_sequence = Some(video_frame.sequence + 1);
+
+        if self.wait_key_frame {
+            if !video_frame.key_frame {
+                return Ok(());
+            }
+
+            self.wait_key_frame = false;
+        }
+
         unsafe {
             if let Some(decode_context) = self.decode_context.as_ref() {
-                if (*decode_context.codec_ctx).width != video_frame.width
+                if decode_context.codec != video_frame.codec
+                    || (*decode_context.codec_ctx).width != video_frame.width
                     || (*decode_context.codec_ctx).height != video_frame.height
                 {
                     self.decode_context = None;
@@ -41,11 +87,14 @@ impl VideoDecoder {
             }
 
             if self.decode_context.is_none() {
-                self.decode_context =
-                    Some(DecodeContext::new(video_frame.width, video_frame.height)?);
+                self.decode_context = Some(DecodeContext::new(
+                    video_frame.codec,
+                    video_frame.width,
+                    video_frame.height,
+                )?);
             }
 
-            let Some(ref decode_context)= self.decode_context else{
+            let Some(ref decode_context) = self.decode_context else {
                 return Err(core_error!("decode context is empty"));
             };
 
@@ -146,6 +195,31 @@ impl VideoDecoder {
                         ],
                         DesktopDecodeFrameFormat::YUV420P,
                     ),
+                    AV_PIX_FMT_YUV444P | AV_PIX_FMT_YUVJ444P => (
+                        vec![
+                            std::slice::from_raw_parts(
+                                (*tmp_frame).data[0],
+                                ((*tmp_frame).linesize[0] * (*tmp_frame).height) as usize,
+                            )
+                            .to_vec(),
+                            std::slice::from_raw_parts(
+                                (*tmp_frame).data[1],
+                                ((*tmp_frame).linesize[1] * (*tmp_frame).height) as usize,
+                            )
+                            .to_vec(),
+                            std::slice::from_raw_parts(
+                                (*tmp_frame).data[2],
+                                ((*tmp_frame).linesize[2] * (*tmp_frame).height) as usize,
+                            )
+                            .to_vec(),
+                        ],
+                        vec![
+                            (*tmp_frame).linesize[0],
+                            (*tmp_frame).linesize[1],
+                            (*tmp_frame).linesize[2],
+                        ],
+                        DesktopDecodeFrameFormat::YUV444P,
+                    ),
                     _ => {
                         return Err(core_error!(
                             "unsupported format, pix_format: {}",
@@ -155,6 +229,8 @@ impl VideoDecoder {
                 };
 
                 let desktop_decode_frame = DesktopDecodeFrame {
+                    sequence: video_frame.sequence,
+                    capture_time_us: video_frame.timing.capture_time_us,
                     width: (*tmp_frame).width,
                     height: (*tmp_frame).height,
                     plane_data,
@@ -177,6 +253,7 @@ impl VideoDecoder {
 }
 
 struct DecodeContext {
+    codec: VideoCodec,
     codec_ctx: *mut AVCodecContext,
     packet: *mut AVPacket,
     decode_frame: *mut AVFrame,
@@ -184,11 +261,12 @@ struct DecodeContext {
 }
 
 impl DecodeContext {
-    fn new(width: i32, height: i32) -> CoreResult<DecodeContext> {
+    fn new(video_codec: VideoCodec, width: i32, height: i32) -> CoreResult<DecodeContext> {
         unsafe {
             let mut decode_ctx = DecodeContext::default();
+            decode_ctx.codec = video_codec;
 
-            let codec = avcodec_find_decoder(AV_CODEC_ID_H264);
+            let codec = avcodec_find_decoder(video_codec.av_codec_id());
 
             if codec.is_null() {
                 return Err(core_error!("avcodec_find_decoder returns null"));
@@ -202,7 +280,11 @@ impl DecodeContext {
             (*decode_ctx.codec_ctx).width = width;
             (*decode_ctx.codec_ctx).height = height;
             (*decode_ctx.codec_ctx).framerate = AVRational { num: 60, den: 1 };
-            (*decode_ctx.codec_ctx).pix_fmt = AV_PIX_FMT_NV12;
+            (*decode_ctx.codec_ctx).pix_fmt = match video_codec {
+                VideoCodec::H264 | VideoCodec::Hevc => AV_PIX_FMT_NV12,
+                // vp8, vp9 and av1 software decoders only output planar yuv
+                VideoCodec::VP8 | VideoCodec::VP9 | VideoCodec::AV1 => AV_PIX_FMT_YUV420P,
+            };
             // (*decode_ctx.codec_ctx).color_range = AVCOL_RANGE_JPEG;
             // (*decode_ctx.codec_ctx).color_primaries = AVCOL_PRI_BT709;
             // (*decode_ctx.codec_ctx).color_trc = AVCOL_TRC_BT709;
@@ -288,6 +370,7 @@ impl DecodeContext {
 impl Default for DecodeContext {
     fn default() -> Self {
         Self {
+            codec: VideoCodec::H264,
             codec_ctx: std::ptr::null_mut(),
             packet: std::ptr::null_mut(),
             decode_frame: std::ptr::null_mut(),
@@ -350,3 +433,44 @@ impl Drop for DecodeContext {
 
 //     Ok(color32_buffer)
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::endpoint::message::EndPointVideoFrameTiming;
+
+    fn video_frame(sequence: u64) -> EndPointVideoFrame {
+        EndPointVideoFrame {
+            codec: VideoCodec::H264,
+            sequence,
+            key_frame: false,
+            width: 1920,
+            height: 1080,
+            pts: sequence as i64,
+            timing: EndPointVideoFrameTiming::default(),
+            buffer: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_sequence_gap_waits_key_frame() {
+        let (render_frame_tx, mut render_frame_rx) = tokio::sync::mpsc::channel(1);
+        let mut decoder = VideoDecoder::new(render_frame_tx);
+
+        // as if frames up to 9 have been decoded
+        decoder.wait_key_frame = false;
+        decoder.next_sequence = Some(10);
+
+        decoder.decode(video_frame(12)).unwrap();
+        assert!(decoder.is_waiting_key_frame());
+
+        // neither following frames nor the late ones fill the gap
+        for sequence in [13, 10, 11, 14] {
+            decoder.decode(video_frame(sequence)).unwrap();
+            assert!(decoder.is_waiting_key_frame());
+        }
+
+        assert!(decoder.decode_context.is_none());
+        assert!(render_frame_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::{
+    api::config::entity::kv::JitterBufferMode, component::frame::DesktopDecodeFrame,
+    utility::time::unix_micros,
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+// transit times of this many recent frames are kept to measure jitter
+const TRANSIT_WINDOW: usize = 120;
+// frames queued over this limit are released at once, the renderer only paints
+// the newest of them
+const MAX_QUEUED_FRAMES: usize = 30;
+
+// holds decoded frames back so that they are released with the spacing of their
+// capture time rather than their arrival
+//
+// transit is the local arrival time minus the remote capture time, it includes the
+// unknown clock offset, but the offset is the same for every frame, so the frame with
+// the smallest recent transit is regarded as one which came without delay
+pub struct JitterBuffer {
+    mode: JitterBufferMode,
+    frames: VecDeque<(i64, DesktopDecodeFrame)>,
+    transits: VecDeque<i64>,
+    target_delay_us: i64,
+    // capture time of the newest frame queued or released
+    last_capture_time_us: Option<i64>,
+}
+
+impl JitterBuffer {
+    pub fn new(mode: JitterBufferMode) -> Self {
+        Self {
+            mode,
+            frames: VecDeque::new(),
+            transits: VecDeque::with_capacity(TRANSIT_WINDOW),
+            target_delay_us: 0,
+            last_capture_time_us: None,
+        }
+    }
+
+    // forgets queued frames and measured jitter, for a stream which starts over, and
+    // returns sequences of the discarded frames
+    pub fn reset(&mut self) -> Vec<u64> {
+        self.transits.clear();
+        self.target_delay_us = 0;
+        self.last_capture_time_us = None;
+
+        self.frames
+            .drain(..)
+            .map(|(_, frame)| frame.sequence)
+            .collect()
+    }
+
+    // returns false if the frame came too late to be shown
+    pub fn push(&mut self, frame: DesktopDecodeFrame) -> bool {
+        self.push_at(frame, unix_micros())
+    }
+
+    // the instant when the first queued frame should be released
+    pub fn next_release(&self) -> Option<Instant> {
+        self.release_wait_at(unix_micros())
+            .map(|wait| Instant::now() + wait)
+    }
+
+    pub fn pop_ready(&mut self) -> Option<DesktopDecodeFrame> {
+        self.pop_ready_at(unix_micros())
+    }
+
+    fn push_at(&mut self, frame: DesktopDecodeFrame, now_us: i64) -> bool {
+        // a frame captured before one already queued or shown would move the picture
+        // backwards
+        if self
+            .last_capture_time_us
+            .map_or(false, |last_capture_time_us| {
+                frame.capture_time_us <= last_capture_time_us
+            })
+        {
+            return false;
+        }
+
+        self.last_capture_time_us = Some(frame.capture_time_us);
+
+        let transit_us = now_us - frame.capture_time_us;
+
+        if self.transits.len() == TRANSIT_WINDOW {
+            self.transits.pop_front();
+        }
+        self.transits.push_back(transit_us);
+
+        self.update_target_delay();
+
+        let min_transit_us = self.transits.iter().min().copied().unwrap_or(transit_us);
+        let release_us = frame.capture_time_us + min_transit_us + self.target_delay_us;
+
+        // a frame never overtakes an earlier one
+        let release_us = self
+            .frames
+            .back()
+            .map_or(release_us, |(last_release_us, _)| {
+                release_us.max(*last_release_us)
+            });
+
+        self.frames.push_back((release_us, frame));
+        true
+    }
+
+    fn release_wait_at(&self, now_us: i64) -> Option<Duration> {
+        let (release_us, _) = self.frames.front()?;
+
+        if self.frames.len() > MAX_QUEUED_FRAMES {
+            Some(Duration::ZERO)
+        } else {
+            Some(Duration::from_micros((release_us - now_us).max(0) as u64))
+        }
+    }
+
+    fn pop_ready_at(&mut self, now_us: i64) -> Option<DesktopDecodeFrame> {
+        let (release_us, _) = self.frames.front()?;
+
+        if *release_us <= now_us || self.frames.len() > MAX_QUEUED_FRAMES {
+            self.frames.pop_front().map(|(_, frame)| frame)
+        } else {
+            None
+        }
+    }
+
+    fn update_target_delay(&mut self) {
+        let Some(min_transit_us) = self.transits.iter().min().copied() else {
+            return;
+        };
+
+        let mut jitters: Vec<i64> = self
+            .transits
+            .iter()
+            .map(|transit_us| transit_us - min_transit_us)
+            .collect();
+        jitters.sort_unstable();
+
+        let (percentile, max_delay) = match self.mode {
+            JitterBufferMode::LowLatency => (50, Duration::from_millis(20)),
+            JitterBufferMode::Smooth => (95, Duration::from_millis(200)),
+        };
+
+        let index = (jitters.len() * percentile / 100).min(jitters.len() - 1);
+        let target_delay_us = jitters[index].min(max_delay.as_micros() as i64);
+
+        // grows at once when the network gets worse and shrinks slowly, so the delay
+        // doesn't swing with every burst
+        if target_delay_us > self.target_delay_us {
+            self.target_delay_us = target_delay_us;
+        } else {
+            self.target_delay_us -= (self.target_delay_us - target_delay_us) / 32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::frame::DesktopDecodeFrameFormat;
+
+    const FRAME_INTERVAL_US: i64 = 16_000;
+
+    fn decode_frame(sequence: u64, width: i32) -> DesktopDecodeFrame {
+        DesktopDecodeFrame {
+            sequence,
+            capture_time_us: 1_000_000 + sequence as i64 * FRAME_INTERVAL_US,
+            width,
+            height: 720,
+            plane_data: Vec::new(),
+            line_sizes: Vec::new(),
+            format: DesktopDecodeFrameFormat::NV12,
+        }
+    }
+
+    fn pop_all(jitter_buffer: &mut JitterBuffer, now_us: i64) -> Vec<u64> {
+        std::iter::from_fn(|| jitter_buffer.pop_ready_at(now_us))
+            .map(|frame| frame.sequence)
+            .collect()
+    }
+
+    #[test]
+    fn test_paces_by_capture_time() {
+        let mut jitter_buffer = JitterBuffer::new(JitterBufferMode::Smooth);
+
+        // four frames arrive in a burst after the first came without delay
+        let arrival_us = 5_000_000;
+        jitter_buffer.push_at(decode_frame(0, 1280), arrival_us);
+        for sequence in 1..4 {
+            jitter_buffer.push_at(decode_frame(sequence, 1280), arrival_us + 1_000);
+        }
+
+        assert_eq!(pop_all(&mut jitter_buffer, arrival_us + 1_000), [0]);
+        assert!(jitter_buffer.release_wait_at(arrival_us + 1_000).unwrap() > Duration::ZERO);
+
+        let mut released = Vec::new();
+        for step in 1..=200 {
+            released.extend(pop_all(&mut jitter_buffer, arrival_us + step * 1_000));
+        }
+
+        assert_eq!(released, [1, 2, 3]);
+        assert_eq!(jitter_buffer.release_wait_at(arrival_us), None);
+    }
+
+    #[test]
+    fn test_reordered_and_late_frames_dropped() {
+        let mut jitter_buffer = JitterBuffer::new(JitterBufferMode::LowLatency);
+
+        let now_us = 5_000_000;
+        assert!(jitter_buffer.push_at(decode_frame(0, 1280), now_us));
+        assert!(jitter_buffer.push_at(decode_frame(2, 1280), now_us));
+        assert!(!jitter_buffer.push_at(decode_frame(1, 1280), now_us));
+        assert!(jitter_buffer.push_at(decode_frame(3, 1280), now_us));
+
+        assert_eq!(pop_all(&mut jitter_buffer, now_us + 1_000_000), [0, 2, 3]);
+
+        // frames behind the released ones, or repeated, are never shown
+        assert!(!jitter_buffer.push_at(decode_frame(3, 1280), now_us + 1_000_000));
+        assert!(!jitter_buffer.push_at(decode_frame(1, 1280), now_us + 1_000_000));
+        assert_eq!(jitter_buffer.release_wait_at(now_us + 1_000_000), None);
+
+        jitter_buffer.push_at(decode_frame(4, 1280), now_us + 1_000_000);
+        assert_eq!(pop_all(&mut jitter_buffer, now_us + 1_000_000), [4]);
+    }
+
+    #[test]
+    fn test_queue_depth_limited() {
+        let mut jitter_buffer = JitterBuffer::new(JitterBufferMode::Smooth);
+
+        // capture times far ahead of arrival, none of them is due yet
+        let now_us = 0;
+        for sequence in 0..MAX_QUEUED_FRAMES as u64 + 5 {
+            jitter_buffer.push_at(decode_frame(sequence, 1280), now_us);
+        }
+
+        // frames over the limit are released at once, the rest keep waiting
+        assert_eq!(jitter_buffer.release_wait_at(now_us), Some(Duration::ZERO));
+        assert_eq!(pop_all(&mut jitter_buffer, now_us), [0, 1, 2, 3, 4]);
+        assert_eq!(jitter_buffer.frames.len(), MAX_QUEUED_FRAMES);
+        assert_eq!(pop_all(&mut jitter_buffer, now_us), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut jitter_buffer = JitterBuffer::new(JitterBufferMode::Smooth);
+
+        // a delayed burst raises the target delay
+        let now_us = 5_000_000;
+        jitter_buffer.push_at(decode_frame(0, 1280), now_us);
+        for sequence in 1..10 {
+            jitter_buffer.push_at(decode_frame(sequence, 1280), now_us + 150_000);
+        }
+        assert!(jitter_buffer.target_delay_us > 0);
+        assert_eq!(pop_all(&mut jitter_buffer, now_us), [0]);
+
+        assert_eq!(jitter_buffer.reset(), (1..10).collect::<Vec<u64>>());
+        assert_eq!(jitter_buffer.target_delay_us, 0);
+        assert_eq!(jitter_buffer.release_wait_at(now_us), None);
+
+        // the new stream may begin with an earlier capture time and isn't delayed by
+        // the jitter of the old one
+        assert!(jitter_buffer.push_at(decode_frame(5, 1920), now_us));
+        assert_eq!(jitter_buffer.release_wait_at(now_us), Some(Duration::ZERO));
+        assert_eq!(pop_all(&mut jitter_buffer, now_us), [5]);
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{
+    api::endpoint::message::{EndPointTimeSyncResponse, EndPointVideoFrame},
+    utility::time::unix_micros,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+const CLOCK_SAMPLES: usize = 8;
+const STATISTICS_WINDOW: Duration = Duration::from_secs(2);
+// timings of frames which are never decoded or rendered are discarded over this limit
+const PENDING_FRAMES_LIMIT: usize = 256;
+
+// estimates remote clock minus local clock, the sample with the shortest round trip
+// among recent exchanges is the least affected by queueing
+#[derive(Debug, Default)]
+struct ClockOffsetEstimator {
+    // round trip and offset in microseconds
+    samples: VecDeque<(i64, i64)>,
+}
+
+impl ClockOffsetEstimator {
+    fn on_time_sync_response(&mut self, response: &EndPointTimeSyncResponse, arrival_us: i64) {
+        let round_trip_us =
+            (arrival_us - response.originate_us) - (response.transmit_us - response.receive_us);
+        let offset_us = ((response.receive_us - response.originate_us)
+            + (response.transmit_us - arrival_us))
+            / 2;
+
+        if self.samples.len() == CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+
+        self.samples.push_back((round_trip_us.max(0), offset_us));
+    }
+
+    fn estimate(&self) -> Option<(i64, i64)> {
+        self.samples
+            .iter()
+            .min_by_key(|(round_trip_us, _)| *round_trip_us)
+            .copied()
+    }
+}
+
+#[derive(Debug)]
+struct PendingFrame {
+    capture_time_us: i64,
+    encode_cost_us: u32,
+    received_us: i64,
+    decoded_us: Option<i64>,
+}
+
+#[derive(Debug)]
+struct RenderedFrame {
+    instant: Instant,
+    // from capture on the remote to render, only known after clock offset estimated
+    total_us: Option<i64>,
+    network_us: Option<i64>,
+    encode_us: i64,
+    decode_us: i64,
+    render_us: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VideoStatisticsSnapshot {
+    pub received_fps: f32,
+    pub rendered_fps: f32,
+    pub bitrate_kbps: u32,
+    pub latency_p50_ms: Option<f32>,
+    pub latency_p95_ms: Option<f32>,
+    pub latency_p99_ms: Option<f32>,
+    // average of every stage, network includes queueing on both endpoints
+    pub encode_ms: f32,
+    pub network_ms: Option<f32>,
+    pub decode_ms: f32,
+    pub render_ms: f32,
+    pub round_trip_ms: Option<f32>,
+    pub clock_offset_ms: Option<f32>,
+    pub lost_frames: u64,
+    pub dropped_frames: u64,
+}
+
+// follows every video frame from capture on the remote to render, stages are stamped
+// with the local wall clock and remote times are mapped by the estimated clock offset
+#[derive(Debug, Default)]
+pub struct VideoStatistics {
+    clock: ClockOffsetEstimator,
+    pending: HashMap<u64, PendingFrame>,
+    received: VecDeque<(Instant, usize)>,
+    rendered: VecDeque<RenderedFrame>,
+    next_sequence: Option<u64>,
+    lost_frames: u64,
+    dropped_frames: u64,
+}
+
+impl VideoStatistics {
+    pub fn on_time_sync_response(&mut self, response: &EndPointTimeSyncResponse) {
+        self.clock.on_time_sync_response(response, unix_micros());
+    }
+
+    pub fn on_video_received(&mut self, video_frame: &EndPointVideoFrame) {
+        let now = Instant::now();
+
+        if let Some(next_sequence) = self.next_sequence {
+            if video_frame.sequence > next_sequence {
+                self.lost_frames += video_frame.sequence - next_sequence;
+            }
+        }
+
+        self.next_sequence = Some(
+            self.next_sequence
+                .map_or(video_frame.sequence + 1, |next_sequence| {
+                    next_sequence.max(video_frame.sequence + 1)
+                }),
+        );
+
+        self.discard_expired(now);
+        self.received.push_back((now, video_frame.buffer.len()));
+
+        if self.pending.len() >= PENDING_FRAMES_LIMIT {
+            self.pending.clear();
+        }
+
+        self.pending.insert(
+            video_frame.sequence,
+            PendingFrame {
+                capture_time_us: video_frame.timing.capture_time_us,
+                encode_cost_us: video_frame.timing.encode_cost_us,
+                received_us: unix_micros(),
+                decoded_us: None,
+            },
+        );
+    }
+
+    pub fn on_video_decoded(&mut self, sequence: u64) {
+        if let Some(pending_frame) = self.pending.get_mut(&sequence) {
+            pending_frame.decoded_us = Some(unix_micros());
+        }
+    }
+
+    // frames discarded by decoder or replaced by a newer frame before render
+    pub fn on_video_dropped(&mut self, sequence: u64) {
+        self.pending.remove(&sequence);
+        self.dropped_frames += 1;
+    }
+
+    pub fn on_video_rendered(&mut self, sequence: u64) {
+        let Some(pending_frame) = self.pending.remove(&sequence) else {
+            return;
+        };
+
+        let rendered_us = unix_micros();
+        let decoded_us = pending_frame.decoded_us.unwrap_or(rendered_us);
+
+        // remote capture time in local clock
+        let capture_time_us = self
+            .clock
+            .estimate()
+            .map(|(_, offset_us)| pending_frame.capture_time_us - offset_us);
+
+        self.rendered.push_back(RenderedFrame {
+            instant: Instant::now(),
+            total_us: capture_time_us.map(|capture_time_us| rendered_us - capture_time_us),
+            network_us: capture_time_us.map(|capture_time_us| {
+                pending_frame.received_us - capture_time_us - pending_frame.encode_cost_us as i64
+            }),
+            encode_us: pending_frame.encode_cost_us as i64,
+            decode_us: decoded_us - pending_frame.received_us,
+            render_us: rendered_us - decoded_us,
+        });
+    }
+
+    pub fn snapshot(&mut self) -> VideoStatisticsSnapshot {
+        self.discard_expired(Instant::now());
+
+        let window_secs = STATISTICS_WINDOW.as_secs_f32();
+        let received_bytes: usize = self.received.iter().map(|(_, bytes)| bytes).sum();
+
+        let mut totals: Vec<i64> = self
+            .rendered
+            .iter()
+            .filter_map(|rendered_frame| rendered_frame.total_us)
+            .collect();
+        totals.sort_unstable();
+
+        let networks: Vec<i64> = self
+            .rendered
+            .iter()
+            .filter_map(|rendered_frame| rendered_frame.network_us)
+            .collect();
+
+        let clock = self.clock.estimate();
+
+        VideoStatisticsSnapshot {
+            received_fps: self.received.len() as f32 / window_secs,
+            rendered_fps: self.rendered.len() as f32 / window_secs,
+            bitrate_kbps: (received_bytes as f32 * 8.0 / 1000.0 / window_secs) as u32,
+            latency_p50_ms: percentile_ms(&totals, 50),
+            latency_p95_ms: percentile_ms(&totals, 95),
+            latency_p99_ms: percentile_ms(&totals, 99),
+            encode_ms: average_ms(self.rendered.iter().map(|frame| frame.encode_us)).unwrap_or(0.0),
+            network_ms: average_ms(networks.into_iter()),
+            decode_ms: average_ms(self.rendered.iter().map(|frame| frame.decode_us)).unwrap_or(0.0),
+            render_ms: average_ms(self.rendered.iter().map(|frame| frame.render_us)).unwrap_or(0.0),
+            round_trip_ms: clock.map(|(round_trip_us, _)| round_trip_us as f32 / 1000.0),
+            clock_offset_ms: clock.map(|(_, offset_us)| offset_us as f32 / 1000.0),
+            lost_frames: self.lost_frames,
+            dropped_frames: self.dropped_frames,
+        }
+    }
+}
+
+impl VideoStatistics {
+    fn discard_expired(&mut self, now: Instant) {
+        while let Some((instant, _)) = self.received.front() {
+            if now.duration_since(*instant) <= STATISTICS_WINDOW {
+                break;
+            }
+            self.received.pop_front();
+        }
+
+        while let Some(rendered_frame) = self.rendered.front() {
+            if now.duration_since(rendered_frame.instant) <= STATISTICS_WINDOW {
+                break;
+            }
+            self.rendered.pop_front();
+        }
+    }
+}
+
+// nearest rank, the smallest value which is not less than the given percent of values
+fn percentile_ms(sorted_values: &[i64], percentile: usize) -> Option<f32> {
+    if sorted_values.is_empty() {
+        return None;
+    }
+
+    let rank = (sorted_values.len() * percentile + 99) / 100;
+    let index = rank.clamp(1, sorted_values.len()) - 1;
+    Some(sorted_values[index] as f32 / 1000.0)
+}
+
+fn average_ms(values: impl Iterator<Item = i64>) -> Option<f32> {
+    let (sum, count) = values.fold((0i64, 0i64), |(sum, count), value| (sum + value, count + 1));
+    if count == 0 {
+        None
+    } else {
+        Some(sum as f32 / count as f32 / 1000.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an exchange with the remote clock ahead by offset_us, the request and the response
+    // spend the given times on the network and the remote holds it for 100us
+    fn exchange(
+        clock: &mut ClockOffsetEstimator,
+        offset_us: i64,
+        request_us: i64,
+        response_us: i64,
+    ) {
+        let originate_us = 1_000_000;
+        let receive_us = originate_us + request_us + offset_us;
+        let transmit_us = receive_us + 100;
+        let arrival_us = transmit_us - offset_us + response_us;
+
+        clock.on_time_sync_response(
+            &EndPointTimeSyncResponse {
+                originate_us,
+                receive_us,
+                transmit_us,
+            },
+            arrival_us,
+        );
+    }
+
+    #[test]
+    fn test_clock_offset_prefers_shortest_round_trip() {
+        let mut clock = ClockOffsetEstimator::default();
+        assert_eq!(clock.estimate(), None);
+
+        // queueing in one direction skews the offset by half of the asymmetry
+        exchange(&mut clock, 5_000, 40_000, 2_000);
+        assert_eq!(clock.estimate(), Some((42_000, 24_000)));
+
+        exchange(&mut clock, 5_000, 3_000, 1_000);
+        exchange(&mut clock, 5_000, 1_000, 30_000);
+        assert_eq!(clock.estimate(), Some((4_000, 6_000)));
+
+        exchange(&mut clock, 5_000, 1_000, 1_000);
+        assert_eq!(clock.estimate(), Some((2_000, 5_000)));
+
+        // the best sample is forgotten once enough newer exchanges arrived
+        for _ in 0..CLOCK_SAMPLES - 1 {
+            exchange(&mut clock, 5_000, 8_000, 2_000);
+        }
+        assert_eq!(clock.estimate(), Some((2_000, 5_000)));
+
+        exchange(&mut clock, 5_000, 8_000, 2_000);
+        assert_eq!(clock.estimate(), Some((10_000, 8_000)));
+
+        // clock jumps can't make a negative round trip
+        exchange(&mut clock, 5_000, -3_000, 1_000);
+        assert_eq!(clock.estimate(), Some((0, 3_000)));
+    }
+
+    #[test]
+    fn test_percentile_edges() {
+        assert_eq!(percentile_ms(&[], 50), None);
+        assert_eq!(percentile_ms(&[7_000], 50), Some(7.0));
+        assert_eq!(percentile_ms(&[7_000], 95), Some(7.0));
+
+        assert_eq!(percentile_ms(&[1_000, 2_000], 50), Some(1.0));
+        assert_eq!(percentile_ms(&[1_000, 2_000, 3_000], 50), Some(2.0));
+
+        let values: Vec<i64> = (1..=100).map(|value| value * 1000).collect();
+        assert_eq!(percentile_ms(&values, 50), Some(50.0));
+        assert_eq!(percentile_ms(&values, 95), Some(95.0));
+        assert_eq!(percentile_ms(&values, 99), Some(99.0));
+
+        // 95% of 20 values are covered by the 19th, a single outlier stays above p95
+        let values: Vec<i64> = (1..=20).map(|value| value * 1000).collect();
+        assert_eq!(percentile_ms(&values, 50), Some(10.0));
+        assert_eq!(percentile_ms(&values, 95), Some(19.0));
+        assert_eq!(percentile_ms(&values, 99), Some(20.0));
+
+        let values: Vec<i64> = (1..=21).map(|value| value * 1000).collect();
+        assert_eq!(percentile_ms(&values, 50), Some(11.0));
+        assert_eq!(percentile_ms(&values, 95), Some(20.0));
+    }
+
+    #[test]
+    fn test_average() {
+        assert_eq!(average_ms(std::iter::empty()), None);
+        assert_eq!(average_ms([1_000, 2_000, 6_000].into_iter()), Some(3.0));
+    }
+}
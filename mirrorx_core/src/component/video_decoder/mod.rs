@@ -1 +1,4 @@
 pub mod decoder;
+pub mod jitter_buffer;
+pub mod latency;
+pub mod statistics;
@@ -0,0 +1,161 @@
+use crate::api::endpoint::message::{EndPointReceiverReport, EndPointVideoFrame};
+use std::time::Instant;
+
+pub struct ReceiverStatistics {
+    received_frames: u32,
+    received_bytes: u64,
+    lost_frames: u32,
+    next_sequence: Option<u64>,
+    arrival_base: Instant,
+    last_transit_us: Option<i64>,
+    jitter_ms: f64,
+    report_instant: Instant,
+}
+
+impl Default for ReceiverStatistics {
+    fn default() -> Self {
+        Self {
+            received_frames: 0,
+            received_bytes: 0,
+            lost_frames: 0,
+            next_sequence: None,
+            arrival_base: Instant::now(),
+            last_transit_us: None,
+            jitter_ms: 0.0,
+            report_instant: Instant::now(),
+        }
+    }
+}
+
+impl ReceiverStatistics {
+    pub fn on_video_frame(&mut self, video_frame: &EndPointVideoFrame) {
+        if let Some(next_sequence) = self.next_sequence {
+            if video_frame.sequence > next_sequence {
+                self.lost_frames += (video_frame.sequence - next_sequence) as u32;
+            }
+        }
+
+        self.next_sequence = Some(
+            self.next_sequence
+                .map_or(video_frame.sequence + 1, |next_sequence| {
+                    next_sequence.max(video_frame.sequence + 1)
+                }),
+        );
+
+        self.received_frames += 1;
+        self.received_bytes += video_frame.buffer.len() as u64;
+
+        // frames from remotes which don't send capture time can't be measured
+        let capture_time_us = video_frame.timing.capture_time_us;
+        if capture_time_us == 0 {
+            self.last_transit_us = None;
+            return;
+        }
+
+        // rtp interarrival jitter, D = (Rj - Ri) - (Sj - Si) smoothed by 1/16. the clock
+        // offset between both endpoints cancels out, and so do intentional pauses like an
+        // unchanged screen or a frame rate change since they delay capture and arrival alike
+        // the send time is taken after encoding, so encoder stalls aren't counted as jitter
+        let send_us = capture_time_us + video_frame.timing.encode_cost_us as i64;
+        let arrival_us = self.arrival_base.elapsed().as_micros() as i64;
+        let transit_us = arrival_us - send_us;
+
+        if let Some(last_transit_us) = self.last_transit_us {
+            let deviation_ms = (transit_us - last_transit_us).abs() as f64 / 1000.0;
+            self.jitter_ms += (deviation_ms - self.jitter_ms) / 16.0;
+        }
+
+        self.last_transit_us = Some(transit_us);
+    }
+
+    // counters are reset after each report, the jitter is kept smoothing
+    pub fn report(&mut self, decode_queue_depth: usize) -> EndPointReceiverReport {
+        let elapsed = self.report_instant.elapsed().as_secs_f64();
+        let throughput_kbps = if elapsed > 0.0 {
+            (self.received_bytes as f64 * 8.0 / 1000.0 / elapsed) as u32
+        } else {
+            0
+        };
+
+        let report = EndPointReceiverReport {
+            decode_queue_depth: decode_queue_depth as u32,
+            jitter_ms: self.jitter_ms as u32,
+            received_frames: self.received_frames,
+            lost_frames: self.lost_frames,
+            throughput_kbps,
+        };
+
+        self.received_frames = 0;
+        self.received_bytes = 0;
+        self.lost_frames = 0;
+        self.report_instant = Instant::now();
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::endpoint::message::{EndPointVideoFrameTiming, VideoCodec};
+
+    fn video_frame(sequence: u64, capture_time_us: i64) -> EndPointVideoFrame {
+        EndPointVideoFrame {
+            codec: VideoCodec::H264,
+            sequence,
+            key_frame: sequence == 0,
+            width: 1920,
+            height: 1080,
+            pts: sequence as i64,
+            timing: EndPointVideoFrameTiming {
+                capture_time_us,
+                encode_cost_us: 0,
+            },
+            buffer: vec![0; 1000],
+        }
+    }
+
+    #[test]
+    fn test_lost_frames_counted() {
+        let mut statistics = ReceiverStatistics::default();
+
+        statistics.on_video_frame(&video_frame(0, 0));
+        statistics.on_video_frame(&video_frame(1, 0));
+        statistics.on_video_frame(&video_frame(4, 0));
+
+        // a late frame fills no gap twice and doesn't move the sequence back
+        statistics.on_video_frame(&video_frame(2, 0));
+        statistics.on_video_frame(&video_frame(5, 0));
+
+        let report = statistics.report(3);
+        assert_eq!(report.received_frames, 5);
+        assert_eq!(report.lost_frames, 2);
+        assert_eq!(report.decode_queue_depth, 3);
+        assert_eq!(report.jitter_ms, 0);
+
+        // counters start over after each report
+        statistics.on_video_frame(&video_frame(6, 0));
+        let report = statistics.report(0);
+        assert_eq!(report.received_frames, 1);
+        assert_eq!(report.lost_frames, 0);
+    }
+
+    #[test]
+    fn test_jitter_smoothed() {
+        let mut statistics = ReceiverStatistics::default();
+
+        // frames captured a second apart arrive at once, so the transit time changes
+        // by a second
+        statistics.on_video_frame(&video_frame(0, 1_000_000));
+        statistics.on_video_frame(&video_frame(1, 2_000_000));
+
+        let jitter_ms = statistics.report(0).jitter_ms;
+        assert!((60..=63).contains(&jitter_ms), "{jitter_ms}");
+
+        // the jitter is kept across reports and a frame without capture time isn't
+        // measured against the previous one
+        statistics.on_video_frame(&video_frame(2, 0));
+        statistics.on_video_frame(&video_frame(3, 10_000_000));
+        assert_eq!(statistics.report(0).jitter_ms, jitter_ms);
+    }
+}
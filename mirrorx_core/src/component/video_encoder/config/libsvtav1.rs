@@ -0,0 +1,41 @@
+use super::{set_codec_ctx_option, EncoderConfig};
+use crate::error::CoreResult;
+use mirrorx_native::ffmpeg::{
+    codecs::{avcodec::AVCodecContext, codec_id::*},
+    utils::pixfmt::{AVPixelFormat, AV_PIX_FMT_YUV420P},
+};
+use std::ffi::CString;
+
+pub struct LibSvtAV1Config {
+    ffmpeg_encoder_name: CString,
+}
+
+impl Default for LibSvtAV1Config {
+    fn default() -> Self {
+        LibSvtAV1Config {
+            ffmpeg_encoder_name: CString::new("libsvtav1").unwrap(),
+        }
+    }
+}
+
+impl EncoderConfig for LibSvtAV1Config {
+    fn apply_option(&self, codec_ctx: *mut AVCodecContext) -> CoreResult<()> {
+        set_codec_ctx_option(codec_ctx, "preset", "12", 0)?;
+        // low delay prediction structure, frames are never reordered
+        set_codec_ctx_option(codec_ctx, "svtav1-params", "pred-struct=1", 0)?;
+
+        Ok(())
+    }
+
+    fn ffmpeg_encoder_name(&self) -> *const i8 {
+        self.ffmpeg_encoder_name.as_ptr()
+    }
+
+    fn av_codec_id(&self) -> AVCodecID {
+        AV_CODEC_ID_AV1
+    }
+
+    fn pixel_format(&self) -> AVPixelFormat {
+        AV_PIX_FMT_YUV420P
+    }
+}
@@ -0,0 +1,46 @@
+use super::{set_codec_ctx_option, EncoderConfig};
+use crate::error::CoreResult;
+use mirrorx_native::ffmpeg::{
+    codecs::{avcodec::AVCodecContext, codec_id::*},
+    utils::pixfmt::{AVPixelFormat, AV_PIX_FMT_YUV420P},
+};
+use std::ffi::CString;
+
+pub struct LibvpxVP8Config {
+    ffmpeg_encoder_name: CString,
+}
+
+impl Default for LibvpxVP8Config {
+    fn default() -> Self {
+        LibvpxVP8Config {
+            ffmpeg_encoder_name: CString::new("libvpx").unwrap(),
+        }
+    }
+}
+
+impl EncoderConfig for LibvpxVP8Config {
+    fn apply_option(&self, codec_ctx: *mut AVCodecContext) -> CoreResult<()> {
+        unsafe {
+            (*codec_ctx).thread_count = 4;
+        }
+
+        set_codec_ctx_option(codec_ctx, "deadline", "realtime", 0)?;
+        set_codec_ctx_option(codec_ctx, "cpu-used", "12", 0)?;
+        set_codec_ctx_option(codec_ctx, "lag-in-frames", "0", 0)?;
+        set_codec_ctx_option(codec_ctx, "error-resilient", "default", 0)?;
+
+        Ok(())
+    }
+
+    fn ffmpeg_encoder_name(&self) -> *const i8 {
+        self.ffmpeg_encoder_name.as_ptr()
+    }
+
+    fn av_codec_id(&self) -> AVCodecID {
+        AV_CODEC_ID_VP8
+    }
+
+    fn pixel_format(&self) -> AVPixelFormat {
+        AV_PIX_FMT_YUV420P
+    }
+}
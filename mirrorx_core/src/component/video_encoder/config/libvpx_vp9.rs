@@ -0,0 +1,55 @@
+use super::{set_codec_ctx_option, EncoderConfig};
+use crate::error::CoreResult;
+use mirrorx_native::ffmpeg::{
+    codecs::{avcodec::AVCodecContext, codec_id::*},
+    utils::pixfmt::{AVPixelFormat, AV_PIX_FMT_YUV420P},
+};
+use std::ffi::CString;
+
+pub struct LibvpxVP9Config {
+    ffmpeg_encoder_name: CString,
+}
+
+impl Default for LibvpxVP9Config {
+    fn default() -> Self {
+        LibvpxVP9Config {
+            ffmpeg_encoder_name: CString::new("libvpx-vp9").unwrap(),
+        }
+    }
+}
+
+impl EncoderConfig for LibvpxVP9Config {
+    fn apply_option(&self, codec_ctx: *mut AVCodecContext) -> CoreResult<()> {
+        unsafe {
+            // row-mt and tile columns only take effect with multiple threads
+            (*codec_ctx).thread_count = 4;
+        }
+
+        set_codec_ctx_option(codec_ctx, "deadline", "realtime", 0)?;
+        set_codec_ctx_option(codec_ctx, "cpu-used", "8", 0)?;
+        set_codec_ctx_option(codec_ctx, "lag-in-frames", "0", 0)?;
+        set_codec_ctx_option(codec_ctx, "row-mt", "1", 0)?;
+        set_codec_ctx_option(codec_ctx, "tile-columns", "2", 0)?;
+        set_codec_ctx_option(codec_ctx, "frame-parallel", "0", 0)?;
+        set_codec_ctx_option(codec_ctx, "aq-mode", "3", 0)?;
+
+        Ok(())
+    }
+
+    fn ffmpeg_encoder_name(&self) -> *const i8 {
+        self.ffmpeg_encoder_name.as_ptr()
+    }
+
+    fn av_codec_id(&self) -> AVCodecID {
+        AV_CODEC_ID_VP9
+    }
+
+    fn pixel_format(&self) -> AVPixelFormat {
+        AV_PIX_FMT_YUV420P
+    }
+
+    // profile 1 is selected by the encoder for yuv444p input
+    fn yuv444_supported(&self) -> bool {
+        true
+    }
+}
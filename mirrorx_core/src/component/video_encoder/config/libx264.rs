@@ -1,6 +1,9 @@
 use super::{set_codec_ctx_option, EncoderConfig};
 use crate::error::CoreResult;
-use mirrorx_native::ffmpeg::codecs::{avcodec::AVCodecContext, codec_id::*};
+use mirrorx_native::ffmpeg::{
+    codecs::{avcodec::AVCodecContext, codec_id::*},
+    utils::pixfmt::AV_PIX_FMT_YUV444P,
+};
 use std::ffi::CString;
 
 pub struct Libx264Config {
@@ -17,10 +20,18 @@ impl Default for Libx264Config {
 
 impl EncoderConfig for Libx264Config {
     fn apply_option(&self, codec_ctx: *mut AVCodecContext) -> CoreResult<()> {
-        set_codec_ctx_option(codec_ctx, "profile", "baseline", 0)?;
+        // baseline profile is 4:2:0 only
+        let profile = if unsafe { (*codec_ctx).pix_fmt } == AV_PIX_FMT_YUV444P {
+            "high444"
+        } else {
+            "baseline"
+        };
+
+        set_codec_ctx_option(codec_ctx, "profile", profile, 0)?;
         set_codec_ctx_option(codec_ctx, "level", "5.0", 0)?;
         set_codec_ctx_option(codec_ctx, "preset", "ultrafast", 0)?;
         set_codec_ctx_option(codec_ctx, "tune", "zerolatency", 0)?;
+        set_codec_ctx_option(codec_ctx, "forced-idr", "1", 0)?;
 
         Ok(())
     }
@@ -32,4 +43,16 @@ impl EncoderConfig for Libx264Config {
     fn av_codec_id(&self) -> AVCodecID {
         AV_CODEC_ID_H264
     }
+
+    fn yuv444_supported(&self) -> bool {
+        true
+    }
+
+    fn bitrate_reconfigurable(&self) -> bool {
+        true
+    }
+
+    fn apply_intra_refresh(&self, codec_ctx: *mut AVCodecContext) -> CoreResult<()> {
+        set_codec_ctx_option(codec_ctx, "intra-refresh", "1", 0)
+    }
 }
@@ -1,21 +1,82 @@
 pub mod h264_videotoolbox;
 pub mod hevc_videotoolbox;
+pub mod libaom_av1;
+pub mod libsvtav1;
+pub mod libvpx_vp8;
+pub mod libvpx_vp9;
 pub mod libx264;
 
-use crate::{core_error, error::CoreResult};
+use crate::{api::endpoint::message::VideoCodec, core_error, error::CoreResult};
 use mirrorx_native::ffmpeg::{
-    codecs::{avcodec::AVCodecContext, codec_id::AVCodecID},
+    codecs::{avcodec::AVCodecContext, codec::avcodec_find_encoder_by_name, codec_id::AVCodecID},
     utils::{
         error::{AVERROR, AVERROR_OPTION_NOT_FOUND},
         opt::av_opt_set,
+        pixfmt::{AVPixelFormat, AV_PIX_FMT_NV12},
     },
 };
 use std::ffi::CString;
 
-pub trait EncoderConfig {
+pub trait EncoderConfig: Send {
     fn apply_option(&self, codec_ctx: *mut AVCodecContext) -> CoreResult<()>;
     fn ffmpeg_encoder_name(&self) -> *const i8;
     fn av_codec_id(&self) -> AVCodecID;
+
+    // captured frames are nv12, encoders without nv12 input get planar yuv420p
+    fn pixel_format(&self) -> AVPixelFormat {
+        AV_PIX_FMT_NV12
+    }
+
+    // encoders which accept planar yuv444 frames, the pixel format of codec context
+    // is already set to yuv444p when apply_option is called in that case
+    fn yuv444_supported(&self) -> bool {
+        false
+    }
+
+    // encoders which pick up bitrate changes of the codec context between frames,
+    // others are recreated to apply a new bitrate
+    fn bitrate_reconfigurable(&self) -> bool {
+        false
+    }
+
+    // encoders without intra refresh fall back to periodic key frames
+    fn apply_intra_refresh(&self, _codec_ctx: *mut AVCodecContext) -> CoreResult<()> {
+        Ok(())
+    }
+}
+
+// candidate encoders of each codec in order of preference, encoders which are not
+// built into the linked ffmpeg are skipped
+fn encoder_configs(codec: VideoCodec) -> Vec<Box<dyn EncoderConfig>> {
+    match codec {
+        VideoCodec::H264 => vec![
+            Box::<libx264::Libx264Config>::default(),
+            Box::<h264_videotoolbox::H264VideoToolboxConfig>::default(),
+        ],
+        VideoCodec::Hevc => vec![Box::<hevc_videotoolbox::HEVCVideoToolboxConfig>::default()],
+        VideoCodec::VP8 => vec![Box::<libvpx_vp8::LibvpxVP8Config>::default()],
+        VideoCodec::VP9 => vec![Box::<libvpx_vp9::LibvpxVP9Config>::default()],
+        VideoCodec::AV1 => vec![
+            Box::<libsvtav1::LibSvtAV1Config>::default(),
+            Box::<libaom_av1::LibaomAV1Config>::default(),
+        ],
+    }
+}
+
+pub fn new_encoder_config(codec: VideoCodec) -> CoreResult<Box<dyn EncoderConfig>> {
+    encoder_configs(codec)
+        .into_iter()
+        .find(|config| unsafe {
+            !avcodec_find_encoder_by_name(config.ffmpeg_encoder_name()).is_null()
+        })
+        .ok_or_else(|| core_error!("no available encoder for codec {:?}", codec))
+}
+
+pub fn supported_encoders() -> Vec<VideoCodec> {
+    VideoCodec::ALL
+        .into_iter()
+        .filter(|codec| new_encoder_config(*codec).is_ok())
+        .collect()
 }
 
 fn set_codec_ctx_option(
@@ -0,0 +1,319 @@
+use crate::api::{
+    config::entity::kv::{Settings, MAX_BITRATE_KBPS},
+    endpoint::message::EndPointReceiverReport,
+};
+use tokio::sync::watch::{Receiver, Sender};
+
+const MIN_BITRATE: i64 = 100_000;
+const MAX_DECODE_QUEUE_DEPTH: u32 = 8;
+const MAX_JITTER_MS: u32 = 100;
+// reports without congestion before the output resolution steps up again
+const STABLE_REPORTS_TO_UPSCALE: u32 = 10;
+// output size in percent of the captured size
+const SCALE_STEPS: [u32; 3] = [100, 75, 50];
+// congestion below this percent of the target bitrate lowers the resolution, since a
+// smaller picture looks better than a blurry one at the same bitrate
+const SCALE_DOWN_BITRATE_PERCENT: i64 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncoderTarget {
+    pub bitrate: i64,
+    pub max_bitrate: i64,
+    pub scale: u32,
+    pub frame_rate: u8,
+    // output size requested by the visitor, zero means no limit
+    pub max_width: u16,
+    pub max_height: u16,
+}
+
+impl EncoderTarget {
+    pub fn boost(&self, multiplier: i64) -> EncoderTarget {
+        let max_bitrate = i64::from(MAX_BITRATE_KBPS) * 1000;
+
+        EncoderTarget {
+            bitrate: (self.bitrate * multiplier).min(max_bitrate),
+            max_bitrate: (self.max_bitrate * multiplier).min(max_bitrate),
+            ..*self
+        }
+    }
+
+    pub fn scaled_size(&self, width: i32, height: i32) -> (i32, i32) {
+        // frames are fit into the requested size first, the congestion scale applies
+        // on top of it
+        let mut ratio = f64::from(self.scale) / 100.0;
+        if self.max_width > 0 && self.max_height > 0 && width > 0 && height > 0 {
+            let fit_ratio = (f64::from(self.max_width) / f64::from(width))
+                .min(f64::from(self.max_height) / f64::from(height));
+
+            ratio *= fit_ratio.min(1.0);
+        }
+
+        if ratio >= 1.0 {
+            return (width, height);
+        }
+
+        // yuv 4:2:0 needs even dimensions
+        let scale = |length: i32| ((f64::from(length) * ratio) as i32).max(2) & !1;
+        (scale(width), scale(height))
+    }
+}
+
+#[derive(Debug)]
+pub struct CongestionController {
+    target_bitrate: i64,
+    max_bitrate: i64,
+    min_bitrate: i64,
+    bitrate: i64,
+    scale_step: usize,
+    stable_reports: u32,
+    target_tx: Sender<EncoderTarget>,
+}
+
+impl CongestionController {
+    pub fn new(settings: &Settings) -> (CongestionController, Receiver<EncoderTarget>) {
+        let target_bitrate = settings.target_bitrate();
+
+        let (target_tx, target_rx) = tokio::sync::watch::channel(EncoderTarget {
+            bitrate: target_bitrate,
+            max_bitrate: settings.max_bitrate(),
+            scale: SCALE_STEPS[0],
+            frame_rate: settings.frame_rate,
+            max_width: 0,
+            max_height: 0,
+        });
+
+        let controller = CongestionController {
+            target_bitrate,
+            max_bitrate: settings.max_bitrate(),
+            min_bitrate: MIN_BITRATE.min(target_bitrate),
+            bitrate: target_bitrate,
+            scale_step: 0,
+            stable_reports: 0,
+            target_tx,
+        };
+
+        (controller, target_rx)
+    }
+
+    pub fn on_receiver_report(&mut self, report: &EndPointReceiverReport) {
+        let congested = report.lost_frames > 0
+            || report.decode_queue_depth > MAX_DECODE_QUEUE_DEPTH
+            || report.jitter_ms > MAX_JITTER_MS;
+
+        if congested {
+            self.stable_reports = 0;
+
+            // multiplicative decrease, bounded by what the receiver actually got
+            let throughput = i64::from(report.throughput_kbps) * 1000;
+            let bitrate = if throughput > 0 && throughput < self.bitrate {
+                (throughput * 9 / 10).max(self.bitrate / 2)
+            } else {
+                self.bitrate * 85 / 100
+            };
+
+            let scale_down_bitrate =
+                (self.target_bitrate * SCALE_DOWN_BITRATE_PERCENT / 100).max(self.min_bitrate);
+
+            if bitrate <= scale_down_bitrate && self.scale_step + 1 < SCALE_STEPS.len() {
+                self.scale_step += 1;
+            }
+
+            self.bitrate = bitrate.max(self.min_bitrate);
+        } else {
+            self.stable_reports += 1;
+
+            // additive increase
+            self.bitrate = (self.bitrate + self.target_bitrate / 20).min(self.target_bitrate);
+
+            if self.scale_step > 0
+                && self.stable_reports >= STABLE_REPORTS_TO_UPSCALE
+                && self.bitrate >= self.target_bitrate / 2
+            {
+                self.scale_step -= 1;
+                self.stable_reports = 0;
+            }
+        }
+
+        let target = EncoderTarget {
+            bitrate: self.bitrate,
+            max_bitrate: self.max_bitrate * self.bitrate / self.target_bitrate.max(1),
+            scale: SCALE_STEPS[self.scale_step],
+            ..*self.target_tx.borrow()
+        };
+
+        // reconfiguring the encoder isn't free, small bitrate changes are ignored unless
+        // the bitrate is fully recovered
+        let target_bitrate = self.target_bitrate;
+        self.target_tx.send_if_modified(|current| {
+            if current.scale != target.scale
+                || (current.bitrate - target.bitrate).abs() * 10 >= current.bitrate
+                || (target.bitrate == target_bitrate && current.bitrate != target.bitrate)
+            {
+                tracing::info!(?target, ?report, "encoder target changed");
+                *current = target;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    pub fn set_frame_rate(&self, frame_rate: u8) {
+        self.target_tx.send_if_modified(|current| {
+            if current.frame_rate != frame_rate {
+                tracing::info!(?frame_rate, "encoder frame rate changed");
+                current.frame_rate = frame_rate;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    pub fn set_max_size(&self, max_width: u16, max_height: u16) {
+        self.target_tx.send_if_modified(|current| {
+            if current.max_width != max_width || current.max_height != max_height {
+                tracing::info!(?max_width, ?max_height, "encoder max size changed");
+                current.max_width = max_width;
+                current.max_height = max_height;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(lost_frames: u32, throughput_kbps: u32) -> EndPointReceiverReport {
+        EndPointReceiverReport {
+            decode_queue_depth: 0,
+            jitter_ms: 0,
+            received_frames: 30,
+            lost_frames,
+            throughput_kbps,
+        }
+    }
+
+    fn target(scale: u32, max_width: u16, max_height: u16) -> EncoderTarget {
+        EncoderTarget {
+            bitrate: 0,
+            max_bitrate: 0,
+            scale,
+            frame_rate: 30,
+            max_width,
+            max_height,
+        }
+    }
+
+    #[test]
+    fn test_scaled_size() {
+        // no limit and no congestion keeps the captured size
+        assert_eq!(target(100, 0, 0).scaled_size(3840, 2160), (3840, 2160));
+
+        // frames are fit into the requested size by the tighter side, never enlarged
+        assert_eq!(target(100, 1280, 720).scaled_size(3840, 2160), (1280, 720));
+        assert_eq!(target(100, 1280, 720).scaled_size(2560, 1600), (1152, 720));
+        assert_eq!(
+            target(100, 7680, 4320).scaled_size(1920, 1080),
+            (1920, 1080)
+        );
+
+        // the congestion scale applies on top of the fit
+        assert_eq!(target(50, 1280, 720).scaled_size(3840, 2160), (640, 360));
+
+        // odd results round down to even sizes, which are two at least
+        assert_eq!(target(75, 0, 0).scaled_size(1920, 1080), (1440, 810));
+        assert_eq!(target(75, 0, 0).scaled_size(1366, 768), (1024, 576));
+        assert_eq!(target(50, 0, 0).scaled_size(1366, 766), (682, 382));
+        assert_eq!(target(50, 0, 0).scaled_size(3, 3), (2, 2));
+    }
+
+    #[test]
+    fn test_back_off_on_loss() {
+        let (mut controller, target_rx) = CongestionController::new(&Settings::default());
+        let target_bitrate = controller.target_bitrate;
+
+        controller.on_receiver_report(&report(1, 0));
+        assert_eq!(controller.bitrate, target_bitrate * 85 / 100);
+        assert_eq!(target_rx.borrow().bitrate, controller.bitrate);
+        assert_eq!(target_rx.borrow().scale, 100);
+
+        // the receiver's throughput bounds the decrease, but by half at most
+        let throughput_kbps = (controller.bitrate / 1000 * 8 / 10) as u32;
+        controller.on_receiver_report(&report(1, throughput_kbps));
+        assert_eq!(
+            controller.bitrate,
+            i64::from(throughput_kbps) * 1000 * 9 / 10
+        );
+
+        let bitrate = controller.bitrate;
+        controller.on_receiver_report(&report(1, 1));
+        assert_eq!(controller.bitrate, bitrate / 2);
+
+        // a deep decode queue or jitter count as congestion as well
+        let bitrate = controller.bitrate;
+        controller.on_receiver_report(&EndPointReceiverReport {
+            decode_queue_depth: MAX_DECODE_QUEUE_DEPTH + 1,
+            ..report(0, 0)
+        });
+        assert!(controller.bitrate < bitrate);
+
+        let bitrate = controller.bitrate;
+        controller.on_receiver_report(&EndPointReceiverReport {
+            jitter_ms: MAX_JITTER_MS + 1,
+            ..report(0, 0)
+        });
+        assert!(controller.bitrate < bitrate);
+    }
+
+    #[test]
+    fn test_probe_up_after_clean_reports() {
+        let (mut controller, target_rx) = CongestionController::new(&Settings::default());
+        let target_bitrate = controller.target_bitrate;
+
+        controller.on_receiver_report(&report(1, 0));
+        controller.on_receiver_report(&report(1, 0));
+        let backed_off_bitrate = controller.bitrate;
+
+        controller.on_receiver_report(&report(0, 0));
+        assert_eq!(controller.bitrate, backed_off_bitrate + target_bitrate / 20);
+
+        for _ in 0..20 {
+            controller.on_receiver_report(&report(0, 0));
+        }
+
+        // small steps aren't applied to the encoder, but the full recovery is
+        assert_eq!(controller.bitrate, target_bitrate);
+        assert_eq!(target_rx.borrow().bitrate, target_bitrate);
+        assert_eq!(target_rx.borrow().max_bitrate, controller.max_bitrate);
+    }
+
+    #[test]
+    fn test_bitrate_and_scale_clamped() {
+        let (mut controller, target_rx) = CongestionController::new(&Settings::default());
+        let target_bitrate = controller.target_bitrate;
+
+        for _ in 0..100 {
+            controller.on_receiver_report(&report(10, 1));
+            assert!(controller.bitrate >= MIN_BITRATE.min(target_bitrate));
+        }
+
+        assert_eq!(controller.bitrate, MIN_BITRATE.min(target_bitrate));
+        assert_eq!(target_rx.borrow().bitrate, controller.bitrate);
+        assert_eq!(target_rx.borrow().scale, SCALE_STEPS[SCALE_STEPS.len() - 1]);
+
+        for _ in 0..200 {
+            controller.on_receiver_report(&report(0, 0));
+            assert!(controller.bitrate <= target_bitrate);
+        }
+
+        // the resolution steps back up after enough stable reports
+        assert_eq!(controller.bitrate, target_bitrate);
+        assert_eq!(target_rx.borrow().scale, SCALE_STEPS[0]);
+        assert_eq!(target_rx.borrow().max_bitrate, controller.max_bitrate);
+    }
+}
@@ -1,52 +1,135 @@
-use super::config::EncoderConfig;
+use super::{
+    config::{new_encoder_config, EncoderConfig},
+    congestion::EncoderTarget,
+    scaler::Scaler,
+    static_screen::StaticScreenDetector,
+};
 use crate::{
     api::endpoint::{
         client::EndPointClient,
-        message::{EndPointMessage, EndPointVideoFrame},
+        message::{EndPointMessage, EndPointVideoFrame, EndPointVideoFrameTiming, VideoCodec},
+    },
+    component::frame::{
+        capture_time_now, capture_time_to_unix_micros, DesktopEncodeFrame, DesktopEncodeFrameFormat,
     },
-    component::frame::DesktopEncodeFrame,
     core_error,
     error::CoreResult,
 };
 use mirrorx_native::ffmpeg::{
     codecs::{avcodec::*, codec::*, packet::*},
-    utils::{error::*, frame::*, imgutils::*, log::*, pixfmt::*, rational::AVRational},
+    utils::{
+        avutil::{AV_PICTURE_TYPE_I, AV_PICTURE_TYPE_NONE},
+        error::*,
+        frame::*,
+        imgutils::*,
+        log::*,
+        pixfmt::*,
+        rational::AVRational,
+    },
 };
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::watch::Receiver;
 
-pub struct VideoEncoder<T>
-where
-    T: EncoderConfig,
-{
-    encoder_config: T,
+// the whole picture is refreshed within this period when intra refresh is enabled
+const INTRA_REFRESH_INTERVAL_SECS: i32 = 2;
+
+pub struct VideoEncoder {
+    codec: VideoCodec,
+    encoder_config: Box<dyn EncoderConfig>,
     encode_context: Option<EncodeContext>,
+    scaler: Option<Scaler>,
+    static_screen_detector: StaticScreenDetector,
+    bitrate_multiplier: i64,
     client: Arc<EndPointClient>,
+    target_rx: Receiver<EncoderTarget>,
+    target: EncoderTarget,
+    frame_scale: (f32, f32),
+    sequence: u64,
+    last_capture_time: Option<Duration>,
+    last_pts: i64,
 }
 
-impl<T> VideoEncoder<T>
-where
-    T: EncoderConfig,
-{
-    pub fn new(encoder_config: T, client: Arc<EndPointClient>) -> CoreResult<VideoEncoder<T>> {
+impl VideoEncoder {
+    pub fn new(
+        codec: VideoCodec,
+        client: Arc<EndPointClient>,
+        mut target_rx: Receiver<EncoderTarget>,
+    ) -> CoreResult<VideoEncoder> {
+        let encoder_config = new_encoder_config(codec)?;
+        let target = *target_rx.borrow_and_update();
+
         unsafe {
             av_log_set_level(AV_LOG_INFO);
             av_log_set_flags(AV_LOG_SKIP_REPEATED);
         }
 
         Ok(VideoEncoder {
+            codec,
             encoder_config,
             encode_context: None,
+            scaler: None,
+            static_screen_detector: StaticScreenDetector::default(),
+            bitrate_multiplier: 1,
             client,
+            target_rx,
+            target,
+            frame_scale: (1.0, 1.0),
+            sequence: 0,
+            last_capture_time: None,
+            last_pts: -1,
         })
     }
 
     pub fn encode(&mut self, capture_frame: DesktopEncodeFrame) -> CoreResult<()> {
+        if self.target_rx.has_changed().unwrap_or(false) {
+            let target = *self.target_rx.borrow_and_update();
+            self.apply_target(target);
+        }
+
+        // frames captured faster than the target frame rate are dropped, a little
+        // tolerance keeps capture timing jitter from dropping wanted frames
+        let frame_interval = Duration::from_secs(1) / u32::from(self.target.frame_rate.max(1));
+        if let Some(last_capture_time) = self.last_capture_time {
+            if capture_frame.capture_time.saturating_sub(last_capture_time)
+                < frame_interval * 9 / 10
+            {
+                return Ok(());
+            }
+        }
+
+        // an unchanged screen is only refreshed now and then, but a requested key frame
+        // is always sent
+        if !self
+            .static_screen_detector
+            .should_encode(&capture_frame, self.client.is_key_frame_requested())
+        {
+            return Ok(());
+        }
+
+        self.last_capture_time = Some(capture_frame.capture_time);
+
+        // refreshes of a static screen raise the bitrate step by step, so the picture
+        // goes from the quality of motion to a sharp one, changes drop it back at once
+        let bitrate_multiplier = self.static_screen_detector.bitrate_multiplier();
+        if bitrate_multiplier != self.bitrate_multiplier {
+            self.bitrate_multiplier = bitrate_multiplier;
+
+            if let Some(ref encode_context) = self.encode_context {
+                if self.encoder_config.bitrate_reconfigurable() {
+                    unsafe { encode_context.set_bitrate(&self.encode_target()) };
+                }
+            }
+        }
+
+        let capture_frame = self.scale(capture_frame)?;
+
         unsafe {
             let mut ret: i32;
 
             if let Some(ref encode_context) = self.encode_context {
                 if (*encode_context.codec_ctx).width != capture_frame.width
                     || (*encode_context.codec_ctx).height != capture_frame.height
+                    || encode_context.format != capture_frame.format
                 {
                     self.encode_context = None;
                 }
@@ -54,13 +137,16 @@ where
 
             if self.encode_context.is_none() {
                 self.encode_context = Some(EncodeContext::new(
+                    capture_frame.format,
                     capture_frame.width,
                     capture_frame.height,
-                    &self.encoder_config,
+                    self.encoder_config.as_ref(),
+                    &self.encode_target(),
+                    self.client.settings().intra_refresh,
                 )?);
             }
 
-            let Some(ref encode_context)= self.encode_context else{
+            let Some(ref mut encode_context)= self.encode_context else{
                 return Err(core_error!("encode context is empty"))
             };
 
@@ -74,11 +160,47 @@ where
 
             (*(encode_context).frame).data[0] = capture_frame.luminance_bytes.as_ptr() as *mut _;
             (*(encode_context).frame).linesize[0] = capture_frame.luminance_stride;
-            (*(encode_context).frame).data[1] = capture_frame.chrominance_bytes.as_ptr() as *mut _;
-            (*(encode_context).frame).linesize[1] = capture_frame.chrominance_stride;
-            (*(encode_context).frame).pts = (capture_frame.capture_time.as_secs_f64()
+
+            if capture_frame.format == DesktopEncodeFrameFormat::YUV444P {
+                let (u_plane, v_plane) = capture_frame
+                    .chrominance_bytes
+                    .split_at((capture_frame.chrominance_stride * capture_frame.height) as usize);
+
+                (*(encode_context).frame).data[1] = u_plane.as_ptr() as *mut _;
+                (*(encode_context).frame).linesize[1] = capture_frame.chrominance_stride;
+                (*(encode_context).frame).data[2] = v_plane.as_ptr() as *mut _;
+                (*(encode_context).frame).linesize[2] = capture_frame.chrominance_stride;
+            } else if (*encode_context.codec_ctx).pix_fmt == AV_PIX_FMT_YUV420P {
+                split_chrominance(
+                    &capture_frame.chrominance_bytes,
+                    &mut encode_context.u_plane,
+                    &mut encode_context.v_plane,
+                );
+
+                (*(encode_context).frame).data[1] = encode_context.u_plane.as_mut_ptr();
+                (*(encode_context).frame).linesize[1] = capture_frame.chrominance_stride / 2;
+                (*(encode_context).frame).data[2] = encode_context.v_plane.as_mut_ptr();
+                (*(encode_context).frame).linesize[2] = capture_frame.chrominance_stride / 2;
+            } else {
+                (*(encode_context).frame).data[1] =
+                    capture_frame.chrominance_bytes.as_ptr() as *mut _;
+                (*(encode_context).frame).linesize[1] = capture_frame.chrominance_stride;
+            }
+
+            // pts must increase strictly even if two frames round into the same tick
+            let pts = (capture_frame.capture_time.as_secs_f64()
                 * ((*(encode_context).codec_ctx).time_base.den as f64))
                 as i64;
+            self.last_pts = pts.max(self.last_pts + 1);
+            (*(encode_context).frame).pts = self.last_pts;
+
+            // a new codec context always starts with a key frame
+            (*(encode_context).frame).pict_type = if self.client.take_key_frame_request() {
+                tracing::info!("encode key frame on request");
+                AV_PICTURE_TYPE_I
+            } else {
+                AV_PICTURE_TYPE_NONE
+            };
 
             ret = avcodec_send_frame((encode_context).codec_ctx, (encode_context).frame);
 
@@ -107,9 +229,18 @@ where
                 }
 
                 let frame = EndPointVideoFrame {
+                    codec: self.codec,
+                    sequence: self.sequence,
+                    key_frame: (*(encode_context).packet).flags & AV_PKT_FLAG_KEY != 0,
                     width: (*(encode_context).codec_ctx).width,
                     height: (*(encode_context).codec_ctx).height,
                     pts: (*(encode_context).packet).pts,
+                    timing: EndPointVideoFrameTiming {
+                        capture_time_us: capture_time_to_unix_micros(capture_frame.capture_time),
+                        encode_cost_us: capture_time_now()
+                            .saturating_sub(capture_frame.capture_time)
+                            .as_micros() as u32,
+                    },
                     buffer: std::slice::from_raw_parts(
                         (*(encode_context).packet).data,
                         (*(encode_context).packet).size as usize,
@@ -121,33 +252,130 @@ where
                     .blocking_send(&EndPointMessage::VideoFrame(frame))?;
 
                 av_packet_unref((encode_context).packet);
+                self.sequence += 1;
             }
         }
     }
+
+    fn apply_target(&mut self, target: EncoderTarget) {
+        if let Some(ref encode_context) = self.encode_context {
+            if target.frame_rate != self.target.frame_rate {
+                // time base can't be changed after the codec context is opened
+                self.encode_context = None;
+                self.last_pts = -1;
+            } else if self.encoder_config.bitrate_reconfigurable() {
+                unsafe { encode_context.set_bitrate(&target.boost(self.bitrate_multiplier)) };
+            } else {
+                // the codec context is recreated with the new bitrate at next frame
+                self.encode_context = None;
+            }
+        }
+
+        // a resolution change is applied by the scaler, the codec context follows the
+        // size of scaled frames
+        // a static screen is sent again at the new size without waiting for a refresh
+        if target.scale != self.target.scale
+            || target.max_width != self.target.max_width
+            || target.max_height != self.target.max_height
+        {
+            self.static_screen_detector = StaticScreenDetector::default();
+        }
+
+        self.target = target;
+    }
+
+    // encoders which can't change bitrate between frames would send a key frame for each
+    // refresh, they stay at the target bitrate
+    fn encode_target(&self) -> EncoderTarget {
+        if self.encoder_config.bitrate_reconfigurable() {
+            self.target.boost(self.bitrate_multiplier)
+        } else {
+            self.target
+        }
+    }
+
+    fn scale(&mut self, capture_frame: DesktopEncodeFrame) -> CoreResult<DesktopEncodeFrame> {
+        let (width, height) = self
+            .target
+            .scaled_size(capture_frame.width, capture_frame.height);
+
+        let frame_scale = (
+            capture_frame.width as f32 / width as f32,
+            capture_frame.height as f32 / height as f32,
+        );
+
+        if frame_scale != self.frame_scale {
+            self.client.blocking_set_frame_scale(frame_scale);
+            self.frame_scale = frame_scale;
+        }
+
+        if width == capture_frame.width && height == capture_frame.height {
+            self.scaler = None;
+            return Ok(capture_frame);
+        }
+
+        let scaler = match self.scaler.take() {
+            Some(scaler)
+                if scaler.is_match(
+                    capture_frame.format,
+                    capture_frame.width,
+                    capture_frame.height,
+                    width,
+                    height,
+                ) =>
+            {
+                scaler
+            }
+            _ => Scaler::new(
+                capture_frame.format,
+                capture_frame.width,
+                capture_frame.height,
+                width,
+                height,
+            )?,
+        };
+
+        let scaled_frame = scaler.scale(&capture_frame)?;
+        self.scaler = Some(scaler);
+
+        Ok(scaled_frame)
+    }
 }
 
 struct EncodeContext {
+    format: DesktopEncodeFrameFormat,
     codec_ctx: *mut AVCodecContext,
     frame: *mut AVFrame,
     packet: *mut AVPacket,
+    // planar chrominance buffers for encoders which don't accept nv12
+    u_plane: Vec<u8>,
+    v_plane: Vec<u8>,
 }
 
 impl EncodeContext {
     pub fn new(
+        format: DesktopEncodeFrameFormat,
         width: i32,
         height: i32,
         encoder_config: &dyn EncoderConfig,
+        target: &EncoderTarget,
+        intra_refresh: bool,
     ) -> CoreResult<EncodeContext> {
         unsafe {
-            let codec = avcodec_find_encoder(encoder_config.av_codec_id());
+            let codec = avcodec_find_encoder_by_name(encoder_config.ffmpeg_encoder_name());
             if codec.is_null() {
-                return Err(core_error!("avcodec_find_encoder returns null pointer"));
+                return Err(core_error!(
+                    "avcodec_find_encoder_by_name returns null pointer"
+                ));
             }
 
             let encoder_context = EncodeContext {
+                format,
                 codec_ctx: avcodec_alloc_context3(codec),
                 frame: av_frame_alloc(),
                 packet: av_packet_alloc(),
+                u_plane: Vec::new(),
+                v_plane: Vec::new(),
             };
 
             if encoder_context.codec_ctx.is_null()
@@ -159,16 +387,26 @@ impl EncodeContext {
 
             (*encoder_context.codec_ctx).width = width;
             (*encoder_context.codec_ctx).height = height;
-            (*encoder_context.codec_ctx).framerate = AVRational { num: 60, den: 1 };
-            (*encoder_context.codec_ctx).time_base = AVRational { num: 1, den: 60 };
-            (*encoder_context.codec_ctx).gop_size = 4000;
-            (*encoder_context.codec_ctx).bit_rate = 4000 * 1000;
-            (*encoder_context.codec_ctx).rc_max_rate = 4000 * 1000;
-            (*encoder_context.codec_ctx).rc_min_rate = 4000 * 1000;
-            (*encoder_context.codec_ctx).rc_buffer_size = 4000 * 1000 * 2;
+            (*encoder_context.codec_ctx).framerate = AVRational {
+                num: i32::from(target.frame_rate),
+                den: 1,
+            };
+            (*encoder_context.codec_ctx).time_base = AVRational {
+                num: 1,
+                den: i32::from(target.frame_rate),
+            };
+            (*encoder_context.codec_ctx).gop_size = if intra_refresh {
+                i32::from(target.frame_rate) * INTRA_REFRESH_INTERVAL_SECS
+            } else {
+                4000
+            };
+            encoder_context.set_bitrate(target);
             (*encoder_context.codec_ctx).has_b_frames = 0;
             (*encoder_context.codec_ctx).max_b_frames = 0;
-            (*encoder_context.codec_ctx).pix_fmt = AV_PIX_FMT_NV12;
+            (*encoder_context.codec_ctx).pix_fmt = match format {
+                DesktopEncodeFrameFormat::NV12 => encoder_config.pixel_format(),
+                DesktopEncodeFrameFormat::YUV444P => AV_PIX_FMT_YUV444P,
+            };
             (*encoder_context.codec_ctx).flags2 |= AV_CODEC_FLAG2_LOCAL_HEADER;
             (*encoder_context.codec_ctx).color_range = AVCOL_RANGE_JPEG;
             (*encoder_context.codec_ctx).color_primaries = AVCOL_PRI_BT709;
@@ -181,6 +419,10 @@ impl EncodeContext {
 
             encoder_config.apply_option(encoder_context.codec_ctx)?;
 
+            if intra_refresh {
+                encoder_config.apply_intra_refresh(encoder_context.codec_ctx)?;
+            }
+
             let mut ret = av_frame_get_buffer(encoder_context.frame, 0);
             if ret < 0 {
                 return Err(core_error!(
@@ -205,6 +447,13 @@ impl EncodeContext {
             Ok(encoder_context)
         }
     }
+
+    unsafe fn set_bitrate(&self, target: &EncoderTarget) {
+        (*self.codec_ctx).bit_rate = target.bitrate;
+        (*self.codec_ctx).rc_max_rate = target.max_bitrate;
+        (*self.codec_ctx).rc_min_rate = target.bitrate;
+        (*self.codec_ctx).rc_buffer_size = (target.max_bitrate * 2).min(i32::MAX as i64) as i32;
+    }
 }
 
 impl Drop for EncodeContext {
@@ -225,3 +474,13 @@ impl Drop for EncodeContext {
         }
     }
 }
+
+fn split_chrominance(chrominance: &[u8], u_plane: &mut Vec<u8>, v_plane: &mut Vec<u8>) {
+    u_plane.clear();
+    v_plane.clear();
+
+    for uv in chrominance.chunks_exact(2) {
+        u_plane.push(uv[0]);
+        v_plane.push(uv[1]);
+    }
+}
@@ -1,2 +1,5 @@
 pub mod config;
+pub mod congestion;
 pub mod encoder;
+pub mod scaler;
+pub mod static_screen;
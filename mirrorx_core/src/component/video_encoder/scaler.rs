@@ -0,0 +1,161 @@
+use crate::{
+    component::frame::{DesktopEncodeFrame, DesktopEncodeFrameFormat},
+    core_error,
+    error::CoreResult,
+};
+use mirrorx_native::ffmpeg::{
+    swscale::*,
+    utils::pixfmt::{AV_PIX_FMT_NV12, AV_PIX_FMT_YUV444P},
+};
+
+pub struct Scaler {
+    sws_ctx: *mut SwsContext,
+    format: DesktopEncodeFrameFormat,
+    src_width: i32,
+    src_height: i32,
+    dst_width: i32,
+    dst_height: i32,
+}
+
+unsafe impl Send for Scaler {}
+
+impl Scaler {
+    pub fn new(
+        format: DesktopEncodeFrameFormat,
+        src_width: i32,
+        src_height: i32,
+        dst_width: i32,
+        dst_height: i32,
+    ) -> CoreResult<Scaler> {
+        let pix_fmt = match format {
+            DesktopEncodeFrameFormat::NV12 => AV_PIX_FMT_NV12,
+            DesktopEncodeFrameFormat::YUV444P => AV_PIX_FMT_YUV444P,
+        };
+
+        unsafe {
+            let sws_ctx = sws_getContext(
+                src_width,
+                src_height,
+                pix_fmt,
+                dst_width,
+                dst_height,
+                pix_fmt,
+                SWS_FAST_BILINEAR,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null(),
+            );
+
+            if sws_ctx.is_null() {
+                return Err(core_error!("sws_getContext returns null pointer"));
+            }
+
+            Ok(Scaler {
+                sws_ctx,
+                format,
+                src_width,
+                src_height,
+                dst_width,
+                dst_height,
+            })
+        }
+    }
+
+    pub fn is_match(
+        &self,
+        format: DesktopEncodeFrameFormat,
+        src_width: i32,
+        src_height: i32,
+        dst_width: i32,
+        dst_height: i32,
+    ) -> bool {
+        self.format == format
+            && self.src_width == src_width
+            && self.src_height == src_height
+            && self.dst_width == dst_width
+            && self.dst_height == dst_height
+    }
+
+    pub fn scale(&self, frame: &DesktopEncodeFrame) -> CoreResult<DesktopEncodeFrame> {
+        // nv12 chrominance plane interleaves u and v, so its stride equals the width
+        let plane_size = (self.dst_width * self.dst_height) as usize;
+        let mut luminance_bytes = vec![0u8; plane_size];
+
+        let (chrominance_bytes, src_slice, src_stride, dst, dst_stride) = match self.format {
+            DesktopEncodeFrameFormat::NV12 => {
+                let mut chrominance_bytes = vec![0u8; plane_size / 2];
+
+                let src_slice = vec![
+                    frame.luminance_bytes.as_ptr(),
+                    frame.chrominance_bytes.as_ptr(),
+                ];
+                let src_stride = vec![frame.luminance_stride, frame.chrominance_stride];
+                let dst = vec![luminance_bytes.as_mut_ptr(), chrominance_bytes.as_mut_ptr()];
+                let dst_stride = vec![self.dst_width, self.dst_width];
+
+                (chrominance_bytes, src_slice, src_stride, dst, dst_stride)
+            }
+            DesktopEncodeFrameFormat::YUV444P => {
+                let mut chrominance_bytes = vec![0u8; plane_size * 2];
+                let src_plane_size = (frame.chrominance_stride * self.src_height) as usize;
+
+                let src_slice = vec![
+                    frame.luminance_bytes.as_ptr(),
+                    frame.chrominance_bytes.as_ptr(),
+                    frame.chrominance_bytes[src_plane_size..].as_ptr(),
+                ];
+                let src_stride = vec![
+                    frame.luminance_stride,
+                    frame.chrominance_stride,
+                    frame.chrominance_stride,
+                ];
+                let (u_plane, v_plane) = chrominance_bytes.split_at_mut(plane_size);
+                let dst = vec![
+                    luminance_bytes.as_mut_ptr(),
+                    u_plane.as_mut_ptr(),
+                    v_plane.as_mut_ptr(),
+                ];
+                let dst_stride = vec![self.dst_width, self.dst_width, self.dst_width];
+
+                (chrominance_bytes, src_slice, src_stride, dst, dst_stride)
+            }
+        };
+
+        unsafe {
+            let ret = sws_scale(
+                self.sws_ctx,
+                src_slice.as_ptr(),
+                src_stride.as_ptr(),
+                0,
+                self.src_height,
+                dst.as_ptr(),
+                dst_stride.as_ptr(),
+            );
+
+            if ret != self.dst_height {
+                return Err(core_error!("sws_scale returns unexpected height: {}", ret));
+            }
+        }
+
+        Ok(DesktopEncodeFrame {
+            capture_time: frame.capture_time,
+            format: self.format,
+            width: self.dst_width,
+            height: self.dst_height,
+            luminance_bytes,
+            luminance_stride: self.dst_width,
+            chrominance_bytes,
+            chrominance_stride: self.dst_width,
+        })
+    }
+}
+
+impl Drop for Scaler {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.sws_ctx.is_null() {
+                sws_freeContext(self.sws_ctx);
+            }
+        }
+    }
+}
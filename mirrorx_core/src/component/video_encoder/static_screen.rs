@@ -0,0 +1,126 @@
+use crate::component::frame::DesktopEncodeFrame;
+use std::{hash::Hasher, time::Duration};
+
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+// the bitrate doubles with each refresh, up to eight times of the target bitrate
+const MAX_REFRESH_BITRATE_STEPS: u32 = 3;
+
+// detects unchanged frames by hashing all planes, frames of a static screen are encoded
+// at growing intervals and growing bitrate, each of them refines the picture a bit more
+pub struct StaticScreenDetector {
+    last_hash: Option<u64>,
+    last_encode_time: Option<Duration>,
+    refresh_interval: Duration,
+    refreshes: u32,
+}
+
+impl Default for StaticScreenDetector {
+    fn default() -> Self {
+        Self {
+            last_hash: None,
+            last_encode_time: None,
+            refresh_interval: MIN_REFRESH_INTERVAL,
+            refreshes: 0,
+        }
+    }
+}
+
+impl StaticScreenDetector {
+    pub fn should_encode(&mut self, capture_frame: &DesktopEncodeFrame, force: bool) -> bool {
+        let mut hasher = fxhash::FxHasher64::default();
+        hasher.write_i32(capture_frame.width);
+        hasher.write_i32(capture_frame.height);
+        hasher.write(&capture_frame.luminance_bytes);
+        hasher.write(&capture_frame.chrominance_bytes);
+        let hash = hasher.finish();
+
+        let changed = self.last_hash != Some(hash);
+        self.last_hash = Some(hash);
+
+        if changed || force {
+            self.refresh_interval = MIN_REFRESH_INTERVAL;
+            self.refreshes = 0;
+            self.last_encode_time = Some(capture_frame.capture_time);
+            return true;
+        }
+
+        if let Some(last_encode_time) = self.last_encode_time {
+            if capture_frame.capture_time.saturating_sub(last_encode_time) < self.refresh_interval {
+                return false;
+            }
+        }
+
+        self.refresh_interval = (self.refresh_interval * 2).min(MAX_REFRESH_INTERVAL);
+        self.refreshes += 1;
+        self.last_encode_time = Some(capture_frame.capture_time);
+
+        true
+    }
+
+    // multiplier of the target bitrate for the frame about to be encoded
+    pub fn bitrate_multiplier(&self) -> i64 {
+        1 << self.refreshes.min(MAX_REFRESH_BITRATE_STEPS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::component::frame::DesktopEncodeFrameFormat;
+
+    fn capture_frame(capture_time_ms: u64, luminance: u8, chrominance: u8) -> DesktopEncodeFrame {
+        DesktopEncodeFrame {
+            capture_time: Duration::from_millis(capture_time_ms),
+            format: DesktopEncodeFrameFormat::NV12,
+            width: 4,
+            height: 2,
+            luminance_bytes: vec![luminance; 8],
+            luminance_stride: 4,
+            chrominance_bytes: vec![chrominance; 4],
+            chrominance_stride: 4,
+        }
+    }
+
+    #[test]
+    fn test_chrominance_change_is_not_static() {
+        let mut detector = StaticScreenDetector::default();
+
+        assert!(detector.should_encode(&capture_frame(0, 16, 128), false));
+        assert!(!detector.should_encode(&capture_frame(10, 16, 128), false));
+
+        // only the color changes, the luminance plane stays the same
+        assert!(detector.should_encode(&capture_frame(20, 16, 200), false));
+        assert_eq!(detector.bitrate_multiplier(), 1);
+    }
+
+    #[test]
+    fn test_refresh_bitrate_steps_once_per_refresh() {
+        let mut detector = StaticScreenDetector::default();
+
+        assert!(detector.should_encode(&capture_frame(0, 16, 128), false));
+        assert_eq!(detector.bitrate_multiplier(), 1);
+
+        let mut encoded = Vec::new();
+        for capture_time_ms in (10..=6000).step_by(10) {
+            if detector.should_encode(&capture_frame(capture_time_ms, 16, 128), false) {
+                encoded.push((capture_time_ms, detector.bitrate_multiplier()));
+            }
+        }
+
+        // refreshes at doubling intervals, each one doubles the bitrate exactly once
+        assert_eq!(
+            &encoded[..5],
+            [(100, 2), (300, 4), (700, 8), (1500, 8), (3100, 8)]
+        );
+        assert_eq!(encoded[5], (5100, 8));
+        assert_eq!(encoded.len(), 6);
+
+        // a forced key frame or a change starts over with the target bitrate
+        assert!(detector.should_encode(&capture_frame(6010, 16, 128), true));
+        assert_eq!(detector.bitrate_multiplier(), 1);
+        assert!(!detector.should_encode(&capture_frame(6020, 16, 128), false));
+        assert!(detector.should_encode(&capture_frame(6110, 16, 128), false));
+        assert_eq!(detector.bitrate_multiplier(), 2);
+    }
+}
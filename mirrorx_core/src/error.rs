@@ -97,6 +97,15 @@ pub enum CoreError {
 
     #[error("get network interfaces error ({0:?})")]
     NetworkInterfacesError(#[from] network_interface::Error),
+
+    #[error("parse semantic version failed ({0:?})")]
+    SemverError(#[from] semver::Error),
+
+    #[error("tls error ({0:?})")]
+    TlsError(#[from] native_tls::Error),
+
+    #[error("client too old, please upgrade (current={current}, required={required})")]
+    ClientTooOld { current: String, required: String },
 }
 
 impl serde::Serialize for CoreError {
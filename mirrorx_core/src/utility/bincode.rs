@@ -28,3 +28,90 @@ where
     let ty = SERIALIZER.deserialize(bytes)?;
     Ok(ty)
 }
+
+// decodes a message which may be followed by an extension, peers of older versions
+// don't send the extension and a malformed one is dropped
+pub fn bincode_deserialize_with_extension<'a, T, E>(bytes: &'a [u8]) -> CoreResult<(T, Option<E>)>
+where
+    T: serde::Deserialize<'a> + serde::Serialize,
+    E: serde::Deserialize<'a>,
+{
+    let ty: T = SERIALIZER.allow_trailing_bytes().deserialize(bytes)?;
+    let length = SERIALIZER.serialized_size(&ty)? as usize;
+
+    let extension = match bytes.get(length..) {
+        Some(extension_bytes) if !extension_bytes.is_empty() => SERIALIZER
+            .allow_trailing_bytes()
+            .deserialize(extension_bytes)
+            .ok(),
+        _ => None,
+    };
+
+    Ok((ty, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Message {
+        value: u32,
+        #[serde(with = "serde_bytes")]
+        bytes: Vec<u8>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Extension {
+        version: u8,
+        name: String,
+    }
+
+    fn message() -> Message {
+        Message {
+            value: 300,
+            bytes: vec![1, 2, 3],
+        }
+    }
+
+    #[test]
+    fn test_deserialize_without_extension() {
+        let buffer = bincode_serialize(&message()).unwrap();
+
+        let (decoded, extension) =
+            bincode_deserialize_with_extension::<Message, Extension>(&buffer).unwrap();
+
+        assert_eq!(decoded, message());
+        assert_eq!(extension, None);
+    }
+
+    #[test]
+    fn test_deserialize_with_extension() {
+        let extension = Extension {
+            version: 1,
+            name: String::from("extension"),
+        };
+
+        let mut buffer = bincode_serialize(&message()).unwrap();
+        buffer.extend(bincode_serialize(&extension).unwrap());
+
+        let (decoded, decoded_extension) =
+            bincode_deserialize_with_extension::<Message, Extension>(&buffer).unwrap();
+
+        assert_eq!(decoded, message());
+        assert_eq!(decoded_extension, Some(extension));
+    }
+
+    #[test]
+    fn test_deserialize_with_malformed_extension() {
+        let mut buffer = bincode_serialize(&message()).unwrap();
+        buffer.push(1);
+
+        let (decoded, extension) =
+            bincode_deserialize_with_extension::<Message, Extension>(&buffer).unwrap();
+
+        assert_eq!(decoded, message());
+        assert_eq!(extension, None);
+    }
+}
@@ -3,3 +3,4 @@ pub mod macros;
 pub mod nonce_value;
 pub mod os;
 pub mod rand;
+pub mod time;
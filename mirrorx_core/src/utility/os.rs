@@ -1,7 +1,7 @@
 use crate::error::CoreResult;
 use network_interface::NetworkInterfaceConfig;
 use serde::Serialize;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr};
 
 #[derive(Debug, Serialize)]
 pub struct GraphicsCards {
@@ -10,6 +10,8 @@ pub struct GraphicsCards {
 }
 
 pub fn enum_graphics_cards() -> CoreResult<Vec<GraphicsCards>> {
+    // graphics cards aren't listed on linux yet
+    #[allow(unused_mut)]
     let mut graphics_cards = Vec::new();
 
     #[cfg(target_os = "macos")]
@@ -82,3 +84,53 @@ pub fn enum_broadcast_network_interfaces() -> CoreResult<Vec<(String, IpAddr)>>
 
     Ok(valid_interfaces)
 }
+
+pub fn enum_host_addrs() -> CoreResult<Vec<IpAddr>> {
+    let interfaces = network_interface::NetworkInterface::show()?;
+    let mut addrs = Vec::new();
+
+    for interface in interfaces {
+        let Some(addr) = interface.addr else {
+            continue;
+        };
+
+        let ip = addr.ip();
+
+        if ip.is_loopback() || ip.is_ipv6() || ip.is_unspecified() {
+            continue;
+        }
+
+        if !addrs.contains(&ip) {
+            addrs.push(ip);
+        }
+    }
+
+    Ok(addrs)
+}
+
+pub fn enum_host_networks() -> CoreResult<Vec<(Ipv4Addr, Ipv4Addr)>> {
+    let interfaces = network_interface::NetworkInterface::show()?;
+    let mut networks = Vec::new();
+
+    for interface in interfaces {
+        let Some(network_interface::Addr::V4(addr)) = interface.addr else {
+            continue;
+        };
+
+        if addr.ip.is_loopback() || addr.ip.is_unspecified() {
+            continue;
+        }
+
+        // treat interface without netmask as a class c network
+        let netmask = addr.netmask.unwrap_or(Ipv4Addr::new(255, 255, 255, 0));
+
+        networks.push((addr.ip, netmask));
+    }
+
+    Ok(networks)
+}
+
+pub fn os_description() -> String {
+    let os_info = os_info::get();
+    format!("{} {}", os_info.os_type(), os_info.version())
+}
@@ -0,0 +1,5 @@
+// wall clock in microseconds, endpoints exchange it to estimate their clock offset
+#[inline]
+pub fn unix_micros() -> i64 {
+    chrono::Utc::now().timestamp_micros()
+}
@@ -56,6 +56,15 @@ fn link_media_libraries_artifacts() {
     );
     println!("cargo:rustc-link-lib=opus");
 
+    println!(
+        "cargo:rustc-link-search={}",
+        mirrorx_media_libraries_path
+            .join("libyuv")
+            .join("lib")
+            .display()
+    );
+    println!("cargo:rustc-link-lib=yuv");
+
     println!(
         "cargo:rustc-link-search={}",
         mirrorx_media_libraries_path
@@ -68,6 +77,7 @@ fn link_media_libraries_artifacts() {
     println!("cargo:rustc-link-lib=avformat");
     println!("cargo:rustc-link-lib=avdevice");
     println!("cargo:rustc-link-lib=swresample");
+    println!("cargo:rustc-link-lib=swscale");
 }
 
 #[cfg(target_os = "windows")]
@@ -88,9 +98,38 @@ fn link_media_libraries_artifacts() {
     println!("cargo:rustc-link-lib=libx265");
     println!("cargo:rustc-link-lib=libopus");
     println!("cargo:rustc-link-lib=libmfx");
+    println!("cargo:rustc-link-lib=yuv");
     println!("cargo:rustc-link-lib=libavcodec");
     println!("cargo:rustc-link-lib=libavutil");
     println!("cargo:rustc-link-lib=libavformat");
     println!("cargo:rustc-link-lib=libavdevice");
     println!("cargo:rustc-link-lib=libswresample");
+    println!("cargo:rustc-link-lib=libswscale");
+}
+
+#[cfg(target_os = "linux")]
+fn link_media_libraries_artifacts() {
+    // prebuilt artifacts share the macos layout, without them the system libraries are
+    // linked. x11 isn't linked here, x11-dl loads it at runtime
+    if let Ok(path) = std::env::var("MIRRORX_MEDIA_LIBS_PATH") {
+        let mirrorx_media_libraries_path = PathBuf::from(path);
+
+        for library in ["opus", "libyuv", "ffmpeg"] {
+            println!(
+                "cargo:rustc-link-search={}",
+                mirrorx_media_libraries_path
+                    .join(library)
+                    .join("lib")
+                    .display()
+            );
+        }
+    }
+
+    println!("cargo:rustc-link-lib=opus");
+    println!("cargo:rustc-link-lib=yuv");
+    println!("cargo:rustc-link-lib=avcodec");
+    println!("cargo:rustc-link-lib=avutil");
+    println!("cargo:rustc-link-lib=avformat");
+    println!("cargo:rustc-link-lib=swresample");
+    println!("cargo:rustc-link-lib=swscale");
 }
@@ -1,3 +1,14 @@
+use super::codec_id::AVCodecID;
+use crate::ffmpeg::utils::{
+    avutil::AVMediaType,
+    channel_layout::AVChannelLayout,
+    pixfmt::{
+        AVChromaLocation, AVColorPrimaries, AVColorRange, AVColorSpace,
+        AVColorTransferCharacteristic,
+    },
+    rational::AVRational,
+};
+
 pub type AVFieldOrder = u32;
 pub const AV_FIELD_UNKNOWN: AVFieldOrder = 0;
 pub const AV_FIELD_PROGRESSIVE: AVFieldOrder = 1;
@@ -5,3 +16,37 @@ pub const AV_FIELD_TT: AVFieldOrder = 2; //< Top coded_first, top displayed firs
 pub const AV_FIELD_BB: AVFieldOrder = 3; //< Bottom coded first, bottom displayed first
 pub const AV_FIELD_TB: AVFieldOrder = 4; //< Top coded first, bottom displayed first
 pub const AV_FIELD_BT: AVFieldOrder = 5; //< Bottom coded first, top displayed first
+
+#[repr(C)]
+pub struct AVCodecParameters {
+    pub codec_type: AVMediaType,
+    pub codec_id: AVCodecID,
+    pub codec_tag: u32,
+    pub extradata: *mut u8,
+    pub extradata_size: i32,
+    pub format: i32,
+    pub bit_rate: i64,
+    pub bits_per_coded_sample: i32,
+    pub bits_per_raw_sample: i32,
+    pub profile: i32,
+    pub level: i32,
+    pub width: i32,
+    pub height: i32,
+    pub sample_aspect_ratio: AVRational,
+    pub field_order: AVFieldOrder,
+    pub color_range: AVColorRange,
+    pub color_primaries: AVColorPrimaries,
+    pub color_trc: AVColorTransferCharacteristic,
+    pub color_space: AVColorSpace,
+    pub chroma_location: AVChromaLocation,
+    pub video_delay: i32,
+    pub channel_layout: u64,
+    pub channels: i32,
+    pub sample_rate: i32,
+    pub block_align: i32,
+    pub frame_size: i32,
+    pub initial_padding: i32,
+    pub trailing_padding: i32,
+    pub seek_preroll: i32,
+    pub ch_layout: AVChannelLayout,
+}
@@ -17,3 +17,5 @@ pub const AVDISCARD_BIDIR: AVDiscard = 16;
 pub const AVDISCARD_NONINTRA: AVDiscard = 24;
 pub const AVDISCARD_NONKEY: AVDiscard = 32;
 pub const AVDISCARD_ALL: AVDiscard = 48;
+
+pub const AV_INPUT_BUFFER_PADDING_SIZE: usize = 64;
@@ -35,6 +35,10 @@ pub const AV_PKT_DATA_DOVI_CONF: AVPacketSideDataType = 29;
 pub const AV_PKT_DATA_S12M_TIMECODE: AVPacketSideDataType = 30;
 pub const AV_PKT_DATA_DYNAMIC_HDR10_PLUS: AVPacketSideDataType = 31;
 
+pub const AV_PKT_FLAG_KEY: i32 = 0x0001;
+pub const AV_PKT_FLAG_CORRUPT: i32 = 0x0002;
+pub const AV_PKT_FLAG_DISCARD: i32 = 0x0004;
+
 #[repr(C)]
 pub struct AVPacketSideData {
     pub data: *mut u8,
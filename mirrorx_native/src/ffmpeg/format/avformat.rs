@@ -0,0 +1,85 @@
+use super::avio::AVIOContext;
+use crate::ffmpeg::{
+    codecs::{codec_id::AVCodecID, codec_par::AVCodecParameters, packet::AVPacket},
+    utils::{dict::AVDictionary, log::AVClass, rational::AVRational},
+};
+use std::{ffi::c_void, os::raw::c_char};
+
+pub const AVFMT_NOFILE: i32 = 0x0001;
+pub const AVFMT_NEEDNUMBER: i32 = 0x0002;
+pub const AVFMT_GLOBALHEADER: i32 = 0x0040;
+pub const AVFMT_NOTIMESTAMPS: i32 = 0x0080;
+pub const AVFMT_VARIABLE_FPS: i32 = 0x0400;
+pub const AVFMT_NODIMENSIONS: i32 = 0x0800;
+pub const AVFMT_NOSTREAMS: i32 = 0x1000;
+
+pub const AVFMT_FLAG_FLUSH_PACKETS: i32 = 0x0200;
+
+#[repr(C)]
+pub struct AVOutputFormat {
+    pub name: *const c_char,
+    pub long_name: *const c_char,
+    pub mime_type: *const c_char,
+    pub extensions: *const c_char,
+    pub audio_codec: AVCodecID,
+    pub video_codec: AVCodecID,
+    pub subtitle_codec: AVCodecID,
+    pub flags: i32,
+}
+
+// only the leading public fields are bound, both contexts are always allocated
+// by libavformat and accessed through pointers
+#[repr(C)]
+pub struct AVStream {
+    pub index: i32,
+    pub id: i32,
+    pub priv_data: *mut c_void,
+    pub time_base: AVRational,
+    pub start_time: i64,
+    pub duration: i64,
+    pub nb_frames: i64,
+    pub disposition: i32,
+    pub discard: i32,
+    pub sample_aspect_ratio: AVRational,
+    pub metadata: *mut AVDictionary,
+    pub avg_frame_rate: AVRational,
+    pub attached_pic: AVPacket,
+    pub side_data: *mut c_void,
+    pub nb_side_data: i32,
+    pub event_flags: i32,
+    pub r_frame_rate: AVRational,
+    pub codecpar: *mut AVCodecParameters,
+}
+
+#[repr(C)]
+pub struct AVFormatContext {
+    pub av_class: *const AVClass,
+    pub iformat: *const c_void,
+    pub oformat: *const AVOutputFormat,
+    pub priv_data: *mut c_void,
+    pub pb: *mut AVIOContext,
+    pub ctx_flags: i32,
+    pub nb_streams: u32,
+    pub streams: *mut *mut AVStream,
+    pub url: *mut c_char,
+    pub start_time: i64,
+    pub duration: i64,
+    pub bit_rate: i64,
+    pub packet_size: u32,
+    pub max_delay: i32,
+    pub flags: i32,
+}
+
+extern "C" {
+    pub fn avformat_alloc_output_context2(
+        ctx: *mut *mut AVFormatContext,
+        oformat: *const AVOutputFormat,
+        format_name: *const c_char,
+        filename: *const c_char,
+    ) -> i32;
+    pub fn avformat_free_context(s: *mut AVFormatContext);
+    pub fn avformat_new_stream(s: *mut AVFormatContext, c: *const c_void) -> *mut AVStream;
+    pub fn avformat_write_header(s: *mut AVFormatContext, options: *mut *mut AVDictionary) -> i32;
+    pub fn av_interleaved_write_frame(s: *mut AVFormatContext, pkt: *mut AVPacket) -> i32;
+    pub fn av_write_trailer(s: *mut AVFormatContext) -> i32;
+}
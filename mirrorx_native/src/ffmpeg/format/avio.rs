@@ -0,0 +1,17 @@
+use std::os::raw::c_char;
+
+pub const AVIO_FLAG_READ: i32 = 1;
+pub const AVIO_FLAG_WRITE: i32 = 2;
+pub const AVIO_FLAG_READ_WRITE: i32 = AVIO_FLAG_READ | AVIO_FLAG_WRITE;
+
+// the context is only handed back to libavformat, so the layout stays opaque
+#[repr(C)]
+pub struct AVIOContext {
+    _private: [u8; 0],
+}
+
+extern "C" {
+    pub fn avio_open(s: *mut *mut AVIOContext, url: *const c_char, flags: i32) -> i32;
+    pub fn avio_closep(s: *mut *mut AVIOContext) -> i32;
+    pub fn avio_flush(s: *mut AVIOContext);
+}
@@ -0,0 +1,2 @@
+pub mod avformat;
+pub mod avio;
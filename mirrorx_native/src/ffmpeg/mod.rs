@@ -1,3 +1,5 @@
 pub mod codecs;
+pub mod format;
 pub mod swresample;
+pub mod swscale;
 pub mod utils;
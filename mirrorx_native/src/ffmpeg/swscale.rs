@@ -0,0 +1,49 @@
+use super::utils::pixfmt::AVPixelFormat;
+
+pub const SWS_FAST_BILINEAR: i32 = 1;
+pub const SWS_BILINEAR: i32 = 2;
+pub const SWS_BICUBIC: i32 = 4;
+pub const SWS_POINT: i32 = 0x10;
+pub const SWS_AREA: i32 = 0x20;
+
+pub const SWS_CS_ITU709: i32 = 1;
+pub const SWS_CS_DEFAULT: i32 = 5;
+
+pub enum SwsContext {}
+pub enum SwsFilter {}
+
+extern "C" {
+    pub fn sws_getContext(
+        src_w: i32,
+        src_h: i32,
+        src_format: AVPixelFormat,
+        dst_w: i32,
+        dst_h: i32,
+        dst_format: AVPixelFormat,
+        flags: i32,
+        src_filter: *mut SwsFilter,
+        dst_filter: *mut SwsFilter,
+        param: *const f64,
+    ) -> *mut SwsContext;
+    pub fn sws_freeContext(swscontext: *mut SwsContext);
+    pub fn sws_getCoefficients(colorspace: i32) -> *const i32;
+    pub fn sws_setColorspaceDetails(
+        c: *mut SwsContext,
+        inv_table: *const i32,
+        src_range: i32,
+        table: *const i32,
+        dst_range: i32,
+        brightness: i32,
+        contrast: i32,
+        saturation: i32,
+    ) -> i32;
+    pub fn sws_scale(
+        c: *mut SwsContext,
+        src_slice: *const *const u8,
+        src_stride: *const i32,
+        src_slice_y: i32,
+        src_slice_h: i32,
+        dst: *const *mut u8,
+        dst_stride: *const i32,
+    ) -> i32;
+}
@@ -22,4 +22,5 @@ pub struct AVChannelLayout {
 
 extern "C" {
     pub fn av_channel_layout_check(channel_layout: *const AVChannelLayout) -> i32;
+    pub fn av_channel_layout_default(ch_layout: *mut AVChannelLayout, nb_channels: i32);
 }
@@ -2,4 +2,5 @@ use std::ffi::c_void;
 
 extern "C" {
     pub fn av_freep(ptr: *mut c_void);
+    pub fn av_mallocz(size: usize) -> *mut c_void;
 }
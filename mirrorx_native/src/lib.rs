@@ -1,3 +1,4 @@
 pub mod ffmpeg;
+pub mod libyuv;
 pub mod opus;
 pub mod os;
@@ -19,29 +19,55 @@ extern "C" {
 }
 
 extern "C" {
-    #[link(kind = "static")]
     pub fn NV12ToARGBMatrix(
         src_y: *const u8,
-        src_stride_y: isize,
+        src_stride_y: i32,
         src_uv: *const u8,
-        src_stride_uv: isize,
+        src_stride_uv: i32,
         dst_argb: *mut u8,
-        dst_stride_argb: isize,
+        dst_stride_argb: i32,
         yuvconstants: *const YuvConstants,
-        width: isize,
-        height: isize,
-    ) -> isize;
+        width: i32,
+        height: i32,
+    ) -> i32;
 
-    #[link(kind = "static")]
     pub fn NV21ToARGBMatrix(
         src_y: *const u8,
-        src_stride_y: isize,
+        src_stride_y: i32,
         src_uv: *const u8,
-        src_stride_uv: isize,
+        src_stride_uv: i32,
         dst_argb: *mut u8,
-        dst_stride_argb: isize,
+        dst_stride_argb: i32,
         yuvconstants: *const YuvConstants,
-        width: isize,
-        height: isize,
-    ) -> isize;
+        width: i32,
+        height: i32,
+    ) -> i32;
+
+    pub fn I420ToARGBMatrix(
+        src_y: *const u8,
+        src_stride_y: i32,
+        src_u: *const u8,
+        src_stride_u: i32,
+        src_v: *const u8,
+        src_stride_v: i32,
+        dst_argb: *mut u8,
+        dst_stride_argb: i32,
+        yuvconstants: *const YuvConstants,
+        width: i32,
+        height: i32,
+    ) -> i32;
+
+    pub fn I444ToARGBMatrix(
+        src_y: *const u8,
+        src_stride_y: i32,
+        src_u: *const u8,
+        src_stride_u: i32,
+        src_v: *const u8,
+        src_stride_v: i32,
+        dst_argb: *mut u8,
+        dst_stride_argb: i32,
+        yuvconstants: *const YuvConstants,
+        width: i32,
+        height: i32,
+    ) -> i32;
 }
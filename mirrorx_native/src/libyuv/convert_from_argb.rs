@@ -0,0 +1,12 @@
+extern "C" {
+    pub fn ARGBToNV12(
+        src_argb: *const u8,
+        src_stride_argb: i32,
+        dst_y: *mut u8,
+        dst_stride_y: i32,
+        dst_uv: *mut u8,
+        dst_stride_uv: i32,
+        width: i32,
+        height: i32,
+    ) -> i32;
+}
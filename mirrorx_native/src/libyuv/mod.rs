@@ -1,7 +1,9 @@
 #![allow(unused)]
 
 mod convert_argb;
+mod convert_from_argb;
 mod row;
 
 pub use convert_argb::*;
+pub use convert_from_argb::*;
 pub use row::*;
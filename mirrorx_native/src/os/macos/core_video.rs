@@ -36,6 +36,8 @@ extern "C" {
         -> i32;
     pub fn CVPixelBufferGetWidth(pixel_buffer: CVPixelBufferRef) -> usize;
     pub fn CVPixelBufferGetHeight(pixel_buffer: CVPixelBufferRef) -> usize;
+    pub fn CVPixelBufferGetBytesPerRow(pixel_buffer: CVPixelBufferRef) -> usize;
+    pub fn CVPixelBufferGetBaseAddress(pixel_buffer: CVPixelBufferRef) -> *mut c_void;
     pub fn CVPixelBufferGetBytesPerRowOfPlane(
         pixel_buffer: CVPixelBufferRef,
         planeIndex: usize,